@@ -0,0 +1,854 @@
+// Command-line interface. Every command other than `gui` runs without ever creating a window so
+// that the tool stays usable on headless machines and in scripts.
+//
+// Saves are written with the same temp-file-and-backup logic the GUI uses (write_costume_save and
+// create_costume_save).
+
+use crate::{costume, create_costume_save, json, library, pack, parse_keyword_list, rename, tags, write_costume_save, AppConfig, LOGGER};
+
+use std::{
+    fmt,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+pub const USAGE: &str = "\
+Usage: ccm [gui]
+       ccm inspect <path>... [--format <format>] [--long]
+       ccm set <costume save file path> [options]
+       ccm rename --template <template> <path>... [--dry-run]
+       ccm export <path>... --output <library file> [--images <embed|sidecar>]
+       ccm import <library file> [--output-dir <dir>] [--dry-run]
+       ccm pack <path>... --output <pack file> [--note <note>]
+       ccm unpack <pack file> [options]
+       ccm help
+
+gui
+    Launch the GUI. This is the default if no command is given.
+
+inspect <path>... [--format <format>] [--long]
+    Print file name, in-game save display, and costume metadata. Each path can
+    be a costume save, a directory (every costume save directly inside it is
+    inspected) or a glob whose file name part contains * or ?, e.g.
+    \"screenshots/Costume_*FightClub*.jpg\". Saves that fail to load are
+    reported as errors without stopping the rest of the run.
+
+    --format <text|json|ndjson|csv>
+        Defaults to text. The other formats are machine-readable and have one
+        record per save with the following fields: path, file_name, save_name,
+        j2000_timestamp, in_game_display_name, account_name, character_name,
+        hash, hash_valid, keywords, spec_length, image_width, image_height and
+        error. error is only set for saves that failed to load, in which case
+        every field other than path and file_name is empty.
+
+    --long
+        Text format only. Also print the costume hash and proprietary costume
+        specification.
+
+set <costume save file path> [options]
+    Apply the given changes to the save then print the updated information.
+
+    -a, --account-name <account_name>
+        Set the account name that will be displayed in-game.
+
+    -c, --character-name <character_name>
+        Set the character name that will be displayed in-game.
+
+    -s, --save-name <save_name>
+        Set the portion of the file name between the \"Costume_\" prefix and the
+        j2000 timestamp suffix (if it exists).
+
+    -t, --strip-timestamp
+        Strip the j2000 timestamp suffix from the file name, removing the date
+        display from its entry in the in-game save menu.
+
+    --timestamp <timestamp>
+        Set the j2000 timestamp suffix. Accepts either a raw j2000 timestamp or a
+        date and time in the form \"YYYY-MM-DD HH:MM:SS\", interpreted in the
+        display time zone configured in the GUI. Timestamps can't go before
+        2000-01-01 00:00:00 UTC.
+
+    -k, --keywords <keywords>
+        Replace the keywords with a comma-separated list, e.g. \"FightClub, FC\".
+        Pass an empty string to remove every keyword.
+
+    --spec <spec file path>
+        EXPERIMENTAL AND DANGEROUS! Replace the costume spec with the contents of
+        the given file and regenerate the costume hash. Refused if the spec has
+        any validation errors, though only its structure is checked for now, not
+        its values. Make a backup!
+
+    --fix-record-version
+        Set the IPTC record version to the one the game writes.
+
+    --dry-run
+        Apply the changes in memory and print the result but don't write
+        anything to disk.
+
+rename --template <template> <path>... [--dry-run]
+    Rename every given save from a save name template, keeping each save's j2000
+    timestamp suffix. Paths are expanded the same way as for inspect and saves
+    are numbered in file name order. Nothing is renamed if any save fails to
+    load or any new name collides with another file, and if a rename fails
+    partway through every save is moved back. Tags and collections given to the
+    saves in the GUI follow them to their new names.
+
+    --template <template>
+        The new save name, with placeholders in braces, e.g.
+        \"{character}_{date:%Y%m%d}_{n:03}\". Use {{ and }} for literal braces.
+
+        {account}          account name
+        {character}        character name
+        {keyword}          first keyword, empty if there are none
+        {name}             original save name
+        {date:<format>}    j2000 timestamp suffix in the display time zone with
+                           a strftime format (default %Y%m%d), empty if none
+        {created:<format>} file creation time, same formatting as {date}
+        {hash:<digits>}    first 1-8 hex digits of the costume hash (default 8)
+        {n:<width>}        counter starting at 1, zero-padded if the width
+                           starts with 0, e.g. {n:03}
+
+        Characters that aren't allowed in file names are replaced with _.
+
+    --dry-run
+        Print the new names without renaming anything.
+
+export <path>... --output <library file> [--images <embed|sidecar>]
+    Write the given saves to a JSON costume library that can be shared or kept
+    under version control. Paths are expanded the same way as for inspect. Each
+    costume records its file name, account and character names, keywords,
+    record version, hash and spec. Nothing is written if any save fails to
+    load.
+
+    -o, --output <library file>
+        Where to write the library. Overwritten if it already exists.
+
+    --images <embed|sidecar>
+        Defaults to embed, which stores each image in the library as base64.
+        sidecar writes the images to a \"<library name>_images\" directory next
+        to the library instead. Either way the images are stored without the
+        costume metadata.
+
+import <library file> [--output-dir <dir>] [--dry-run]
+    Rebuild every costume in a library as a save named after its original file
+    name. Everything but the image comes from the library, and the hash is
+    regenerated from the spec. Costumes whose save already exists are reported
+    as errors without stopping the rest of the import.
+
+    -o, --output-dir <dir>
+        Where to write the saves. Defaults to the current directory.
+
+    --dry-run
+        Rebuild and check every save but don't write anything to disk.
+
+pack <path>... --output <pack file> [--note <note>]
+    Bundle the given saves, unmodified, into a .ccmpack archive (a zip file)
+    with a manifest listing each save's names, hash and keywords. Paths are
+    expanded the same way as for inspect. Nothing is written if any save fails
+    to load.
+
+    -o, --output <pack file>
+        Where to write the pack. Overwritten if it already exists.
+
+    --note <note>
+        Free text stored in the manifest, e.g. who made the pack and why.
+
+unpack <pack file> [options]
+    Verify every save in a pack and copy it into a costume directory. Saves
+    whose hash doesn't match their spec or the manifest are never imported.
+
+    -o, --output-dir <dir>
+        Where to put the saves. Defaults to the costume directory configured in
+        the GUI.
+
+    --on-conflict <rename|skip|overwrite>
+        What to do when a save with the same file name already exists. Defaults
+        to skip, which also skips saves with exactly the same costume spec as an
+        existing save. rename imports as \"<save name> (2)\" and so on, and
+        overwrite replaces the existing save.
+
+    --dry-run
+        Print what would happen without writing anything to disk.
+
+help, -h, --help
+    Show this usage information.
+";
+
+#[derive(Debug)]
+enum CliError {
+    /// The command line itself was malformed.
+    Usage(String),
+    /// The command was well-formed but couldn't be carried out.
+    Failed(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Usage(message) => write!(f, "{message}\n\n{USAGE}"),
+            Self::Failed(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// Run a command. `args` excludes the executable name and must not be empty. Returns the process
+/// exit code.
+pub fn run(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "inspect" => inspect(&args[1..], &AppConfig::load()),
+        "set" => set(&args[1..], &AppConfig::load()),
+        "rename" => rename(&args[1..], &AppConfig::load()),
+        "export" => export(&args[1..]),
+        "import" => import(&args[1..]),
+        "pack" => pack(&args[1..]),
+        "unpack" => unpack(&args[1..], &AppConfig::load()),
+        "help" | "-h" | "--help" => {
+            print!("{USAGE}");
+            Ok(())
+        },
+        command => Err(CliError::Usage(format!("unknown command {command:?}"))),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("error: {err}");
+            match err {
+                CliError::Usage(_) => 2,
+                CliError::Failed(_) => 1,
+            }
+        },
+    }
+}
+
+fn load_save(file_path: &Path) -> Result<(costume::CostumeFileName, costume::CostumeSave), CliError> {
+    if !costume::is_valid_costume_file_name(file_path) {
+        return Err(CliError::Failed(format!("{file_path:?} is not named like a costume save (Costume_<save name>_<j2000 timestamp>.jpg)")));
+    }
+    let file_name = costume::CostumeFileName::parse(file_path.file_name().unwrap().to_str().unwrap()).unwrap();
+
+    let jpeg_raw = fs::read(file_path).map_err(|err| CliError::Failed(format!("failed to read {file_path:?}: {err}")))?;
+    let save = costume::CostumeSave::parse(&jpeg_raw).map_err(|err| CliError::Failed(format!("failed to parse {file_path:?}: {err}")))?;
+    for warning in save.get_warnings() {
+        eprintln!("warning: {file_path:?}: {warning}");
+    }
+
+    Ok((file_name, save))
+}
+
+fn print_inspection(file_name: &costume::CostumeFileName, save: &costume::CostumeSave, long: bool, timestamp_display: &costume::TimestampDisplay) {
+    let metadata = save.get_metadata().unwrap();
+    let keywords: Vec<&str> = metadata.keywords().collect();
+
+    println!("File Name: {file_name}");
+    println!(
+        "In-Game Display: {}",
+        costume::get_in_game_display_name(metadata.account_name(), metadata.character_name(), file_name.j2000_timestamp, timestamp_display),
+    );
+    println!("Account Name: {}", metadata.account_name());
+    println!("Character Name: {}", metadata.character_name());
+    println!("Keywords: {}", keywords.join(", "));
+    match metadata.record_version() {
+        Some(version) => println!("Record Version: {version}"),
+        None => println!("Record Version: missing or invalid"),
+    }
+    if long {
+        // Both of these are stored null-terminated.
+        println!("Costume Hash: {}", metadata.hash().trim_end_matches('\0'));
+        println!("Costume Spec:\n{}", metadata.spec().trim_end_matches('\0'));
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum InspectFormat { Text, Json, Ndjson, Csv }
+
+/// Everything the machine-readable inspect formats report about a save that loaded.
+struct InspectFields {
+    save_name: String,
+    j2000_timestamp: Option<i64>,
+    in_game_display_name: String,
+    account_name: String,
+    character_name: String,
+    /// Without the null terminator.
+    hash: String,
+    /// Whether the stored hash matches the spec.
+    hash_valid: bool,
+    keywords: Vec<String>,
+    spec_length: usize,
+    image_dimensions: Option<(u16, u16)>,
+}
+
+impl InspectFields {
+    fn new(file_name: &costume::CostumeFileName, save: &costume::CostumeSave, timestamp_display: &costume::TimestampDisplay) -> Self {
+        let metadata = save.get_metadata().unwrap();
+        Self {
+            save_name: file_name.save_name.clone(),
+            j2000_timestamp: file_name.j2000_timestamp,
+            in_game_display_name: costume::get_in_game_display_name(metadata.account_name(), metadata.character_name(), file_name.j2000_timestamp, timestamp_display),
+            account_name: metadata.account_name().to_owned(),
+            character_name: metadata.character_name().to_owned(),
+            hash: metadata.hash().trim_end_matches('\0').to_owned(),
+            hash_valid: metadata.hash() == costume::hash::CostumeHash::of(metadata.spec()).to_string(),
+            keywords: metadata.keywords().map(str::to_owned).collect(),
+            spec_length: metadata.spec().len(),
+            image_dimensions: save.0.get_dimensions(),
+        }
+    }
+}
+
+struct InspectRow {
+    path: PathBuf,
+    /// Error rows have the error message instead.
+    fields: Result<InspectFields, String>,
+}
+
+const INSPECT_COLUMNS: [&str; 14] = [
+    "path", "file_name", "save_name", "j2000_timestamp", "in_game_display_name", "account_name", "character_name",
+    "hash", "hash_valid", "keywords", "spec_length", "image_width", "image_height", "error",
+];
+
+/// A single field value, formatted per output format by the caller.
+enum InspectValue<'a> {
+    Null,
+    String(&'a str),
+    Number(i64),
+    Bool(bool),
+    List(&'a [String]),
+}
+
+impl InspectRow {
+    /// Values in the same order as INSPECT_COLUMNS.
+    fn values(&self) -> [InspectValue<'_>; 14] {
+        use InspectValue::*;
+        let file_name = self.path.file_name().map_or(Null, |file_name| String(file_name.to_str().unwrap_or_default()));
+        let path = String(self.path.to_str().unwrap_or_default());
+        match &self.fields {
+            Ok(fields) => [
+                path,
+                file_name,
+                String(&fields.save_name),
+                fields.j2000_timestamp.map_or(Null, Number),
+                String(&fields.in_game_display_name),
+                String(&fields.account_name),
+                String(&fields.character_name),
+                String(&fields.hash),
+                Bool(fields.hash_valid),
+                List(&fields.keywords),
+                Number(fields.spec_length as i64),
+                fields.image_dimensions.map_or(Null, |(width, _)| Number(width.into())),
+                fields.image_dimensions.map_or(Null, |(_, height)| Number(height.into())),
+                Null,
+            ],
+            Err(error) => [
+                path, file_name, Null, Null, Null, Null, Null, Null, Null, Null, Null, Null, Null, String(error),
+            ],
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let members: Vec<String> = INSPECT_COLUMNS.iter().zip(self.values()).map(|(column, value)| {
+            let value = match value {
+                InspectValue::Null => "null".to_owned(),
+                InspectValue::String(s) => json::string(s),
+                InspectValue::Number(n) => n.to_string(),
+                InspectValue::Bool(b) => b.to_string(),
+                InspectValue::List(list) => format!("[{}]", list.iter().map(|s| json::string(s)).collect::<Vec<_>>().join(",")),
+            };
+            format!("{}:{value}", json::string(column))
+        }).collect();
+
+        format!("{{{}}}", members.join(","))
+    }
+
+    fn to_csv(&self) -> String {
+        self.values().into_iter().map(|value| match value {
+            InspectValue::Null => String::new(),
+            InspectValue::String(s) => csv_field(s),
+            InspectValue::Number(n) => n.to_string(),
+            InspectValue::Bool(b) => b.to_string(),
+            // Same separator the GUI uses for editing keywords.
+            InspectValue::List(list) => csv_field(&list.join(", ")),
+        }).collect::<Vec<_>>().join(",")
+    }
+}
+
+/// Quote a CSV field if needed (RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Very small glob matcher for file names, supporting `*` (any run of characters) and `?` (any
+/// single character).
+fn matches_glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| matches_glob(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && matches_glob(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && matches_glob(rest, &text[1..]),
+    }
+}
+
+/// Expand an inspect path argument into save file paths. Directories and globs only yield files
+/// named like costume saves; a plain file path is always kept so that it gets an error row if it
+/// isn't one.
+fn expand_inspect_path(arg: &str) -> Result<Vec<PathBuf>, String> {
+    let path = Path::new(arg);
+    let file_name = path.file_name().and_then(|file_name| file_name.to_str()).unwrap_or_default();
+    let is_glob = file_name.contains(['*', '?']);
+    if !is_glob && !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    // None if the glob is relative to the cwd, in which case the paths should be too.
+    let (dir, pattern) = if is_glob {
+        (path.parent().filter(|dir| !dir.as_os_str().is_empty()), file_name.chars().collect::<Vec<char>>())
+    } else {
+        (Some(path), vec!['*'])
+    };
+
+    let dir_to_read = dir.unwrap_or(Path::new("."));
+    let dir_entries = fs::read_dir(dir_to_read).map_err(|err| format!("failed to read directory {dir_to_read:?}: {err}"))?;
+    let mut paths: Vec<PathBuf> = dir_entries
+        .flatten()
+        .filter(|dir_entry| {
+            let name: Vec<char> = dir_entry.file_name().to_str().unwrap_or_default().chars().collect();
+            let path = dir_entry.path();
+            path.is_file() && costume::is_valid_costume_file_name(&path) && matches_glob(&pattern, &name)
+        })
+        .map(|dir_entry| dir.map_or_else(|| dir_entry.file_name().into(), |dir| dir.join(dir_entry.file_name())))
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn inspect(args: &[String], app_config: &AppConfig) -> Result<(), CliError> {
+    let mut args = args.iter();
+    let mut path_args = Vec::new();
+    let mut format = InspectFormat::Text;
+    let mut long = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--long" => long = true,
+            "--format" => format = match args.next().map(String::as_str) {
+                Some("text") => InspectFormat::Text,
+                Some("json") => InspectFormat::Json,
+                Some("ndjson") => InspectFormat::Ndjson,
+                Some("csv") => InspectFormat::Csv,
+                Some(other) => return Err(CliError::Usage(format!("unknown format {other:?}"))),
+                None => return Err(CliError::Usage("--format requires a value".to_owned())),
+            },
+            _ if arg.starts_with("--") => return Err(CliError::Usage(format!("unknown option {arg:?}"))),
+            _ => path_args.push(arg),
+        }
+    }
+    if path_args.is_empty() {
+        return Err(CliError::Usage("missing costume save file path".to_owned()));
+    }
+    if long && format != InspectFormat::Text {
+        return Err(CliError::Usage("--long is only supported by the text format".to_owned()));
+    }
+
+    // Each save is printed as soon as it's parsed so that long runs stream, e.g. into jq.
+    match format {
+        InspectFormat::Text | InspectFormat::Ndjson => {},
+        InspectFormat::Json => print!("["),
+        InspectFormat::Csv => println!("{}", INSPECT_COLUMNS.join(",")),
+    }
+    let mut num_printed = 0;
+    let mut num_failed = 0;
+    let mut print_row = |path: PathBuf, loaded: Result<(costume::CostumeFileName, costume::CostumeSave), String>| {
+        num_failed += loaded.is_err() as usize;
+        match format {
+            InspectFormat::Text => {
+                if num_printed > 0 { println!(); }
+                match loaded {
+                    Ok((file_name, save)) => print_inspection(&file_name, &save, long, &app_config.timestamp_display),
+                    Err(err) => eprintln!("error: {path:?}: {err}"),
+                }
+            },
+            InspectFormat::Json | InspectFormat::Ndjson | InspectFormat::Csv => {
+                let fields = loaded.map(|(file_name, save)| InspectFields::new(&file_name, &save, &app_config.timestamp_display));
+                let row = InspectRow { path, fields };
+                match format {
+                    InspectFormat::Json => {
+                        // NOTE The separator comes before each object so its line is only
+                        // finished by the next one, hence the flush.
+                        print!("{}\n{}", if num_printed > 0 { "," } else { "" }, row.to_json());
+                        _ = std::io::stdout().flush();
+                    },
+                    InspectFormat::Ndjson => println!("{}", row.to_json()),
+                    InspectFormat::Csv => println!("{}", row.to_csv()),
+                    InspectFormat::Text => unreachable!(),
+                }
+            },
+        }
+        num_printed += 1;
+    };
+    for path_arg in path_args {
+        match expand_inspect_path(path_arg) {
+            Ok(paths) => paths.into_iter().for_each(|path| {
+                let loaded = load_save(&path).map_err(|err| err.to_string());
+                print_row(path, loaded);
+            }),
+            Err(err) => print_row(PathBuf::from(path_arg), Err(err)),
+        }
+    }
+    if format == InspectFormat::Json {
+        println!("\n]");
+    }
+
+    if num_failed > 0 {
+        return Err(CliError::Failed(format!("{num_failed} of the inspected saves failed to load")));
+    }
+
+    Ok(())
+}
+
+fn set(args: &[String], app_config: &AppConfig) -> Result<(), CliError> {
+    let mut args = args.iter();
+    let file_path = Path::new(args.next().ok_or_else(|| CliError::Usage("missing costume save file path".to_owned()))?);
+    let (original_file_name, mut save) = load_save(file_path)?;
+    let original_metadata = save.get_metadata().unwrap();
+    let mut metadata = save.get_metadata().unwrap().into_owned();
+    let mut file_name = original_file_name.clone();
+    let mut dry_run = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| CliError::Usage(format!("{arg} requires a value")));
+        match arg.as_str() {
+            "-a" | "--account-name" => metadata.set_account_name(value()?.clone()),
+            "-c" | "--character-name" => metadata.set_character_name(value()?.clone()),
+            "-s" | "--save-name" => file_name.save_name = value()?.clone(),
+            "-t" | "--strip-timestamp" => file_name.j2000_timestamp = None,
+            "--timestamp" => {
+                let timestamp = value()?;
+                let j2000_timestamp = timestamp.parse::<i64>().ok().or_else(|| {
+                    let naive_datetime = chrono::NaiveDateTime::parse_from_str(timestamp, costume::DEFAULT_TIMESTAMP_FORMAT).ok()?;
+                    app_config.timestamp_display.naive_to_j2000(naive_datetime)
+                });
+                let j2000_timestamp = j2000_timestamp
+                    .filter(|j2000_timestamp| *j2000_timestamp >= 0)
+                    .ok_or_else(|| CliError::Usage(format!("invalid, nonexistent or pre-2000 timestamp {timestamp:?}")))?;
+                file_name.j2000_timestamp = Some(j2000_timestamp);
+            },
+            "-k" | "--keywords" => metadata.set_keywords(parse_keyword_list(value()?)),
+            "--spec" => {
+                let spec_file_path = value()?;
+                let spec = fs::read_to_string(spec_file_path).map_err(|err| CliError::Failed(format!("failed to read {spec_file_path:?}: {err}")))?;
+                // Saves store the spec null-terminated but the terminator is trimmed by inspect.
+                let spec = if spec.ends_with('\0') { spec } else { format!("{spec}\0") };
+
+                let diagnostics = costume::validate_spec(&spec, costume::SpecCatalog::embedded());
+                for diagnostic in diagnostics.iter() {
+                    eprintln!("{spec_file_path}:{diagnostic}");
+                }
+                if costume::has_errors(&diagnostics) {
+                    return Err(CliError::Failed(format!("{spec_file_path:?} has errors")));
+                }
+
+                let hash = costume::hash::CostumeHash::of(&spec);
+                metadata.set_spec_with_hash(spec, hash);
+            },
+            "--fix-record-version" => metadata.set_record_version(Some(costume::EXPECTED_RECORD_VERSION)),
+            "--dry-run" => dry_run = true,
+            _ => return Err(CliError::Usage(format!("unknown option {arg:?}"))),
+        }
+    }
+
+    metadata.forget_unchanged(&original_metadata);
+    drop(original_metadata);
+    if !metadata.is_dirty() && file_name == original_file_name {
+        println!("Nothing to change");
+        return Ok(());
+    }
+
+    let old_tag_key = tags::SaveKey::of(&original_file_name, &save);
+    save.update_metadata(&metadata);
+    if dry_run {
+        print_inspection(&file_name, &save, false, &app_config.timestamp_display);
+        return Ok(());
+    }
+
+    let new_file_path = file_path.with_file_name(file_name.to_string());
+    // FIXME Same Windows case insensitivity issue as the GUI.
+    if file_name != original_file_name && new_file_path.exists() {
+        return Err(CliError::Failed(format!("{new_file_path:?} already exists")));
+    }
+
+    let logger = LOGGER.new_handle("CLI");
+    write_costume_save(&save, file_path, &new_file_path, &logger).map_err(|err| CliError::Failed(err.to_string()))?;
+    relink_tags([(old_tag_key, tags::SaveKey::of(&file_name, &save))]);
+    print_inspection(&file_name, &save, false, &app_config.timestamp_display);
+
+    Ok(())
+}
+
+fn rename(args: &[String], app_config: &AppConfig) -> Result<(), CliError> {
+    let mut args = args.iter();
+    let mut path_args = Vec::new();
+    let mut template = None;
+    let mut dry_run = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--template" => {
+                let value = args.next().ok_or_else(|| CliError::Usage("--template requires a value".to_owned()))?;
+                template = Some(costume::template::RenameTemplate::parse(value).map_err(|err| CliError::Usage(format!("invalid template: {err}")))?);
+            },
+            "--dry-run" => dry_run = true,
+            _ if arg.starts_with("--") => return Err(CliError::Usage(format!("unknown option {arg:?}"))),
+            _ => path_args.push(arg),
+        }
+    }
+    let template = template.ok_or_else(|| CliError::Usage("missing --template".to_owned()))?;
+    if path_args.is_empty() {
+        return Err(CliError::Usage("missing costume save file path".to_owned()));
+    }
+
+    let mut paths = Vec::new();
+    for path_arg in path_args {
+        paths.extend(expand_inspect_path(path_arg).map_err(CliError::Failed)?);
+    }
+    paths.sort();
+    paths.dedup();
+    let saves = paths.iter()
+        .map(|path| load_save(path).map(|(file_name, save)| (path.as_path(), file_name, save)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let plan = rename::plan_renames(&template, saves.iter().map(|(path, file_name, save)| (*path, file_name, save)), &app_config.timestamp_display);
+    for planned in plan.iter() {
+        match &planned.problem {
+            Some(problem) => eprintln!("error: {:?} -> {:?}: {problem}", planned.old_path, planned.new_path),
+            None if planned.is_unchanged() => println!("{:?} (unchanged)", planned.old_path),
+            None => println!("{:?} -> {:?}", planned.old_path, planned.new_path),
+        }
+    }
+
+    let num_problems = plan.iter().filter(|planned| planned.problem.is_some()).count();
+    if num_problems > 0 {
+        return Err(CliError::Failed(format!("{num_problems} of the new names have problems, nothing was renamed")));
+    }
+    if plan.iter().all(rename::PlannedRename::is_unchanged) {
+        println!("Nothing to change");
+        return Ok(());
+    }
+    if !dry_run {
+        rename::apply_renames(&plan).map_err(|err| CliError::Failed(err.to_string()))?;
+        relink_tags(plan.iter().zip(saves.iter()).map(|(planned, (_, file_name, save))| {
+            (tags::SaveKey::of(file_name, save), tags::SaveKey::of(&planned.new_file_name, save))
+        }));
+    }
+
+    Ok(())
+}
+
+/// Keep tags attached to saves that were renamed or edited. Failing to is only a warning since the
+/// saves themselves were already written.
+fn relink_tags(relinks: impl IntoIterator<Item = (tags::SaveKey, tags::SaveKey)>) {
+    let tags_path = tags::TagDatabase::default_path();
+    let result = tags::TagDatabase::load(&tags_path).and_then(|mut database| {
+        if database.relink(relinks) { database.save(&tags_path) } else { Ok(()) }
+    });
+    if let Err(err) = result {
+        eprintln!("warning: tags weren't moved to the new names: {err}");
+    }
+}
+
+fn export(args: &[String]) -> Result<(), CliError> {
+    let mut args = args.iter();
+    let mut path_args = Vec::new();
+    let mut library_path = None;
+    let mut image_storage = library::ImageStorage::Embedded;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| CliError::Usage(format!("{arg} requires a value")));
+        match arg.as_str() {
+            "-o" | "--output" => library_path = Some(PathBuf::from(value()?)),
+            "--images" => image_storage = match value()?.as_str() {
+                "embed" => library::ImageStorage::Embedded,
+                "sidecar" => library::ImageStorage::Sidecar,
+                other => return Err(CliError::Usage(format!("unknown image storage {other:?}"))),
+            },
+            _ if arg.starts_with('-') => return Err(CliError::Usage(format!("unknown option {arg:?}"))),
+            _ => path_args.push(arg),
+        }
+    }
+    let library_path = library_path.ok_or_else(|| CliError::Usage("missing --output".to_owned()))?;
+    if path_args.is_empty() {
+        return Err(CliError::Usage("missing costume save file path".to_owned()));
+    }
+
+    let mut paths = Vec::new();
+    for path_arg in path_args {
+        paths.extend(expand_inspect_path(path_arg).map_err(CliError::Failed)?);
+    }
+    paths.sort();
+    paths.dedup();
+    let saves = paths.iter().map(|path| load_save(path)).collect::<Result<Vec<_>, _>>()?;
+
+    let num_exported = library::export_library(&library_path, saves.iter().map(|(file_name, save)| (file_name, save)), image_storage)
+        .map_err(|err| CliError::Failed(format!("failed to export: {err}")))?;
+    println!("Exported {num_exported} costumes to {library_path:?}");
+
+    Ok(())
+}
+
+fn import(args: &[String]) -> Result<(), CliError> {
+    let mut args = args.iter();
+    let library_path = Path::new(args.next().ok_or_else(|| CliError::Usage("missing library file path".to_owned()))?);
+    let mut output_dir = PathBuf::from(".");
+    let mut dry_run = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output-dir" => output_dir = PathBuf::from(args.next().ok_or_else(|| CliError::Usage(format!("{arg} requires a value")))?),
+            "--dry-run" => dry_run = true,
+            _ => return Err(CliError::Usage(format!("unknown option {arg:?}"))),
+        }
+    }
+
+    let costumes = library::read_library(library_path).map_err(|err| CliError::Failed(format!("failed to read {library_path:?}: {err}")))?;
+    let library_dir = library_path.parent().unwrap_or(Path::new("."));
+    let mut num_failed = 0;
+    for costume in costumes.iter() {
+        let file_path = output_dir.join(&costume.file_name);
+        let result = costume.build_save(library_dir).map_err(|err| err.to_string()).and_then(|save| {
+            let stored_hash = format!("{}\0", costume.hash);
+            if !costume.hash.is_empty() && save.get_metadata().unwrap().hash() != stored_hash {
+                eprintln!("warning: {:?}: hash in the library doesn't match the spec, regenerated it", costume.file_name);
+            }
+            if dry_run {
+                return if file_path.exists() { Err(format!("{file_path:?} already exists")) } else { Ok(()) };
+            }
+
+            // NOTE An existing save is never clobbered.
+            create_costume_save(&save, &file_path).map_err(|err| err.to_string())
+        });
+
+        match result {
+            Ok(()) if dry_run => println!("Would import {file_path:?}"),
+            Ok(()) => println!("Imported {file_path:?}"),
+            Err(err) => {
+                eprintln!("error: {:?}: {err}", costume.file_name);
+                num_failed += 1;
+            },
+        }
+    }
+
+    if num_failed > 0 {
+        return Err(CliError::Failed(format!("{num_failed} of the {} costumes failed to import", costumes.len())));
+    }
+
+    Ok(())
+}
+
+fn pack(args: &[String]) -> Result<(), CliError> {
+    let mut args = args.iter();
+    let mut path_args = Vec::new();
+    let mut pack_path = None;
+    let mut note = String::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| CliError::Usage(format!("{arg} requires a value")));
+        match arg.as_str() {
+            "-o" | "--output" => pack_path = Some(PathBuf::from(value()?)),
+            "--note" => note = value()?.clone(),
+            _ if arg.starts_with('-') => return Err(CliError::Usage(format!("unknown option {arg:?}"))),
+            _ => path_args.push(arg),
+        }
+    }
+    let pack_path = pack_path.ok_or_else(|| CliError::Usage("missing --output".to_owned()))?;
+    if path_args.is_empty() {
+        return Err(CliError::Usage("missing costume save file path".to_owned()));
+    }
+
+    let mut paths = Vec::new();
+    for path_arg in path_args {
+        paths.extend(expand_inspect_path(path_arg).map_err(CliError::Failed)?);
+    }
+    paths.sort();
+    paths.dedup();
+    let saves = paths.iter().map(|path| load_save(path)).collect::<Result<Vec<_>, _>>()?;
+
+    let num_packed = pack::write_pack(&pack_path, saves.iter().map(|(file_name, save)| (file_name, save)), &note)
+        .map_err(|err| CliError::Failed(format!("failed to write {pack_path:?}: {err}")))?;
+    println!("Packed {num_packed} costumes into {pack_path:?}");
+
+    Ok(())
+}
+
+fn unpack(args: &[String], app_config: &AppConfig) -> Result<(), CliError> {
+    let mut args = args.iter();
+    let pack_path = Path::new(args.next().ok_or_else(|| CliError::Usage("missing pack file path".to_owned()))?);
+    let mut output_dir = None;
+    let mut policy = pack::ConflictPolicy::Skip;
+    let mut dry_run = false;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| CliError::Usage(format!("{arg} requires a value")));
+        match arg.as_str() {
+            "-o" | "--output-dir" => output_dir = Some(PathBuf::from(value()?)),
+            "--on-conflict" => policy = match value()?.as_str() {
+                "rename" => pack::ConflictPolicy::Rename,
+                "skip" => pack::ConflictPolicy::Skip,
+                "overwrite" => pack::ConflictPolicy::Overwrite,
+                other => return Err(CliError::Usage(format!("unknown conflict policy {other:?}"))),
+            },
+            "--dry-run" => dry_run = true,
+            _ => return Err(CliError::Usage(format!("unknown option {arg:?}"))),
+        }
+    }
+    let output_dir = output_dir.or_else(|| app_config.costume_dir.clone())
+        .ok_or_else(|| CliError::Usage("no costume directory is configured, pass --output-dir".to_owned()))?;
+
+    let pack = pack::read_pack(pack_path).map_err(|err| CliError::Failed(format!("failed to read {pack_path:?}: {err}")))?;
+    println!("Pack created by {} at {}", pack.created_by, pack.created);
+    if !pack.note.is_empty() {
+        println!("Note: {}", pack.note);
+    }
+
+    // Only needed to spot identical costumes so saves that fail to load are simply ignored.
+    let existing_saves: Vec<(PathBuf, costume::CostumeSave)> = expand_inspect_path(output_dir.to_str().unwrap_or_default())
+        .map_err(CliError::Failed)?
+        .into_iter()
+        .filter_map(|path| {
+            let save = costume::CostumeSave::parse(&fs::read(&path).ok()?).ok()?;
+            Some((path, save))
+        })
+        .collect();
+    let planned = pack::plan_import(&pack, &output_dir, existing_saves.iter().map(|(path, save)| (path.as_path(), save)), policy);
+    let results = if dry_run {
+        planned.iter().map(|_| Ok(None)).collect()
+    } else {
+        pack::apply_import(&pack, &planned, &LOGGER.new_handle("CLI"))
+    };
+
+    let mut num_failed = 0;
+    for (planned, result) in planned.iter().zip(results) {
+        let entry = &pack.entries[planned.entry_index];
+        let would = if dry_run { "Would " } else { "" };
+        if let (Some(identical_to), pack::ImportAction::Create(_) | pack::ImportAction::Overwrite(_)) = (&planned.identical_to, &planned.action) {
+            eprintln!("warning: {}: same costume as {identical_to:?}", entry.file_name);
+        }
+        match (&planned.action, result) {
+            (_, Err(err)) => {
+                eprintln!("error: {}: {err}", entry.file_name);
+                num_failed += 1;
+            },
+            (pack::ImportAction::Invalid(err), _) => {
+                eprintln!("error: {}: {err}", entry.file_name);
+                num_failed += 1;
+            },
+            (pack::ImportAction::Create(path), Ok(_)) => println!("{would}Import {} as {path:?}", entry.file_name),
+            (pack::ImportAction::Overwrite(path), Ok(_)) => println!("{would}Overwrite {path:?} with {}", entry.file_name),
+            (pack::ImportAction::Skip(reason), Ok(_)) => println!("{would}Skip {}: {reason}", entry.file_name),
+        }
+    }
+
+    if num_failed > 0 {
+        return Err(CliError::Failed(format!("{num_failed} of the {} saves in the pack failed to import", pack.entries.len())));
+    }
+
+    Ok(())
+}
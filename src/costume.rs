@@ -1,58 +1,143 @@
 use crate::jpeg;
+use std::borrow::Cow;
 
-const COSTUME_HASH_ASCII_MAP:  [u16; 256] = [
-    0xBCD1, 0xBB65, 0x42C2, 0xDFFE, 0x9666, 0x431B, 0x8504, 0xEB46,
-    0x6379, 0xD460, 0xCF14, 0x53CF, 0xDB51, 0xDB08, 0x12C8, 0xF602,
-    0xE766, 0x2394, 0x250D, 0xDCBB, 0xA678, 0x02AF, 0xA5C6, 0x7EA6,
-    0xB645, 0xCB4D, 0xC44B, 0xE5DC, 0x9FE6, 0x5B5C, 0x35F5, 0x701A,
-    0x220F, 0x6C38, 0x1A56, 0x4CA3, 0xFFC6, 0xB152, 0x8D61, 0x7A58,
-    0x9025, 0x8B3D, 0xBF0F, 0x95A3, 0xE5F4, 0xC127, 0x3BED, 0x320B,
-    0xB7F3, 0x6054, 0x333C, 0xD383, 0x8154, 0x5242, 0x4E0D, 0x0A94,
-    0x7028, 0x8689, 0x3A22, 0x0980, 0x1847, 0xB0F1, 0x9B5C, 0x4176,
-    0xB858, 0xD542, 0x1F6C, 0x2497, 0x6A5A, 0x9FA9, 0x8C5A, 0x7743,
-    0xA8A9, 0x9A02, 0x4918, 0x438C, 0xC388, 0x9E2B, 0x4CAD, 0x01B6,
-    0xAB19, 0xF777, 0x365F, 0x1EB2, 0x091E, 0x7BF8, 0x7A8E, 0x5227,
-    0xEAB1, 0x2074, 0x4523, 0xE781, 0x01A3, 0x163D, 0x3B2E, 0x287D,
-    0x5E7F, 0xA063, 0xB134, 0x8FAE, 0x5E8E, 0xB7B7, 0x4548, 0x1F5A,
-    0xFA56, 0x7A24, 0x900F, 0x42DC, 0xCC69, 0x02A0, 0x0B22, 0xDB31,
-    0x71FE, 0x0C7D, 0x1732, 0x1159, 0xCB09, 0xE1D2, 0x1351, 0x52E9,
-    0xF536, 0x5A4F, 0xC316, 0x6BF9, 0x8994, 0xB774, 0x5F3E, 0xF6D6,
-    0x3A61, 0xF82C, 0xCC22, 0x9D06, 0x299C, 0x09E5, 0x1EEC, 0x514F,
-    0x8D53, 0xA650, 0x5C6E, 0xC577, 0x7958, 0x71AC, 0x8916, 0x9B4F,
-    0x2C09, 0x5211, 0xF6D8, 0xCAAA, 0xF7EF, 0x287F, 0x7A94, 0xAB49,
-    0xFA2C, 0x7222, 0xE457, 0xD71A, 0x00C3, 0x1A76, 0xE98C, 0xC037,
-    0x8208, 0x5C2D, 0xDFDA, 0xE5F5, 0x0B45, 0x15CE, 0x8A7E, 0xFCAD,
-    0xAA2D, 0x4B5C, 0xD42E, 0xB251, 0x907E, 0x9A47, 0xC9A6, 0xD93F,
-    0x085E, 0x35CE, 0xA153, 0x7E7B, 0x9F0B, 0x25AA, 0x5D9F, 0xC04D,
-    0x8A0E, 0x2875, 0x4A1C, 0x295F, 0x1393, 0xF760, 0x9178, 0x0F5B,
-    0xFA7D, 0x83B4, 0x2082, 0x721D, 0x6462, 0x0368, 0x67E2, 0x8624,
-    0x194D, 0x22F6, 0x78FB, 0x6791, 0xB238, 0xB332, 0x7276, 0xF272,
-    0x47EC, 0x4504, 0xA961, 0x9FC8, 0x3FDC, 0xB413, 0x007A, 0x0806,
-    0x7458, 0x95C6, 0xCCAA, 0x18D6, 0xE2AE, 0x1B06, 0xF3F6, 0x5050,
-    0xC8E8, 0xF4AC, 0xC04C, 0xF41C, 0x992F, 0xAE44, 0x5F1B, 0x1113,
-    0x1738, 0xD9A8, 0x19EA, 0x2D33, 0x9698, 0x2FE9, 0x323F, 0xCDE2,
-    0x6D71, 0xE37D, 0xB697, 0x2C4F, 0x4373, 0x9102, 0x075D, 0x8E25,
-    0x1672, 0xEC28, 0x6ACB, 0x86CC, 0x186E, 0x9414, 0xD674, 0xD1A5,
-];
-
-pub fn generate_costume_hash(costume_spec: &str) -> String {
-    let mut upper_bits = std::num::Wrapping(0u16);
-    let mut lower_bits = std::num::Wrapping(0u16);
-    for byte in costume_spec.bytes() {
-        lower_bits += std::num::Wrapping(COSTUME_HASH_ASCII_MAP[byte as usize]);
-        upper_bits += lower_bits;
-    }
-
-    format!("7799{}\0", ((upper_bits.0 as i32) << 16) | (lower_bits.0 as i32))
-}
-
-pub fn get_in_game_display_name(account_name: &str, character_name: &str, timestamp: Option<i64>) -> String {
-    let maybe_datetime_string = timestamp.and_then(|j2000_timestamp| {
-        const JAN_1_2000_UNIX_TIME: i64 = 946684800;
-        let unix_timestamp = JAN_1_2000_UNIX_TIME + j2000_timestamp;
-        chrono::DateTime::from_timestamp(unix_timestamp, 0)
-            .map(|utc_datetime| utc_datetime.format("%Y-%m-%d %H:%M:%S").to_string())
-    });
+mod spec;
+pub use spec::{validate_spec, has_errors, is_empty_spec, SpecCatalog, SpecDiagnostic, SpecDiagnosticSeverity};
+pub mod diff;
+pub mod hash;
+pub use hash::generate_costume_hash;
+pub mod search;
+pub mod template;
+
+const JAN_1_2000_UNIX_TIME: i64 = 946684800;
+
+/// Convert a j2000 timestamp (seconds since 2000-01-01 00:00:00 UTC) into a datetime. Returns None
+/// if the timestamp is negative or outside of the range chrono can represent.
+pub fn j2000_to_datetime(j2000_timestamp: i64) -> Option<chrono::DateTime<chrono::Utc>> {
+    if j2000_timestamp < 0 {
+        return None;
+    }
+    chrono::DateTime::from_timestamp(JAN_1_2000_UNIX_TIME.checked_add(j2000_timestamp)?, 0)
+}
+
+/// Convert a datetime into a j2000 timestamp. Sub-second precision is truncated. Returns None for
+/// datetimes before 2000, since a negative timestamp can't go in a file name (the sign makes it
+/// part of the save name instead).
+pub fn datetime_to_j2000<Tz: chrono::TimeZone>(datetime: &chrono::DateTime<Tz>) -> Option<i64> {
+    Some(datetime.timestamp() - JAN_1_2000_UNIX_TIME).filter(|j2000_timestamp| *j2000_timestamp >= 0)
+}
+
+/// Which time zone j2000 timestamps are displayed in.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum DisplayTimeZone {
+    Utc,
+    /// The system time zone, including its daylight saving rules.
+    Local,
+    /// A fixed offset from UTC, for displaying in a zone other than the system one. Doesn't follow
+    /// any daylight saving rules.
+    Fixed(chrono::FixedOffset),
+}
+
+impl DisplayTimeZone {
+    /// Short name for showing next to a displayed time.
+    pub fn label(&self) -> String {
+        match self {
+            Self::Utc => "UTC".to_owned(),
+            Self::Local => "Local".to_owned(),
+            Self::Fixed(offset) => format!("UTC{offset}"),
+        }
+    }
+}
+
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// How the date portion of an in-game display is rendered. The game formats the date itself
+/// using the player's locale, so this has to be configurable for the preview to match.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TimestampDisplay {
+    pub time_zone: DisplayTimeZone,
+    /// chrono strftime-style format string.
+    pub format: String,
+}
+
+impl Default for TimestampDisplay {
+    fn default() -> Self {
+        Self { time_zone: DisplayTimeZone::Utc, format: DEFAULT_TIMESTAMP_FORMAT.to_owned() }
+    }
+}
+
+impl TimestampDisplay {
+    pub fn is_valid_format(format: &str) -> bool {
+        !chrono::format::StrftimeItems::new(format).any(|item| matches!(item, chrono::format::Item::Error))
+    }
+
+    /// The wall-clock time that a timestamp is displayed as.
+    pub fn j2000_to_naive(&self, j2000_timestamp: i64) -> Option<chrono::NaiveDateTime> {
+        match self.time_zone {
+            DisplayTimeZone::Utc => Self::j2000_to_naive_in(&chrono::Utc, j2000_timestamp),
+            DisplayTimeZone::Local => Self::j2000_to_naive_in(&chrono::Local, j2000_timestamp),
+            DisplayTimeZone::Fixed(offset) => Self::j2000_to_naive_in(&offset, j2000_timestamp),
+        }
+    }
+
+    /// Inverse of `j2000_to_naive`. Returns None for wall-clock times before 2000 and for ones that
+    /// are skipped when daylight saving time starts. Wall-clock times that repeat when it ends
+    /// resolve to the earlier of the two.
+    pub fn naive_to_j2000(&self, naive_datetime: chrono::NaiveDateTime) -> Option<i64> {
+        match self.time_zone {
+            DisplayTimeZone::Utc => Self::naive_to_j2000_in(&chrono::Utc, naive_datetime),
+            DisplayTimeZone::Local => Self::naive_to_j2000_in(&chrono::Local, naive_datetime),
+            DisplayTimeZone::Fixed(offset) => Self::naive_to_j2000_in(&offset, naive_datetime),
+        }
+    }
+
+    /// Returns None if the timestamp is out of range or the format is invalid.
+    pub fn format_j2000(&self, j2000_timestamp: i64) -> Option<String> {
+        self.format_j2000_as(j2000_timestamp, &self.format)
+    }
+
+    /// Same as `format_j2000` but with a format other than the configured one, still in the
+    /// configured time zone.
+    pub fn format_j2000_as(&self, j2000_timestamp: i64, format: &str) -> Option<String> {
+        match self.time_zone {
+            DisplayTimeZone::Utc => Self::format_j2000_in(&chrono::Utc, j2000_timestamp, format),
+            DisplayTimeZone::Local => Self::format_j2000_in(&chrono::Local, j2000_timestamp, format),
+            DisplayTimeZone::Fixed(offset) => Self::format_j2000_in(&offset, j2000_timestamp, format),
+        }
+    }
+
+    /// `j2000_to_naive` in an arbitrary time zone.
+    pub fn j2000_to_naive_in<Tz: chrono::TimeZone>(time_zone: &Tz, j2000_timestamp: i64) -> Option<chrono::NaiveDateTime> {
+        Some(j2000_to_datetime(j2000_timestamp)?.with_timezone(time_zone).naive_local())
+    }
+
+    /// `naive_to_j2000` in an arbitrary time zone.
+    pub fn naive_to_j2000_in<Tz: chrono::TimeZone>(time_zone: &Tz, naive_datetime: chrono::NaiveDateTime) -> Option<i64> {
+        // NOTE Not using earliest() since chrono doesn't always order the ambiguous pair
+        // chronologically.
+        match time_zone.from_local_datetime(&naive_datetime) {
+            chrono::LocalResult::Single(datetime) => datetime_to_j2000(&datetime),
+            chrono::LocalResult::Ambiguous(first, second) => datetime_to_j2000(&first.min(second)),
+            chrono::LocalResult::None => None,
+        }
+    }
+
+    /// `format_j2000_as` in an arbitrary time zone.
+    pub fn format_j2000_in<Tz: chrono::TimeZone>(time_zone: &Tz, j2000_timestamp: i64, format: &str) -> Option<String>
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        use std::fmt::Write;
+
+        let datetime = j2000_to_datetime(j2000_timestamp)?.with_timezone(time_zone);
+        let mut formatted = String::new();
+        // NOTE Using write! rather than to_string() since the latter panics on invalid formats.
+        write!(formatted, "{}", datetime.format(format)).ok().map(|_| formatted)
+    }
+}
+
+pub fn get_in_game_display_name(account_name: &str, character_name: &str, timestamp: Option<i64>, timestamp_display: &TimestampDisplay) -> String {
+    let maybe_datetime_string = timestamp.and_then(|j2000_timestamp| timestamp_display.format_j2000(j2000_timestamp));
 
     if let Some(datetime_string) = maybe_datetime_string {
         format!("{}{} {}", account_name, character_name, datetime_string)
@@ -61,22 +146,83 @@ pub fn get_in_game_display_name(account_name: &str, character_name: &str, timest
     }
 }
 
-pub fn get_file_name(save_name: &str, timestamp: Option<i64>) -> String {
-    if let Some(j2000_timestamp) = timestamp {
-        if save_name.is_empty() {
-            format!("Costume_{}.jpg", j2000_timestamp)
-        } else {
-            format!("Costume_{}_{}.jpg", save_name, j2000_timestamp)
+/// Key for sorting saves by their in-game display. The formatted date can't be compared directly
+/// since it depends on the display format and local times repeat when daylight saving time ends,
+/// so the raw timestamp is used instead.
+pub fn in_game_display_sort_key(account_name: &str, character_name: &str, timestamp: Option<i64>) -> (String, Option<i64>) {
+    (format!("{}{}", account_name, character_name).to_ascii_lowercase(), timestamp)
+}
+
+const FILE_NAME_PREFIX: &str = "Costume_";
+
+/// A parsed costume save file name, e.g. "Costume_accountname_charactername_CC_Comic_page_Blue_J2000Timestamp.jpg".
+///
+/// The only required part of the file name is the "Costume_" prefix. The game pulls the date it
+/// displays from the j2000 timestamp suffix, if there is one.
+///
+/// `parse` followed by `to_string` always reproduces the original file name. The reverse doesn't
+/// necessarily hold: a save name that ends in "_<digits>" with no timestamp will be read back as a
+/// timestamped save.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CostumeFileName {
+    /// Everything between the "Costume_" prefix and either the j2000 timestamp suffix (if
+    /// included) or the file extension. Technically this can be empty.
+    pub save_name: String,
+    pub j2000_timestamp: Option<i64>,
+    /// Excludes the '.'
+    pub extension: String,
+}
+
+impl CostumeFileName {
+    pub fn parse(file_name: &str) -> Result<Self, CostumeParseError> {
+        let (stem, extension) = file_name.rsplit_once('.').ok_or(CostumeParseError::InvalidFileName)?;
+        let rest = stem.strip_prefix(FILE_NAME_PREFIX).ok_or(CostumeParseError::InvalidFileName)?;
+
+        // Only accept timestamps that would be written back out exactly the same way so that
+        // parsing then displaying always round-trips (e.g. no signs or leading zeros).
+        fn parse_timestamp(s: &str) -> Option<i64> {
+            let is_canonical = !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) && (s == "0" || !s.starts_with('0'));
+            if is_canonical { s.parse().ok() } else { None }
+        }
+
+        let (save_name, j2000_timestamp) = match rest.rsplit_once('_') {
+            Some((save_name, suffix)) if !save_name.is_empty() => match parse_timestamp(suffix) {
+                Some(timestamp) => (save_name, Some(timestamp)),
+                None => (rest, None),
+            },
+            // "Costume_<timestamp>.jpg" is what we write for an empty save name with a timestamp.
+            _ => match parse_timestamp(rest) {
+                Some(timestamp) => ("", Some(timestamp)),
+                None => (rest, None),
+            },
+        };
+
+        Ok(Self {
+            save_name: save_name.to_owned(),
+            j2000_timestamp,
+            extension: extension.to_owned(),
+        })
+    }
+
+    pub fn is_jpg(&self) -> bool {
+        self.extension.eq_ignore_ascii_case("jpg")
+    }
+}
+
+impl std::fmt::Display for CostumeFileName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let Self { save_name, j2000_timestamp, extension } = self;
+        match j2000_timestamp {
+            Some(j2000_timestamp) if save_name.is_empty() => write!(f, "{FILE_NAME_PREFIX}{j2000_timestamp}.{extension}"),
+            Some(j2000_timestamp) => write!(f, "{FILE_NAME_PREFIX}{save_name}_{j2000_timestamp}.{extension}"),
+            None => write!(f, "{FILE_NAME_PREFIX}{save_name}.{extension}"),
         }
-    } else {
-        format!("Costume_{}.jpg", save_name)
     }
 }
 
 pub fn is_valid_costume_file_name(file_path: &std::path::Path) -> bool {
-    let Some(extension) = file_path.extension().and_then(|s| s.to_str()) else { return false };
-    let Some(file_stem) = file_path.file_stem().and_then(|s| s.to_str()) else { return false };
-    file_stem.starts_with("Costume_") && extension.eq_ignore_ascii_case("jpg")
+    let Some(file_name) = file_path.file_name().and_then(|s| s.to_str()) else { return false };
+    CostumeFileName::parse(file_name).is_ok_and(|file_name| file_name.is_jpg())
 }
 
 const ACCOUNT_NAME_INDEX: usize = 0;
@@ -84,6 +230,10 @@ const CHARACTER_NAME_INDEX: usize = 1;
 const COSTUME_HASH_INDEX: usize = 2;
 const COSTUME_SPEC_INDEX: usize = 0;
 
+/// The IPTC application record version written by the game. It's stored as a 2-byte big-endian
+/// integer in dataset 2:00.
+pub const EXPECTED_RECORD_VERSION: u16 = 2;
+
 static EXPECTED_APP13_SEGMENT_ID: &str = "Photoshop 3.0\0";
 static EXPECTED_APP13_RESOURCE_TYPE: &[u8; 4] = b"8BIM";
 const EXPECTED_APP13_RESOURCE_ID: u16 = 0x0404;
@@ -91,13 +241,17 @@ static EXPECTED_APP13_RESOURCE_NAME: &str = "\0\0";
 
 #[derive(Debug)]
 pub enum CostumeParseError {
-    #[allow(dead_code)]
     InvalidFileName,
     InvalidApp13SegmentCount { count: usize },
     InvalidApp13SegmentId { actual: Box<[u8]> },
     InvalidApp13ResourceType { actual: u32 },
     InvalidApp13ResourceId { actual: u16 },
     InvalidApp13ResourceName { actual: Box<[u8]> },
+    /// There are fewer caption datasets than the account name, character name, and hash need.
+    MissingCaption { expected: usize, count: usize },
+    MissingSpec,
+    /// `offset` is the index of the first invalid byte within the dataset's data.
+    InvalidUtf8 { field: &'static str, offset: usize },
     JpegParseError(jpeg::ParseError),
 }
 
@@ -111,55 +265,186 @@ impl std::fmt::Display for CostumeParseError {
             Self::InvalidApp13SegmentId { actual } => write!(
                 f,
                 "Invalid App13 segment id: expected {EXPECTED_APP13_SEGMENT_ID:?} but found {:?}",
-                unsafe { std::str::from_utf8_unchecked(actual) }
+                String::from_utf8_lossy(actual)
             ),
             Self::InvalidApp13ResourceType { actual } => write!(
                 f,
                 "Invalid App13 resource type: expected {:?} but found {:?}",
-                unsafe { std::str::from_utf8_unchecked(EXPECTED_APP13_RESOURCE_TYPE) },
-                unsafe { std::str::from_utf8_unchecked(&actual.to_be_bytes()) },
+                String::from_utf8_lossy(EXPECTED_APP13_RESOURCE_TYPE),
+                String::from_utf8_lossy(&actual.to_be_bytes()),
             ),
             Self::InvalidApp13ResourceId { actual } => write!(f, "Invalid App13 resource id: expected {EXPECTED_APP13_RESOURCE_ID:#06X} but found {actual:#06X}"),
             Self::InvalidApp13ResourceName { actual } => write!(
                 f,
                 "Invalid App13 resource name: expected {EXPECTED_APP13_RESOURCE_NAME:?} but found {:?}",
-                unsafe { std::str::from_utf8_unchecked(actual) },
+                String::from_utf8_lossy(actual),
             ),
+            Self::MissingCaption { expected, count } => write!(f, "Missing caption: expected at least {expected} caption datasets but found {count}"),
+            Self::MissingSpec => write!(f, "Missing costume spec"),
+            Self::InvalidUtf8 { field, offset } => write!(f, "Invalid UTF-8 in {field} at byte {offset}"),
             Self::JpegParseError(parse_error) => write!(f, "Failed to parse jpeg: {parse_error}"),
         }
     }
 }
 
+/// Problems that don't prevent us from loading a save but that the game (or other tools) might not
+/// like.
+#[derive(Debug)]
+pub enum CostumeParseWarning {
+    MissingRecordVersion,
+    UnexpectedRecordVersion { actual: Box<[u8]> },
+}
+
+impl std::fmt::Display for CostumeParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::MissingRecordVersion => write!(f, "Missing record version: expected {EXPECTED_RECORD_VERSION}"),
+            Self::UnexpectedRecordVersion { actual } => write!(f, "Unexpected record version: expected {EXPECTED_RECORD_VERSION} but found {actual:02X?}"),
+        }
+    }
+}
+
 pub struct CostumeSave(pub jpeg::Jpeg);
 
-// TODO can we coalesce UpdateCostumeMetadata and CostumeMetadata?
-// e.g. account_name: Option<Cow<'a, str>>
-// That might feel terrible to work with.
+/// Set of CostumeMetadata fields, used to track which fields have been changed.
+#[derive(Default, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct MetadataFields(u8);
+
+impl MetadataFields {
+    pub const ACCOUNT_NAME: Self = Self(1 << 0);
+    pub const CHARACTER_NAME: Self = Self(1 << 1);
+    /// Always changes along with the spec.
+    pub const HASH: Self = Self(1 << 2);
+    pub const SPEC: Self = Self(1 << 3);
+    pub const KEYWORDS: Self = Self(1 << 4);
+    pub const RECORD_VERSION: Self = Self(1 << 5);
+
+    pub fn contains(self, fields: Self) -> bool { self.0 & fields.0 == fields.0 }
+    pub fn is_empty(self) -> bool { self.0 == 0 }
+    fn insert(&mut self, fields: Self) { self.0 |= fields.0; }
+    fn remove(&mut self, fields: Self) { self.0 &= !fields.0; }
+}
+
+/// Costume metadata that either borrows from a CostumeSave or owns its data (e.g. when it's being
+/// edited in the UI).
+///
+/// Fields can only be changed through setters so that we know exactly which fields are dirty.
+/// `CostumeSave::update_metadata` only rewrites the datasets of dirty fields, so a default instance
+/// with a few fields set works as a partial update.
+#[derive(Default, Clone)]
 pub struct CostumeMetadata<'a> {
-    pub account_name: &'a str,
-    pub character_name: &'a str,
-    pub hash: &'a str,
-    pub spec: &'a str,
+    account_name: Cow<'a, str>,
+    character_name: Cow<'a, str>,
+    hash: Cow<'a, str>,
+    spec: Cow<'a, str>,
+    /// e.g. "FightClub", "FC", and the character's gender.
+    keywords: Vec<Cow<'a, str>>,
+    /// None if the record version is missing or isn't a 2-byte integer.
+    record_version: Option<u16>,
+    dirty: MetadataFields,
 }
 
 impl<'a> CostumeMetadata<'a> {
-    /// Create an instance of UpdateCostumeMetadata by cloning each field.
-    pub fn create_update(&self) -> UpdateCostumeMetadata {
-        UpdateCostumeMetadata {
-            account_name: Some(self.account_name.to_owned()),
-            character_name: Some(self.character_name.to_owned()),
-            hash: Some(self.hash.to_owned()),
-            spec: Some(self.spec.to_owned()),
+    pub fn account_name(&self) -> &str { &self.account_name }
+    pub fn character_name(&self) -> &str { &self.character_name }
+    pub fn hash(&self) -> &str { &self.hash }
+    pub fn spec(&self) -> &str { &self.spec }
+    pub fn keywords(&self) -> impl Iterator<Item = &str> { self.keywords.iter().map(AsRef::as_ref) }
+    pub fn record_version(&self) -> Option<u16> { self.record_version }
+
+    pub fn has_keyword(&self, keyword: &str) -> bool {
+        self.keywords().any(|k| k.eq_ignore_ascii_case(keyword))
+    }
+
+    pub fn set_account_name(&mut self, account_name: impl Into<Cow<'a, str>>) {
+        let account_name = account_name.into();
+        if account_name != self.account_name {
+            self.account_name = account_name;
+            self.dirty.insert(MetadataFields::ACCOUNT_NAME);
+        }
+    }
+
+    pub fn set_character_name(&mut self, character_name: impl Into<Cow<'a, str>>) {
+        let character_name = character_name.into();
+        if character_name != self.character_name {
+            self.character_name = character_name;
+            self.dirty.insert(MetadataFields::CHARACTER_NAME);
+        }
+    }
+
+    /// Also regenerates the hash.
+    /// `hash` must be the hash of `spec`, e.g. from a hash::SpecHasher or hash::CostumeHash::of.
+    /// It's taken as an argument so that callers editing the spec can hash it incrementally.
+    pub fn set_spec_with_hash(&mut self, spec: impl Into<Cow<'a, str>>, hash: hash::CostumeHash) {
+        let spec = spec.into();
+        if spec != self.spec {
+            self.hash = hash.to_string().into();
+            self.spec = spec;
+            self.dirty.insert(MetadataFields::SPEC);
+            self.dirty.insert(MetadataFields::HASH);
+        }
+    }
+
+    pub fn set_keywords(&mut self, keywords: Vec<Cow<'a, str>>) {
+        if keywords != self.keywords {
+            self.keywords = keywords;
+            self.dirty.insert(MetadataFields::KEYWORDS);
         }
     }
-}
 
-#[derive(Default)]
-pub struct UpdateCostumeMetadata {
-    pub account_name: Option<String>,
-    pub character_name: Option<String>,
-    pub hash: Option<String>,
-    pub spec: Option<String>,
+    /// Setting None removes the record version entirely.
+    pub fn set_record_version(&mut self, record_version: Option<u16>) {
+        if record_version != self.record_version {
+            self.record_version = record_version;
+            self.dirty.insert(MetadataFields::RECORD_VERSION);
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool { !self.dirty.is_empty() }
+    pub fn mark_clean(&mut self) { self.dirty = MetadataFields::default(); }
+
+    /// Un-dirty every field whose value is back to what it is in `original`, e.g. after a user
+    /// undoes their edits by hand.
+    pub fn forget_unchanged(&mut self, original: &CostumeMetadata) {
+        let unchanged = [
+            (MetadataFields::ACCOUNT_NAME, self.account_name == original.account_name),
+            (MetadataFields::CHARACTER_NAME, self.character_name == original.character_name),
+            (MetadataFields::HASH, self.hash == original.hash),
+            (MetadataFields::SPEC, self.spec == original.spec),
+            (MetadataFields::KEYWORDS, self.keywords == original.keywords),
+            (MetadataFields::RECORD_VERSION, self.record_version == original.record_version),
+        ];
+        for (field, is_unchanged) in unchanged {
+            if is_unchanged { self.dirty.remove(field); }
+        }
+    }
+
+    /// Copy only the fields that `changes` would overwrite, marked dirty so that applying the
+    /// result undoes `changes`. All other fields are left at their defaults.
+    pub fn revert_snapshot(&self, changes: &CostumeMetadata) -> CostumeMetadata<'static> {
+        let fields = changes.dirty;
+        let mut snapshot = CostumeMetadata::default();
+        if fields.contains(MetadataFields::ACCOUNT_NAME) { snapshot.account_name = self.account_name.to_string().into(); }
+        if fields.contains(MetadataFields::CHARACTER_NAME) { snapshot.character_name = self.character_name.to_string().into(); }
+        if fields.contains(MetadataFields::HASH) { snapshot.hash = self.hash.to_string().into(); }
+        if fields.contains(MetadataFields::SPEC) { snapshot.spec = self.spec.to_string().into(); }
+        if fields.contains(MetadataFields::KEYWORDS) { snapshot.keywords = self.keywords.iter().map(|k| k.to_string().into()).collect(); }
+        if fields.contains(MetadataFields::RECORD_VERSION) { snapshot.record_version = self.record_version; }
+        snapshot.dirty = fields;
+        snapshot
+    }
+
+    pub fn into_owned(self) -> CostumeMetadata<'static> {
+        CostumeMetadata {
+            account_name: self.account_name.into_owned().into(),
+            character_name: self.character_name.into_owned().into(),
+            hash: self.hash.into_owned().into(),
+            spec: self.spec.into_owned().into(),
+            keywords: self.keywords.into_iter().map(|keyword| keyword.into_owned().into()).collect(),
+            record_version: self.record_version,
+            dirty: self.dirty,
+        }
+    }
 }
 
 impl CostumeSave {
@@ -187,47 +472,387 @@ impl CostumeSave {
 
         // TODO validate that the costume hash matches a hash of the spec?
 
-        Ok(Self(jpeg))
+        let save = Self(jpeg);
+        // Catch missing datasets and invalid UTF-8 now so that nothing downstream has to.
+        save.get_metadata()?;
+
+        // NOTE Record version problems are only warnings (see get_warnings). Saves produced by
+        // third-party tools sometimes omit it and we don't want to refuse to load those.
+
+        Ok(save)
+    }
+
+    /// Build a brand-new save out of any JPEG image. Any IPTC data already in the image is replaced.
+    ///
+    /// A trailing null terminator is added to the spec if it doesn't already have one, matching
+    /// the saves the game writes.
+    pub fn new(image_jpeg: &[u8], account_name: &str, character_name: &str, spec: &str) -> Result<Self, CostumeParseError> {
+        let mut jpeg = jpeg::Jpeg::parse(image_jpeg).map_err(CostumeParseError::JpegParseError)?;
+
+        let spec = if spec.ends_with('\0') { spec.to_owned() } else { format!("{spec}\0") };
+        let mut app13_payload = jpeg::JpegApp13Payload::new(
+            EXPECTED_APP13_SEGMENT_ID.as_bytes().into(),
+            u32::from_be_bytes(*EXPECTED_APP13_RESOURCE_TYPE),
+            EXPECTED_APP13_RESOURCE_ID,
+            EXPECTED_APP13_RESOURCE_NAME.as_bytes().into(),
+        );
+        // NOTE Datasets are serialized in (record, dataset) order, which happens to be the order the
+        // game writes them in: version, keywords, caption, then the spec.
+        app13_payload.set_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_VERSION, vec![Box::from(EXPECTED_RECORD_VERSION.to_be_bytes())]);
+        // The game writes a single empty keywords dataset for a save without keywords.
+        app13_payload.set_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_KEYWORD, vec![Box::default()]);
+        // Must match the caption indices (ACCOUNT_NAME_INDEX etc).
+        app13_payload.set_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_CAPTION, vec![
+            account_name.as_bytes().into(),
+            character_name.as_bytes().into(),
+            generate_costume_hash(&spec).into_bytes().into_boxed_slice(),
+        ]);
+        app13_payload.set_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_OBJECT_DATA_PREVIEW, vec![spec.into_bytes().into_boxed_slice()]);
+        jpeg.set_app13_segment(app13_payload);
+
+        // Round-trip through the parser so that the new save gets exactly the same validation as one
+        // loaded from disk.
+        Self::parse(&jpeg.serialize())
+    }
+
+    /// The save's JPEG without any costume metadata, as accepted by `new`.
+    pub fn image_jpeg(&self) -> Vec<u8> {
+        // NOTE Re-parsing a serialized save can't fail.
+        let mut jpeg = jpeg::Jpeg::parse(&self.0.serialize()).unwrap();
+        jpeg.remove_app13_segments();
+        jpeg.serialize().into_vec()
+    }
+
+    /// The save without its image, as accepted by `parse`. A fraction of the size of the whole save,
+    /// for caching metadata.
+    pub fn metadata_only_bytes(&self) -> Box<[u8]> {
+        self.0.serialize_app13_only()
     }
 
-    pub fn get_metadata(&self) -> CostumeMetadata {
+    /// Whether this save was parsed from `metadata_only_bytes`, in which case it must not be written
+    /// out or exported.
+    pub fn is_metadata_only(&self) -> bool {
+        !self.0.has_image_data()
+    }
+
+    /// Never fails for a save returned by `parse` since the metadata is validated up front.
+    pub fn get_metadata(&self) -> Result<CostumeMetadata<'_>, CostumeParseError> {
+        fn to_str<'a>(dataset: &'a jpeg::IptcDataset, field: &'static str) -> Result<&'a str, CostumeParseError> {
+            std::str::from_utf8(&dataset.data).map_err(|err| CostumeParseError::InvalidUtf8 { field, offset: err.valid_up_to() })
+        }
+
+        let app13_segment = self.0.get_segment(jpeg::JpegSegmentType::APP13)
+            .and_then(|segments| segments.first().copied())
+            .ok_or(CostumeParseError::InvalidApp13SegmentCount { count: 0 })?;
+        let app13_payload = app13_segment.get_payload_as::<jpeg::JpegApp13Payload>();
+
+        let caption_datasets = app13_payload.get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_CAPTION).map_or(&[][..], Vec::as_slice);
+        const EXPECTED_CAPTION_COUNT: usize = COSTUME_HASH_INDEX + 1;
+        if caption_datasets.len() < EXPECTED_CAPTION_COUNT {
+            return Err(CostumeParseError::MissingCaption { expected: EXPECTED_CAPTION_COUNT, count: caption_datasets.len() });
+        }
+
+        let spec_dataset = app13_payload.get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_OBJECT_DATA_PREVIEW)
+            .and_then(|datasets| datasets.get(COSTUME_SPEC_INDEX))
+            .ok_or(CostumeParseError::MissingSpec)?;
+
+        let record_version = app13_payload.get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_VERSION)
+            .and_then(|datasets| datasets.first())
+            .and_then(|dataset| <[u8; 2]>::try_from(&*dataset.data).ok())
+            .map(u16::from_be_bytes);
+
+        // Keywords are optional so unlike the other datasets it's fine if these are missing. An
+        // empty dataset is how a save without keywords is written rather than an empty keyword.
+        let keywords = app13_payload.get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_KEYWORD)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .filter(|dataset| !dataset.data.is_empty())
+            .map(|dataset| to_str(dataset, "keyword").map(Cow::Borrowed))
+            .collect::<Result<Vec<Cow<str>>, CostumeParseError>>()?;
+
+        Ok(CostumeMetadata {
+            account_name: to_str(&caption_datasets[ACCOUNT_NAME_INDEX], "account name")?.into(),
+            character_name: to_str(&caption_datasets[CHARACTER_NAME_INDEX], "character name")?.into(),
+            hash: to_str(&caption_datasets[COSTUME_HASH_INDEX], "costume hash")?.into(),
+            spec: to_str(spec_dataset, "costume spec")?.into(),
+            keywords,
+            record_version,
+            dirty: MetadataFields::default(),
+        })
+    }
+
+    /// Check for anything that would not stop us from loading the save but that should still be
+    /// reported (and potentially fixed).
+    pub fn get_warnings(&self) -> Vec<CostumeParseWarning> {
         let app13_segment = self.0.get_segment(jpeg::JpegSegmentType::APP13).unwrap()[0];
         let app13_payload = app13_segment.get_payload_as::<jpeg::JpegApp13Payload>();
-        let caption_datasets = app13_payload.get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_CAPTION).unwrap();
-        let app_object_data_preview_datasets = app13_payload.get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_OBJECT_DATA_PREVIEW).unwrap();
-        // TODO nuke unsafe code in favor of safe variants, return Result to account for failures
-        CostumeMetadata {
-            account_name: unsafe { std::str::from_utf8_unchecked(&caption_datasets[ACCOUNT_NAME_INDEX].data) },
-            character_name: unsafe { std::str::from_utf8_unchecked(&caption_datasets[CHARACTER_NAME_INDEX].data) },
-            hash: unsafe { std::str::from_utf8_unchecked(&caption_datasets[COSTUME_HASH_INDEX].data) },
-            spec: unsafe { std::str::from_utf8_unchecked(&app_object_data_preview_datasets[COSTUME_SPEC_INDEX].data) },
+        let mut warnings = Vec::new();
+
+        match app13_payload.get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_VERSION).map(Vec::as_slice) {
+            None | Some([]) => warnings.push(CostumeParseWarning::MissingRecordVersion),
+            Some([version]) if *version.data == EXPECTED_RECORD_VERSION.to_be_bytes() => {},
+            Some([version, ..]) => warnings.push(CostumeParseWarning::UnexpectedRecordVersion { actual: version.data.clone() }),
         }
+
+        warnings
     }
 
-    pub fn update_metadata(&mut self, updates: UpdateCostumeMetadata) {
+    /// Rewrite the datasets of every dirty field in `metadata`. Clean fields are ignored.
+    pub fn update_metadata(&mut self, metadata: &CostumeMetadata) {
         let app13_segment = self.0.get_segment_mut(jpeg::JpegSegmentType::APP13).unwrap().swap_remove(0);
         let app13_payload = app13_segment.get_payload_as_mut::<jpeg::JpegApp13Payload>();
+        let dirty = metadata.dirty;
 
-        fn into_boxed_bytes(s: String) -> Box<[u8]> { s.into_bytes().into_boxed_slice() }
+        fn to_boxed_bytes(s: &str) -> Box<[u8]> { s.as_bytes().into() }
 
         {
             let caption_datasets = app13_payload.get_datasets_mut(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_CAPTION).unwrap();
-            if let Some(account_name) = updates.account_name.map(into_boxed_bytes) {
-                caption_datasets[ACCOUNT_NAME_INDEX].data = account_name;
+            if dirty.contains(MetadataFields::ACCOUNT_NAME) {
+                caption_datasets[ACCOUNT_NAME_INDEX].data = to_boxed_bytes(&metadata.account_name);
             }
-            if let Some(character_name) = updates.character_name.map(into_boxed_bytes) {
-                caption_datasets[CHARACTER_NAME_INDEX].data = character_name;
+            if dirty.contains(MetadataFields::CHARACTER_NAME) {
+                caption_datasets[CHARACTER_NAME_INDEX].data = to_boxed_bytes(&metadata.character_name);
             }
-            if let Some(hash) = updates.hash.map(into_boxed_bytes) {
-                caption_datasets[COSTUME_HASH_INDEX].data = hash;
+            if dirty.contains(MetadataFields::HASH) {
+                caption_datasets[COSTUME_HASH_INDEX].data = to_boxed_bytes(&metadata.hash);
             }
         }
 
-        {
+        if dirty.contains(MetadataFields::SPEC) {
             let app_object_data_preview_datasets = app13_payload.get_datasets_mut(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_OBJECT_DATA_PREVIEW).unwrap();
-            if let Some(spec) = updates.spec.map(into_boxed_bytes) {
-                app_object_data_preview_datasets[0].data = spec;
+            app_object_data_preview_datasets[COSTUME_SPEC_INDEX].data = to_boxed_bytes(&metadata.spec);
+        }
+
+        if dirty.contains(MetadataFields::RECORD_VERSION) {
+            app13_payload.set_datasets(
+                jpeg::APP13_RECORD_APP,
+                jpeg::APP13_RECORD_APP_VERSION,
+                metadata.record_version.map(|version| Box::from(version.to_be_bytes())).into_iter().collect(),
+            );
+        }
+
+        if dirty.contains(MetadataFields::KEYWORDS) {
+            // Like CostumeSave::new, a single empty dataset rather than none when there aren't any.
+            let keywords = match metadata.keywords.is_empty() {
+                true => vec![Box::default()],
+                false => metadata.keywords().map(to_boxed_bytes).collect(),
+            };
+            app13_payload.set_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_KEYWORD, keywords);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc_to_j2000(utc: &str) -> i64 {
+        datetime_to_j2000(&chrono::DateTime::parse_from_rfc3339(utc).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn j2000_epoch() {
+        assert_eq!(j2000_to_datetime(0).unwrap().to_rfc3339(), "2000-01-01T00:00:00+00:00");
+        assert_eq!(j2000_to_datetime(-1), None);
+        assert_eq!(j2000_to_datetime(i64::MAX), None);
+        assert_eq!(utc_to_j2000("2000-01-01T00:00:00Z"), 0);
+        assert_eq!(utc_to_j2000("2000-01-01T00:00:00.999Z"), 0);
+        assert_eq!(datetime_to_j2000(&chrono::DateTime::parse_from_rfc3339("1999-12-31T23:59:59Z").unwrap()), None);
+        // Midnight local time in a time zone ahead of UTC is still 1999 in UTC.
+        assert_eq!(datetime_to_j2000(&chrono::DateTime::parse_from_rfc3339("2000-01-01T00:00:00+01:00").unwrap()), None);
+
+        let naive = |utc: &str| chrono::NaiveDateTime::parse_from_str(utc, DEFAULT_TIMESTAMP_FORMAT).unwrap();
+        let timestamp_display = TimestampDisplay::default();
+        assert_eq!(timestamp_display.naive_to_j2000(naive("2000-01-01 00:00:00")), Some(0));
+        assert_eq!(timestamp_display.naive_to_j2000(naive("1999-12-31 23:59:59")), None);
+        assert_eq!(timestamp_display.j2000_to_naive(0), Some(naive("2000-01-01 00:00:00")));
+        assert_eq!(timestamp_display.format_j2000(-100), None);
+    }
+
+    #[test]
+    fn file_names_round_trip() {
+        let parse = |file_name: &str| {
+            let parsed = CostumeFileName::parse(file_name).unwrap();
+            assert_eq!(parsed.to_string(), file_name);
+            (parsed.save_name, parsed.j2000_timestamp, parsed.extension)
+        };
+        let owned = |save_name: &str, j2000_timestamp: Option<i64>, extension: &str| (save_name.to_owned(), j2000_timestamp, extension.to_owned());
+
+        assert_eq!(parse("Costume_foo_bar_123abc.jpg"), owned("foo_bar_123abc", None, "jpg"));
+        assert_eq!(parse("Costume_foo_bar_123.jpg"), owned("foo_bar", Some(123), "jpg"));
+        assert_eq!(parse("Costume__123.jpg"), owned("_123", None, "jpg"));
+        assert_eq!(parse("Costume_123.jpg"), owned("", Some(123), "jpg"));
+        assert_eq!(parse("Costume_.jpg"), owned("", None, "jpg"));
+        assert_eq!(parse("Costume_Ünïcødé_Hëld_762000000.jpg"), owned("Ünïcødé_Hëld", Some(762000000), "jpg"));
+        // Leading zeros and signs would be written back differently, so they're part of the name.
+        assert_eq!(parse("Costume_A_0.jpg"), owned("A", Some(0), "jpg"));
+        assert_eq!(parse("Costume_A_0123.jpg"), owned("A_0123", None, "jpg"));
+        assert_eq!(parse("Costume_A_-100.jpg"), owned("A_-100", None, "jpg"));
+        assert_eq!(parse("Costume_A_+100.jpg"), owned("A_+100", None, "jpg"));
+        assert_eq!(parse("Costume_A_99999999999999999999.jpg"), owned("A_99999999999999999999", None, "jpg"));
+        assert_eq!(parse("Costume_A.b_1.JPG"), owned("A.b", Some(1), "JPG"));
+        assert_eq!(parse("Costume_A.tar.gz"), owned("A.tar", None, "gz"));
+        assert_eq!(parse("Costume_A."), owned("A", None, ""));
+        assert!(CostumeFileName::parse("Costume_A").is_err());
+        assert!(CostumeFileName::parse("costume_A.jpg").is_err());
+        assert!(CostumeFileName::parse("A_Costume_1.jpg").is_err());
+
+        // Every combination of awkward parts. Displaying and parsing again always gives back the same
+        // file name, and the same parts unless a save name without a timestamp looks like it has one.
+        let save_names = ["", "A", "foo_bar", "_", "__", "_7", "12", "0123", "x_-100", "123abc", "a.b", "Ünïcødé", "名前_1"];
+        let j2000_timestamps = [None, Some(0), Some(7), Some(762000000), Some(i64::MAX)];
+        let extensions = ["jpg", "JPG", "jpeg", "", "jpé"];
+        for save_name in save_names {
+            for j2000_timestamp in j2000_timestamps {
+                for extension in extensions {
+                    let file_name = CostumeFileName { save_name: save_name.to_owned(), j2000_timestamp, extension: extension.to_owned() };
+                    let reparsed = CostumeFileName::parse(&file_name.to_string()).unwrap();
+                    assert_eq!(reparsed.to_string(), file_name.to_string());
+                    let looks_timestamped = ["12", "_7", "名前_1"].contains(&save_name);
+                    if j2000_timestamp.is_some() || !looks_timestamped {
+                        assert_eq!(reparsed, file_name);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn utc_display() {
+        let timestamp_display = TimestampDisplay::default();
+        let j2000_timestamp = utc_to_j2000("2024-03-10T07:00:00Z");
+        assert_eq!(
+            get_in_game_display_name("@account", "Character", Some(j2000_timestamp), &timestamp_display),
+            "@accountCharacter 2024-03-10 07:00:00",
+        );
+        assert_eq!(get_in_game_display_name("@account", "Character", None, &timestamp_display), "@accountCharacter");
+        assert!(!TimestampDisplay::is_valid_format("%Y-%"));
+    }
+
+    #[test]
+    fn fixed_offset_display() {
+        let offset = chrono::FixedOffset::west_opt(3 * 3600 + 30 * 60).unwrap();
+        let timestamp_display = TimestampDisplay { time_zone: DisplayTimeZone::Fixed(offset), ..TimestampDisplay::default() };
+        let j2000_timestamp = utc_to_j2000("2024-03-10T07:00:00Z");
+        assert_eq!(timestamp_display.format_j2000(j2000_timestamp).unwrap(), "2024-03-10 03:30:00");
+        assert_eq!(timestamp_display.naive_to_j2000(timestamp_display.j2000_to_naive(j2000_timestamp).unwrap()), Some(j2000_timestamp));
+        assert_eq!(timestamp_display.time_zone.label(), "UTC-03:30");
+    }
+
+    #[test]
+    fn new_save_round_trips() {
+        let save = crate::test_util::save("@account", "Character", "CostumeV2\n{\n}");
+        let metadata = save.get_metadata().unwrap();
+        assert_eq!(metadata.account_name(), "@account");
+        assert_eq!(metadata.character_name(), "Character");
+        assert_eq!(metadata.spec(), "CostumeV2\n{\n}\0");
+        assert_eq!(metadata.hash(), generate_costume_hash("CostumeV2\n{\n}\0"));
+        assert_eq!(metadata.record_version(), Some(EXPECTED_RECORD_VERSION));
+        assert_eq!(metadata.keywords().count(), 0);
+        assert!(save.get_warnings().is_empty());
+        let app13_segment = save.0.get_segment(jpeg::JpegSegmentType::APP13).unwrap()[0];
+        let keyword_datasets = app13_segment.get_payload_as::<jpeg::JpegApp13Payload>().get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_KEYWORD);
+        assert_eq!(keyword_datasets.map(Vec::len), Some(1));
+
+        // Clearing the keywords writes the same single empty dataset as a new save.
+        let mut save = save;
+        let mut metadata = save.get_metadata().unwrap().into_owned();
+        metadata.set_keywords(vec!["FightClub".into(), "FC".into()]);
+        save.update_metadata(&metadata);
+        let mut reparsed = CostumeSave::parse(&save.0.serialize()).unwrap();
+        assert_eq!(reparsed.get_metadata().unwrap().keywords().collect::<Vec<_>>(), ["FightClub", "FC"]);
+        let mut metadata = reparsed.get_metadata().unwrap().into_owned();
+        metadata.set_keywords(Vec::new());
+        reparsed.update_metadata(&metadata);
+        let reparsed = CostumeSave::parse(&reparsed.0.serialize()).unwrap();
+        assert_eq!(reparsed.get_metadata().unwrap().keywords().count(), 0);
+        let app13_segment = reparsed.0.get_segment(jpeg::JpegSegmentType::APP13).unwrap()[0];
+        let keyword_datasets = app13_segment.get_payload_as::<jpeg::JpegApp13Payload>().get_datasets(jpeg::APP13_RECORD_APP, jpeg::APP13_RECORD_APP_KEYWORD);
+        assert_eq!(keyword_datasets.map(|datasets| datasets.iter().map(|dataset| dataset.data.len()).collect::<Vec<_>>()), Some(vec![0]));
+
+        // Building a save out of an existing save replaces its metadata rather than adding to it.
+        let serialized = save.0.serialize();
+        let rebuilt = CostumeSave::new(&serialized, "@other", "Other", "CostumeV2\n{\n}").unwrap();
+        assert_eq!(rebuilt.get_metadata().unwrap().account_name(), "@other");
+        assert_eq!(CostumeSave::parse(&rebuilt.0.serialize()).unwrap().0.serialize(), rebuilt.0.serialize());
+    }
+
+    /// US Eastern time with the 2024 daylight saving time transitions, so that the DST tests don't
+    /// depend on the system time zone.
+    #[derive(Clone, Copy, Debug)]
+    struct Eastern2024;
+
+    impl Eastern2024 {
+        const EST: i32 = -5 * 3600;
+        const EDT: i32 = -4 * 3600;
+
+        fn offset_at(utc: &chrono::NaiveDateTime) -> chrono::FixedOffset {
+            let dst_start = chrono::NaiveDate::from_ymd_opt(2024, 3, 10).unwrap().and_hms_opt(7, 0, 0).unwrap();
+            let dst_end = chrono::NaiveDate::from_ymd_opt(2024, 11, 3).unwrap().and_hms_opt(6, 0, 0).unwrap();
+            let seconds = if (dst_start..dst_end).contains(utc) { Self::EDT } else { Self::EST };
+            chrono::FixedOffset::east_opt(seconds).unwrap()
+        }
+    }
+
+    impl chrono::TimeZone for Eastern2024 {
+        type Offset = chrono::FixedOffset;
+
+        fn from_offset(_: &chrono::FixedOffset) -> Self { Self }
+
+        fn offset_from_local_date(&self, local: &chrono::NaiveDate) -> chrono::LocalResult<chrono::FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &chrono::NaiveDateTime) -> chrono::LocalResult<chrono::FixedOffset> {
+            // Whichever offsets map back onto themselves.
+            let offsets: Vec<chrono::FixedOffset> = [Self::EST, Self::EDT].into_iter()
+                .map(|seconds| chrono::FixedOffset::east_opt(seconds).unwrap())
+                .filter(|offset| Self::offset_at(&(*local - *offset)) == *offset)
+                .collect();
+            match offsets[..] {
+                [] => chrono::LocalResult::None,
+                [offset] => chrono::LocalResult::Single(offset),
+                [first, second, ..] => chrono::LocalResult::Ambiguous(first, second),
             }
         }
+
+        fn offset_from_utc_date(&self, utc: &chrono::NaiveDate) -> chrono::FixedOffset {
+            Self::offset_at(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &chrono::NaiveDateTime) -> chrono::FixedOffset {
+            Self::offset_at(utc)
+        }
+    }
+
+    #[test]
+    fn display_across_dst_boundaries() {
+        let format = |j2000_timestamp| TimestampDisplay::format_j2000_in(&Eastern2024, j2000_timestamp, DEFAULT_TIMESTAMP_FORMAT).unwrap();
+        let to_j2000 = |local: &str| TimestampDisplay::naive_to_j2000_in(&Eastern2024, chrono::NaiveDateTime::parse_from_str(local, DEFAULT_TIMESTAMP_FORMAT).unwrap());
+
+        // Spring forward: 02:00 EST jumps to 03:00 EDT.
+        let before_spring = utc_to_j2000("2024-03-10T06:59:59Z");
+        let after_spring = utc_to_j2000("2024-03-10T07:00:00Z");
+        assert_eq!(format(before_spring), "2024-03-10 01:59:59");
+        assert_eq!(format(after_spring), "2024-03-10 03:00:00");
+        assert_eq!(to_j2000("2024-03-10 02:30:00"), None);
+        assert_eq!(to_j2000("2024-03-10 03:00:00"), Some(after_spring));
+
+        // Fall back: 02:00 EDT repeats 01:00-02:00 as EST.
+        let first_one_thirty = utc_to_j2000("2024-11-03T05:30:00Z");
+        let second_one_thirty = utc_to_j2000("2024-11-03T06:30:00Z");
+        assert_eq!(format(first_one_thirty), "2024-11-03 01:30:00");
+        assert_eq!(format(second_one_thirty), "2024-11-03 01:30:00");
+        assert_eq!(to_j2000("2024-11-03 01:30:00"), Some(first_one_thirty));
+        assert_eq!(
+            TimestampDisplay::j2000_to_naive_in(&Eastern2024, second_one_thirty).and_then(|naive| TimestampDisplay::naive_to_j2000_in(&Eastern2024, naive)),
+            Some(first_one_thirty),
+        );
+
+        // Identical displayed times still sort in the order the saves were made.
+        assert!(
+            in_game_display_sort_key("@account", "Character", Some(first_one_thirty))
+                < in_game_display_sort_key("@account", "Character", Some(second_one_thirty))
+        );
     }
 }
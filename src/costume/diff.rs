@@ -0,0 +1,90 @@
+// Line diffs between two versions of a costume spec, for reviewing conflicting edits.
+//
+// Plain longest common subsequence over lines after trimming the common prefix and suffix. Specs
+// are a few hundred lines at most so the quadratic table is fine, but past MAX_TABLE_SIZE we give
+// up and show everything in between as removed and re-added.
+
+const MAX_TABLE_SIZE: usize = 4_000_000;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// How to get from `old` to `new`, line by line.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let prefix_len = old_lines.iter().zip(new_lines.iter()).take_while(|(old, new)| old == new).count();
+    let suffix_len = old_lines[prefix_len..].iter().rev()
+        .zip(new_lines[prefix_len..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old_middle = &old_lines[prefix_len..old_lines.len() - suffix_len];
+    let new_middle = &new_lines[prefix_len..new_lines.len() - suffix_len];
+
+    let mut diff: Vec<DiffLine> = old_lines[..prefix_len].iter().map(|line| DiffLine::Same(line)).collect();
+    if (old_middle.len() + 1) * (new_middle.len() + 1) > MAX_TABLE_SIZE {
+        diff.extend(old_middle.iter().map(|line| DiffLine::Removed(line)));
+        diff.extend(new_middle.iter().map(|line| DiffLine::Added(line)));
+    } else {
+        // lcs_lens[i][j] is the length of the longest common subsequence of old_middle[i..] and
+        // new_middle[j..].
+        let width = new_middle.len() + 1;
+        let mut lcs_lens = vec![0usize; (old_middle.len() + 1) * width];
+        for i in (0..old_middle.len()).rev() {
+            for j in (0..new_middle.len()).rev() {
+                lcs_lens[i * width + j] = if old_middle[i] == new_middle[j] {
+                    lcs_lens[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs_lens[(i + 1) * width + j].max(lcs_lens[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < old_middle.len() && j < new_middle.len() {
+            if old_middle[i] == new_middle[j] {
+                diff.push(DiffLine::Same(old_middle[i]));
+                i += 1;
+                j += 1;
+            } else if lcs_lens[(i + 1) * width + j] >= lcs_lens[i * width + j + 1] {
+                diff.push(DiffLine::Removed(old_middle[i]));
+                i += 1;
+            } else {
+                diff.push(DiffLine::Added(new_middle[j]));
+                j += 1;
+            }
+        }
+        diff.extend(old_middle[i..].iter().map(|line| DiffLine::Removed(line)));
+        diff.extend(new_middle[j..].iter().map(|line| DiffLine::Added(line)));
+    }
+    diff.extend(old_lines[old_lines.len() - suffix_len..].iter().map(|line| DiffLine::Same(line)));
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_lines() {
+        let old = "CostumeV2\n{\n\tSkeleton Female\n\tGeometry Cape_Long\n\tHeight 6\n}\n";
+        let new = "CostumeV2\n{\n\tSkeleton Female\n\tHeight 7\n\tStance Heroic\n}\n";
+        assert_eq!(diff_lines(old, new), [
+            DiffLine::Same("CostumeV2"),
+            DiffLine::Same("{"),
+            DiffLine::Same("\tSkeleton Female"),
+            DiffLine::Removed("\tGeometry Cape_Long"),
+            DiffLine::Removed("\tHeight 6"),
+            DiffLine::Added("\tHeight 7"),
+            DiffLine::Added("\tStance Heroic"),
+            DiffLine::Same("}"),
+        ]);
+        assert_eq!(diff_lines("a\nb", "a\nb"), [DiffLine::Same("a"), DiffLine::Same("b")]);
+        assert_eq!(diff_lines("", "a"), [DiffLine::Added("a")]);
+    }
+}
@@ -0,0 +1,300 @@
+// The costume hash stored in the third caption dataset of every save.
+//
+// It's a Fletcher-style checksum: every byte of the spec is mapped through
+// COSTUME_HASH_ASCII_MAP and added to a running 16-bit sum (lower), and that running sum is in
+// turn added to a second 16-bit sum (upper). The two halves are packed into an i32 and written as
+// "7799{i32}\0".
+//
+// Because both sums are linear, the hash of a concatenation can be computed from the hashes of
+// its pieces, which is what makes incremental rehashing cheap. It also means collisions are easy
+// to come by, so two saves sharing a hash does NOT mean they share a spec.
+
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+const COSTUME_HASH_ASCII_MAP:  [u16; 256] = [
+    0xBCD1, 0xBB65, 0x42C2, 0xDFFE, 0x9666, 0x431B, 0x8504, 0xEB46,
+    0x6379, 0xD460, 0xCF14, 0x53CF, 0xDB51, 0xDB08, 0x12C8, 0xF602,
+    0xE766, 0x2394, 0x250D, 0xDCBB, 0xA678, 0x02AF, 0xA5C6, 0x7EA6,
+    0xB645, 0xCB4D, 0xC44B, 0xE5DC, 0x9FE6, 0x5B5C, 0x35F5, 0x701A,
+    0x220F, 0x6C38, 0x1A56, 0x4CA3, 0xFFC6, 0xB152, 0x8D61, 0x7A58,
+    0x9025, 0x8B3D, 0xBF0F, 0x95A3, 0xE5F4, 0xC127, 0x3BED, 0x320B,
+    0xB7F3, 0x6054, 0x333C, 0xD383, 0x8154, 0x5242, 0x4E0D, 0x0A94,
+    0x7028, 0x8689, 0x3A22, 0x0980, 0x1847, 0xB0F1, 0x9B5C, 0x4176,
+    0xB858, 0xD542, 0x1F6C, 0x2497, 0x6A5A, 0x9FA9, 0x8C5A, 0x7743,
+    0xA8A9, 0x9A02, 0x4918, 0x438C, 0xC388, 0x9E2B, 0x4CAD, 0x01B6,
+    0xAB19, 0xF777, 0x365F, 0x1EB2, 0x091E, 0x7BF8, 0x7A8E, 0x5227,
+    0xEAB1, 0x2074, 0x4523, 0xE781, 0x01A3, 0x163D, 0x3B2E, 0x287D,
+    0x5E7F, 0xA063, 0xB134, 0x8FAE, 0x5E8E, 0xB7B7, 0x4548, 0x1F5A,
+    0xFA56, 0x7A24, 0x900F, 0x42DC, 0xCC69, 0x02A0, 0x0B22, 0xDB31,
+    0x71FE, 0x0C7D, 0x1732, 0x1159, 0xCB09, 0xE1D2, 0x1351, 0x52E9,
+    0xF536, 0x5A4F, 0xC316, 0x6BF9, 0x8994, 0xB774, 0x5F3E, 0xF6D6,
+    0x3A61, 0xF82C, 0xCC22, 0x9D06, 0x299C, 0x09E5, 0x1EEC, 0x514F,
+    0x8D53, 0xA650, 0x5C6E, 0xC577, 0x7958, 0x71AC, 0x8916, 0x9B4F,
+    0x2C09, 0x5211, 0xF6D8, 0xCAAA, 0xF7EF, 0x287F, 0x7A94, 0xAB49,
+    0xFA2C, 0x7222, 0xE457, 0xD71A, 0x00C3, 0x1A76, 0xE98C, 0xC037,
+    0x8208, 0x5C2D, 0xDFDA, 0xE5F5, 0x0B45, 0x15CE, 0x8A7E, 0xFCAD,
+    0xAA2D, 0x4B5C, 0xD42E, 0xB251, 0x907E, 0x9A47, 0xC9A6, 0xD93F,
+    0x085E, 0x35CE, 0xA153, 0x7E7B, 0x9F0B, 0x25AA, 0x5D9F, 0xC04D,
+    0x8A0E, 0x2875, 0x4A1C, 0x295F, 0x1393, 0xF760, 0x9178, 0x0F5B,
+    0xFA7D, 0x83B4, 0x2082, 0x721D, 0x6462, 0x0368, 0x67E2, 0x8624,
+    0x194D, 0x22F6, 0x78FB, 0x6791, 0xB238, 0xB332, 0x7276, 0xF272,
+    0x47EC, 0x4504, 0xA961, 0x9FC8, 0x3FDC, 0xB413, 0x007A, 0x0806,
+    0x7458, 0x95C6, 0xCCAA, 0x18D6, 0xE2AE, 0x1B06, 0xF3F6, 0x5050,
+    0xC8E8, 0xF4AC, 0xC04C, 0xF41C, 0x992F, 0xAE44, 0x5F1B, 0x1113,
+    0x1738, 0xD9A8, 0x19EA, 0x2D33, 0x9698, 0x2FE9, 0x323F, 0xCDE2,
+    0x6D71, 0xE37D, 0xB697, 0x2C4F, 0x4373, 0x9102, 0x075D, 0x8E25,
+    0x1672, 0xEC28, 0x6ACB, 0x86CC, 0x186E, 0x9414, 0xD674, 0xD1A5,
+];
+
+const HASH_PREFIX: &str = "7799";
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct CostumeHash {
+    pub upper: u16,
+    pub lower: u16,
+}
+
+impl CostumeHash {
+    pub fn of(costume_spec: &str) -> Self {
+        let mut hasher = CostumeHasher::new();
+        hasher.update(costume_spec.as_bytes());
+        hasher.finish()
+    }
+
+    /// Parse a hash as stored in a save, e.g. "7799-1234567890\0", back into its two halves.
+    pub fn parse(hash: &str) -> Result<Self, HashParseError> {
+        let rest = hash.strip_prefix(HASH_PREFIX).ok_or(HashParseError::MissingPrefix)?;
+        let number = rest.strip_suffix('\0').ok_or(HashParseError::MissingNullTerminator)?;
+        let packed = number.parse::<i32>().map_err(|_| HashParseError::InvalidNumber)?;
+        let parsed = Self { upper: (packed as u32 >> 16) as u16, lower: packed as u16 };
+
+        // Reject things like leading zeros or a '+' sign so that parsing always round-trips.
+        if parsed.to_string() != hash {
+            return Err(HashParseError::InvalidNumber);
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl std::fmt::Display for CostumeHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{HASH_PREFIX}{}\0", (((self.upper as u32) << 16) | self.lower as u32) as i32)
+    }
+}
+
+#[derive(Debug)]
+pub enum HashParseError {
+    MissingPrefix,
+    MissingNullTerminator,
+    InvalidNumber,
+}
+
+impl std::error::Error for HashParseError {}
+
+impl std::fmt::Display for HashParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::MissingPrefix => write!(f, "Costume hash doesn't start with {HASH_PREFIX:?}"),
+            Self::MissingNullTerminator => write!(f, "Costume hash isn't null-terminated"),
+            Self::InvalidNumber => write!(f, "Costume hash isn't a valid 32-bit integer"),
+        }
+    }
+}
+
+/// Streaming costume hasher. Feeding a spec in pieces gives the same result as hashing it all at
+/// once.
+#[derive(PartialEq, Eq, Copy, Clone, Default, Debug)]
+pub struct CostumeHasher {
+    upper: Wrapping<u16>,
+    lower: Wrapping<u16>,
+    /// Number of bytes hashed, needed to combine hashes. Only the low 16 bits matter.
+    len: Wrapping<u16>,
+}
+
+impl CostumeHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.lower += Wrapping(COSTUME_HASH_ASCII_MAP[*byte as usize]);
+            self.upper += self.lower;
+        }
+        self.len += Wrapping(bytes.len() as u16);
+    }
+
+    /// The state after hashing everything hashed by `self` followed by everything hashed by
+    /// `next`, without rescanning either.
+    pub fn chain(self, next: Self) -> Self {
+        Self {
+            // Every byte of `next` adds the whole of self.lower to upper one more time.
+            upper: self.upper + next.upper + next.len * self.lower,
+            lower: self.lower + next.lower,
+            len: self.len + next.len,
+        }
+    }
+
+    pub fn finish(&self) -> CostumeHash {
+        CostumeHash { upper: self.upper.0, lower: self.lower.0 }
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> CostumeHasher {
+    let mut hasher = CostumeHasher::new();
+    hasher.update(bytes);
+    hasher
+}
+
+/// Keeps the hash of a spec up to date while it's being edited. Every line is hashed on its own
+/// and the line hashes are chained together, so an edit only hashes the lines it touched.
+#[derive(Clone, Default)]
+pub struct SpecHasher {
+    spec: String,
+    /// Length and hash of each line of `spec`, including its '\n'.
+    lines: Vec<(usize, CostumeHasher)>,
+}
+
+impl SpecHasher {
+    pub fn new(costume_spec: &str) -> Self {
+        Self { spec: costume_spec.to_owned(), lines: hash_lines(costume_spec.as_bytes()) }
+    }
+
+    pub fn hash(&self) -> CostumeHash {
+        self.lines.iter().fold(CostumeHasher::new(), |state, (_, line)| state.chain(*line)).finish()
+    }
+
+    pub fn update(&mut self, new_spec: &str) -> CostumeHash {
+        let old = self.spec.as_bytes();
+        let new = new_spec.as_bytes();
+        let prefix_len = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let max_suffix_len = old.len().min(new.len()) - prefix_len;
+        let suffix_len = old.iter().rev().zip(new.iter().rev()).take(max_suffix_len).take_while(|(a, b)| a == b).count();
+
+        // Lines are kept if they're entirely within the unchanged prefix or suffix and still start
+        // and end at line breaks in the new spec.
+        let mut kept_head = 0;
+        let mut head_len = 0;
+        for (len, _) in self.lines.iter() {
+            if head_len + len > prefix_len || old[head_len + len - 1] != b'\n' { break; }
+            kept_head += 1;
+            head_len += len;
+        }
+        let mut kept_tail = 0;
+        let mut tail_len = 0;
+        for (len, _) in self.lines[kept_head..].iter().rev() {
+            // NOTE The byte before the line has to be in the unchanged suffix too.
+            if tail_len + len >= suffix_len { break; }
+            kept_tail += 1;
+            tail_len += len;
+        }
+
+        let first_changed_line = kept_head;
+        let last_changed_line = self.lines.len() - kept_tail;
+        self.lines.splice(first_changed_line..last_changed_line, hash_lines(&new[head_len..new.len() - tail_len]));
+        self.spec.clear();
+        self.spec.push_str(new_spec);
+        self.hash()
+    }
+}
+
+fn hash_lines(spec: &[u8]) -> Vec<(usize, CostumeHasher)> {
+    spec.split_inclusive(|byte| *byte == b'\n').map(|line| (line.len(), hash_bytes(line))).collect()
+}
+
+pub fn generate_costume_hash(costume_spec: &str) -> String {
+    CostumeHash::of(costume_spec).to_string()
+}
+
+/// Group saves that share a hash but have different specs. Each collision holds one group of keys
+/// per distinct spec, so saves that are true duplicates of each other end up in the same group.
+pub fn find_collisions<'a, K>(saves: impl IntoIterator<Item = (K, CostumeHash, &'a str)>) -> Vec<(CostumeHash, Vec<Vec<K>>)> {
+    let mut by_hash: HashMap<CostumeHash, HashMap<&'a str, Vec<K>>> = HashMap::new();
+    for (key, hash, spec) in saves {
+        by_hash.entry(hash).or_default().entry(spec).or_default().push(key);
+    }
+
+    by_hash.into_iter()
+        .filter(|(_, by_spec)| by_spec.len() > 1)
+        .map(|(hash, by_spec)| (hash, by_spec.into_values().collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = "CostumeV2\n{\n\tSkeleton Male\n\tHeight 5.5\n}\n\0";
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let one_shot = CostumeHash::of(SPEC);
+        let (head, tail) = SPEC.split_at(12);
+        let mut hasher = CostumeHasher::new();
+        hasher.update(head.as_bytes());
+        hasher.update(tail.as_bytes());
+        assert_eq!(hasher.finish(), one_shot);
+
+        let chained = hash_bytes(head.as_bytes()).chain(hash_bytes(tail.as_bytes()));
+        assert_eq!(chained.finish(), one_shot);
+    }
+
+    #[test]
+    fn spec_hasher_tracks_edits() {
+        let mut spec_hasher = SpecHasher::new(SPEC);
+        for edited in [
+            "CostumeV2\n{\n\tSkeleton Male\n\tHeight 6.5\n}\n\0",
+            "CostumeV2\n{\n\tSkeleton Female\n\tHeight 6.5\n}\n\0",
+            "CostumeV2\n{\n\tSkeleton Female\n\tMood Happy\n\tHeight 6.5\n}\n\0",
+            "CostumeV2\n{\n\tSkeleton Female\n\tHeight 6.5\n}\n\0",
+            "CostumeV2\n{\n\tSkeleton Female\tHeight 6.5\n}\n\0",
+            "CostumeV2\n{\n\tSkeleton Female\n\n\tHeight 6.5\n}\n\0",
+            "a\na\na\n",
+            "a\na\n",
+            "a\na\na\na\n",
+            "a\n\na\na\n",
+            "X",
+            "",
+            SPEC,
+            SPEC,
+        ] {
+            assert_eq!(spec_hasher.update(edited), CostumeHash::of(edited), "{edited:?}");
+        }
+    }
+
+    #[test]
+    fn parse_round_trips() {
+        let hash = CostumeHash::of(SPEC);
+        assert_eq!(CostumeHash::parse(&hash.to_string()).unwrap(), hash);
+        assert_eq!(generate_costume_hash(SPEC), hash.to_string());
+
+        let negative = CostumeHash { upper: 0xFFFF, lower: 0x0001 };
+        assert_eq!(negative.to_string(), "7799-65535\0");
+        assert_eq!(CostumeHash::parse("7799-65535\0").unwrap(), negative);
+
+        assert!(matches!(CostumeHash::parse("7798123\0"), Err(HashParseError::MissingPrefix)));
+        assert!(matches!(CostumeHash::parse("7799123"), Err(HashParseError::MissingNullTerminator)));
+        assert!(matches!(CostumeHash::parse("7799+123\0"), Err(HashParseError::InvalidNumber)));
+        assert!(matches!(CostumeHash::parse("77990123\0"), Err(HashParseError::InvalidNumber)));
+    }
+
+    #[test]
+    fn collisions_ignore_duplicates() {
+        let hash = CostumeHash { upper: 1, lower: 2 };
+        let other_hash = CostumeHash { upper: 3, lower: 4 };
+        let collisions = find_collisions([
+            ("a", hash, "spec one"),
+            ("b", hash, "spec one"),
+            ("c", hash, "spec two"),
+            ("d", other_hash, "spec three"),
+            ("e", other_hash, "spec three"),
+        ]);
+        assert_eq!(collisions.len(), 1);
+        let (collision_hash, mut groups) = collisions.into_iter().next().unwrap();
+        assert_eq!(collision_hash, hash);
+        groups.sort();
+        assert_eq!(groups, vec![vec!["a", "b"], vec!["c"]]);
+    }
+}
@@ -0,0 +1,259 @@
+// Search queries for the save list, e.g. `cape character:"Dr X" -has:keyword before:2024-01-01`.
+//
+// A query is whitespace-separated terms that must all match. Double quotes group words into one
+// value (and make a term free text even if it contains a colon). A leading '-' negates a term.
+//
+//     TEXT              the display name, file name or spec contains TEXT
+//     account:TEXT      the account name contains TEXT
+//     character:TEXT    the character name contains TEXT
+//     keyword:KEYWORD   has exactly this keyword
+//     part:TEXT         the spec references a part whose name contains TEXT
+//     has:timestamp     the file name has a timestamp suffix
+//     has:keyword       has at least one keyword
+//     before:DATE       timestamp suffix is before DATE (YYYY-MM-DD, display time zone)
+//     after:DATE        timestamp suffix is on or after DATE
+//
+// Matching is case-insensitive throughout. Saves without a timestamp never match before/after.
+
+use super::{SpecCatalog, TimestampDisplay};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+enum Filter {
+    // Text values are stored lowercased.
+    Text(String),
+    Account(String),
+    Character(String),
+    Keyword(String),
+    Part(String),
+    HasTimestamp,
+    HasKeyword,
+    Before(chrono::NaiveDate),
+    After(chrono::NaiveDate),
+}
+
+impl Filter {
+    /// Whether every save this filter matches is also matched by `other`.
+    fn implies(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Text(a), Self::Text(b))
+            | (Self::Account(a), Self::Account(b))
+            | (Self::Character(a), Self::Character(b))
+            | (Self::Part(a), Self::Part(b)) => a.contains(b.as_str()),
+            (Self::Before(a), Self::Before(b)) => a <= b,
+            (Self::After(a), Self::After(b)) => a >= b,
+            (a, b) => a == b,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+struct Term {
+    negated: bool,
+    filter: Filter,
+}
+
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct SearchQuery(Vec<Term>);
+
+/// Everything a query can refer to for a single save.
+pub struct SearchInput<'a> {
+    pub display_name: &'a str,
+    pub file_name: &'a str,
+    pub account_name: &'a str,
+    pub character_name: &'a str,
+    pub keywords: &'a [&'a str],
+    pub spec: &'a str,
+    pub j2000_timestamp: Option<i64>,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum SearchError {
+    /// Byte offset of the '"'.
+    UnclosedQuote(usize),
+    UnknownField(String),
+    InvalidValue { field: String, value: String },
+}
+
+impl std::error::Error for SearchError {}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnclosedQuote(offset) => write!(f, "'\"' at {offset} is never closed"),
+            Self::UnknownField(field) => write!(f, "unknown field \"{field}:\" (quote the term to search for it as text)"),
+            Self::InvalidValue { field, value } => write!(f, "invalid value {value:?} for \"{field}:\""),
+        }
+    }
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self, SearchError> {
+        let mut terms = Vec::new();
+        let mut chars = query.char_indices().peekable();
+        loop {
+            while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+
+            // Read up to the next unquoted whitespace, noting where the first unquoted colon was.
+            let mut raw = String::new();
+            let mut field_end = None;
+            let mut starts_quoted = false;
+            while let Some((offset, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+                match c {
+                    '"' => {
+                        starts_quoted |= raw.is_empty() || raw == "-";
+                        loop {
+                            match chars.next() {
+                                Some((_, '"')) => break,
+                                Some((_, c)) => raw.push(c),
+                                None => return Err(SearchError::UnclosedQuote(offset)),
+                            }
+                        }
+                    },
+                    ':' if field_end.is_none() && !starts_quoted => {
+                        field_end = Some(raw.len());
+                        raw.push(c);
+                    },
+                    _ => raw.push(c),
+                }
+            }
+
+            let (negated, raw, field_end) = match raw.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (true, rest, field_end.map(|end| end - 1)),
+                _ => (false, raw.as_str(), field_end),
+            };
+            let filter = match field_end {
+                Some(end) => Self::parse_field(&raw[..end], &raw[end + 1..])?,
+                None if raw.is_empty() => continue,
+                None => Filter::Text(raw.to_lowercase()),
+            };
+            terms.push(Term { negated, filter });
+        }
+
+        Ok(Self(terms))
+    }
+
+    fn parse_field(field: &str, value: &str) -> Result<Filter, SearchError> {
+        let invalid_value = || SearchError::InvalidValue { field: field.to_owned(), value: value.to_owned() };
+        let text = || if value.is_empty() { Err(invalid_value()) } else { Ok(value.to_lowercase()) };
+        let date = || chrono::NaiveDate::parse_from_str(value, DATE_FORMAT).map_err(|_| invalid_value());
+
+        match field.to_ascii_lowercase().as_str() {
+            "account" => Ok(Filter::Account(text()?)),
+            "character" => Ok(Filter::Character(text()?)),
+            "keyword" => Ok(Filter::Keyword(text()?)),
+            "part" => Ok(Filter::Part(text()?)),
+            "has" => match value.to_ascii_lowercase().as_str() {
+                "timestamp" => Ok(Filter::HasTimestamp),
+                "keyword" | "keywords" => Ok(Filter::HasKeyword),
+                _ => Err(invalid_value()),
+            },
+            "before" => Ok(Filter::Before(date()?)),
+            "after" => Ok(Filter::After(date()?)),
+            _ => Err(SearchError::UnknownField(field.to_owned())),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn matches(&self, input: &SearchInput, timestamp_display: &TimestampDisplay) -> bool {
+        let date = || input.j2000_timestamp
+            .and_then(|j2000_timestamp| timestamp_display.j2000_to_naive(j2000_timestamp))
+            .map(|datetime| datetime.date());
+
+        self.0.iter().all(|Term { negated, filter }| {
+            let matched = match filter {
+                Filter::Text(text) => [input.display_name, input.file_name, input.spec].iter().any(|haystack| contains_ignore_case(haystack, text)),
+                Filter::Account(text) => contains_ignore_case(input.account_name, text),
+                Filter::Character(text) => contains_ignore_case(input.character_name, text),
+                Filter::Keyword(keyword) => input.keywords.iter().any(|k| k.to_lowercase() == *keyword),
+                Filter::Part(text) => SpecCatalog::embedded().referenced_parts(input.spec).iter().any(|part| contains_ignore_case(part, text)),
+                Filter::HasTimestamp => input.j2000_timestamp.is_some(),
+                Filter::HasKeyword => !input.keywords.is_empty(),
+                Filter::Before(before) => date().is_some_and(|date| date < *before),
+                Filter::After(after) => date().is_some_and(|date| date >= *after),
+            };
+            matched != *negated
+        })
+    }
+
+    /// Whether every save this query matches is also matched by `previous`, meaning the results of
+    /// `previous` can be filtered further instead of searching everything again.
+    pub fn narrows(&self, previous: &Self) -> bool {
+        previous.0.iter().all(|previous_term| self.0.iter().any(|term| {
+            term.negated == previous_term.negated && if term.negated {
+                // Excluding `previous_term`'s saves has to exclude at least `term`'s.
+                previous_term.filter.implies(&term.filter)
+            } else {
+                term.filter.implies(&previous_term.filter)
+            }
+        }))
+    }
+}
+
+/// `needle` must already be lowercase.
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    if needle.is_empty() {
+        true
+    } else if needle.is_ascii() {
+        // Avoids lowercasing whole specs on every keystroke.
+        haystack.as_bytes().windows(needle.len()).any(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+    } else {
+        haystack.to_lowercase().contains(needle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_terms() {
+        let input = SearchInput {
+            display_name: "@AccountDr X 2024-02-23",
+            file_name: "Costume_Dr X_762000000.jpg",
+            account_name: "@Account",
+            character_name: "Dr X",
+            keywords: &["FightClub"],
+            spec: "CostumeV2\n{\n\tGeometry Cape_Long\n}\n",
+            j2000_timestamp: Some(762_000_000),
+        };
+        let timestamp_display = TimestampDisplay::default();
+        let matches = |query: &str| SearchQuery::parse(query).unwrap().matches(&input, &timestamp_display);
+
+        assert!(matches(""));
+        assert!(matches("dr costumev2"));
+        assert!(matches("character:\"dr x\" account:acc keyword:fightclub part:cape"));
+        assert!(matches("has:timestamp has:keyword before:2024-02-24 after:2024-02-23"));
+        assert!(matches("-part:boots -\"dr y\""));
+        assert!(!matches("before:2024-02-23"));
+        assert!(!matches("keyword:fight"));
+        assert!(!matches("-has:keyword"));
+        assert!(!matches("part:costumev2"));
+    }
+
+    #[test]
+    fn parses_and_narrows() {
+        assert_eq!(SearchQuery::parse("a \"b"), Err(SearchError::UnclosedQuote(2)));
+        assert_eq!(SearchQuery::parse("colour:red"), Err(SearchError::UnknownField("colour".to_owned())));
+        assert_eq!(SearchQuery::parse("has:cape"), Err(SearchError::InvalidValue { field: "has".to_owned(), value: "cape".to_owned() }));
+        assert_eq!(SearchQuery::parse("-\"colour:red\"").unwrap(), SearchQuery(vec![Term { negated: true, filter: Filter::Text("colour:red".to_owned()) }]));
+
+        let narrows = |query: &str, previous: &str| SearchQuery::parse(query).unwrap().narrows(&SearchQuery::parse(previous).unwrap());
+        assert!(narrows("cap", ""));
+        assert!(narrows("cape", "cap"));
+        assert!(narrows("cape account:x", "cap"));
+        assert!(narrows("before:2024-01-01", "before:2024-06-01"));
+        assert!(narrows("-cap", "-cape"));
+        assert!(!narrows("cap", "cape"));
+        assert!(!narrows("-cape", "-cap"));
+        assert!(!narrows("after:2024-01-01", "after:2024-06-01"));
+        assert!(!narrows("", "cap"));
+    }
+}
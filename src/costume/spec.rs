@@ -0,0 +1,397 @@
+// Validation of the proprietary costume spec stored in the object data preview dataset.
+//
+// The spec is a Cryptic text-parser style document: each line holds a field name followed by zero
+// or more values, and a field followed by a `{` opens a nested block that is closed by a matching
+// `}`. Values containing whitespace are wrapped in double quotes.
+//
+// We only understand the shape of the format, not its full schema, so validation is split into
+// two severities. Errors are things we know will make the game reject the save (broken structure,
+// values outside of the catalog's ranges) and must block saving. Hints are things we can't be sure
+// about (references to parts that aren't in our catalog) and are only reported.
+//
+// FIXME The embedded catalog is still a placeholder with no ranges and no parts, since we don't have
+// a corpus of saves known to load in-game to build it from. Until then only the structure is really
+// checked.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum SpecDiagnosticSeverity {
+    Hint,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct SpecDiagnostic {
+    pub severity: SpecDiagnosticSeverity,
+    /// 1-based line of the offending text.
+    pub line: usize,
+    /// 1-based column (in characters, not bytes) of the offending text.
+    pub column: usize,
+    /// Byte range of the offending text within the spec, used for highlighting.
+    pub byte_range: std::ops::Range<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for SpecDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let severity = match self.severity {
+            SpecDiagnosticSeverity::Hint => "hint",
+            SpecDiagnosticSeverity::Error => "error",
+        };
+        write!(f, "{}:{}: {severity}: {}", self.line, self.column, self.message)
+    }
+}
+
+pub fn has_errors(diagnostics: &[SpecDiagnostic]) -> bool {
+    diagnostics.iter().any(|diagnostic| diagnostic.severity == SpecDiagnosticSeverity::Error)
+}
+
+/// Whether there's nothing but whitespace and the null terminator that saves end their spec with.
+pub fn is_empty_spec(spec: &str) -> bool {
+    spec.trim_matches(|c: char| c.is_whitespace() || c == '\0').is_empty()
+}
+
+/// Known-good value ranges and part names, meant to be built from a corpus of saves that load
+/// in-game. See `spec_catalog.txt` for the format.
+#[derive(Clone)]
+pub struct SpecCatalog {
+    /// (field name pattern, min, max)
+    ranges: Vec<(String, f64, f64)>,
+    /// Key: name of a field that references a part, Value: every part name seen for that field.
+    parts: HashMap<String, HashSet<String>>,
+}
+
+static EMBEDDED_CATALOG: LazyLock<SpecCatalog> = LazyLock::new(|| SpecCatalog::parse(include_str!("spec_catalog.txt")));
+
+impl SpecCatalog {
+    pub fn embedded() -> &'static Self {
+        &EMBEDDED_CATALOG
+    }
+
+    fn parse(catalog: &str) -> Self {
+        let mut ranges = Vec::new();
+        let mut parts: HashMap<String, HashSet<String>> = HashMap::new();
+        for line in catalog.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next(), words.next()) {
+                (Some("range"), Some(pattern), Some(min), Some(max)) => {
+                    let (Ok(min), Ok(max)) = (min.parse::<f64>(), max.parse::<f64>()) else { continue };
+                    ranges.push((pattern.to_owned(), min, max));
+                },
+                (Some("partfield"), Some(field), None, None) => {
+                    parts.entry(field.to_owned()).or_default();
+                },
+                (Some("part"), Some(field), Some(name), None) => {
+                    parts.entry(field.to_owned()).or_default().insert(name.to_owned());
+                },
+                _ => {},
+            }
+        }
+
+        Self { ranges, parts }
+    }
+
+    /// Record every part referenced by a spec that is known to load in-game, so that later
+    /// validations don't warn about it.
+    pub fn learn_parts_from_spec(&mut self, spec: &str) {
+        let Ok(fields) = parse_fields(spec) else { return };
+        for field in fields {
+            if let (Some(known_parts), Some(value)) = (self.parts.get_mut(field.name.text), field.values.first()) {
+                known_parts.insert(value.text.to_owned());
+            }
+        }
+    }
+
+    /// Every part name a spec references, in order. Empty if the spec is malformed.
+    pub fn referenced_parts<'s>(&self, spec: &'s str) -> Vec<&'s str> {
+        let Ok(fields) = parse_fields(spec) else { return Vec::new() };
+        fields.into_iter()
+            .filter(|field| self.parts.contains_key(field.name.text))
+            .filter_map(|field| Some(field.values.first()?.text))
+            .collect()
+    }
+
+    fn get_range(&self, field_name: &str) -> Option<(f64, f64)> {
+        self.ranges.iter()
+            .find(|(pattern, _, _)| matches_pattern(pattern, field_name))
+            .map(|(_, min, max)| (*min, *max))
+    }
+}
+
+/// Very small glob matcher supporting only `*` (any run of characters).
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let mut pieces = pattern.split('*');
+    let Some(first) = pieces.next() else { return text.is_empty() };
+    let Some(mut rest) = text.strip_prefix(first) else { return false };
+    let pieces: Vec<&str> = pieces.collect();
+    let Some((last, middle)) = pieces.split_last() else { return rest.is_empty() };
+    for piece in middle {
+        let Some(index) = rest.find(piece) else { return false };
+        rest = &rest[index + piece.len()..];
+    }
+    rest.ends_with(last)
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum TokenKind { OpenBrace, CloseBrace, Word, Quoted }
+
+#[derive(Copy, Clone)]
+struct Token<'a> {
+    kind: TokenKind,
+    /// For quoted tokens this excludes the quotes.
+    text: &'a str,
+    line: usize,
+    column: usize,
+    byte_range: (usize, usize),
+}
+
+impl Token<'_> {
+    fn diagnostic(&self, severity: SpecDiagnosticSeverity, message: String) -> SpecDiagnostic {
+        SpecDiagnostic {
+            severity,
+            line: self.line,
+            column: self.column,
+            byte_range: self.byte_range.0 .. self.byte_range.1,
+            message,
+        }
+    }
+}
+
+fn tokenize(spec: &str) -> Result<Vec<Token<'_>>, SpecDiagnostic> {
+    let mut tokens = Vec::new();
+    let mut chars = spec.char_indices().peekable();
+    let mut line = 1;
+    let mut column = 1;
+
+    while let Some((start, c)) = chars.next() {
+        let start_column = column;
+        column += 1;
+        match c {
+            '\n' => { line += 1; column = 1; },
+            // The spec is stored null-terminated.
+            c if c.is_whitespace() || c == '\0' => {},
+            '{' | '}' => tokens.push(Token {
+                kind: if c == '{' { TokenKind::OpenBrace } else { TokenKind::CloseBrace },
+                text: &spec[start..start + 1],
+                line,
+                column: start_column,
+                byte_range: (start, start + 1),
+            }),
+            '"' => {
+                let mut end = None;
+                let mut escaped = false;
+                for (index, c) in chars.by_ref() {
+                    column += 1;
+                    match c {
+                        '\n' => break,
+                        '\\' if !escaped => { escaped = true; continue; },
+                        '"' if !escaped => { end = Some(index); break; },
+                        _ => {},
+                    }
+                    escaped = false;
+                }
+
+                let Some(end) = end else {
+                    let line_end = spec[start..].find('\n').map_or(spec.len(), |index| start + index);
+                    return Err(SpecDiagnostic {
+                        severity: SpecDiagnosticSeverity::Error,
+                        line,
+                        column: start_column,
+                        byte_range: start .. line_end,
+                        message: "unterminated string".to_owned(),
+                    });
+                };
+
+                tokens.push(Token {
+                    kind: TokenKind::Quoted,
+                    text: &spec[start + 1..end],
+                    line,
+                    column: start_column,
+                    byte_range: (start, end + 1),
+                });
+            },
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(index, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '\0' | '{' | '}' | '"') { break; }
+                    chars.next();
+                    column += 1;
+                    end = index + c.len_utf8();
+                }
+
+                tokens.push(Token {
+                    kind: TokenKind::Word,
+                    text: &spec[start..end],
+                    line,
+                    column: start_column,
+                    byte_range: (start, end),
+                });
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Field<'a> {
+    name: Token<'a>,
+    values: Vec<Token<'a>>,
+}
+
+/// Flatten the spec into its fields, checking that blocks are balanced along the way.
+fn parse_fields(spec: &str) -> Result<Vec<Field<'_>>, SpecDiagnostic> {
+    let tokens = tokenize(spec)?;
+    let mut fields: Vec<Field> = Vec::new();
+    let mut open_blocks: Vec<Token> = Vec::new();
+    let mut current_line = 0;
+
+    for token in tokens {
+        match token.kind {
+            TokenKind::OpenBrace => open_blocks.push(token),
+            TokenKind::CloseBrace => {
+                if open_blocks.pop().is_none() {
+                    return Err(token.diagnostic(SpecDiagnosticSeverity::Error, "unmatched '}'".to_owned()));
+                }
+            },
+            TokenKind::Word | TokenKind::Quoted => {
+                match fields.last_mut() {
+                    Some(field) if token.line == current_line => field.values.push(token),
+                    _ => fields.push(Field { name: token, values: Vec::new() }),
+                }
+            },
+        }
+        current_line = token.line;
+    }
+
+    if let Some(unclosed) = open_blocks.pop() {
+        return Err(unclosed.diagnostic(SpecDiagnosticSeverity::Error, "unclosed '{'".to_owned()));
+    }
+
+    Ok(fields)
+}
+
+/// Check a costume spec for structural problems, out-of-range values, and references to parts
+/// that the catalog doesn't know about.
+pub fn validate_spec(spec: &str, catalog: &SpecCatalog) -> Vec<SpecDiagnostic> {
+    if is_empty_spec(spec) {
+        return vec![SpecDiagnostic {
+            severity: SpecDiagnosticSeverity::Error,
+            line: 1,
+            column: 1,
+            byte_range: 0 .. spec.len(),
+            message: "costume spec is empty".to_owned(),
+        }];
+    }
+
+    let fields = match parse_fields(spec) {
+        Ok(fields) => fields,
+        Err(diagnostic) => return vec![diagnostic],
+    };
+
+    let mut diagnostics = Vec::new();
+    for field in fields.iter() {
+        if let Some((min, max)) = catalog.get_range(field.name.text) {
+            for value in field.values.iter() {
+                match value.text.parse::<f64>() {
+                    Ok(number) if (min..=max).contains(&number) => {},
+                    Ok(number) => diagnostics.push(value.diagnostic(
+                        SpecDiagnosticSeverity::Error,
+                        format!("{} value {number} is outside of the known-good range {min} to {max}", field.name.text),
+                    )),
+                    Err(_) => diagnostics.push(value.diagnostic(
+                        SpecDiagnosticSeverity::Error,
+                        format!("{} expects numeric values but found {:?}", field.name.text, value.text),
+                    )),
+                }
+            }
+        }
+
+        if let Some(known_parts) = catalog.parts.get(field.name.text) {
+            match field.values.first() {
+                Some(part) if known_parts.contains(part.text) => {},
+                Some(part) => diagnostics.push(part.diagnostic(
+                    SpecDiagnosticSeverity::Hint,
+                    format!("{} part {:?} isn't in the catalog or any loaded save", field.name.text, part.text),
+                )),
+                None => diagnostics.push(field.name.diagnostic(
+                    SpecDiagnosticSeverity::Error,
+                    format!("{} is missing a part name", field.name.text),
+                )),
+            }
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(diagnostic: &SpecDiagnostic) -> (usize, usize, std::ops::Range<usize>) {
+        (diagnostic.line, diagnostic.column, diagnostic.byte_range.clone())
+    }
+
+    #[test]
+    fn tokenizes_with_positions() {
+        let spec = "CostumeV2\n{\n\tName \"Ünï \\\"Hëld\\\"\" Ω1\n}\0";
+        let tokens = tokenize(spec).unwrap();
+        let summary: Vec<(&str, usize, usize, (usize, usize))> = tokens.iter().map(|token| (token.text, token.line, token.column, token.byte_range)).collect();
+        assert_eq!(summary, [
+            ("CostumeV2", 1, 1, (0, 9)),
+            ("{", 2, 1, (10, 11)),
+            ("Name", 3, 2, (13, 17)),
+            ("Ünï \\\"Hëld\\\"", 3, 7, (18, 35)),
+            // Columns count characters, not bytes.
+            ("Ω1", 3, 22, (36, 39)),
+            ("}", 4, 1, (40, 41)),
+        ]);
+        assert!(tokens[3].kind == TokenKind::Quoted);
+
+        let Err(err) = tokenize("Name \"Ünï\nNext 1") else { panic!("expected an error") };
+        assert_eq!(err.message, "unterminated string");
+        assert_eq!(position(&err), (1, 6, 5..11));
+        assert_eq!(err.to_string(), "1:6: error: unterminated string");
+    }
+
+    #[test]
+    fn validates_structure_ranges_and_parts() {
+        let catalog = SpecCatalog::parse("range Color* 0 255\nrange *Scale* -1 1\npartfield Geometry\npart Geometry Known\n");
+
+        for empty in ["", "\0", " \n\t\0"] {
+            let errors = validate_spec(empty, &catalog);
+            assert!(has_errors(&errors), "{empty:?}");
+            assert_eq!(errors[0].message, "costume spec is empty");
+        }
+
+        let errors = validate_spec("CostumeV2\n{\n}\n}\0", &catalog);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].message.as_str(), position(&errors[0])), ("unmatched '}'", (4, 1, 14..15)));
+        let errors = validate_spec("CostumeV2\n{\n\tPart\n\t{\n}\0", &catalog);
+        assert_eq!((errors[0].message.as_str(), position(&errors[0])), ("unclosed '{'", (2, 1, 10..11)));
+        assert!(has_errors(&validate_spec(" \n\t", &catalog)));
+
+        let spec = "CostumeV2\n{\n\tColor1 0 255 256\n\tColor2 abc\n\tBodyScale -0.5\n\tGeometry Unknown\n\tGeometry Known\n\tGeometry\n}\0";
+        let diagnostics = validate_spec(spec, &catalog);
+        let summary: Vec<(SpecDiagnosticSeverity, usize, usize, &str)> = diagnostics.iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.line, diagnostic.column, &spec[diagnostic.byte_range.clone()]))
+            .collect();
+        assert_eq!(summary, [
+            (SpecDiagnosticSeverity::Error, 3, 15, "256"),
+            (SpecDiagnosticSeverity::Error, 4, 9, "abc"),
+            (SpecDiagnosticSeverity::Hint, 6, 11, "Unknown"),
+            (SpecDiagnosticSeverity::Error, 8, 2, "Geometry"),
+        ]);
+        assert!(has_errors(&diagnostics));
+
+        // Hints alone don't block saving, and parts from loaded saves stop being hints.
+        let mut catalog = catalog;
+        assert!(!has_errors(&validate_spec("Geometry Unknown\0", &catalog)));
+        catalog.learn_parts_from_spec("Geometry Unknown\0");
+        assert!(validate_spec("Geometry Unknown\0", &catalog).is_empty());
+        assert_eq!(catalog.referenced_parts("Geometry Known\nColor1 1\nGeometry Unknown"), ["Known", "Unknown"]);
+    }
+}
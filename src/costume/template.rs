@@ -0,0 +1,205 @@
+// Save name templates for batch renaming, e.g. "{character}_{date:%Y%m%d}_{n:03}".
+//
+// A template is literal text with placeholders in braces. "{{" and "}}" are literal braces. Some
+// placeholders take an argument after a colon:
+//
+//     {account}         account name
+//     {character}       character name
+//     {keyword}         first keyword, empty if there are none
+//     {name}            original save name
+//     {date:FORMAT}     j2000 timestamp suffix, empty if there isn't one
+//     {created:FORMAT}  file creation time, empty if unavailable
+//     {hash:N}          first N (1-8, default 8) hex digits of the costume hash
+//     {n:WIDTH}         counter starting at 1, zero-padded if WIDTH starts with 0 (e.g. "03")
+//
+// Dates use the display time zone and default to "%Y%m%d". The rendered save name never contains
+// characters that aren't allowed in Windows file names; they're replaced with '_'.
+
+use super::{hash::CostumeHash, TimestampDisplay};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d";
+const HASH_HEX_DIGITS: usize = 8;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+enum Segment {
+    Literal(String),
+    Account,
+    Character,
+    Keyword,
+    SaveName,
+    Date(String),
+    Created(String),
+    Hash(usize),
+    Counter { width: usize, zero_pad: bool },
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RenameTemplate(Vec<Segment>);
+
+/// Everything a template can refer to for a single save.
+pub struct TemplateInput<'a> {
+    pub account_name: &'a str,
+    pub character_name: &'a str,
+    pub first_keyword: Option<&'a str>,
+    pub save_name: &'a str,
+    pub j2000_timestamp: Option<i64>,
+    pub created_j2000_timestamp: Option<i64>,
+    pub hash: CostumeHash,
+    pub counter: usize,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum TemplateError {
+    /// Byte offset of the '{'.
+    UnclosedPlaceholder(usize),
+    /// Byte offset of the '}'.
+    UnmatchedClose(usize),
+    UnknownPlaceholder(String),
+    InvalidArgument { placeholder: String, argument: String },
+}
+
+impl std::error::Error for TemplateError {}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnclosedPlaceholder(offset) => write!(f, "'{{' at {offset} is never closed (use \"{{{{\" for a literal brace)"),
+            Self::UnmatchedClose(offset) => write!(f, "'}}' at {offset} has no matching '{{' (use \"}}}}\" for a literal brace)"),
+            Self::UnknownPlaceholder(name) => write!(f, "unknown placeholder {{{name}}}"),
+            Self::InvalidArgument { placeholder, argument } => write!(f, "invalid argument {argument:?} for {{{placeholder}}}"),
+        }
+    }
+}
+
+impl RenameTemplate {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.char_indices().peekable();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '{' if chars.next_if(|&(_, c)| c == '{').is_some() => literal.push('{'),
+                '}' if chars.next_if(|&(_, c)| c == '}').is_some() => literal.push('}'),
+                '}' => return Err(TemplateError::UnmatchedClose(offset)),
+                '{' => {
+                    let rest = &template[offset + 1..];
+                    let end = rest.find('}').ok_or(TemplateError::UnclosedPlaceholder(offset))?;
+                    let placeholder = &rest[..end];
+                    // Skip past the placeholder and its closing brace.
+                    while chars.next_if(|&(next_offset, _)| next_offset <= offset + 1 + end).is_some() {}
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Self::parse_placeholder(placeholder)?);
+                },
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self(segments))
+    }
+
+    fn parse_placeholder(placeholder: &str) -> Result<Segment, TemplateError> {
+        let (name, argument) = match placeholder.split_once(':') {
+            Some((name, argument)) => (name.trim(), Some(argument)),
+            None => (placeholder.trim(), None),
+        };
+        let invalid_argument = || TemplateError::InvalidArgument { placeholder: name.to_owned(), argument: argument.unwrap_or_default().to_owned() };
+        let no_argument = |segment| if argument.is_none() { Ok(segment) } else { Err(invalid_argument()) };
+        let date_format = || match argument {
+            None => Ok(DEFAULT_DATE_FORMAT.to_owned()),
+            Some(format) if !format.is_empty() && TimestampDisplay::is_valid_format(format) => Ok(format.to_owned()),
+            Some(_) => Err(invalid_argument()),
+        };
+
+        match name {
+            "account" => no_argument(Segment::Account),
+            "character" => no_argument(Segment::Character),
+            "keyword" => no_argument(Segment::Keyword),
+            "name" => no_argument(Segment::SaveName),
+            "date" => Ok(Segment::Date(date_format()?)),
+            "created" => Ok(Segment::Created(date_format()?)),
+            "hash" => match argument.map(str::parse::<usize>) {
+                None => Ok(Segment::Hash(HASH_HEX_DIGITS)),
+                Some(Ok(digits @ 1..=HASH_HEX_DIGITS)) => Ok(Segment::Hash(digits)),
+                Some(_) => Err(invalid_argument()),
+            },
+            "n" => match argument {
+                None => Ok(Segment::Counter { width: 0, zero_pad: false }),
+                Some(width) => {
+                    let zero_pad = width.len() > 1 && width.starts_with('0');
+                    let width = width.parse().ok().filter(|&width| width <= 16).ok_or_else(invalid_argument)?;
+                    Ok(Segment::Counter { width, zero_pad })
+                },
+            },
+            _ => Err(TemplateError::UnknownPlaceholder(name.to_owned())),
+        }
+    }
+
+    pub fn render(&self, input: &TemplateInput, timestamp_display: &TimestampDisplay) -> String {
+        let format_date = |j2000_timestamp: Option<i64>, format: &str| {
+            j2000_timestamp.and_then(|j2000_timestamp| timestamp_display.format_j2000_as(j2000_timestamp, format)).unwrap_or_default()
+        };
+
+        let mut rendered = String::new();
+        for segment in self.0.iter() {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Account => rendered.push_str(input.account_name),
+                Segment::Character => rendered.push_str(input.character_name),
+                Segment::Keyword => rendered.push_str(input.first_keyword.unwrap_or_default()),
+                Segment::SaveName => rendered.push_str(input.save_name),
+                Segment::Date(format) => rendered.push_str(&format_date(input.j2000_timestamp, format)),
+                Segment::Created(format) => rendered.push_str(&format_date(input.created_j2000_timestamp, format)),
+                Segment::Hash(digits) => {
+                    let CostumeHash { upper, lower } = input.hash;
+                    rendered.push_str(&format!("{upper:04X}{lower:04X}")[..*digits]);
+                },
+                Segment::Counter { width, zero_pad: true } => rendered.push_str(&format!("{:0width$}", input.counter)),
+                Segment::Counter { width, zero_pad: false } => rendered.push_str(&format!("{:width$}", input.counter)),
+            }
+        }
+
+        rendered.chars()
+            .map(|c| if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') { '_' } else { c })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_placeholders() {
+        let template = RenameTemplate::parse("{character}_{date:%Y%m%d}_{n:03}{{{hash:4}}}").unwrap();
+        let input = TemplateInput {
+            account_name: "@Account",
+            character_name: "Hero/Villain",
+            first_keyword: None,
+            save_name: "Old",
+            j2000_timestamp: Some(0),
+            created_j2000_timestamp: None,
+            hash: CostumeHash { upper: 0xABCD, lower: 0x0123 },
+            counter: 7,
+        };
+        assert_eq!(template.render(&input, &TimestampDisplay::default()), "Hero_Villain_20000101_007{ABCD}");
+
+        let template = RenameTemplate::parse("{keyword}{created}-{n}").unwrap();
+        assert_eq!(template.render(&input, &TimestampDisplay::default()), "-7");
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert_eq!(RenameTemplate::parse("{character"), Err(TemplateError::UnclosedPlaceholder(0)));
+        assert_eq!(RenameTemplate::parse("a}b"), Err(TemplateError::UnmatchedClose(1)));
+        assert_eq!(RenameTemplate::parse("{costume}"), Err(TemplateError::UnknownPlaceholder("costume".to_owned())));
+        assert!(matches!(RenameTemplate::parse("{hash:9}"), Err(TemplateError::InvalidArgument { .. })));
+        assert!(matches!(RenameTemplate::parse("{name:x}"), Err(TemplateError::InvalidArgument { .. })));
+        assert!(matches!(RenameTemplate::parse("{date:%Q}"), Err(TemplateError::InvalidArgument { .. })));
+    }
+}
@@ -0,0 +1,212 @@
+// Finding saves of the same costume. Used by the GUI's duplicate review.
+//
+// There are two notions of duplicate:
+// - Same spec: the costume itself is identical, whatever the names and preview image. Saves are
+//   bucketed by costume hash first, but the hash collides easily (see costume::hash) so specs in a
+//   bucket are always compared in full.
+// - Similar preview image: a 64-bit difference hash ("dHash") of the preview, compared by Hamming
+//   distance. Catches the same costume saved with small spec tweaks, e.g. a different stance. The
+//   hashes are computed by the decode threads since they already decode the previews.
+
+use crate::costume;
+
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Where archived duplicates are moved, relative to the save's directory. The scanner doesn't look
+/// in subdirectories so archived saves drop out of the list.
+pub const ARCHIVE_DIR_NAME: &str = "Duplicates";
+/// Images within this Hamming distance are considered the same by default. Out of 64 bits.
+pub const DEFAULT_MAX_IMAGE_DISTANCE: u32 = 6;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct ImageHash(pub u64);
+
+impl ImageHash {
+    /// `rgb` is packed 8-bit RGB, row-major.
+    pub fn of_rgb(rgb: &[u8], width: usize, height: usize) -> Self {
+        const HASH_WIDTH: usize = 9;
+        const HASH_HEIGHT: usize = 8;
+        debug_assert_eq!(rgb.len(), width * height * 3);
+        if width == 0 || height == 0 {
+            return Self(0);
+        }
+
+        // Box-filter down to a 9x8 grayscale thumbnail. Every source pixel lands in exactly one cell
+        // so nothing is skipped however big the image is.
+        let mut sums = [[0u64; HASH_WIDTH]; HASH_HEIGHT];
+        let mut counts = [[0u64; HASH_WIDTH]; HASH_HEIGHT];
+        for (y, row) in rgb.chunks_exact(width * 3).enumerate() {
+            let cell_y = y * HASH_HEIGHT / height;
+            for (x, pixel) in row.chunks_exact(3).enumerate() {
+                let cell_x = x * HASH_WIDTH / width;
+                // Rec. 601 luma, scaled by 1000.
+                sums[cell_y][cell_x] += 299 * pixel[0] as u64 + 587 * pixel[1] as u64 + 114 * pixel[2] as u64;
+                counts[cell_y][cell_x] += 1;
+            }
+        }
+
+        // One bit per horizontally adjacent pair: is the left cell brighter?
+        let mut hash = 0u64;
+        for y in 0..HASH_HEIGHT {
+            let luma = |x: usize| sums[y][x].checked_div(counts[y][x]).unwrap_or(0);
+            for x in 0..HASH_WIDTH - 1 {
+                hash = (hash << 1) | (luma(x) > luma(x + 1)) as u64;
+            }
+        }
+
+        Self(hash)
+    }
+
+    pub fn distance(self, other: Self) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+}
+
+/// Group saves with exactly the same costume spec. Only groups of two or more are returned. Groups
+/// and the saves in them are sorted by path.
+pub fn group_by_spec<'a>(saves: impl IntoIterator<Item = (&'a Path, &'a costume::CostumeSave)>) -> Vec<Vec<PathBuf>> {
+    let mut buckets: HashMap<costume::hash::CostumeHash, Vec<(&Path, String)>> = HashMap::new();
+    for (path, save) in saves {
+        // NOTE Parsed saves always have valid metadata.
+        let spec = save.get_metadata().unwrap().spec().to_owned();
+        // The stored hash could be stale or garbage, so always hash the spec ourselves.
+        buckets.entry(costume::hash::CostumeHash::of(&spec)).or_default().push((path, spec));
+    }
+
+    let mut groups = Vec::new();
+    for bucket in buckets.into_values().filter(|bucket| bucket.len() > 1) {
+        let mut by_spec: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for (path, spec) in bucket {
+            by_spec.entry(spec).or_default().push(path.to_path_buf());
+        }
+        groups.extend(by_spec.into_values().filter(|group| group.len() > 1));
+    }
+
+    sort_groups(groups)
+}
+
+/// Group saves whose preview images are within `max_distance` of each other, transitively. Only
+/// groups of two or more are returned. Groups and the saves in them are sorted by path.
+pub fn group_by_image<'a>(hashes: impl IntoIterator<Item = (&'a Path, ImageHash)>, max_distance: u32) -> Vec<Vec<PathBuf>> {
+    let hashes: Vec<(&Path, ImageHash)> = hashes.into_iter().collect();
+
+    // Union-find over every pair. Quadratic, but a popcount per pair is cheap even for thousands of
+    // saves.
+    fn root(parents: &mut [usize], mut idx: usize) -> usize {
+        while parents[idx] != idx {
+            parents[idx] = parents[parents[idx]];
+            idx = parents[idx];
+        }
+        idx
+    }
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+    for a in 0..hashes.len() {
+        for b in a + 1..hashes.len() {
+            if hashes[a].1.distance(hashes[b].1) <= max_distance {
+                let (root_a, root_b) = (root(&mut parents, a), root(&mut parents, b));
+                parents[root_b] = root_a;
+            }
+        }
+    }
+
+    let mut by_root: HashMap<usize, Vec<PathBuf>> = HashMap::new();
+    for (idx, (path, _)) in hashes.iter().enumerate() {
+        by_root.entry(root(&mut parents, idx)).or_default().push(path.to_path_buf());
+    }
+
+    sort_groups(by_root.into_values().filter(|group| group.len() > 1).collect())
+}
+
+fn sort_groups(mut groups: Vec<Vec<PathBuf>>) -> Vec<Vec<PathBuf>> {
+    for group in groups.iter_mut() {
+        group.sort();
+    }
+    groups.sort();
+    groups
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Keep {
+    Newest,
+    Oldest,
+}
+
+/// When a save was made: the timestamp in its file name, or failing that the file's modified time.
+pub fn save_j2000_timestamp(path: &Path, file_name: &costume::CostumeFileName) -> Option<i64> {
+    file_name.j2000_timestamp.or_else(|| {
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok()?;
+        costume::datetime_to_j2000(&chrono::DateTime::<chrono::Utc>::from(modified))
+    })
+}
+
+/// Index of the save to keep given each save's `save_j2000_timestamp`. Saves of unknown age are only
+/// kept if nothing else is known. Ties go to the first.
+pub fn pick_keeper(timestamps: &[Option<i64>], keep: Keep) -> usize {
+    let known = timestamps.iter().enumerate().filter_map(|(idx, timestamp)| Some((idx, (*timestamp)?)));
+    let picked = match keep {
+        Keep::Newest => known.rev().max_by_key(|(_, timestamp)| *timestamp),
+        Keep::Oldest => known.min_by_key(|(_, timestamp)| *timestamp),
+    };
+    picked.map_or(0, |(idx, _)| idx)
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Removal {
+    /// Move into ARCHIVE_DIR_NAME next to the save.
+    Archive,
+    /// Move to the trash, the same as deleting any other save.
+    Trash,
+}
+
+/// Move a duplicate into ARCHIVE_DIR_NAME next to it. Returns where it was archived to.
+pub fn archive_duplicate(path: &Path) -> io::Result<PathBuf> {
+    let archive_dir = path.with_file_name(ARCHIVE_DIR_NAME);
+    fs::create_dir_all(&archive_dir)?;
+    let archive_path = archive_dir.join(path.file_name().unwrap());
+    // NOTE fs::rename silently replaces the destination on some platforms.
+    if archive_path.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{archive_path:?} already exists")));
+    }
+    fs::rename(path, &archive_path)?;
+    Ok(archive_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_hash_tolerates_small_changes() {
+        let gradient = |width: usize, height: usize, noise: u8| -> Vec<u8> {
+            (0..width * height).flat_map(|idx| {
+                let (x, y) = (idx % width, idx / width);
+                let value = ((x * 255 / width) as u8 ^ ((y * 8 / height) as u8 * 32)).saturating_add(noise * (idx % 3) as u8);
+                [value, value, value]
+            }).collect()
+        };
+        let original = ImageHash::of_rgb(&gradient(64, 48, 0), 64, 48);
+        let noisy = ImageHash::of_rgb(&gradient(64, 48, 2), 64, 48);
+        let resized = ImageHash::of_rgb(&gradient(90, 70, 0), 90, 70);
+        let flat = ImageHash::of_rgb(&[128; 32 * 32 * 3], 32, 32);
+
+        assert!(original.distance(noisy) <= DEFAULT_MAX_IMAGE_DISTANCE);
+        assert!(original.distance(resized) <= DEFAULT_MAX_IMAGE_DISTANCE);
+        assert!(original.distance(flat) > DEFAULT_MAX_IMAGE_DISTANCE);
+
+        let (a, b, c, d) = (Path::new("a"), Path::new("b"), Path::new("c"), Path::new("d"));
+        let groups = group_by_image([(c, flat), (a, original), (d, resized), (b, noisy)], DEFAULT_MAX_IMAGE_DISTANCE);
+        assert_eq!(groups, [[a, b, d]]);
+    }
+
+    #[test]
+    fn keeper_ignores_unknown_ages() {
+        assert_eq!(pick_keeper(&[Some(5), None, Some(9), Some(9)], Keep::Newest), 2);
+        assert_eq!(pick_keeper(&[None, Some(5), Some(1)], Keep::Oldest), 2);
+        assert_eq!(pick_keeper(&[None, None], Keep::Oldest), 0);
+    }
+}
@@ -0,0 +1,292 @@
+// Persistent cache of what the scanner learns about each save, so that startup doesn't have to read
+// and parse every file in the costume directory. It lives next to the executable and is rewritten
+// from the loaded saves whenever the GUI exits.
+//
+// Entries are keyed by path and only used while the file's size and modified time still match.
+// Each holds the save minus its image (see CostumeSave::metadata_only_bytes), the hash of the whole
+// file, the spec hash, and once the preview has been decoded, its perceptual hash and a small JPEG
+// thumbnail. The format is private and little-endian:
+//
+//     "CCMINDEX", u32 version, u32 number of entries, then for each entry:
+//     u32 length + UTF-8 path, u64 size, u64 modified seconds + u32 modified nanoseconds
+//     (since the Unix epoch), u64 content hash, u32 length + metadata, u32 length + spec hash,
+//     u8 has image hash + u64 image hash, u32 length + thumbnail JPEG (0 if none)
+//
+// Anything unreadable, including an index written by another version, is thrown away since it can
+// always be rebuilt.
+
+use crate::{costume, dedupe};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs,
+    io::{self, Cursor, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+pub const FILE_NAME: &str = "ccm_index.bin";
+const MAGIC: &[u8; 8] = b"CCMINDEX";
+const VERSION: u32 = 2;
+/// Longest side of a thumbnail, in pixels.
+const THUMBNAIL_SIZE: u32 = 96;
+
+/// What a file looked like when it was indexed.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct FileStamp {
+    size: u64,
+    /// Since the Unix epoch.
+    modified: Duration,
+}
+
+impl FileStamp {
+    /// None if the platform doesn't report modified times or it's before the Unix epoch, in which
+    /// case the file is never indexed.
+    pub fn of(metadata: &fs::Metadata) -> Option<Self> {
+        let modified = metadata.modified().ok()?.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        Some(Self { size: metadata.len(), modified })
+    }
+}
+
+/// FNV-1a hash of a whole file. Tells whether a file whose modified time changed was actually
+/// rewritten with different contents.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct ContentHash(pub u64);
+
+impl ContentHash {
+    pub fn of(bytes: &[u8]) -> Self {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+        Self(bytes.iter().fold(OFFSET_BASIS, |hash, byte| (hash ^ *byte as u64).wrapping_mul(PRIME)))
+    }
+}
+
+pub struct IndexedSave {
+    pub stamp: FileStamp,
+    pub content_hash: ContentHash,
+    /// See CostumeSave::metadata_only_bytes.
+    pub metadata: Box<[u8]>,
+    pub spec_hash: costume::hash::CostumeHash,
+    pub image_hash: Option<dedupe::ImageHash>,
+    /// JPEG, see `thumbnail_jpeg`.
+    pub thumbnail: Option<Arc<[u8]>>,
+}
+
+#[derive(Debug)]
+pub enum IndexError {
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl std::error::Error for IndexError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MetadataIndex(HashMap<PathBuf, IndexedSave>);
+
+impl MetadataIndex {
+    pub fn default_path() -> PathBuf {
+        std::env::current_exe()
+            .expect("failed to get dir of executable")
+            .with_file_name(FILE_NAME)
+    }
+
+    /// A missing file is an empty index.
+    pub fn load(path: &Path) -> Result<Self, IndexError> {
+        match fs::read(path) {
+            Ok(bytes) => Self::parse(&bytes),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(IndexError::Io(err)),
+        }
+    }
+
+    /// Writes to a temp file first so that a failed save never leaves a truncated index behind.
+    pub fn save(&self, path: &Path) -> Result<(), IndexError> {
+        let mut serialized = Vec::new();
+        self.write(&mut serialized).map_err(IndexError::Io)?;
+        let temp_path = path.with_extension("bin.tmp");
+        fs::write(&temp_path, serialized)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(IndexError::Io)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, IndexError> {
+        let mut reader = Cursor::new(bytes);
+        let mut magic = [0u8; MAGIC.len()];
+        if reader.read_exact(&mut magic).is_err() || magic != *MAGIC {
+            return Err(IndexError::Invalid("not a metadata index".to_owned()));
+        }
+        let version = reader.read_u32::<LittleEndian>().map_err(|_| IndexError::Invalid("truncated".to_owned()))?;
+        if version != VERSION {
+            return Err(IndexError::Invalid(format!("unsupported version {version}")));
+        }
+
+        Self::read_entries(&mut reader).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => IndexError::Invalid("truncated".to_owned()),
+            _ => IndexError::Invalid(err.to_string()),
+        })
+    }
+
+    fn read_entries(reader: &mut impl Read) -> io::Result<Self> {
+        fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+            let length = reader.read_u32::<LittleEndian>()?;
+            let mut bytes = Vec::new();
+            reader.take(length as u64).read_to_end(&mut bytes)?;
+            if bytes.len() != length as usize {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(bytes)
+        }
+        fn read_string(reader: &mut impl Read) -> io::Result<String> {
+            String::from_utf8(read_bytes(reader)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+
+        let num_entries = reader.read_u32::<LittleEndian>()?;
+        let mut entries = HashMap::new();
+        for _ in 0..num_entries {
+            let path = PathBuf::from(read_string(reader)?);
+            let size = reader.read_u64::<LittleEndian>()?;
+            let (modified_secs, modified_nanos) = (reader.read_u64::<LittleEndian>()?, reader.read_u32::<LittleEndian>()?);
+            if modified_nanos >= 1_000_000_000 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: invalid modified time")));
+            }
+            let modified = Duration::new(modified_secs, modified_nanos);
+            let content_hash = ContentHash(reader.read_u64::<LittleEndian>()?);
+            let metadata = read_bytes(reader)?.into_boxed_slice();
+            let spec_hash = costume::hash::CostumeHash::parse(&read_string(reader)?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {err}")))?;
+            let has_image_hash = reader.read_u8()? != 0;
+            let image_hash = reader.read_u64::<LittleEndian>()?;
+            let thumbnail = read_bytes(reader)?;
+
+            entries.insert(path, IndexedSave {
+                stamp: FileStamp { size, modified },
+                content_hash,
+                metadata,
+                spec_hash,
+                image_hash: has_image_hash.then_some(dedupe::ImageHash(image_hash)),
+                thumbnail: (!thumbnail.is_empty()).then(|| thumbnail.into()),
+            });
+        }
+
+        Ok(Self(entries))
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+            writer.write_u32::<LittleEndian>(bytes.len() as u32)?;
+            writer.write_all(bytes)
+        }
+
+        writer.write_all(MAGIC)?;
+        writer.write_u32::<LittleEndian>(VERSION)?;
+        writer.write_u32::<LittleEndian>(self.0.len() as u32)?;
+        for (path, indexed) in self.0.iter() {
+            // NOTE Only saves with valid file names are indexed, and those are always UTF-8.
+            write_bytes(writer, path.to_str().unwrap().as_bytes())?;
+            writer.write_u64::<LittleEndian>(indexed.stamp.size)?;
+            writer.write_u64::<LittleEndian>(indexed.stamp.modified.as_secs())?;
+            writer.write_u32::<LittleEndian>(indexed.stamp.modified.subsec_nanos())?;
+            writer.write_u64::<LittleEndian>(indexed.content_hash.0)?;
+            write_bytes(writer, &indexed.metadata)?;
+            write_bytes(writer, indexed.spec_hash.to_string().as_bytes())?;
+            writer.write_u8(indexed.image_hash.is_some() as u8)?;
+            writer.write_u64::<LittleEndian>(indexed.image_hash.map_or(0, |image_hash| image_hash.0))?;
+            write_bytes(writer, indexed.thumbnail.as_deref().unwrap_or_default())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn insert(&mut self, path: PathBuf, indexed: IndexedSave) {
+        self.0.insert(path, indexed);
+    }
+
+    /// Take out what was indexed for `path`, as long as the file hasn't changed since.
+    pub fn take(&mut self, path: &Path, stamp: FileStamp) -> Option<IndexedSave> {
+        self.0.remove(path).filter(|indexed| indexed.stamp == stamp)
+    }
+}
+
+/// Shrink a decoded preview for the index. `rgb` is packed 8-bit RGB, row-major.
+pub fn thumbnail_jpeg(rgb: &[u8], width: usize, height: usize) -> Option<Arc<[u8]>> {
+    let image = image::RgbImage::from_raw(width as u32, height as u32, rgb.to_vec())?;
+    let scale = THUMBNAIL_SIZE as f32 / width.max(height) as f32;
+    let thumbnail = image::imageops::thumbnail(
+        &image,
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    );
+
+    let mut thumbnail_jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut thumbnail_jpeg, 80).encode_image(&thumbnail).ok()?;
+    Some(thumbnail_jpeg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_round_trips_metadata_only_saves() {
+        let spec = "CostumeV2\n{\n\tSkeleton Female\n}\n";
+        let save = crate::test_util::save("@Account", "A", spec);
+        let metadata_only = costume::CostumeSave::parse(&save.metadata_only_bytes()).unwrap();
+        assert!(!save.is_metadata_only());
+        assert!(metadata_only.is_metadata_only());
+        assert_eq!(metadata_only.get_metadata().unwrap().spec(), save.get_metadata().unwrap().spec());
+
+        let stamp = FileStamp { size: 1234, modified: Duration::new(1_700_000_000, 5) };
+        let mut index = MetadataIndex::default();
+        index.insert(PathBuf::from("saves/Costume_A.jpg"), IndexedSave {
+            stamp,
+            content_hash: ContentHash::of(&save.0.serialize()),
+            metadata: save.metadata_only_bytes(),
+            spec_hash: costume::hash::CostumeHash::of(spec),
+            image_hash: Some(dedupe::ImageHash(0xdead_beef)),
+            thumbnail: thumbnail_jpeg(&[200; 40 * 30 * 3], 40, 30),
+        });
+        let mut serialized = Vec::new();
+        index.write(&mut serialized).unwrap();
+
+        let mut index = MetadataIndex::parse(&serialized).unwrap();
+        let path = Path::new("saves/Costume_A.jpg");
+        assert!(index.take(path, FileStamp { size: 1235, ..stamp }).is_none());
+        assert!(index.take(path, stamp).is_none());
+
+        let mut index = MetadataIndex::parse(&serialized).unwrap();
+        let indexed = index.take(path, stamp).unwrap();
+        assert_eq!(*indexed.metadata, *save.metadata_only_bytes());
+        assert_eq!(indexed.content_hash, ContentHash::of(&save.0.serialize()));
+        assert_eq!(indexed.spec_hash, costume::hash::CostumeHash::of(spec));
+        assert_eq!(indexed.image_hash, Some(dedupe::ImageHash(0xdead_beef)));
+        assert!(indexed.thumbnail.is_some());
+
+        assert!(MetadataIndex::parse(&serialized[..serialized.len() - 1]).is_err());
+        serialized[MAGIC.len()] = 1;
+        assert!(MetadataIndex::parse(&serialized).is_err());
+    }
+}
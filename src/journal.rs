@@ -0,0 +1,595 @@
+// Undo history for everything the GUI does to the costume directory.
+//
+// Each user action (saving a costume, a batch rename, deleting a selection, ...) becomes one entry
+// listing the file changes it made, in order. Undoing an entry reverses its changes in reverse
+// order, redoing applies them again. Recording a new entry throws away whatever was undone.
+//
+// The contents of files that were written or deleted are kept as blobs named by their content hash,
+// so identical contents are only stored once. Renames only need the paths. Before anything is
+// reversed, the files have to be exactly as the entry left them. Otherwise the step is refused
+// instead of clobbering changes made since. If a step fails partway through, whatever was done is
+// rolled back.
+//
+// It lives in a directory next to the executable, capped at MAX_ENTRIES entries and MAX_BLOB_BYTES
+// of blobs (the oldest entries go first):
+//
+//     ccm_journal/journal.json
+//     ccm_journal/blobs/0123456789abcdef.jpg
+//
+// {
+//   "format": "ccm-journal",
+//   "version": 1,
+//   "position": 1,
+//   "entries": [
+//     {
+//       "description": "Renamed 1 save",
+//       "time": 1700000000,
+//       "changes": [
+//         { "kind": "renamed", "from": "C:\\...\\Costume_A.jpg", "to": "C:\\...\\Costume_B.jpg" },
+//         { "kind": "written", "old_path": "...", "old_content": "0123456789abcdef", "new_path": "...", "new_content": "..." },
+//         { "kind": "created", "path": "...", "content": "..." },
+//         { "kind": "deleted", "path": "...", "content": "..." }
+//       ]
+//     }
+//   ]
+// }
+//
+// `position` is how many entries are currently applied. The ones after it can be redone. Times are
+// seconds since the Unix epoch.
+
+use crate::{index::ContentHash, json::{self, JsonValue}, transaction::{SaveTransaction, StagedSave}};
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+pub const DIR_NAME: &str = "ccm_journal";
+const FILE_NAME: &str = "journal.json";
+const BLOB_DIR_NAME: &str = "blobs";
+const FORMAT_NAME: &str = "ccm-journal";
+const FORMAT_VERSION: i64 = 1;
+const MAX_ENTRIES: usize = 100;
+const MAX_BLOB_BYTES: u64 = 512 * 1024 * 1024;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum FileChange {
+    Created { path: PathBuf, content: ContentHash },
+    /// Overwritten, and moved if the paths differ.
+    Written { old_path: PathBuf, old_content: ContentHash, new_path: PathBuf, new_content: ContentHash },
+    Renamed { from: PathBuf, to: PathBuf },
+    Deleted { path: PathBuf, content: ContentHash },
+}
+
+impl FileChange {
+    fn contents(&self) -> impl Iterator<Item = ContentHash> {
+        let (first, second) = match self {
+            Self::Created { content, .. } | Self::Deleted { content, .. } => (Some(*content), None),
+            Self::Written { old_content, new_content, .. } => (Some(*old_content), Some(*new_content)),
+            Self::Renamed { .. } => (None, None),
+        };
+        first.into_iter().chain(second)
+    }
+
+    /// Where a save moved from and to when the change is applied, or undone if `!forward`.
+    fn moved(&self, forward: bool) -> Option<(&Path, &Path)> {
+        let (from, to) = match self {
+            Self::Written { old_path, new_path, .. } => (old_path.as_path(), new_path.as_path()),
+            Self::Renamed { from, to } => (from.as_path(), to.as_path()),
+            Self::Created { .. } | Self::Deleted { .. } => return None,
+        };
+        Some(if forward { (from, to) } else { (to, from) })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JournalEntry {
+    pub description: String,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    pub changes: Vec<FileChange>,
+}
+
+impl JournalEntry {
+    /// Every path the entry touches, for registering it as one of the app's own operations.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for change in self.changes.iter() {
+            match change {
+                FileChange::Created { path, .. } | FileChange::Deleted { path, .. } => paths.push(path.clone()),
+                FileChange::Written { old_path, new_path, .. } => paths.extend([old_path.clone(), new_path.clone()]),
+                FileChange::Renamed { from, to } => paths.extend([from.clone(), to.clone()]),
+            }
+        }
+        paths
+    }
+
+    /// Where each save ends up when the entry is applied, or undone if `!forward`, following it
+    /// through any temporary names. Saves that end up back where they started are left out.
+    pub fn net_moves(&self, forward: bool) -> Vec<(PathBuf, PathBuf)> {
+        let changes: Vec<&FileChange> = match forward {
+            true => self.changes.iter().collect(),
+            false => self.changes.iter().rev().collect(),
+        };
+        // (where the save started, where it is now)
+        let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for (from, to) in changes.into_iter().filter_map(|change| change.moved(forward)) {
+            match moves.iter_mut().find(|(_, current)| current == from) {
+                Some((_, current)) => *current = to.to_path_buf(),
+                None => moves.push((from.to_path_buf(), to.to_path_buf())),
+            }
+        }
+        moves.retain(|(start, current)| start != current);
+        moves
+    }
+}
+
+/// The changes made by one action, with the file contents needed to reverse them. Hand it to
+/// `Journal::record` once the action is done.
+#[derive(Default)]
+pub struct Recording {
+    changes: Vec<FileChange>,
+    blobs: HashMap<ContentHash, Vec<u8>>,
+}
+
+impl Recording {
+    fn add_blob(&mut self, bytes: Vec<u8>) -> ContentHash {
+        let content = ContentHash::of(&bytes);
+        self.blobs.entry(content).or_insert(bytes);
+        content
+    }
+
+    pub fn created(&mut self, path: PathBuf, bytes: Vec<u8>) {
+        let content = self.add_blob(bytes);
+        self.changes.push(FileChange::Created { path, content });
+    }
+
+    pub fn written(&mut self, old_path: PathBuf, old_bytes: Vec<u8>, new_path: PathBuf, new_bytes: Vec<u8>) {
+        let old_content = self.add_blob(old_bytes);
+        let new_content = self.add_blob(new_bytes);
+        self.changes.push(FileChange::Written { old_path, old_content, new_path, new_content });
+    }
+
+    pub fn renamed(&mut self, from: PathBuf, to: PathBuf) {
+        self.changes.push(FileChange::Renamed { from, to });
+    }
+
+    pub fn deleted(&mut self, path: PathBuf, bytes: Vec<u8>) {
+        let content = self.add_blob(bytes);
+        self.changes.push(FileChange::Deleted { path, content });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io { which: PathBuf, source: io::Error },
+    Json(json::JsonParseError),
+    /// The file is valid JSON but not a journal.
+    Invalid(String),
+    /// A file isn't how the entry left it, e.g. because it was changed outside of the app since.
+    Conflict { which: PathBuf, message: String },
+    /// A step failed and so did rolling back the part of it that was already done.
+    RollbackFailed { cause: Box<JournalError>, rollback: Box<JournalError> },
+}
+
+impl std::error::Error for JournalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Json(err) => Some(err),
+            Self::RollbackFailed { cause, .. } => Some(cause),
+            Self::Invalid(_) | Self::Conflict { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { which, source } => write!(f, "{which:?}: {source}"),
+            Self::Json(err) => write!(f, "invalid JSON at {err}"),
+            Self::Invalid(message) => write!(f, "{message}"),
+            Self::Conflict { which, message } => write!(f, "{which:?} {message}"),
+            Self::RollbackFailed { cause, rollback } => write!(f, "{cause}, and rolling back what was already done failed too: {rollback}"),
+        }
+    }
+}
+
+fn io_error(which: &Path) -> impl FnOnce(io::Error) -> JournalError + '_ {
+    move |source| JournalError::Io { which: which.to_path_buf(), source }
+}
+
+pub struct Journal {
+    /// None if the history is only kept in memory, in which case so are the blobs.
+    dir: Option<PathBuf>,
+    memory_blobs: HashMap<ContentHash, Vec<u8>>,
+    entries: Vec<JournalEntry>,
+    /// How many of the entries are applied.
+    position: usize,
+}
+
+impl Journal {
+    /// The journal next to the executable.
+    pub fn default_dir() -> PathBuf {
+        std::env::current_exe()
+            .expect("failed to get dir of executable")
+            .with_file_name(DIR_NAME)
+    }
+
+    /// A history that's lost on exit, e.g. when the journal on disk can't be read.
+    pub fn in_memory() -> Self {
+        Self { dir: None, memory_blobs: HashMap::new(), entries: Vec::new(), position: 0 }
+    }
+
+    /// A missing directory is an empty journal.
+    pub fn load(dir: &Path) -> Result<Self, JournalError> {
+        let path = dir.join(FILE_NAME);
+        let mut journal = match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::in_memory(),
+            Err(source) => return Err(JournalError::Io { which: path, source }),
+        };
+        journal.dir = Some(dir.to_path_buf());
+        Ok(journal)
+    }
+
+    fn parse(text: &str) -> Result<Self, JournalError> {
+        let document = JsonValue::parse(text).map_err(JournalError::Json)?;
+        if document.get("format").and_then(JsonValue::as_str) != Some(FORMAT_NAME) {
+            return Err(JournalError::Invalid(format!("not a journal (expected \"format\": {FORMAT_NAME:?})")));
+        }
+        match document.get("version").and_then(JsonValue::as_i64) {
+            Some(FORMAT_VERSION) => {},
+            Some(version) => return Err(JournalError::Invalid(format!("unsupported journal version {version}"))),
+            None => return Err(JournalError::Invalid("missing \"version\"".to_owned())),
+        }
+
+        let mut entries = Vec::new();
+        for (index, entry) in document.get("entries").and_then(JsonValue::as_array).unwrap_or_default().iter().enumerate() {
+            let invalid = |message: String| JournalError::Invalid(format!("entry {}: {message}", index + 1));
+            let string = |value: &JsonValue, key: &str| value.get(key).and_then(JsonValue::as_str).map(str::to_owned)
+                .ok_or_else(|| invalid(format!("missing {key:?}")));
+            let path = |value: &JsonValue, key: &str| string(value, key).map(PathBuf::from);
+            let content = |value: &JsonValue, key: &str| string(value, key).and_then(|hex| {
+                u64::from_str_radix(&hex, 16).map(ContentHash).map_err(|_| invalid(format!("invalid {key:?} {hex:?}")))
+            });
+
+            let mut changes = Vec::new();
+            for change in entry.get("changes").and_then(JsonValue::as_array).unwrap_or_default() {
+                changes.push(match string(change, "kind")?.as_str() {
+                    "created" => FileChange::Created { path: path(change, "path")?, content: content(change, "content")? },
+                    "written" => FileChange::Written {
+                        old_path: path(change, "old_path")?,
+                        old_content: content(change, "old_content")?,
+                        new_path: path(change, "new_path")?,
+                        new_content: content(change, "new_content")?,
+                    },
+                    "renamed" => FileChange::Renamed { from: path(change, "from")?, to: path(change, "to")? },
+                    "deleted" => FileChange::Deleted { path: path(change, "path")?, content: content(change, "content")? },
+                    kind => return Err(invalid(format!("unknown change kind {kind:?}"))),
+                });
+            }
+            entries.push(JournalEntry {
+                description: string(entry, "description")?,
+                time: entry.get("time").and_then(JsonValue::as_i64).ok_or_else(|| invalid("missing \"time\"".to_owned()))?,
+                changes,
+            });
+        }
+
+        let position = document.get("position").and_then(JsonValue::as_i64)
+            .and_then(|position| usize::try_from(position).ok())
+            .filter(|position| *position <= entries.len())
+            .ok_or_else(|| JournalError::Invalid("missing or invalid \"position\"".to_owned()))?;
+
+        Ok(Self { dir: None, memory_blobs: HashMap::new(), entries, position })
+    }
+
+    fn serialize(&self) -> String {
+        let path = |path: &Path| json::string(&path.to_string_lossy());
+        let content = |content: &ContentHash| json::string(&format!("{:016x}", content.0));
+        let entries: Vec<String> = self.entries.iter().map(|entry| {
+            let changes: Vec<String> = entry.changes.iter().map(|change| match change {
+                FileChange::Created { path: created, content: created_content } => format!(
+                    "{{ \"kind\": \"created\", \"path\": {}, \"content\": {} }}", path(created), content(created_content),
+                ),
+                FileChange::Written { old_path, old_content, new_path, new_content } => format!(
+                    "{{ \"kind\": \"written\", \"old_path\": {}, \"old_content\": {}, \"new_path\": {}, \"new_content\": {} }}",
+                    path(old_path), content(old_content), path(new_path), content(new_content),
+                ),
+                FileChange::Renamed { from, to } => format!("{{ \"kind\": \"renamed\", \"from\": {}, \"to\": {} }}", path(from), path(to)),
+                FileChange::Deleted { path: deleted, content: deleted_content } => format!(
+                    "{{ \"kind\": \"deleted\", \"path\": {}, \"content\": {} }}", path(deleted), content(deleted_content),
+                ),
+            }).map(|change| format!("        {change}")).collect();
+            format!(
+                "    {{\n      \"description\": {},\n      \"time\": {},\n      \"changes\": [\n{}\n      ]\n    }}",
+                json::string(&entry.description),
+                entry.time,
+                changes.join(",\n"),
+            )
+        }).collect();
+
+        format!(
+            "{{\n  \"format\": {},\n  \"version\": {FORMAT_VERSION},\n  \"position\": {},\n  \"entries\": [\n{}\n  ]\n}}\n",
+            json::string(FORMAT_NAME),
+            self.position,
+            entries.join(",\n"),
+        )
+    }
+
+    /// Written to a temporary file first so that a failed write never loses the old journal.
+    fn save(&self) -> Result<(), JournalError> {
+        let Some(dir) = self.dir.as_ref() else { return Ok(()) };
+        let path = dir.join(FILE_NAME);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, self.serialize())
+            .and_then(|_| fs::rename(&temp_path, &path))
+            .map_err(io_error(&path))
+    }
+
+    fn blob_path(dir: &Path, content: ContentHash) -> PathBuf {
+        dir.join(BLOB_DIR_NAME).join(format!("{:016x}.jpg", content.0))
+    }
+
+    fn read_blob(&self, content: ContentHash) -> Result<Vec<u8>, JournalError> {
+        match self.dir.as_ref() {
+            Some(dir) => {
+                let blob_path = Self::blob_path(dir, content);
+                fs::read(&blob_path).map_err(io_error(&blob_path))
+            },
+            None => self.memory_blobs.get(&content).cloned()
+                .ok_or_else(|| JournalError::Invalid(format!("missing contents {:016x}", content.0))),
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// How many of the entries are applied. The rest were undone and can be redone.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn next_undo(&self) -> Option<&JournalEntry> {
+        self.position.checked_sub(1).map(|index| &self.entries[index])
+    }
+
+    pub fn next_redo(&self) -> Option<&JournalEntry> {
+        self.entries.get(self.position)
+    }
+
+    /// Add an action that was just done. Does nothing if it didn't change anything.
+    pub fn record(&mut self, description: String, recording: Recording, time: i64) -> Result<(), JournalError> {
+        if recording.is_empty() {
+            return Ok(());
+        }
+        match self.dir.as_ref() {
+            Some(dir) => {
+                let blob_dir = dir.join(BLOB_DIR_NAME);
+                fs::create_dir_all(&blob_dir).map_err(io_error(&blob_dir))?;
+                for (content, bytes) in recording.blobs {
+                    let blob_path = Self::blob_path(dir, content);
+                    if !blob_path.exists() {
+                        fs::write(&blob_path, bytes).map_err(io_error(&blob_path))?;
+                    }
+                }
+            },
+            None => self.memory_blobs.extend(recording.blobs),
+        }
+
+        self.entries.truncate(self.position);
+        self.entries.push(JournalEntry { description, time, changes: recording.changes });
+        self.position = self.entries.len();
+        self.trim();
+        self.save()?;
+        self.remove_unused_blobs()
+    }
+
+    /// Drop the oldest entries until the journal is within its caps. The newest entry is always
+    /// kept, however big.
+    fn trim(&mut self) {
+        let blob_sizes: HashMap<ContentHash, u64> = match self.dir.as_ref() {
+            Some(dir) => self.entries.iter()
+                .flat_map(|entry| entry.changes.iter().flat_map(FileChange::contents))
+                .map(|content| (content, fs::metadata(Self::blob_path(dir, content)).map_or(0, |metadata| metadata.len())))
+                .collect(),
+            None => self.memory_blobs.iter().map(|(content, bytes)| (*content, bytes.len() as u64)).collect(),
+        };
+        let blob_bytes = |entries: &[JournalEntry]| -> u64 {
+            let contents: HashSet<ContentHash> = entries.iter().flat_map(|entry| entry.changes.iter().flat_map(FileChange::contents)).collect();
+            contents.iter().map(|content| blob_sizes.get(content).copied().unwrap_or(0)).sum()
+        };
+
+        let mut num_dropped = self.entries.len().saturating_sub(MAX_ENTRIES);
+        while num_dropped + 1 < self.entries.len() && blob_bytes(&self.entries[num_dropped..]) > MAX_BLOB_BYTES {
+            num_dropped += 1;
+        }
+        self.entries.drain(..num_dropped);
+        self.position = self.position.saturating_sub(num_dropped);
+    }
+
+    fn remove_unused_blobs(&mut self) -> Result<(), JournalError> {
+        let used: HashSet<ContentHash> = self.entries.iter().flat_map(|entry| entry.changes.iter().flat_map(FileChange::contents)).collect();
+        let Some(dir) = self.dir.as_ref() else {
+            self.memory_blobs.retain(|content, _| used.contains(content));
+            return Ok(());
+        };
+        let blob_dir = dir.join(BLOB_DIR_NAME);
+        for dir_entry in fs::read_dir(&blob_dir).map_err(io_error(&blob_dir))?.flatten() {
+            let blob_path = dir_entry.path();
+            let content = blob_path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| u64::from_str_radix(stem, 16).ok())
+                .map(ContentHash);
+            if content.is_some_and(|content| !used.contains(&content)) {
+                fs::remove_file(&blob_path).map_err(io_error(&blob_path))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverse the most recent applied entry.
+    pub fn undo(&mut self) -> Result<(), JournalError> {
+        let Some(index) = self.position.checked_sub(1) else { return Ok(()) };
+        let changes: Vec<&FileChange> = self.entries[index].changes.iter().rev().collect();
+        self.apply_all(&changes, false)?;
+        self.position = index;
+        self.save()
+    }
+
+    /// Apply the most recently undone entry again.
+    pub fn redo(&mut self) -> Result<(), JournalError> {
+        let Some(entry) = self.entries.get(self.position) else { return Ok(()) };
+        let changes: Vec<&FileChange> = entry.changes.iter().collect();
+        self.apply_all(&changes, true)?;
+        self.position += 1;
+        self.save()
+    }
+
+    /// Apply every change in order, rolling back if one fails.
+    fn apply_all(&self, changes: &[&FileChange], forward: bool) -> Result<(), JournalError> {
+        for (num_applied, change) in changes.iter().enumerate() {
+            if let Err(cause) = self.apply(change, forward) {
+                for applied in changes[..num_applied].iter().rev() {
+                    if let Err(rollback) = self.apply(applied, !forward) {
+                        return Err(JournalError::RollbackFailed { cause: Box::new(cause), rollback: Box::new(rollback) });
+                    }
+                }
+                return Err(cause);
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a change, or undo it if `!forward`.
+    fn apply(&self, change: &FileChange, forward: bool) -> Result<(), JournalError> {
+        let expect_missing = |path: &Path| match path.exists() {
+            true => Err(JournalError::Conflict { which: path.to_path_buf(), message: "already exists".to_owned() }),
+            false => Ok(()),
+        };
+        let expect_content = |path: &Path, content: ContentHash| {
+            let bytes = fs::read(path).map_err(io_error(path))?;
+            match ContentHash::of(&bytes) == content {
+                true => Ok(()),
+                false => Err(JournalError::Conflict { which: path.to_path_buf(), message: "was changed since".to_owned() }),
+            }
+        };
+
+        match change {
+            FileChange::Created { path, content } | FileChange::Deleted { path, content } => {
+                // Creating a file is the reverse of deleting it.
+                if forward == matches!(change, FileChange::Created { .. }) {
+                    expect_missing(path)?;
+                    self.write_blob(None, path, *content)
+                } else {
+                    expect_content(path, *content)?;
+                    fs::remove_file(path).map_err(io_error(path))
+                }
+            },
+            FileChange::Written { old_path, old_content, new_path, new_content } => {
+                let ((from, from_content), (to, to_content)) = match forward {
+                    true => ((old_path, *old_content), (new_path, *new_content)),
+                    false => ((new_path, *new_content), (old_path, *old_content)),
+                };
+                expect_content(from, from_content)?;
+                if from != to {
+                    expect_missing(to)?;
+                }
+                self.write_blob(Some(from), to, to_content)
+            },
+            FileChange::Renamed { from, to } => {
+                let (from, to) = if forward { (from, to) } else { (to, from) };
+                if !from.exists() {
+                    return Err(JournalError::Conflict { which: from.to_path_buf(), message: "no longer exists".to_owned() });
+                }
+                expect_missing(to)?;
+                fs::rename(from, to).map_err(io_error(from))
+            },
+        }
+    }
+
+    /// Through a save transaction so that `path` is never left half written. Replaces `old_path`,
+    /// which may be `path` itself, or creates `path` if there's no original.
+    fn write_blob(&self, old_path: Option<&Path>, path: &Path, content: ContentHash) -> Result<(), JournalError> {
+        let bytes = self.read_blob(content)?;
+        let transaction = match old_path {
+            Some(old_path) => SaveTransaction::new(old_path, path),
+            None => SaveTransaction::create(path),
+        };
+        transaction.stage(&bytes, |_, _| {})
+            .and_then(StagedSave::commit)
+            .map_err(|err| JournalError::Io { which: path.to_path_buf(), source: io::Error::new(err.source.kind(), err.to_string()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn undoes_and_redoes_across_restarts() {
+        let test_dir = TestDir::new("journal");
+        let (saves_dir, journal_dir) = (test_dir.join("saves"), test_dir.join(DIR_NAME));
+        fs::create_dir_all(&saves_dir).unwrap();
+        let save = |name: &str| saves_dir.join(format!("Costume_{name}.jpg"));
+
+        // Save A as B with new contents, delete C, create D.
+        fs::write(save("B"), b"new A").unwrap();
+        fs::write(save("D"), b"D").unwrap();
+        let mut recording = Recording::default();
+        recording.written(save("A"), b"old A".to_vec(), save("B"), b"new A".to_vec());
+        recording.deleted(save("C"), b"C".to_vec());
+        recording.created(save("D"), b"D".to_vec());
+        let mut journal = Journal::load(&journal_dir).unwrap();
+        journal.record("Edit".to_owned(), recording, 0).unwrap();
+        let mut recording = Recording::default();
+        fs::rename(save("D"), save("E")).unwrap();
+        recording.renamed(save("D"), save("E"));
+        journal.record("Rename".to_owned(), recording, 1).unwrap();
+
+        let mut journal = Journal::load(&journal_dir).unwrap();
+        assert_eq!(journal.position(), 2);
+        journal.undo().unwrap();
+        journal.undo().unwrap();
+        assert_eq!(fs::read(save("A")).unwrap(), b"old A");
+        assert_eq!(fs::read(save("C")).unwrap(), b"C");
+        assert!(!save("B").exists() && !save("D").exists() && !save("E").exists());
+
+        let mut journal = Journal::load(&journal_dir).unwrap();
+        assert_eq!(journal.next_redo().unwrap().description, "Edit");
+        journal.redo().unwrap();
+        assert_eq!(fs::read(save("B")).unwrap(), b"new A");
+        assert!(!save("A").exists() && !save("C").exists() && save("D").exists());
+
+        // D was changed since, so renaming it again is refused and nothing moves.
+        fs::write(save("E"), b"someone else's").unwrap();
+        assert!(matches!(journal.redo(), Err(JournalError::Conflict { .. })));
+        assert!(save("D").exists());
+        assert_eq!(journal.position(), 1);
+
+        // Recording after an undo drops what could have been redone, and its blobs.
+        fs::remove_file(save("E")).unwrap();
+        journal.undo().unwrap();
+        journal.record("Rename".to_owned(), Recording { changes: vec![FileChange::Renamed { from: save("X"), to: save("Y") }], blobs: HashMap::new() }, 2).unwrap();
+        assert_eq!(journal.entries().len(), 1);
+        assert_eq!(fs::read_dir(journal_dir.join(BLOB_DIR_NAME)).unwrap().count(), 0);
+
+        // Swapping names through a temporary one.
+        let swap = JournalEntry { description: "Swap".to_owned(), time: 3, changes: vec![
+            FileChange::Renamed { from: save("A"), to: save("T") },
+            FileChange::Renamed { from: save("B"), to: save("A") },
+            FileChange::Renamed { from: save("T"), to: save("B") },
+        ] };
+        assert_eq!(swap.net_moves(true), [(save("A"), save("B")), (save("B"), save("A"))]);
+        assert_eq!(swap.net_moves(false), [(save("B"), save("A")), (save("A"), save("B"))]);
+    }
+}
@@ -173,6 +173,11 @@ fn to_iptc_dataset_key(record_number: u8, dataset_number: u8) -> u16 {
 }
 
 impl JpegApp13Payload {
+    /// Create a payload with no datasets. `resource_name` must already be padded to an even length.
+    pub fn new(id: Box<[u8]>, resource_type: u32, resource_id: u16, resource_name: Box<[u8]>) -> Self {
+        Self { id, resource_type, resource_id, resource_name, datasets: BTreeMap::new() }
+    }
+
     pub fn get_datasets(&self, record_number: u8, dataset_number: u8) -> Option<&Vec<IptcDataset>> {
         let key = to_iptc_dataset_key(record_number, dataset_number);
         let result = self.datasets.get(&key);
@@ -184,6 +189,18 @@ impl JpegApp13Payload {
         let result = self.datasets.get_mut(&key);
         result
     }
+
+    /// Replace every dataset with the given record and dataset number, removing them entirely if
+    /// `data` is empty. Datasets are serialized in the order they are given.
+    pub fn set_datasets(&mut self, record_number: u8, dataset_number: u8, data: Vec<Box<[u8]>>) {
+        let key = to_iptc_dataset_key(record_number, dataset_number);
+        if data.is_empty() {
+            self.datasets.remove(&key);
+        } else {
+            let datasets = data.into_iter().map(|data| IptcDataset { record_number, dataset_number, data }).collect();
+            self.datasets.insert(key, datasets);
+        }
+    }
 }
 
 // https://dev.exiv2.org/projects/exiv2/wiki/The_Metadata_in_JPEG_files
@@ -194,8 +211,31 @@ pub struct JpegSegment {
     additional_data: Option<Box<[u8]>>,
 }
 
+// APP13 payloads are really a boxed JpegApp13Payload (see Jpeg::parse) so they need to be dropped
+// as one, otherwise the datasets leak and the payload is deallocated with the wrong layout.
+impl Drop for JpegSegment {
+    fn drop(&mut self) {
+        if self.segment_type == JpegSegmentType::APP13 {
+            if let Some(payload) = self.payload.take() {
+                drop(unsafe { Box::from_raw(Box::into_raw(payload) as *mut JpegApp13Payload) });
+            }
+        }
+    }
+}
+
 // FIXME need to guard against the payload not matching the segment type!
 impl JpegSegment {
+    fn new_app13(payload: JpegApp13Payload) -> Self {
+        let payload = Box::new(payload);
+        #[allow(clippy::cast_slice_from_raw_parts)]
+        let payload = unsafe { Box::from_raw(std::slice::from_raw_parts_mut(Box::into_raw(payload) as *mut u8, std::mem::size_of::<JpegApp13Payload>())) };
+        Self {
+            segment_type: JpegSegmentType::APP13,
+            payload: Some(payload),
+            additional_data: None,
+        }
+    }
+
     pub fn get_payload_as<T: SegmentPayload>(&self) -> &T {
         let payload = self.payload.as_ref().unwrap();
         unsafe { &*(payload.as_ptr() as *const T) }
@@ -525,7 +565,7 @@ impl Jpeg {
                         }
                     }
 
-                    let payload = Box::new(JpegApp13Payload {
+                    let payload = JpegApp13Payload {
                         // SAFETY: We've already done checking to ensure that our identifier is
                         // null-terminated and doesn't contain interior null bytes.
                         id: identifier.into_boxed_slice(),
@@ -533,16 +573,11 @@ impl Jpeg {
                         resource_id,
                         resource_name: resource_name.to_owned().into_boxed_slice(),
                         datasets,
-                    });
-                    let payload = unsafe { Box::from_raw(std::slice::from_raw_parts_mut(Box::into_raw(payload) as *mut u8, std::mem::size_of::<JpegApp13Payload>())) };
+                    };
 
                     let segment_type = JpegSegmentType::APP13;
                     let index = parsed.segments.len();
-                    parsed.segments.push(JpegSegment {
-                        segment_type,
-                        payload: Some(payload),
-                        additional_data: None,
-                    });
+                    parsed.segments.push(JpegSegment::new_app13(payload));
                     parsed.segment_indices.entry(segment_type).or_default().push(index);
 
                     // remember that the whole app 13 segment payload is padded to be an even size
@@ -587,6 +622,58 @@ impl Jpeg {
         encoded.into_boxed_slice()
     }
 
+    /// Serialize only the SOI, APP13 and EOI segments, i.e. the IPTC metadata without the image. The
+    /// result still parses.
+    pub fn serialize_app13_only(&self) -> Box<[u8]> {
+        self.segments.iter()
+            .filter(|segment| matches!(segment.segment_type, JpegSegmentType::SOI | JpegSegmentType::APP13 | JpegSegmentType::EOI))
+            .flat_map(|segment| segment.serialize().into_vec())
+            .collect()
+    }
+
+    /// Whether there's any scan data, i.e. an actual image.
+    pub fn has_image_data(&self) -> bool {
+        self.segment_indices.contains_key(&JpegSegmentType::SOS)
+    }
+
+    /// Replace every APP13 segment with a single one holding `payload`. The new segment is placed
+    /// after SOI and any other leading APPn segments.
+    pub fn set_app13_segment(&mut self, payload: JpegApp13Payload) {
+        self.segments.retain(|segment| segment.segment_type != JpegSegmentType::APP13);
+        let index = self.segments.iter()
+            .position(|segment| !matches!(segment.segment_type as u8, JPEG_MARKER_SOI | JPEG_MARKER_APP0 ..= JPEG_MARKER_APP15))
+            .unwrap_or(self.segments.len());
+        self.segments.insert(index, JpegSegment::new_app13(payload));
+        self.rebuild_segment_indices();
+    }
+
+    pub fn remove_app13_segments(&mut self) {
+        self.segments.retain(|segment| segment.segment_type != JpegSegmentType::APP13);
+        self.rebuild_segment_indices();
+    }
+
+    /// Call after inserting or removing segments since every index after that point has shifted.
+    fn rebuild_segment_indices(&mut self) {
+        self.segment_indices.clear();
+        for (index, segment) in self.segments.iter().enumerate() {
+            if matches!(segment.segment_type, JpegSegmentType::SOS | JpegSegmentType::APP13) {
+                self.segment_indices.entry(segment.segment_type).or_default().push(index);
+            }
+        }
+    }
+
+    /// (width, height) of the image according to the first SOF segment.
+    pub fn get_dimensions(&self) -> Option<(u16, u16)> {
+        let frame_header = self.segments.iter()
+            .find(|segment| matches!(segment.segment_type, JpegSegmentType::SOF0 | JpegSegmentType::SOF2))?;
+        let payload = frame_header.payload.as_ref()?;
+        // Sample precision (1 byte), then height and width (2 bytes each).
+        let height = BigEndian::read_u16(payload.get(1..3)?);
+        let width = BigEndian::read_u16(payload.get(3..5)?);
+
+        Some((width, height))
+    }
+
     pub fn get_segment(&self, segment_type: JpegSegmentType) -> Option<Vec<&JpegSegment>> {
         self.segment_indices
             .get(&segment_type)
@@ -0,0 +1,297 @@
+// Just enough JSON for our own documents (inspect output and costume libraries). Numbers are kept
+// as their source text so that integers round-trip exactly; callers parse them as needed.
+
+use std::fmt;
+
+/// Arrays and objects nested any deeper are rejected rather than risk overflowing the stack on a
+/// malicious document, since we parse files other people send around (libraries and packs).
+const MAX_DEPTH: usize = 128;
+
+#[derive(PartialEq, Debug)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// Members in document order. Duplicate keys are kept; `get` returns the first.
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            Self::Object(members) => members.iter().find(|(member_key, _)| member_key == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Number(number) => number.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, JsonParseError> {
+        let mut parser = Parser { text, offset: 0, depth: 0 };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.offset != text.len() {
+            return Err(parser.error("trailing characters after the document"));
+        }
+
+        Ok(value)
+    }
+}
+
+/// Quote and escape a string.
+pub fn string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[derive(Debug)]
+pub struct JsonParseError {
+    /// 1-based.
+    pub line: usize,
+    /// 1-based, in characters.
+    pub column: usize,
+    pub message: &'static str,
+}
+
+impl std::error::Error for JsonParseError {}
+
+impl fmt::Display for JsonParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Recursive descent over the raw text. `offset` is always on a char boundary.
+struct Parser<'a> {
+    text: &'a str,
+    offset: usize,
+    /// How many arrays and objects the parser is inside of.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &'static str) -> JsonParseError {
+        let before = &self.text[..self.offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        JsonParseError {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.offset += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char, message: &'static str) -> Result<(), JsonParseError> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.offset += 1;
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, JsonParseError> {
+        self.skip_whitespace();
+        let rest = &self.text[self.offset..];
+        for (literal, value) in [("null", JsonValue::Null), ("true", JsonValue::Bool(true)), ("false", JsonValue::Bool(false))] {
+            if rest.starts_with(literal) {
+                self.offset += literal.len();
+                return Ok(value);
+            }
+        }
+
+        match self.peek() {
+            Some('"') => self.parse_string().map(JsonValue::String),
+            Some(c @ ('[' | '{')) => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error("arrays and objects nested too deeply"));
+                }
+                self.depth += 1;
+                let value = if c == '[' { self.parse_array() } else { self.parse_object() };
+                self.depth -= 1;
+                value
+            },
+            Some('-' | '0'..='9') => {
+                let length = rest.find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9')).unwrap_or(rest.len());
+                let number = &rest[..length];
+                // Leaning on f64 parsing for validation. It's laxer than JSON but only about things
+                // like leading zeros, which we never write.
+                if number.parse::<f64>().is_err() {
+                    return Err(self.error("invalid number"));
+                }
+                self.offset += length;
+                Ok(JsonValue::Number(number.to_owned()))
+            },
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of document")),
+        }
+    }
+
+    /// Expects to be on the opening bracket.
+    fn parse_array(&mut self) -> Result<JsonValue, JsonParseError> {
+        self.offset += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.offset += 1;
+            return Ok(JsonValue::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.bump() {
+                Some(',') => continue,
+                Some(']') => return Ok(JsonValue::Array(values)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    /// Expects to be on the opening brace.
+    fn parse_object(&mut self) -> Result<JsonValue, JsonParseError> {
+        self.offset += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.offset += 1;
+            return Ok(JsonValue::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a member name"));
+            }
+            let key = self.parse_string()?;
+            self.expect(':', "expected ':'")?;
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.bump() {
+                Some(',') => continue,
+                Some('}') => return Ok(JsonValue::Object(members)),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    /// Expects to be on the opening quote.
+    fn parse_string(&mut self) -> Result<String, JsonParseError> {
+        self.offset += 1;
+        let mut parsed = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => return Ok(parsed),
+                Some('\\') => match self.bump() {
+                    Some('"') => parsed.push('"'),
+                    Some('\\') => parsed.push('\\'),
+                    Some('/') => parsed.push('/'),
+                    Some('b') => parsed.push('\u{8}'),
+                    Some('f') => parsed.push('\u{c}'),
+                    Some('n') => parsed.push('\n'),
+                    Some('r') => parsed.push('\r'),
+                    Some('t') => parsed.push('\t'),
+                    Some('u') => {
+                        let high = self.parse_hex4()?;
+                        let code_point = if (0xD800..0xDC00).contains(&high) {
+                            // Surrogate pair.
+                            if !self.text[self.offset..].starts_with("\\u") {
+                                return Err(self.error("unpaired surrogate"));
+                            }
+                            self.offset += 2;
+                            let low = self.parse_hex4()?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err(self.error("unpaired surrogate"));
+                            }
+                            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                        } else {
+                            high
+                        };
+                        parsed.push(char::from_u32(code_point).ok_or_else(|| self.error("invalid unicode escape"))?);
+                    },
+                    _ => return Err(self.error("invalid escape")),
+                },
+                Some(c) if (c as u32) < 0x20 => return Err(self.error("unescaped control character in string")),
+                Some(c) => parsed.push(c),
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonParseError> {
+        let hex = self.text.get(self.offset..self.offset + 4)
+            // NOTE from_str_radix also takes a leading sign.
+            .filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        let value = u32::from_str_radix(hex, 16).map_err(|_| self.error("invalid unicode escape"))?;
+        self.offset += 4;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_hostile_documents() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(JsonValue::parse(&nested(MAX_DEPTH)).is_ok());
+        let err = JsonValue::parse(&nested(MAX_DEPTH + 1)).unwrap_err();
+        assert_eq!((err.line, err.column), (1, MAX_DEPTH + 1));
+        // Deep enough to overflow the stack without the limit.
+        assert!(JsonValue::parse(&"[{\"a\":".repeat(1_000_000)).is_err());
+
+        assert_eq!(JsonValue::parse(r#""\u00e9""#).unwrap(), JsonValue::String("é".to_owned()));
+        assert!(JsonValue::parse(r#""\u+0e9""#).is_err());
+        assert!(JsonValue::parse(r#""\ud83d\u+e00""#).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+// Costume libraries: a JSON document describing any number of costumes as text so that they can be
+// shared between machines or kept under version control. Shared by `ccm export`/`ccm import` and
+// the GUI's "Export Selected..." button.
+//
+// Each costume records its original file name, metadata and spec (one string per line so that
+// diffs stay readable). The image is stored WITHOUT the costume metadata, either embedded as base64
+// or as a sidecar JPEG in "<library name>_images/" next to the document. Importing always rebuilds
+// the save through CostumeSave::new so the document is the single source of truth for everything
+// but the pixels. The stored hash is informational only and gets regenerated from the spec.
+//
+// {
+//   "format": "ccm-library",
+//   "version": 1,
+//   "costumes": [
+//     {
+//       "file_name": "Costume_Example_700000000.jpg",
+//       "account_name": "@Account",
+//       "character_name": "Character",
+//       "keywords": ["FightClub"],
+//       "record_version": 2,
+//       "hash": "7799123456",
+//       "spec": ["CostumeV2", "{", ...],
+//       "image_base64": "/9j/4AAQ..."      or      "image_file": "library_images/Costume_Example_700000000.jpg"
+//     }
+//   ]
+// }
+
+use crate::{costume, json::{self, JsonValue}};
+
+use std::{
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+const FORMAT_NAME: &str = "ccm-library";
+const FORMAT_VERSION: i64 = 1;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum ImageStorage { Embedded, Sidecar }
+
+pub enum LibraryImage {
+    Embedded(Vec<u8>),
+    /// Relative to the directory of the document, always with '/' separators.
+    Sidecar(String),
+}
+
+pub struct LibraryCostume {
+    pub file_name: String,
+    pub account_name: String,
+    pub character_name: String,
+    pub keywords: Vec<String>,
+    pub record_version: Option<u16>,
+    /// Without the null terminator.
+    pub hash: String,
+    /// Without the null terminator.
+    pub spec: String,
+    pub image: LibraryImage,
+}
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Io { which: PathBuf, source: io::Error },
+    Json(json::JsonParseError),
+    /// The document is valid JSON but not a valid library.
+    Invalid(String),
+    InvalidSave { file_name: String, source: costume::CostumeParseError },
+}
+
+impl std::error::Error for LibraryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Json(err) => Some(err),
+            Self::Invalid(_) => None,
+            Self::InvalidSave { source, .. } => Some(source),
+        }
+    }
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { which, source } => write!(f, "{which:?}: {source}"),
+            Self::Json(err) => write!(f, "invalid JSON at {err}"),
+            Self::Invalid(message) => write!(f, "{message}"),
+            Self::InvalidSave { file_name, source } => write!(f, "failed to rebuild {file_name:?}: {source}"),
+        }
+    }
+}
+
+impl LibraryCostume {
+    pub fn new(file_name: &costume::CostumeFileName, save: &costume::CostumeSave, image: LibraryImage) -> Self {
+        let metadata = save.get_metadata().unwrap();
+        Self {
+            file_name: file_name.to_string(),
+            account_name: metadata.account_name().to_owned(),
+            character_name: metadata.character_name().to_owned(),
+            keywords: metadata.keywords().map(str::to_owned).collect(),
+            record_version: metadata.record_version(),
+            hash: metadata.hash().trim_end_matches('\0').to_owned(),
+            spec: metadata.spec().trim_end_matches('\0').to_owned(),
+            image,
+        }
+    }
+
+    /// Rebuild the save. `library_dir` is only used to find sidecar images.
+    pub fn build_save(&self, library_dir: &Path) -> Result<costume::CostumeSave, LibraryError> {
+        let image_jpeg = match &self.image {
+            LibraryImage::Embedded(image_jpeg) => image_jpeg.clone(),
+            LibraryImage::Sidecar(image_file) => {
+                let image_path = library_dir.join(image_file);
+                fs::read(&image_path).map_err(|source| LibraryError::Io { which: image_path, source })?
+            },
+        };
+
+        let invalid_save = |source| LibraryError::InvalidSave { file_name: self.file_name.clone(), source };
+        let mut save = costume::CostumeSave::new(&image_jpeg, &self.account_name, &self.character_name, &self.spec).map_err(invalid_save)?;
+        let mut metadata = costume::CostumeMetadata::default();
+        metadata.set_keywords(self.keywords.iter().map(|keyword| keyword.as_str().into()).collect());
+        metadata.set_record_version(self.record_version);
+        save.update_metadata(&metadata);
+
+        Ok(save)
+    }
+
+    fn to_json(&self) -> String {
+        let keywords: Vec<String> = self.keywords.iter().map(|keyword| json::string(keyword)).collect();
+        let spec_lines: Vec<String> = self.spec.split('\n').map(|line| format!("\n        {}", json::string(line))).collect();
+        let image = match &self.image {
+            LibraryImage::Embedded(image_jpeg) => format!("\"image_base64\": {}", json::string(&base64_encode(image_jpeg))),
+            LibraryImage::Sidecar(image_file) => format!("\"image_file\": {}", json::string(image_file)),
+        };
+
+        let mut members = vec![
+            format!("\"file_name\": {}", json::string(&self.file_name)),
+            format!("\"account_name\": {}", json::string(&self.account_name)),
+            format!("\"character_name\": {}", json::string(&self.character_name)),
+            format!("\"keywords\": [{}]", keywords.join(", ")),
+        ];
+        if let Some(record_version) = self.record_version {
+            members.push(format!("\"record_version\": {record_version}"));
+        }
+        members.push(format!("\"hash\": {}", json::string(&self.hash)));
+        members.push(format!("\"spec\": [{}\n      ]", spec_lines.join(",")));
+        members.push(image);
+
+        format!("    {{\n      {}\n    }}", members.join(",\n      "))
+    }
+
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        let string = |key| value.get(key).and_then(JsonValue::as_str).map(str::to_owned).ok_or_else(|| format!("missing string {key:?}"));
+        let string_list = |key| -> Result<Vec<String>, String> {
+            value.get(key)
+                .and_then(JsonValue::as_array)
+                .and_then(|values| values.iter().map(|value| value.as_str().map(str::to_owned)).collect())
+                .ok_or_else(|| format!("missing list of strings {key:?}"))
+        };
+
+        let record_version = match value.get("record_version") {
+            None | Some(JsonValue::Null) => None,
+            Some(record_version) => Some(
+                record_version.as_i64().and_then(|version| u16::try_from(version).ok()).ok_or("invalid \"record_version\"")?
+            ),
+        };
+        let image = match (value.get("image_base64"), value.get("image_file")) {
+            (Some(image_base64), None) => LibraryImage::Embedded(
+                image_base64.as_str().and_then(base64_decode).ok_or("invalid \"image_base64\"")?
+            ),
+            (None, Some(image_file)) => LibraryImage::Sidecar(image_file.as_str().ok_or("invalid \"image_file\"")?.to_owned()),
+            _ => return Err("expected exactly one of \"image_base64\" and \"image_file\"".to_owned()),
+        };
+
+        let file_name = string("file_name")?;
+        // Also rules out anything that could escape the import directory.
+        if Path::new(&file_name).file_name().and_then(|name| name.to_str()) != Some(file_name.as_str())
+            || !costume::is_valid_costume_file_name(Path::new(&file_name))
+        {
+            return Err(format!("{file_name:?} is not a costume save file name"));
+        }
+
+        Ok(Self {
+            file_name,
+            account_name: string("account_name")?,
+            character_name: string("character_name")?,
+            keywords: string_list("keywords")?,
+            record_version,
+            hash: string("hash").unwrap_or_default(),
+            spec: string_list("spec")?.join("\n"),
+            image,
+        })
+    }
+}
+
+/// Write a library document to `library_path`, plus the sidecar images if requested.
+pub fn export_library<'a>(
+    library_path: &Path,
+    saves: impl IntoIterator<Item = (&'a costume::CostumeFileName, &'a costume::CostumeSave)>,
+    image_storage: ImageStorage,
+) -> Result<usize, LibraryError> {
+    fn io_error(which: &Path) -> impl FnOnce(io::Error) -> LibraryError + '_ {
+        move |source| LibraryError::Io { which: which.to_path_buf(), source }
+    }
+    let images_dir_name = format!("{}_images", library_path.file_stem().unwrap_or_default().to_string_lossy());
+    let images_dir = library_path.with_file_name(&images_dir_name);
+    if image_storage == ImageStorage::Sidecar {
+        fs::create_dir_all(&images_dir).map_err(io_error(&images_dir))?;
+    }
+
+    let mut costumes = Vec::new();
+    for (file_name, save) in saves {
+        let image_jpeg = save.image_jpeg();
+        let image = match image_storage {
+            ImageStorage::Embedded => LibraryImage::Embedded(image_jpeg),
+            ImageStorage::Sidecar => {
+                let image_path = images_dir.join(file_name.to_string());
+                fs::write(&image_path, &image_jpeg).map_err(io_error(&image_path))?;
+                LibraryImage::Sidecar(format!("{images_dir_name}/{file_name}"))
+            },
+        };
+        costumes.push(LibraryCostume::new(file_name, save, image).to_json());
+    }
+
+    let document = format!(
+        "{{\n  \"format\": {},\n  \"version\": {FORMAT_VERSION},\n  \"costumes\": [\n{}\n  ]\n}}\n",
+        json::string(FORMAT_NAME),
+        costumes.join(",\n"),
+    );
+    fs::write(library_path, document).map_err(io_error(library_path))?;
+
+    Ok(costumes.len())
+}
+
+pub fn read_library(library_path: &Path) -> Result<Vec<LibraryCostume>, LibraryError> {
+    let text = fs::read_to_string(library_path).map_err(|source| LibraryError::Io { which: library_path.to_path_buf(), source })?;
+    let document = JsonValue::parse(&text).map_err(LibraryError::Json)?;
+
+    if document.get("format").and_then(JsonValue::as_str) != Some(FORMAT_NAME) {
+        return Err(LibraryError::Invalid(format!("not a costume library (expected \"format\": {FORMAT_NAME:?})")));
+    }
+    match document.get("version").and_then(JsonValue::as_i64) {
+        Some(FORMAT_VERSION) => {},
+        Some(version) => return Err(LibraryError::Invalid(format!("unsupported library version {version}"))),
+        None => return Err(LibraryError::Invalid("missing \"version\"".to_owned())),
+    }
+
+    let costumes = document.get("costumes").and_then(JsonValue::as_array).ok_or_else(|| LibraryError::Invalid("missing \"costumes\" list".to_owned()))?;
+    costumes.iter().enumerate()
+        .map(|(index, costume)| LibraryCostume::from_json(costume).map_err(|message| LibraryError::Invalid(format!("costume {}: {message}", index + 1))))
+        .collect()
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, &byte)| group | (byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Strict apart from ignoring whitespace, so that wrapped base64 is accepted too.
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded: Vec<u8> = encoded.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    for (chunk_index, chunk) in encoded.chunks(4).enumerate() {
+        let is_last = chunk_index == encoded.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return None;
+        }
+
+        let mut group = 0u32;
+        for &b in &chunk[..4 - padding] {
+            let value = BASE64_ALPHABET.iter().position(|&c| c == b)?;
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding;
+        decoded.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, TestDir};
+
+    #[test]
+    fn base64_round_trips() {
+        for bytes in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar", &[0xFF, 0x00, 0xFE]] {
+            let encoded = base64_encode(bytes);
+            assert_eq!(base64_decode(&encoded).as_deref(), Some(bytes), "{encoded}");
+        }
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert!(base64_decode("Zm8").is_none());
+        assert!(base64_decode("Zm=8").is_none());
+    }
+
+    #[test]
+    fn library_round_trips() {
+        let mut save = test_util::save("@Account", "Character \"Quoted\"", "CostumeV2\n{\n}\n");
+        let mut metadata = costume::CostumeMetadata::default();
+        metadata.set_keywords(vec!["FightClub".into(), "Ünïcode".into()]);
+        save.update_metadata(&metadata);
+        let file_name = costume::CostumeFileName::parse("Costume_Test_700000000.jpg").unwrap();
+        let dir = TestDir::new("library");
+
+        for image_storage in [ImageStorage::Embedded, ImageStorage::Sidecar] {
+            let library_path = dir.join("library.json");
+            assert_eq!(export_library(&library_path, [(&file_name, &save)], image_storage).unwrap(), 1);
+            let costumes = read_library(&library_path).unwrap();
+            assert_eq!(costumes.len(), 1);
+            assert_eq!(costumes[0].file_name, file_name.to_string());
+            let rebuilt = costumes[0].build_save(&dir).unwrap();
+            assert_eq!(rebuilt.0.serialize(), save.0.serialize());
+        }
+    }
+}
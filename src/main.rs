@@ -16,9 +16,6 @@
 // TODO add an actual event system? e.g. saving/deleting files could be triggered by events
 // dispatched from the UI, and any errors would also be fed into the event system instead of the
 // logging system.
-// This could also solve an issue where displaying the UI takes a while because the scanner thread
-// locks the saves hashmap and parses _every_ costume file on startup. I probably could change this
-// now using a channel and just lock the hashmap to retrieve the current keys.
 
 // TODO refactor and simplify the app update loop. Really just for the modal/dialog stuff at the
 // moment since we only ever should show one of those at a time.
@@ -35,8 +32,23 @@
 
 mod jpeg;
 mod costume;
+mod cli;
+mod dedupe;
+mod index;
+mod journal;
+mod json;
+mod library;
+mod pack;
+mod rename;
+mod tags;
+mod transaction;
+mod trash;
+mod watcher;
+#[cfg(test)]
+mod test_util;
 
 use eframe::egui;
+use chrono::Timelike;
 
 use std::{
     str,
@@ -47,7 +59,6 @@ use std::{
     num::NonZero,
     cmp::Ordering,
     collections::{HashMap, HashSet, VecDeque},
-    io::prelude::*,
     path::{PathBuf, Path},
     fs,
     sync::{Arc, Mutex, RwLock, atomic, mpsc, LazyLock},
@@ -64,61 +75,177 @@ enum CostumeImage {
 
 // TODO audit what fields we actually need.
 //
-// TODO should we move file_name, in_game_display_name, and j2000_timestamp into CostumeSave?
+// TODO should we move file_name and in_game_display_name into CostumeSave?
 // We have a weird situation where functions in the costume module need information that's only
 // available on the CostumeEntry, which is more of a UI thing.
 struct CostumeEntry {
     /// Parsed costume jpeg save.
     save: costume::CostumeSave,
-    /// The full name of the file including the extension.
-    file_name: String,
+    file_name: costume::CostumeFileName,
     /// Represents how the name of the file appears in-game.
     in_game_display_name: String,
-    j2000_timestamp: Option<i64>,
     image_texture: CostumeImage,
     image_visible_in_grid: bool,
     image_visible_in_edit: bool,
+    /// Perceptual hash of the preview image. Filled in by the decode threads.
+    image_hash: Option<dedupe::ImageHash>,
+    /// Hash of the spec itself, which may differ from the stored hash. Kept up to date by
+    /// `update_metadata`.
+    spec_hash: costume::hash::CostumeHash,
+    /// None if the save was written since it was scanned, so that it's parsed again next startup
+    /// instead of trusting the metadata index.
+    file_stamp: Option<index::FileStamp>,
+    /// Hash of the file as it was last read or written, to tell whether it was changed outside of
+    /// the app. None if unknown.
+    content_hash: Option<index::ContentHash>,
+    /// Small JPEG of the preview shown while the full image decodes. Filled in by the decode threads.
+    thumbnail: Option<Arc<[u8]>>,
+    /// `save.get_warnings()`, refreshed whenever the save is loaded or written.
+    warnings: Vec<costume::CostumeParseWarning>,
 }
 
 impl CostumeEntry {
-    fn new(file_path: &Path, save: costume::CostumeSave) -> Self {
-        let file_name = file_path.file_name().unwrap().to_str().unwrap().to_owned();
-        let j2000_timestamp = file_path.file_stem().unwrap().to_str().unwrap()
-            .split('_')
-            .next_back().unwrap()
-            .parse::<i64>().ok();
-        let metadata = save.get_metadata();
-        let in_game_display_name = costume::get_in_game_display_name(metadata.account_name, metadata.character_name, j2000_timestamp);
+    /// `file_path` must have already been validated with `costume::is_valid_costume_file_name`.
+    fn new(file_path: &Path, save: costume::CostumeSave, timestamp_display: &costume::TimestampDisplay) -> Self {
+        let file_name = costume::CostumeFileName::parse(file_path.file_name().unwrap().to_str().unwrap()).unwrap();
+        // NOTE The metadata of a parsed save is always valid so the unwraps on get_metadata
+        // throughout the UI are fine.
+        let metadata = save.get_metadata().unwrap();
+        let in_game_display_name = costume::get_in_game_display_name(metadata.account_name(), metadata.character_name(), file_name.j2000_timestamp, timestamp_display);
+        let spec_hash = costume::hash::CostumeHash::of(metadata.spec());
+        drop(metadata);
+        let warnings = save.get_warnings();
 
         Self {
             save,
-            j2000_timestamp,
+            warnings,
             image_texture: CostumeImage::NotLoaded,
             image_visible_in_grid: false,
             image_visible_in_edit: false,
+            image_hash: None,
+            spec_hash,
+            file_stamp: None,
+            content_hash: None,
+            thumbnail: None,
             file_name,
             in_game_display_name,
         }
     }
 
-    /// Get the name of the save file as it appears between the "Costume_" prefix and either the
-    /// j2000 timestamp suffix (if included) or the file extension.
-    fn get_save_name(&self) -> &str {
-        let save_name_start = self.file_name.find("_").unwrap();
-        let save_name_end = if self.j2000_timestamp.is_some() {
-            self.file_name.rfind("_").unwrap()
-        } else {
-            self.file_name.rfind(".").unwrap()
-        };
+    /// The save only has its metadata until `load_full_save` is called.
+    fn from_index(file_path: &Path, indexed: index::IndexedSave, timestamp_display: &costume::TimestampDisplay) -> Result<Self, costume::CostumeParseError> {
+        let save = costume::CostumeSave::parse(&indexed.metadata)?;
+        Ok(Self {
+            image_hash: indexed.image_hash,
+            spec_hash: indexed.spec_hash,
+            file_stamp: Some(indexed.stamp),
+            content_hash: Some(indexed.content_hash),
+            thumbnail: indexed.thumbnail,
+            ..Self::new(file_path, save, timestamp_display)
+        })
+    }
+
+    fn to_index(&self) -> Option<index::IndexedSave> {
+        Some(index::IndexedSave {
+            stamp: self.file_stamp?,
+            content_hash: self.content_hash?,
+            metadata: self.save.metadata_only_bytes(),
+            spec_hash: self.spec_hash,
+            image_hash: self.image_hash,
+            thumbnail: self.thumbnail.clone(),
+        })
+    }
+
+    /// Read and parse a save from disk. `file_path` must have already been validated with
+    /// `costume::is_valid_costume_file_name`.
+    fn load(file_path: &Path, timestamp_display: &costume::TimestampDisplay) -> Result<Self, AppError> {
+        // NOTE Stamp the file before reading it so that a change in between makes the stamp stale
+        // rather than wrong.
+        let file_stamp = fs::metadata(file_path).ok().and_then(|metadata| index::FileStamp::of(&metadata));
+        let (save, content_hash) = Self::read_save(file_path)?;
+        Ok(Self {
+            file_stamp,
+            content_hash: Some(content_hash),
+            ..Self::new(file_path, save, timestamp_display)
+        })
+    }
+
+    fn read_save(file_path: &Path) -> Result<(costume::CostumeSave, index::ContentHash), AppError> {
+        fs::read(file_path)
+            .and_then(|jpeg_raw| match costume::CostumeSave::parse(&jpeg_raw) {
+                Ok(save) => Ok((save, index::ContentHash::of(&jpeg_raw))),
+                Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+            })
+            .map_err(|source| AppError::CostumeLoadFailed { source, which: file_path.to_path_buf() })
+    }
+
+    /// Saves from the metadata index only hold their metadata. Call before anything that needs the
+    /// whole file, e.g. writing it back out or exporting it.
+    fn load_full_save(&mut self, file_path: &Path) -> Result<(), AppError> {
+        if !self.save.is_metadata_only() {
+            return Ok(());
+        }
+        let (save, content_hash) = Self::read_save(file_path)?;
+        // The file may have been changed underneath us since it was indexed.
+        self.spec_hash = costume::hash::CostumeHash::of(save.get_metadata().unwrap().spec());
+        self.content_hash = Some(content_hash);
+        self.warnings = save.get_warnings();
+        self.save = save;
+        Ok(())
+    }
+
+    /// Whether the file no longer holds what was last read from or written to it. False if that's
+    /// unknown or the file can't be read, in which case writing it will fail on its own.
+    fn changed_on_disk(&self, file_path: &Path) -> bool {
+        let Some(content_hash) = self.content_hash else { return false };
+        let Ok(metadata) = fs::metadata(file_path) else { return false };
+        if self.file_stamp.is_some() && self.file_stamp == index::FileStamp::of(&metadata) {
+            return false;
+        }
+        fs::read(file_path).is_ok_and(|jpeg_raw| index::ContentHash::of(&jpeg_raw) != content_hash)
+    }
+
+    /// Call after writing the save to `file_path` so that later external changes can be detected, and
+    /// so that `warnings` are about what was written.
+    fn mark_written(&mut self, file_path: &Path) {
+        self.content_hash = Some(index::ContentHash::of(&self.save.0.serialize()));
+        self.warnings = self.save.get_warnings();
+        self.file_stamp = fs::metadata(file_path).ok().and_then(|metadata| index::FileStamp::of(&metadata));
+    }
+
+    /// Use instead of `save.update_metadata` so that `spec_hash` stays in sync.
+    fn update_metadata(&mut self, metadata: &costume::CostumeMetadata) {
+        self.save.update_metadata(metadata);
+        self.spec_hash = costume::hash::CostumeHash::of(self.save.get_metadata().unwrap().spec());
+        self.file_stamp = None;
+    }
 
-        // Technically the file name can just be "Costume_.jpg"
-        if save_name_start == save_name_end {
-            ""
-        } else {
-            &self.file_name[save_name_start + 1 .. save_name_end]
+    fn matches_search(&self, query: &costume::search::SearchQuery, timestamp_display: &costume::TimestampDisplay) -> bool {
+        if query.is_empty() {
+            return true;
         }
+        let metadata = self.save.get_metadata().unwrap();
+        let keywords: Vec<&str> = metadata.keywords().collect();
+        query.matches(&costume::search::SearchInput {
+            display_name: &self.in_game_display_name,
+            file_name: &self.file_name.to_string(),
+            account_name: metadata.account_name(),
+            character_name: metadata.character_name(),
+            keywords: &keywords,
+            spec: metadata.spec(),
+            j2000_timestamp: self.file_name.j2000_timestamp,
+        }, timestamp_display)
+    }
+
+    fn tag_key(&self) -> tags::SaveKey {
+        tags::SaveKey::new(&self.file_name, self.spec_hash)
     }
 
+    /// Call this whenever the timestamp display settings change.
+    fn regenerate_in_game_display_name(&mut self, timestamp_display: &costume::TimestampDisplay) {
+        let metadata = self.save.get_metadata().unwrap();
+        self.in_game_display_name = costume::get_in_game_display_name(metadata.account_name(), metadata.character_name(), self.file_name.j2000_timestamp, timestamp_display);
+    }
 }
 
 // TODO If there are many error types maybe break the types into their own enum and do this:
@@ -128,6 +255,26 @@ impl CostumeEntry {
 #[derive(Debug)]
 enum AppError {
     CostumeSaveFailed { source: Option<io::Error>, which: PathBuf, message: String },
+    /// Reading the whole of a save that was loaded from the metadata index failed.
+    CostumeLoadFailed { source: io::Error, which: PathBuf },
+    BatchRenameFailed(rename::RenameError),
+    /// Every save a bulk edit failed to write. The rest were written successfully.
+    BulkEditIncomplete { failures: Vec<AppError>, attempted: usize },
+    ExportFailed(library::LibraryError),
+    PackFailed(pack::PackError),
+    /// Every pack save that failed to import. The rest were imported successfully.
+    PackImportIncomplete { failures: Vec<AppError>, attempted: usize },
+    /// Every duplicate that failed to be archived or deleted. The rest were removed successfully.
+    DuplicateRemovalIncomplete { failures: Vec<(PathBuf, io::Error)>, attempted: usize },
+    TagDatabaseFailed(tags::TagDatabaseError),
+    HistoryFailed(journal::JournalError),
+    TrashFailed(trash::TrashError),
+    /// Recovering an interrupted save failed. Its leftover files were left alone.
+    RecoveryFailed { which: PathBuf, source: io::Error },
+    /// Every save that failed to be moved to, restored from or deleted from the trash. The rest
+    /// went through.
+    TrashIncomplete { action: &'static str, failures: Vec<trash::TrashError>, attempted: usize },
+    UndoFailed { redo: bool, description: String, source: journal::JournalError },
 }
 
 impl fmt::Display for AppError {
@@ -140,7 +287,34 @@ impl fmt::Display for AppError {
                 } else {
                     write!(f, "{header}")
                 }
-            }
+            },
+            Self::CostumeLoadFailed { source, which } => write!(f, "Failed to read costume {which:?}: {source}"),
+            Self::BatchRenameFailed(err) => write!(f, "Failed to batch rename saves: {err}"),
+            Self::BulkEditIncomplete { failures, attempted } => {
+                write!(f, "Failed to save {} of {attempted} costumes:", failures.len())?;
+                failures.iter().try_for_each(|failure| write!(f, "\n{failure}"))
+            },
+            Self::ExportFailed(err) => write!(f, "Failed to export costume library: {err}"),
+            Self::PackFailed(err) => write!(f, "Failed to read or write costume pack: {err}"),
+            Self::PackImportIncomplete { failures, attempted } => {
+                write!(f, "Failed to import {} of {attempted} costumes:", failures.len())?;
+                failures.iter().try_for_each(|failure| write!(f, "\n{failure}"))
+            },
+            Self::DuplicateRemovalIncomplete { failures, attempted } => {
+                write!(f, "Failed to archive {} of {attempted} duplicates:", failures.len())?;
+                failures.iter().try_for_each(|(which, err)| write!(f, "\n{which:?}: {err}"))
+            },
+            Self::TagDatabaseFailed(err) => write!(f, "Failed to load or save tags, changes to tags won't be kept: {err}"),
+            Self::HistoryFailed(err) => write!(f, "Failed to load or save the undo history: {err}"),
+            Self::TrashFailed(err) => write!(f, "Failed to read the trash: {err}"),
+            Self::RecoveryFailed { which, source } => write!(f, "Failed to recover the interrupted save of {which:?}: {source}"),
+            Self::TrashIncomplete { action, failures, attempted } => {
+                write!(f, "Failed to {action} {} of {attempted} saves:", failures.len())?;
+                failures.iter().try_for_each(|failure| write!(f, "\n{failure}"))
+            },
+            Self::UndoFailed { redo, description, source } => {
+                write!(f, "Failed to {} \"{description}\": {source}", if *redo { "redo" } else { "undo" })
+            },
         }
     }
 }
@@ -148,7 +322,20 @@ impl fmt::Display for AppError {
 impl error::Error for AppError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
-            Self::CostumeSaveFailed { source, .. } => source.as_ref().map(|err| err as &dyn error::Error)
+            Self::CostumeSaveFailed { source, .. } => source.as_ref().map(|err| err as &dyn error::Error),
+            Self::CostumeLoadFailed { source, .. } => Some(source),
+            Self::BatchRenameFailed(err) => Some(err),
+            Self::BulkEditIncomplete { .. } => None,
+            Self::ExportFailed(err) => Some(err),
+            Self::PackFailed(err) => Some(err),
+            Self::PackImportIncomplete { .. } => None,
+            Self::DuplicateRemovalIncomplete { .. } => None,
+            Self::TagDatabaseFailed(err) => Some(err),
+            Self::HistoryFailed(err) => Some(err),
+            Self::TrashFailed(err) => Some(err),
+            Self::RecoveryFailed { source, .. } => Some(source),
+            Self::TrashIncomplete { .. } => None,
+            Self::UndoFailed { source, .. } => Some(source),
         }
     }
 }
@@ -177,6 +364,8 @@ impl fmt::Display for LogLevel {
     }
 }
 
+// TODO use the extra fields once we have a log viewer that can filter
+#[allow(dead_code)]
 struct Log {
     level: LogLevel,
     timestamp: chrono::NaiveDateTime,
@@ -228,7 +417,8 @@ impl Logger {
     fn add_log(&mut self, log: Log) {
         // TODO remove, debugging only
         // Or maybe find a way to also print logs only if the console is running
-        println!("{}", log.message);
+        // NOTE stderr, since scripts read the CLI's output from stdout.
+        eprintln!("{}", log.message);
         if self.logs.len() == self.max_logs.into() {
             self.logs.pop_back();
         }
@@ -287,286 +477,2257 @@ static LOGGER: LazyLock<GlobalLogger> = LazyLock::new(|| GlobalLogger(Arc::new(R
 
 /// Critical messages that must be handled as soon as possible
 enum UiPriorityMessage {
-    /// We have detected that the file system has changed underneath us in some way.
-    FileListChangedExternally,
+    /// We have detected that the file system has changed underneath us in some way. Saves that were
+    /// renamed or edited need their tags relinked. `modified` are the saves that were reloaded
+    /// because their contents changed. Undo and redo refresh the UI through this too.
+    FileListChangedExternally { tag_relinks: Vec<(tags::SaveKey, tags::SaveKey)>, modified: Vec<PathBuf> },
 }
 
 /// Regular messages whose handling can be delayed for one or many frames
 enum UiMessage {
-    JpegDecoded { file_path: PathBuf, texture_handle: egui::TextureHandle },
+    /// `content_hash` is the one of the DecodeJob, which the decoded file matched.
+    JpegDecoded { file_path: PathBuf, content_hash: Option<index::ContentHash>, texture_handle: egui::TextureHandle, image_hash: dedupe::ImageHash, thumbnail: Option<Arc<[u8]>> },
+    /// Answer to a DecodeJob with hash_only set.
+    ImageHashed { file_path: PathBuf, content_hash: Option<index::ContentHash>, image_hash: dedupe::ImageHash, thumbnail: Option<Arc<[u8]>> },
+    /// The file no longer matched the `content_hash` of its DecodeJob so nothing was decoded.
+    /// `content_hash` is what's on disk now.
+    DecodeStale { file_path: PathBuf, content_hash: index::ContentHash },
+    /// How many of the saves the scanner found it has loaded so far. Sent periodically while it's
+    /// loading and once it's done.
+    ScanProgress { num_loaded: usize, num_found: usize },
 }
 
-#[derive(Default)]
-struct CostumeEdit {
-    strip_timestamp: bool,
+struct DecodeJob {
+    file_path: PathBuf,
+    /// Hash of the entry when the job was sent. The results are only for that version of the save,
+    /// so that a file that changed in the meantime never lends its image to the wrong entry.
+    content_hash: Option<index::ContentHash>,
+    /// Only compute the perceptual hash, don't upload a texture. For finding duplicates among saves
+    /// that aren't on screen.
+    hash_only: bool,
+    /// Also shrink the image for the metadata index.
+    make_thumbnail: bool,
+}
 
-    timestamp: Option<i64>,
-    save_name: String,
+struct CostumeEdit {
+    /// The save name is edited in place. The extension is kept as-is.
+    file_name: costume::CostumeFileName,
+    // Text edit buffers for metadata fields. Call apply_text_edits after changing any of these.
     account_name: String,
     character_name: String,
 
     // These fields do not affect the indirect fields.
+    /// Comma-separated list of keywords.
+    keywords: String,
     costume_spec: String,
-    costume_hash: String,
+    /// Result of validating costume_spec. Must be refreshed whenever the spec changes.
+    spec_diagnostics: Vec<costume::SpecDiagnostic>,
+    /// Rehashes costume_spec incrementally since apply_text_edits runs on every keystroke.
+    spec_hasher: costume::hash::SpecHasher,
+
+    /// The edited metadata. Tracks which fields actually differ from the save so that only those
+    /// get written.
+    metadata: costume::CostumeMetadata<'static>,
 
     // Indirect fields: The follow fields aren't directly edited; they are just cached for efficiency.
-    file_name: String,
     in_game_display_name: String,
 }
 
 impl CostumeEdit {
     fn new_from_entry(entry: &CostumeEntry) -> Self {
-        let file_name = entry.file_name.to_owned();
+        let file_name = entry.file_name.clone();
         let in_game_display_name = entry.in_game_display_name.to_owned();
-        let save_name = entry.get_save_name().to_owned();
-        let timestamp = entry.j2000_timestamp;
-        let metadata = entry.save.get_metadata();
+        let metadata = entry.save.get_metadata().unwrap().into_owned();
 
         Self {
-            strip_timestamp: timestamp.is_none(),
-            save_name,
-            timestamp,
-            account_name: metadata.account_name.to_owned(),
-            character_name: metadata.character_name.to_owned(),
-            costume_spec: metadata.spec.to_owned(),
-            costume_hash: metadata.hash.to_owned(),
-            file_name, 
+            account_name: metadata.account_name().to_owned(),
+            character_name: metadata.character_name().to_owned(),
+            keywords: metadata.keywords().collect::<Vec<_>>().join(", "),
+            costume_spec: metadata.spec().to_owned(),
+            // The original spec is assumed to be loadable so there's no need to validate it until
+            // the user actually starts editing it.
+            spec_diagnostics: Vec::new(),
+            spec_hasher: costume::hash::SpecHasher::new(metadata.spec()),
+            metadata,
+            file_name,
             in_game_display_name,
         }
     }
 
-    fn create_update(&self) -> costume::UpdateCostumeMetadata {
-        costume::UpdateCostumeMetadata {
-            account_name: Some(self.account_name.clone()),
-            character_name: Some(self.character_name.clone()),
-            spec: Some(self.costume_spec.clone()),
-            hash: Some(self.costume_hash.clone()),
-        }
+    /// Push the text edit buffers into the edited metadata. `original` is the metadata of the save
+    /// being edited, used to figure out which fields are actually dirty.
+    fn apply_text_edits(&mut self, original: &costume::CostumeMetadata) {
+        self.metadata.set_account_name(self.account_name.clone());
+        self.metadata.set_character_name(self.character_name.clone());
+        let spec_hash = self.spec_hasher.update(&self.costume_spec);
+        self.metadata.set_spec_with_hash(self.costume_spec.clone(), spec_hash);
+        self.metadata.set_keywords(parse_keyword_list(&self.keywords));
+        self.metadata.forget_unchanged(original);
+    }
+
+    fn has_changes(&self, entry: &CostumeEntry) -> bool {
+        self.metadata.is_dirty() || self.file_name != entry.file_name
     }
 
     /// Call this to regenerate indirect fields whenever one of the following is changed:
-    /// - timestamp
-    /// - save_name
+    /// - file_name.j2000_timestamp
     /// - account_name
     /// - character_name
-    fn regenerate_indirect_fields(&mut self) {
-        self.file_name = costume::get_file_name(&self.save_name, self.timestamp);
-        self.in_game_display_name = costume::get_in_game_display_name(&self.account_name, &self.character_name, self.timestamp);
+    /// - the timestamp display settings
+    fn regenerate_indirect_fields(&mut self, timestamp_display: &costume::TimestampDisplay) {
+        self.in_game_display_name = costume::get_in_game_display_name(&self.account_name, &self.character_name, self.file_name.j2000_timestamp, timestamp_display);
+    }
+
+    fn validate_spec(&mut self, catalog: &costume::SpecCatalog) {
+        self.spec_diagnostics = costume::validate_spec(&self.costume_spec, catalog);
     }
 }
 
-#[derive(PartialEq, Copy, Clone)]
-enum DisplayType { DisplayName, FileName }
+/// The j2000 timestamp to use when re-adding a timestamp to a save: the file's creation time,
+/// falling back to the current time on platforms/file systems that don't record it.
+/// Parse a comma-separated keyword list as typed by the user, e.g. "FightClub, FC, Female".
+fn parse_keyword_list(keywords: &str) -> Vec<std::borrow::Cow<'static, str>> {
+    keywords
+        .split(',')
+        .map(str::trim)
+        .filter(|keyword| !keyword.is_empty())
+        .map(|keyword| keyword.to_owned().into())
+        .collect()
+}
 
-#[derive(PartialEq, Copy, Clone)]
-enum SortType { Name, CreationTime, ModifiedTime }
+fn get_default_j2000_timestamp(file_path: &Path) -> i64 {
+    let creation_time = fs::metadata(file_path)
+        .and_then(|metadata| metadata.created())
+        .unwrap_or_else(|_| SystemTime::now());
+    // Clamped, since files copied off old media can claim to be older than a timestamp can be.
+    costume::datetime_to_j2000(&chrono::DateTime::<chrono::Utc>::from(creation_time)).unwrap_or(0)
+}
 
-// TODO maybe tie the selected costume and costume edit together so they can never get out of sync?
-struct App {
-    costume_dir: Arc<RwLock<Option<PathBuf>>>,
+/// Amount to shift the timestamps of every selected save by.
+#[derive(Default)]
+struct TimestampShift {
+    days: i64,
+    hours: i64,
+    minutes: i64,
+}
 
-    costume_entries: Arc<Mutex<HashMap<PathBuf, CostumeEntry>>>,
+impl TimestampShift {
+    fn as_seconds(&self) -> i64 {
+        ((self.days * 24 + self.hours) * 60 + self.minutes) * 60
+    }
+}
 
-    logger: LoggerHandle,
+#[derive(Default, PartialEq, Copy, Clone)]
+enum BulkTimestampEdit {
+    #[default]
+    Keep,
+    Strip,
+    /// Set every timestamp to BulkEdit::j2000_timestamp.
+    Set,
+}
 
-    // So that we can gracefully shut down all threads on exit
-    shutdown_flag: Arc<atomic::AtomicBool>,
-    support_thread_handles: Vec<thread::JoinHandle<()>>,
+/// Bulk edit form for when several saves are selected. Only the checked fields are applied.
+#[derive(Default)]
+struct BulkEdit {
+    set_account_name: bool,
+    account_name: String,
+    set_character_name: bool,
+    character_name: String,
+    set_keywords: bool,
+    /// Comma-separated list of keywords.
+    keywords: String,
+    timestamp_edit: BulkTimestampEdit,
+    j2000_timestamp: i64,
+    /// Replaced in every save name. Does nothing while empty.
+    find: String,
+    replace: String,
+}
 
-    ui_priority_message_rx: mpsc::Receiver<UiPriorityMessage>,
-    ui_message_rx: mpsc::Receiver<UiMessage>,
-    scanner_tx: mpsc::Sender<SystemTime>,
-    // TODO can we make this send &Path instead of PathBuf?
-    decode_job_tx: mpsc::Sender<PathBuf>,
+impl BulkEdit {
+    fn edit_file_name(&self, file_name: &costume::CostumeFileName) -> costume::CostumeFileName {
+        let mut file_name = file_name.clone();
+        if !self.find.is_empty() {
+            file_name.save_name = file_name.save_name.replace(&self.find, &self.replace);
+        }
+        match self.timestamp_edit {
+            BulkTimestampEdit::Keep => {},
+            BulkTimestampEdit::Strip => file_name.j2000_timestamp = None,
+            BulkTimestampEdit::Set => file_name.j2000_timestamp = Some(self.j2000_timestamp),
+        }
+        file_name
+    }
 
-    file_exists_warning_modal_open: bool,
-    show_images_in_selection_list: bool,
-    costume_spec_edit_open: bool,
-    confirm_edit_spec: bool,
-    // TODO try to make this a Vec<&Path> if possible
-    sorted_saves: Vec<PathBuf>,
-    /// Values are indices into self.sorted_saves.
-    selected_costumes: HashSet<usize>,
-    selection_range_pivot: usize,
-    display_type: DisplayType,
-    sort_type: SortType,
-    costume_edit: Option<CostumeEdit>,
-}
+    /// Only the fields that actually change are left dirty.
+    fn edit_metadata<'a>(&self, original: &costume::CostumeMetadata<'a>) -> costume::CostumeMetadata<'a> {
+        let mut metadata = original.clone();
+        if self.set_account_name {
+            metadata.set_account_name(self.account_name.clone());
+        }
+        if self.set_character_name {
+            metadata.set_character_name(self.character_name.clone());
+        }
+        if self.set_keywords {
+            metadata.set_keywords(parse_keyword_list(&self.keywords));
+        }
+        metadata.forget_unchanged(original);
+        metadata
+    }
 
-struct AppArgs {
-    costume_dir: Arc<RwLock<Option<PathBuf>>>,
-    costume_entries: Arc<Mutex<HashMap<PathBuf, CostumeEntry>>>,
-    shutdown_flag: Arc<atomic::AtomicBool>,
-    support_thread_handles: Vec<thread::JoinHandle<()>>,
-    ui_priority_message_rx: mpsc::Receiver<UiPriorityMessage>,
-    ui_message_rx: mpsc::Receiver<UiMessage>,
-    scanner_tx: mpsc::Sender<SystemTime>,
-    // TODO can we make this a Sender<&Path>?
-    decode_job_tx: mpsc::Sender<PathBuf>,
-    logger: LoggerHandle,
-}
+    /// `file_paths` must all be in `costume_entries`.
+    fn preview(&self, file_paths: &[PathBuf], costume_entries: &HashMap<PathBuf, CostumeEntry>, timestamp_display: &costume::TimestampDisplay) -> Vec<BulkEditPreview> {
+        let existing: HashSet<PathBuf> = costume_entries.keys().map(|path| rename::collision_key(path)).collect();
+        let mut claimed = HashSet::new();
+        file_paths.iter().map(|file_path| {
+            let entry = &costume_entries[file_path];
+            let metadata = entry.save.get_metadata().unwrap();
+            let new_file_name = self.edit_file_name(&entry.file_name);
+            let account_name = if self.set_account_name { &self.account_name } else { metadata.account_name() };
+            let character_name = if self.set_character_name { &self.character_name } else { metadata.character_name() };
+            let new_in_game_display_name = costume::get_in_game_display_name(account_name, character_name, new_file_name.j2000_timestamp, timestamp_display);
+            let has_changes = new_file_name != entry.file_name || self.edit_metadata(&metadata).is_dirty();
+
+            // NOTE Saves are written one at a time so a save can't take the old name of another
+            // selected save either.
+            let new_key = rename::collision_key(&file_path.with_file_name(new_file_name.to_string()));
+            let name_changed = new_key != rename::collision_key(file_path);
+            let problem = if name_changed && existing.contains(&new_key) {
+                Some("a save with the new name already exists".to_owned())
+            } else if !claimed.insert(new_key) {
+                Some("another selected save would get the same name".to_owned())
+            } else if costume::CostumeFileName::parse(&new_file_name.to_string()).ok().as_ref() != Some(&new_file_name) {
+                Some("save name ends in \"_<digits>\" and would be read as a timestamp".to_owned())
+            } else {
+                None
+            };
 
-impl App {
-    fn new(
-        _cc: &eframe::CreationContext,
-        AppArgs {
-            costume_dir,
-            costume_entries,
-            shutdown_flag,
-            support_thread_handles,
-            ui_priority_message_rx,
-            ui_message_rx,
-            scanner_tx,
-            decode_job_tx,
-            logger,
-        }: AppArgs,
-    ) -> Self
-    {
-        Self {
-            costume_dir,
+            BulkEditPreview { file_path: file_path.clone(), new_file_name, new_in_game_display_name, has_changes, problem }
+        }).collect()
+    }
+}
 
-            costume_entries,
+/// What a bulk edit would do to one save.
+struct BulkEditPreview {
+    file_path: PathBuf,
+    new_file_name: costume::CostumeFileName,
+    new_in_game_display_name: String,
+    has_changes: bool,
+    /// Why the save can't be written, if it can't.
+    problem: Option<String>,
+}
 
-            logger,
+/// State of the new costume modal, which is open whenever `App::new_costume` is Some.
+#[derive(Default)]
+struct NewCostume {
+    save_name: String,
+    account_name: String,
+    character_name: String,
+    costume_spec: String,
+    /// Result of validating costume_spec. Must be refreshed whenever the spec changes.
+    spec_diagnostics: Vec<costume::SpecDiagnostic>,
+    /// Already re-encoded with `encode_costume_image`.
+    image_jpeg: Option<Vec<u8>>,
+    /// Where image_jpeg came from, for display only.
+    image_source: String,
+}
 
-            shutdown_flag,
-            support_thread_handles,
+impl NewCostume {
+    fn validate_spec(&mut self, catalog: &costume::SpecCatalog) {
+        self.spec_diagnostics = costume::validate_spec(&self.costume_spec, catalog);
+    }
+}
 
-            ui_priority_message_rx,
-            ui_message_rx,
-            scanner_tx,
-            decode_job_tx,
+/// A value derived from every costume entry, only recomputed once `App::entries_generation` has
+/// moved on.
+#[derive(Default)]
+struct EntriesCache<T> {
+    generation: Option<u64>,
+    value: T,
+}
 
-            file_exists_warning_modal_open: false,
-            show_images_in_selection_list: false,
-            costume_spec_edit_open: false,
-            confirm_edit_spec: false,
-            sorted_saves: vec![],
-            selected_costumes: HashSet::new(),
-            selection_range_pivot: 0,
-            display_type: DisplayType::DisplayName,
-            sort_type: SortType::Name,
-            costume_edit: None,
+impl<T> EntriesCache<T> {
+    fn get(&mut self, entries_generation: u64, compute: impl FnOnce() -> T) -> &T {
+        if self.generation != Some(entries_generation) {
+            self.value = compute();
+            self.generation = Some(entries_generation);
         }
+        &self.value
     }
+}
 
-    // TODO Should we just clear our selected costumes in here? I think basically every time we
-    // sort we do that.
-    // FIXME We might need to support case-insensitive sorting on non-ascii characters, in which
-    // case we'll need to use to_lowercase(). Might require more cloning than is necessary, so
-    // maybe find a way to do that efficiently.
-    fn sort_saves(sort_type: SortType, display_type: DisplayType, keys_to_sort: &mut [PathBuf], locked_costume_entries: &std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>) {
-        match sort_type {
-            SortType::Name => {
-                match display_type {
-                    DisplayType::DisplayName => {
-                        keys_to_sort.sort_by_key(|k| {
-                            let save = &locked_costume_entries[k];
-                            save.in_game_display_name.to_ascii_lowercase()
-                        });
-                    },
+/// State of the batch rename modal, which is open whenever `App::batch_rename` is Some.
+struct BatchRename {
+    /// The saves being renamed, in counter order.
+    file_paths: Vec<PathBuf>,
+    template: String,
+    template_error: Option<costume::template::TemplateError>,
+    /// Must be refreshed with `replan` whenever the template changes.
+    plan: Vec<rename::PlannedRename>,
+}
 
-                    DisplayType::FileName => {
-                        keys_to_sort.sort_by_key(|k| k.file_stem().unwrap().to_ascii_lowercase());
-                    },
-                }
-            },
+impl BatchRename {
+    const DEFAULT_TEMPLATE: &'static str = "{character}_{n:03}";
 
-            SortType::CreationTime => {
-                keys_to_sort.sort_by_key(|k| {
-                    let metadata = fs::metadata(k).unwrap();
-                    std::cmp::Reverse(metadata.created().unwrap())
-                });
-            },
+    fn new(file_paths: Vec<PathBuf>, costume_entries: &HashMap<PathBuf, CostumeEntry>, timestamp_display: &costume::TimestampDisplay) -> Self {
+        let mut batch_rename = Self { file_paths, template: Self::DEFAULT_TEMPLATE.to_owned(), template_error: None, plan: Vec::new() };
+        batch_rename.replan(costume_entries, timestamp_display);
+        batch_rename
+    }
 
-            SortType::ModifiedTime => {
-                keys_to_sort.sort_by_key(|k| {
-                    let metadata = fs::metadata(k).unwrap();
-                    std::cmp::Reverse(metadata.modified().unwrap())
+    fn replan(&mut self, costume_entries: &HashMap<PathBuf, CostumeEntry>, timestamp_display: &costume::TimestampDisplay) {
+        match costume::template::RenameTemplate::parse(&self.template) {
+            Ok(template) => {
+                // NOTE Saves removed externally close the modal but that's only noticed next frame.
+                let sources = self.file_paths.iter().filter_map(|path| {
+                    let entry = costume_entries.get(path)?;
+                    Some((path.as_path(), &entry.file_name, &entry.save))
                 });
-            }
-        };
+                self.plan = rename::plan_renames(&template, sources, timestamp_display);
+                self.template_error = None;
+            },
+            Err(err) => {
+                self.plan.clear();
+                self.template_error = Some(err);
+            },
+        }
     }
 }
 
-impl eframe::App for App {
-    // Gracefully shut down all our supporting threads.
-    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
-        self.logger.log(LogLevel::Info, "signalling shutdown");
-        self.shutdown_flag.store(true, atomic::Ordering::Release);
-        // NOTE(RA): I think draining the vector like this is okay for now since we shouldn't
-        // update again after handling this exit event.
-        self.support_thread_handles.drain(..).for_each(|thread_handle| {
-            thread_handle.join().unwrap();
-        });
-        self.logger.log(LogLevel::Info, "shutdown complete");
+/// State of the pack import modal, which is open whenever `App::pack_import` is Some.
+struct PackImport {
+    pack_path: PathBuf,
+    pack: pack::Pack,
+    policy: pack::ConflictPolicy,
+    /// Must be refreshed with `replan` whenever the policy changes.
+    planned: Vec<pack::PlannedImport>,
+}
+
+impl PackImport {
+    fn new(pack_path: PathBuf, pack: pack::Pack, costume_dir: &Path, costume_entries: &HashMap<PathBuf, CostumeEntry>) -> Self {
+        let mut pack_import = Self { pack_path, pack, policy: pack::ConflictPolicy::Skip, planned: Vec::new() };
+        pack_import.replan(costume_dir, costume_entries);
+        pack_import
     }
 
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let window_rect = ctx.available_rect();
+    fn replan(&mut self, costume_dir: &Path, costume_entries: &HashMap<PathBuf, CostumeEntry>) {
+        let existing_saves = costume_entries.iter().map(|(path, entry)| (path.as_path(), &entry.save));
+        self.planned = pack::plan_import(&self.pack, costume_dir, existing_saves, self.policy);
+    }
+}
 
-        if self.logger.ui_ack_required() {
-            egui::Modal::new(egui::Id::new("error modal")).show(ctx, |ui| {
-                ui.set_max_size(window_rect.size() * 0.9);
-                ui.set_min_width(0.0);
+#[derive(PartialEq, Copy, Clone)]
+enum DuplicateKind {
+    SameSpec,
+    SimilarImage,
+}
 
-                {
-                    let logger = self.logger.shared_logger.read().unwrap();
-                    let error = logger.last_error.as_ref().unwrap();
-                    let header = match error {
-                        AppError::CostumeSaveFailed { .. } => "Costume Save Failed",
-                    };
-                    let description = error.to_string();
-                    ui.label(header);
-                    ui.label(description);
+/// State of the duplicate review modal, which is open whenever `App::duplicate_review` is Some.
+struct DuplicateReview {
+    kind: DuplicateKind,
+    max_image_distance: u32,
+    removal: dedupe::Removal,
+    groups: Vec<Vec<PathBuf>>,
+    /// Index into each group of the save to keep.
+    keepers: Vec<usize>,
+    /// Number of saves that had an image hash when the groups were last computed. Image groups are
+    /// recomputed as the decode threads catch up.
+    num_hashed: usize,
+}
 
-                    ui.separator();
-                    egui::ScrollArea::both().show(ui, |ui| {
-                        ui.set_width(ui.available_width());
-                        let locked_logger = self.logger.shared_logger.read().unwrap();
-                        for log in locked_logger.logs.iter() {
-                            ui.label(&log.message);
-                        }
-                    });
-                    ui.separator();
-                }
+impl DuplicateReview {
+    fn new(costume_entries: &HashMap<PathBuf, CostumeEntry>) -> Self {
+        let mut duplicate_review = Self {
+            kind: DuplicateKind::SameSpec,
+            max_image_distance: dedupe::DEFAULT_MAX_IMAGE_DISTANCE,
+            removal: dedupe::Removal::Archive,
+            groups: Vec::new(),
+            keepers: Vec::new(),
+            num_hashed: 0,
+        };
+        duplicate_review.regroup(costume_entries);
+        duplicate_review
+    }
 
-                ui.horizontal(|ui| {
-                    if ui.button("Okay").clicked() {
-                        self.logger.ack_errors();
-                    }
+    /// Also resets the keepers to the newest save of each group.
+    fn regroup(&mut self, costume_entries: &HashMap<PathBuf, CostumeEntry>) {
+        self.num_hashed = costume_entries.values().filter(|entry| entry.image_hash.is_some()).count();
+        self.groups = match self.kind {
+            DuplicateKind::SameSpec => dedupe::group_by_spec(costume_entries.iter().map(|(path, entry)| (path.as_path(), &entry.save))),
+            DuplicateKind::SimilarImage => dedupe::group_by_image(
+                costume_entries.iter().filter_map(|(path, entry)| Some((path.as_path(), entry.image_hash?))),
+                self.max_image_distance,
+            ),
+        };
+        self.keep(dedupe::Keep::Newest, costume_entries);
+    }
 
-                    if ui.button("Copy Logs to Clipboard").clicked() {
-                        let logs = &self.logger.shared_logger.read().unwrap().logs;
-                        let num_logs = logs.len();
-                        // 100 bytes per log + bytes for newlines be a good starting assumption
-                        let mut serialized = String::with_capacity(num_logs * 100 + num_logs - 1);
-                        for Log { message, .. } in logs.iter() {
-                            serialized.push_str(message);
-                            serialized.push('\n');
-                        }
+    fn keep(&mut self, keep: dedupe::Keep, costume_entries: &HashMap<PathBuf, CostumeEntry>) {
+        self.keepers = self.groups.iter().map(|group| {
+            let timestamps: Vec<Option<i64>> = group.iter()
+                .map(|path| costume_entries.get(path).and_then(|entry| dedupe::save_j2000_timestamp(path, &entry.file_name)))
+                .collect();
+            dedupe::pick_keeper(&timestamps, keep)
+        }).collect();
+    }
 
-                        ui.output_mut(|platform| platform.copied_text = serialized);
-                    }
-                });
-            });
+    fn num_to_remove(&self) -> usize {
+        self.groups.iter().map(|group| group.len() - 1).sum()
+    }
+}
 
-            return;
-        }
+/// Number of saves with each tag and collection.
+type TagCounts = HashMap<(tags::TagKind, String), usize>;
 
-        let mut costume_entries = self.costume_entries.lock().unwrap();
-        let current_modifiers = ctx.input(|input| input.modifiers);
+/// The user's tag database and where it's kept.
+struct TagStore {
+    database: tags::TagDatabase,
+    /// None if the database failed to load, so that a file we couldn't read is never overwritten.
+    path: Option<PathBuf>,
+    /// For the sidebar. Cleared whenever the database or the list of saves changes.
+    counts: Option<TagCounts>,
+}
+
+impl TagStore {
+    fn load(logger: &LoggerHandle) -> Self {
+        let path = tags::TagDatabase::default_path();
+        match tags::TagDatabase::load(&path) {
+            Ok(database) => Self { database, path: Some(path), counts: None },
+            Err(err) => {
+                logger.log_err_ack_required(AppError::TagDatabaseFailed(err));
+                Self { database: tags::TagDatabase::default(), path: None, counts: None }
+            },
+        }
+    }
+
+    /// Call after every change to the database. Saves it right away.
+    fn changed(&mut self, logger: &LoggerHandle) {
+        self.counts = None;
+        let Some(path) = self.path.as_ref() else { return };
+        if let Err(err) = self.database.save(path) {
+            logger.log_err_ack_required(AppError::TagDatabaseFailed(err));
+            // Don't keep nagging about every later change.
+            self.path = None;
+        }
+    }
+
+    /// Relink saves after they were renamed or edited.
+    fn relink(&mut self, relinks: impl IntoIterator<Item = (tags::SaveKey, tags::SaveKey)>, logger: &LoggerHandle) {
+        if self.database.relink(relinks) {
+            self.changed(logger);
+        }
+    }
+
+    fn counts(&mut self, costume_entries: &HashMap<PathBuf, CostumeEntry>) -> &TagCounts {
+        self.counts.get_or_insert_with(|| {
+            let mut counts = HashMap::new();
+            for entry in costume_entries.values() {
+                let key = entry.tag_key();
+                for kind in [tags::TagKind::Tag, tags::TagKind::Collection] {
+                    for name in self.database.of(&key, kind) {
+                        *counts.entry((kind, name.to_owned())).or_default() += 1;
+                    }
+                }
+            }
+            counts
+        })
+    }
+}
+
+/// Undo history of every file operation the app does.
+struct History {
+    journal: journal::Journal,
+    /// Undo or redo until this many entries are applied, at the start of the next frame.
+    target: Option<usize>,
+    panel_open: bool,
+}
+
+impl History {
+    fn load(logger: &LoggerHandle) -> Self {
+        let journal = journal::Journal::load(&journal::Journal::default_dir()).unwrap_or_else(|err| {
+            logger.log_err_ack_required(AppError::HistoryFailed(err));
+            // Still allow undoing this session's changes, without touching the journal we couldn't
+            // read.
+            journal::Journal::in_memory()
+        });
+        Self { journal, target: None, panel_open: false }
+    }
+
+    /// Call after every file operation, once it's done.
+    fn record(&mut self, description: impl Into<String>, recording: journal::Recording, logger: &LoggerHandle) {
+        if let Err(err) = self.journal.record(description.into(), recording, chrono::Utc::now().timestamp()) {
+            logger.log_err_ack_required(AppError::HistoryFailed(err));
+        }
+    }
+}
+
+/// Only show saves with this tag or in this collection.
+#[derive(PartialEq, Clone)]
+struct TagFilter {
+    kind: tags::TagKind,
+    name: String,
+}
+
+/// Drag and drop payload for saves dragged out of the selection grid.
+struct DraggedSaves(Vec<PathBuf>);
+
+/// The save being edited was changed outside of the app while it had unsaved edits.
+struct EditConflict {
+    file_path: PathBuf,
+    show_differences: bool,
+}
+
+/// State of the trash browser modal.
+struct TrashBrowser {
+    trash: trash::Trash,
+    costume_dir: PathBuf,
+    /// Oldest first.
+    trashed_saves: Vec<trash::TrashedSave>,
+    /// Indices into trashed_saves.
+    selected: HashSet<usize>,
+    confirm_empty: bool,
+}
+
+impl TrashBrowser {
+    fn new(costume_dir: &Path, logger: &LoggerHandle) -> Self {
+        let mut trash_browser = Self {
+            trash: trash::Trash::for_costume_dir(costume_dir),
+            costume_dir: costume_dir.to_path_buf(),
+            trashed_saves: Vec::new(),
+            selected: HashSet::new(),
+            confirm_empty: false,
+        };
+        trash_browser.refresh(logger);
+        trash_browser
+    }
+
+    fn refresh(&mut self, logger: &LoggerHandle) {
+        self.selected.clear();
+        self.confirm_empty = false;
+        self.trashed_saves = self.trash.list(&self.costume_dir).unwrap_or_else(|err| {
+            logger.log_err_ack_required(AppError::TrashFailed(err));
+            Vec::new()
+        });
+    }
+}
+
+/// Delete saves from the trash for good, and their tags with them. Returns the failures.
+fn delete_from_trash<'a>(
+    trash: &trash::Trash,
+    trashed_saves: impl IntoIterator<Item = &'a trash::TrashedSave>,
+    tags: &mut TagStore,
+    logger: &LoggerHandle,
+) -> Vec<trash::TrashError> {
+    let mut failures = Vec::new();
+    let mut forgot_tags = false;
+    for trashed in trashed_saves {
+        // Tags are kept while a save is in the trash so that restoring it brings them back.
+        let tag_key = trashed.original_path.file_name()
+            .and_then(|file_name| costume::CostumeFileName::parse(file_name.to_str()?).ok())
+            .zip(fs::read(&trashed.trashed_path).ok().and_then(|jpeg_raw| costume::CostumeSave::parse(&jpeg_raw).ok()))
+            .map(|(file_name, save)| tags::SaveKey::new(&file_name, costume::hash::CostumeHash::of(save.get_metadata().unwrap().spec())));
+        match trash.delete(trashed) {
+            Ok(()) => {
+                logger.log(LogLevel::Info, format!("deleted {:?} from the trash", trashed.trashed_path).as_str());
+                forgot_tags |= tag_key.is_some_and(|tag_key| tags.database.forget(&tag_key));
+            },
+            Err(err) => failures.push(err),
+        }
+    }
+    if forgot_tags {
+        tags.changed(logger);
+    }
+    failures
+}
+
+/// Delete saves that have been in the trash longer than the retention policy allows.
+fn purge_trash(costume_dir: &Path, retention_days: u32, tags: &mut TagStore, logger: &LoggerHandle) {
+    if retention_days == 0 {
+        return;
+    }
+    let trash = trash::Trash::for_costume_dir(costume_dir);
+    let now = chrono::Local::now().naive_local();
+    let expired: Vec<trash::TrashedSave> = match trash.list(costume_dir) {
+        Ok(trashed_saves) => trashed_saves.into_iter().filter(|trashed| trashed.is_expired(retention_days, now)).collect(),
+        Err(err) => {
+            logger.log(LogLevel::Warn, format!("failed to read the trash to purge it: {err}").as_str());
+            return;
+        },
+    };
+    for err in delete_from_trash(&trash, expired.iter(), tags, logger) {
+        logger.log(LogLevel::Warn, format!("failed to purge the trash: {err}").as_str());
+    }
+}
+
+fn find_interrupted_saves(costume_dir: &Path, logger: &LoggerHandle) -> Vec<transaction::InterruptedSave> {
+    match transaction::find_interrupted_saves(costume_dir) {
+        Ok(interrupted_saves) => interrupted_saves,
+        Err(err) => {
+            logger.log(LogLevel::Warn, format!("failed to look for interrupted saves in {costume_dir:?}: {err}").as_str());
+            Vec::new()
+        },
+    }
+}
+
+/// State of the tag rename modal.
+struct TagRename {
+    kind: tags::TagKind,
+    old_name: String,
+    new_name: String,
+}
+
+/// Re-encode an arbitrary image as a baseline JPEG, which both our parser and the game can handle.
+fn encode_costume_image(image: &image::DynamicImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut image_jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut image_jpeg, 90).encode_image(&image.to_rgb8())?;
+    Ok(image_jpeg)
+}
+
+/// URI that egui keeps a save's decoded thumbnail under.
+fn thumbnail_uri(file_path: &Path) -> String {
+    format!("bytes://thumbnail/{}", file_path.display())
+}
+
+/// List spec diagnostics below a spec editor, noting whether they block saving.
+fn show_spec_diagnostics(ui: &mut egui::Ui, diagnostics: &[costume::SpecDiagnostic]) {
+    if diagnostics.is_empty() { return; }
+
+    ui.separator();
+    egui::ScrollArea::vertical().id_salt("spec diagnostics").max_height(100.0).show(ui, |ui| {
+        for diagnostic in diagnostics.iter() {
+            let color = match diagnostic.severity {
+                costume::SpecDiagnosticSeverity::Error => ui.visuals().error_fg_color,
+                costume::SpecDiagnosticSeverity::Hint => ui.visuals().weak_text_color(),
+            };
+            ui.colored_label(color, diagnostic.to_string());
+        }
+    });
+    if costume::has_errors(diagnostics) {
+        ui.label("The costume cannot be saved until all errors are fixed.");
+    }
+}
+
+/// Lay out the costume spec editor text, underlining every span that has a diagnostic.
+fn layout_costume_spec(ui: &egui::Ui, text: &str, diagnostics: &[costume::SpecDiagnostic], wrap_width: f32) -> egui::text::LayoutJob {
+    let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+    let text_color = ui.visuals().text_color();
+    let mut job = egui::text::LayoutJob::default();
+    job.wrap.max_width = wrap_width;
+
+    // NOTE The diagnostics may be a frame behind the text if the user just typed something, so we
+    // have to be careful that their ranges still land on valid char boundaries.
+    let mut spans: Vec<(std::ops::Range<usize>, costume::SpecDiagnosticSeverity)> = diagnostics.iter()
+        .map(|diagnostic| (diagnostic.byte_range.start.min(text.len()) .. diagnostic.byte_range.end.min(text.len()), diagnostic.severity))
+        .filter(|(range, _)| !range.is_empty() && text.is_char_boundary(range.start) && text.is_char_boundary(range.end))
+        .collect();
+    spans.sort_by_key(|(range, _)| range.start);
+
+    let mut position = 0;
+    for (range, severity) in spans {
+        if range.start < position { continue; }
+        job.append(&text[position..range.start], 0.0, egui::TextFormat::simple(font_id.clone(), text_color));
+        let underline_color = match severity {
+            costume::SpecDiagnosticSeverity::Error => ui.visuals().error_fg_color,
+            costume::SpecDiagnosticSeverity::Hint => ui.visuals().weak_text_color(),
+        };
+        job.append(&text[range.clone()], 0.0, egui::TextFormat {
+            font_id: font_id.clone(),
+            color: text_color,
+            underline: egui::Stroke::new(2.0, underline_color),
+            ..Default::default()
+        });
+        position = range.end;
+    }
+    job.append(&text[position..], 0.0, egui::TextFormat::simple(font_id, text_color));
+
+    job
+}
+
+/// Write a costume save to `new_file_path`, replacing the file at `old_file_path`.
+///
+/// NOTE we write to a temp file first so that we're not immediately overwriting the existing file
+/// in the case the file name hasn't changed. If the save operation fails we don't want to lose or
+/// corrupt the original file. The original is renamed to a backup until the temp file has been
+/// moved into place.
+///
+/// Failures that don't abort the save are logged as warnings. The caller is responsible for
+/// reverting any in-memory changes if this returns an error.
+// TODO Create a SaveError type, map all errors in this block to that and include additional
+// information about the failed operation, and just use the `?` operator.
+// TODO find a way to ergonomically (and efficiently) compress all the error cleanup code.
+fn write_costume_save(save: &costume::CostumeSave, old_file_path: &Path, new_file_path: &Path, logger: &LoggerHandle) -> Result<(), AppError> {
+    // Would throw away the image. See CostumeEntry::load_full_save.
+    debug_assert!(!save.is_metadata_only());
+    let transaction = transaction::SaveTransaction::new(old_file_path, new_file_path);
+    let to_app_error = |err: transaction::TransactionError| {
+        if let Some(cause) = err.cause.as_ref() {
+            // The original file is stuck under its backup name which is worse than the failed
+            // save itself, so that's the error the user needs to see.
+            logger.log(LogLevel::Error, format!("failed to save costume {old_file_path:?}: {cause}").as_str());
+        }
+        AppError::CostumeSaveFailed { which: old_file_path.to_path_buf(), source: Some(err.source), message: err.message }
+    };
+
+    // Need to copy old creation time to new file
+    #[cfg(windows)]
+    let prepare = |temp_file: &fs::File, temp_file_path: &Path| copy_creation_time(old_file_path, temp_file, temp_file_path, logger);
+    #[cfg(not(windows))]
+    let prepare = |_: &fs::File, _: &Path| {};
+    transaction.stage(&save.0.serialize(), prepare).map_err(to_app_error)?.commit().map_err(to_app_error)
+}
+
+/// Write a save that doesn't exist yet. Fails rather than clobber a file that appeared at
+/// `file_path` since it was checked.
+fn create_costume_save(save: &costume::CostumeSave, file_path: &Path) -> Result<(), AppError> {
+    debug_assert!(!save.is_metadata_only());
+    let to_app_error = |err: transaction::TransactionError| AppError::CostumeSaveFailed { which: file_path.to_path_buf(), source: Some(err.source), message: err.message };
+    let transaction = transaction::SaveTransaction::create(file_path);
+    transaction.stage(&save.0.serialize(), |_, _| {}).map_err(to_app_error)?.commit().map_err(to_app_error)
+}
+
+#[cfg(windows)]
+fn copy_creation_time(old_file_path: &Path, temp_file: &fs::File, temp_file_path: &Path, logger: &LoggerHandle) {
+    // NOTE Failure to update file times does NOT abort the
+    // save process. It's not ideal but we can still continue.
+    use std::os::windows::fs::FileTimesExt;
+    let old_file = match fs::File::open(old_file_path) {
+        Ok(file) => file,
+        Err(err) => {
+            logger.log(
+                LogLevel::Warn,
+                format!("failed to update file times: failed to open original file {old_file_path:?}: {err}").as_str(),
+            );
+            return;
+        }
+    };
+    let old_metadata = match old_file.metadata() {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            logger.log(
+                LogLevel::Warn,
+                format!("failed to update file times: failed to get metadata for original file {old_file_path:?}: {err}").as_str(),
+            );
+            return;
+        },
+    };
+    let new_metadata = match temp_file.metadata() {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            logger.log(
+                LogLevel::Warn,
+                format!("failed to update file times: failed to get metadata for temp file {temp_file_path:?}: {err}").as_str(),
+            );
+            return;
+        },
+    };
+    // SAFETY: This section is conditionally compiled for windows so
+    // setting/getting the file creation time should not error.
+    let times = fs::FileTimes::new()
+        .set_created(old_metadata.created().unwrap())
+        .set_accessed(new_metadata.accessed().unwrap())
+        .set_modified(new_metadata.modified().unwrap());
+    if let Err(err) = temp_file.set_times(times) {
+        logger.log(
+            LogLevel::Warn,
+            format!("failed to update file times: failed to set times for {temp_file_path:?}: {err}").as_str(),
+        );
+    }
+}
+
+/// Keeps the costume entries in sync with the costume directory. Runs on the scanner thread.
+struct Scanner {
+    costume_dir: Arc<RwLock<Option<PathBuf>>>,
+    timestamp_display: Arc<RwLock<costume::TimestampDisplay>>,
+    costume_entries: Arc<Mutex<HashMap<PathBuf, CostumeEntry>>>,
+    own_operations: watcher::OwnOperations,
+    ui_message_tx: mpsc::Sender<UiMessage>,
+    ui_priority_message_tx: mpsc::Sender<UiPriorityMessage>,
+    frame: egui::Context,
+    logger: LoggerHandle,
+    /// Saves found on disk are taken out of the index as they're loaded. The App writes a fresh one
+    /// out on exit.
+    metadata_index: index::MetadataIndex,
+    /// The directory being watched. None until there is one, or after the watcher asked for a
+    /// rescan so that it's recreated.
+    watched: Option<(PathBuf, watcher::Watcher)>,
+}
+
+impl Scanner {
+    fn run(mut self, shutdown_flag: &atomic::AtomicBool) {
+        loop {
+            if shutdown_flag.load(atomic::Ordering::Acquire) {
+                break;
+            }
+            if self.logger.ui_ack_required() {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+
+            let costume_dir = self.costume_dir.read().unwrap().clone();
+            if costume_dir.as_ref() != self.watched.as_ref().map(|(dir, _)| dir) {
+                self.watched = None;
+                if let Some(costume_dir) = costume_dir {
+                    // NOTE Start watching before scanning so that nothing that happens during the
+                    // scan is missed.
+                    let watcher = watcher::Watcher::native(&costume_dir, self.own_operations.clone()).unwrap_or_else(|err| {
+                        self.logger.log(LogLevel::Warn, format!("falling back to polling {costume_dir:?} for changes: {err}").as_str());
+                        watcher::Watcher::polling(&costume_dir, self.own_operations.clone())
+                    });
+                    self.logger.log(LogLevel::Info, format!("watching {costume_dir:?} using {}", watcher.backend_name()).as_str());
+                    self.full_scan(&costume_dir);
+                    self.watched = Some((costume_dir, watcher));
+                }
+            }
+
+            let Some((_, watcher)) = self.watched.as_mut() else {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            };
+            let events = watcher.wait(Duration::from_millis(100));
+            if events.contains(&watcher::WatchEvent::Rescan) {
+                self.logger.log(LogLevel::Info, "watcher lost track of the costume directory, scanning it again");
+                self.watched = None;
+            } else if !events.is_empty() {
+                self.apply_events(events);
+            }
+        }
+
+        self.logger.log(LogLevel::Info, "shutting down");
+    }
+
+    /// Load every save in `costume_dir` that isn't loaded yet and forget the ones that are gone.
+    fn full_scan(&mut self, costume_dir: &Path) {
+        let directory_entries = match fs::read_dir(costume_dir) {
+            Ok(directory_entries) => directory_entries,
+            Err(err) => {
+                self.logger.log(LogLevel::Warn, format!("failed to read {costume_dir:?}: {err}").as_str());
+                return;
+            },
+        };
+
+        // Only hold the lock long enough to see what we already have so that the UI stays
+        // responsive while we load whatever's new.
+        let mut missing_files: HashSet<PathBuf> = HashSet::from_iter(self.costume_entries.lock().unwrap().keys().cloned());
+        let mut new_files = Vec::new();
+        for directory_entry in directory_entries.flatten() {
+            let file_path = directory_entry.path();
+            if !missing_files.remove(file_path.as_path()) && costume::is_valid_costume_file_name(&file_path) {
+                new_files.push(file_path);
+            }
+        }
+
+        const PROGRESS_INTERVAL: usize = 64;
+        let num_found = new_files.len();
+        let mut new_entries = Vec::with_capacity(num_found);
+        let mut num_from_index = 0;
+        for (num_loaded, file_path) in new_files.into_iter().enumerate() {
+            if num_loaded % PROGRESS_INTERVAL == 0 {
+                _ = self.ui_message_tx.send(UiMessage::ScanProgress { num_loaded, num_found });
+                self.frame.request_repaint();
+            }
+            if let Some((costume_entry, from_index)) = self.load_entry(&file_path) {
+                num_from_index += from_index as usize;
+                new_entries.push((file_path, costume_entry));
+            }
+        }
+        _ = self.ui_message_tx.send(UiMessage::ScanProgress { num_loaded: num_found, num_found });
+
+        let mut costume_entries = self.costume_entries.lock().unwrap();
+        let num_new_files = new_entries.len();
+        for (file_path, costume_entry) in new_entries {
+            // The UI may have added, renamed or deleted saves while we weren't holding the lock.
+            // Its changes win.
+            if file_path.exists() {
+                costume_entries.entry(file_path).or_insert(costume_entry);
+            }
+        }
+        let num_missing_files = missing_files.len();
+        for missing_file in missing_files {
+            // TODO figure out if we need to explicitly forget image textures here.
+            costume_entries.remove(&missing_file);
+        }
+        self.logger.log(LogLevel::Info, format!("added {num_new_files} new costumes ({num_from_index} from the metadata index), removed {num_missing_files} missing costumes").as_str());
+        _ = self.ui_priority_message_tx.send(UiPriorityMessage::FileListChangedExternally { tag_relinks: Vec::new(), modified: Vec::new() });
+        self.frame.request_repaint();
+    }
+
+    /// Apply changes to individual saves made outside of the app.
+    fn apply_events(&mut self, events: Vec<watcher::WatchEvent>) {
+        use watcher::WatchEvent;
+
+        // Load changed saves before taking the lock, like full_scan. Saves whose contents didn't
+        // actually change, e.g. because they were only touched, are left alone so that their images
+        // don't have to be decoded again.
+        let known_files: HashMap<PathBuf, Option<index::ContentHash>> = self.costume_entries.lock().unwrap().iter()
+            .map(|(file_path, costume_entry)| (file_path.clone(), costume_entry.content_hash))
+            .collect();
+        let mut loaded_entries = HashMap::new();
+        let mut unchanged_files = HashMap::new();
+        for event in events.iter() {
+            let file_path = match event {
+                WatchEvent::Created(file_path) | WatchEvent::Modified(file_path) => file_path,
+                // Renamed from something we never loaded, e.g. a save that failed to parse.
+                WatchEvent::Renamed { from, to } if !known_files.contains_key(from) => to,
+                _ => continue,
+            };
+            let Some((costume_entry, _)) = self.load_entry(file_path) else { continue };
+            if costume_entry.content_hash.is_some() && known_files.get(file_path) == Some(&costume_entry.content_hash) {
+                unchanged_files.insert(file_path.clone(), costume_entry.file_stamp);
+            } else {
+                loaded_entries.insert(file_path.clone(), costume_entry);
+            }
+        }
+
+        let timestamp_display = self.timestamp_display.read().unwrap().clone();
+        let mut costume_entries = self.costume_entries.lock().unwrap();
+        let mut tag_relinks = Vec::new();
+        let mut modified = Vec::new();
+        for event in events {
+            self.logger.log(LogLevel::Info, format!("detected external change: {event:?}").as_str());
+            match event {
+                WatchEvent::Created(file_path) | WatchEvent::Modified(file_path) => {
+                    if let Some(file_stamp) = unchanged_files.remove(&file_path) {
+                        // NOTE Only restamp entries that match what's on disk, see CostumeEntry::file_stamp.
+                        if let Some(costume_entry) = costume_entries.get_mut(&file_path).filter(|costume_entry| costume_entry.file_stamp.is_some()) {
+                            costume_entry.file_stamp = file_stamp;
+                        }
+                        continue;
+                    }
+                    let old_entry = costume_entries.remove(&file_path);
+                    // Not loaded if it's gone again or no longer parses.
+                    let Some(costume_entry) = loaded_entries.remove(&file_path) else { continue };
+                    if let Some(old_entry) = old_entry {
+                        tag_relinks.push((old_entry.tag_key(), costume_entry.tag_key()));
+                        modified.push(file_path.clone());
+                    }
+                    costume_entries.insert(file_path, costume_entry);
+                },
+                WatchEvent::Removed(file_path) => {
+                    costume_entries.remove(&file_path);
+                },
+                WatchEvent::Renamed { from, to } => match costume_entries.remove(&from) {
+                    Some(mut costume_entry) => {
+                        let old_tag_key = costume_entry.tag_key();
+                        costume_entry.file_name = costume::CostumeFileName::parse(to.file_name().unwrap().to_str().unwrap()).unwrap();
+                        costume_entry.regenerate_in_game_display_name(&timestamp_display);
+                        tag_relinks.push((old_tag_key, costume_entry.tag_key()));
+                        costume_entries.insert(to, costume_entry);
+                    },
+                    None => {
+                        if let Some(costume_entry) = loaded_entries.remove(&to) {
+                            costume_entries.insert(to, costume_entry);
+                        }
+                    },
+                },
+                WatchEvent::Rescan => unreachable!("handled by run"),
+            }
+        }
+        drop(costume_entries);
+
+        tag_relinks.retain(|(old_tag_key, new_tag_key)| old_tag_key != new_tag_key);
+        _ = self.ui_priority_message_tx.send(UiPriorityMessage::FileListChangedExternally { tag_relinks, modified });
+        self.frame.request_repaint();
+    }
+
+    /// Load a save from the metadata index if it's unchanged since it was indexed, otherwise from
+    /// disk. The bool is whether it came from the index.
+    fn load_entry(&mut self, file_path: &Path) -> Option<(CostumeEntry, bool)> {
+        let timestamp_display = self.timestamp_display.read().unwrap().clone();
+        let file_stamp = fs::metadata(file_path).ok().and_then(|metadata| index::FileStamp::of(&metadata));
+        if let Some(indexed) = file_stamp.and_then(|file_stamp| self.metadata_index.take(file_path, file_stamp)) {
+            match CostumeEntry::from_index(file_path, indexed, &timestamp_display) {
+                Ok(costume_entry) => return Some((costume_entry, true)),
+                Err(err) => self.logger.log(LogLevel::Warn, format!("ignoring metadata index entry for {file_path:?}: {err}").as_str()),
+            }
+        }
+
+        let costume_entry = match CostumeEntry::load(file_path, &timestamp_display) {
+            Ok(costume_entry) => costume_entry,
+            Err(err) => {
+                self.logger.log(LogLevel::Warn, err.to_string().as_str());
+                return None;
+            },
+        };
+        for warning in costume_entry.warnings.iter() {
+            self.logger.log(LogLevel::Warn, format!("{file_path:?}: {warning}").as_str());
+        }
+        Some((costume_entry, false))
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum DisplayType { DisplayName, FileName }
+
+#[derive(PartialEq, Copy, Clone)]
+enum SortType { Name, CreationTime, ModifiedTime }
+
+// TODO maybe tie the selected costume and costume edit together so they can never get out of sync?
+struct App {
+    costume_dir: Arc<RwLock<Option<PathBuf>>>,
+    timestamp_display: Arc<RwLock<costume::TimestampDisplay>>,
+
+    costume_entries: Arc<Mutex<HashMap<PathBuf, CostumeEntry>>>,
+
+    logger: LoggerHandle,
+
+    // So that we can gracefully shut down all threads on exit
+    shutdown_flag: Arc<atomic::AtomicBool>,
+    support_thread_handles: Vec<thread::JoinHandle<()>>,
+
+    ui_priority_message_rx: mpsc::Receiver<UiPriorityMessage>,
+    ui_message_rx: mpsc::Receiver<UiMessage>,
+    /// Register changes to the costume directory with this before making them so that the scanner
+    /// doesn't report them as external changes.
+    own_operations: watcher::OwnOperations,
+    // TODO can we make this send &Path instead of PathBuf?
+    decode_job_tx: mpsc::Sender<DecodeJob>,
+
+    file_exists_warning_modal_open: bool,
+    show_images_in_selection_list: bool,
+    costume_spec_edit_open: bool,
+    confirm_edit_spec: bool,
+    /// Catalog used to validate spec edits. Includes every part seen in the other loaded saves.
+    spec_catalog: costume::SpecCatalog,
+    // TODO try to make this a Vec<&Path> if possible
+    sorted_saves: Vec<PathBuf>,
+    /// Bumped whenever the saves in costume_entries change, which is whenever sorted_saves is
+    /// filtered again, so that anything derived from every save can be cached.
+    entries_generation: u64,
+    /// Stored hashes shared by saves with different specs.
+    hash_collisions: EntriesCache<Vec<(costume::hash::CostumeHash, Vec<Vec<PathBuf>>)>>,
+    /// Every keyword of every save for the keyword filter, sorted and without case-insensitive
+    /// duplicates.
+    all_keywords: EntriesCache<Vec<String>>,
+    /// Values are indices into self.sorted_saves.
+    selected_costumes: HashSet<usize>,
+    selection_range_pivot: usize,
+    display_type: DisplayType,
+    sort_type: SortType,
+    /// Only show saves that have this keyword.
+    keyword_filter: Option<String>,
+    /// Text edit buffer for the search bar. Only applied while it parses.
+    search_text: String,
+    search_query: costume::search::SearchQuery,
+    search_error: Option<costume::search::SearchError>,
+    tags: TagStore,
+    tag_filter: Option<TagFilter>,
+    history: History,
+    /// (loaded, found) while the scanner is loading new saves.
+    scan_progress: Option<(usize, usize)>,
+    /// Text edit buffers for creating tags and collections from the sidebar.
+    new_tag_name: String,
+    new_collection_name: String,
+    tag_rename: Option<TagRename>,
+    bulk_edit: BulkEdit,
+    timestamp_shift: TimestampShift,
+    /// Text edit buffer for the timestamp display format. Only applied while it's valid.
+    timestamp_format_edit: String,
+    costume_edit: Option<CostumeEdit>,
+    new_costume: Option<NewCostume>,
+    batch_rename: Option<BatchRename>,
+    /// Note for the next exported pack. Some while the pack export modal is open.
+    pack_export_note: Option<String>,
+    pack_import: Option<PackImport>,
+    duplicate_review: Option<DuplicateReview>,
+    edit_conflict: Option<EditConflict>,
+    /// Saves to move to the trash. Some while the delete confirmation modal is open.
+    delete_confirmation: Option<Vec<PathBuf>>,
+    trash_browser: Option<TrashBrowser>,
+    trash_retention_days: u32,
+    /// Saves that were being written when the app last closed. The recovery modal is open while
+    /// there are any.
+    interrupted_saves: Vec<transaction::InterruptedSave>,
+}
+
+struct AppArgs {
+    costume_dir: Arc<RwLock<Option<PathBuf>>>,
+    timestamp_display: Arc<RwLock<costume::TimestampDisplay>>,
+    costume_entries: Arc<Mutex<HashMap<PathBuf, CostumeEntry>>>,
+    shutdown_flag: Arc<atomic::AtomicBool>,
+    support_thread_handles: Vec<thread::JoinHandle<()>>,
+    ui_priority_message_rx: mpsc::Receiver<UiPriorityMessage>,
+    ui_message_rx: mpsc::Receiver<UiMessage>,
+    own_operations: watcher::OwnOperations,
+    // TODO can we make this a Sender<&Path>?
+    decode_job_tx: mpsc::Sender<DecodeJob>,
+    trash_retention_days: u32,
+    logger: LoggerHandle,
+}
+
+impl App {
+    fn new(
+        _cc: &eframe::CreationContext,
+        AppArgs {
+            costume_dir,
+            timestamp_display,
+            costume_entries,
+            shutdown_flag,
+            support_thread_handles,
+            ui_priority_message_rx,
+            ui_message_rx,
+            own_operations,
+            decode_job_tx,
+            trash_retention_days,
+            logger,
+        }: AppArgs,
+    ) -> Self
+    {
+        let timestamp_format_edit = timestamp_display.read().unwrap().format.clone();
+        let mut tags = TagStore::load(&logger);
+        let mut interrupted_saves = Vec::new();
+        if let Some(costume_dir) = costume_dir.read().unwrap().as_ref() {
+            purge_trash(costume_dir, trash_retention_days, &mut tags, &logger);
+            interrupted_saves = find_interrupted_saves(costume_dir, &logger);
+        }
+        let history = History::load(&logger);
+        Self {
+            costume_dir,
+            timestamp_display,
+
+            costume_entries,
+
+            logger,
+
+            shutdown_flag,
+            support_thread_handles,
+
+            ui_priority_message_rx,
+            ui_message_rx,
+            own_operations,
+            decode_job_tx,
+
+            file_exists_warning_modal_open: false,
+            show_images_in_selection_list: false,
+            costume_spec_edit_open: false,
+            confirm_edit_spec: false,
+            spec_catalog: costume::SpecCatalog::embedded().clone(),
+            sorted_saves: vec![],
+            selected_costumes: HashSet::new(),
+            selection_range_pivot: 0,
+            display_type: DisplayType::DisplayName,
+            sort_type: SortType::Name,
+            keyword_filter: None,
+            search_text: String::new(),
+            search_query: costume::search::SearchQuery::default(),
+            search_error: None,
+            tags,
+            tag_filter: None,
+            history,
+            scan_progress: None,
+            new_tag_name: String::new(),
+            new_collection_name: String::new(),
+            tag_rename: None,
+            bulk_edit: BulkEdit::default(),
+            timestamp_shift: TimestampShift::default(),
+            timestamp_format_edit,
+            costume_edit: None,
+            new_costume: None,
+            batch_rename: None,
+            pack_export_note: None,
+            pack_import: None,
+            duplicate_review: None,
+            edit_conflict: None,
+            delete_confirmation: None,
+            trash_browser: None,
+            trash_retention_days,
+            interrupted_saves,
+            entries_generation: 0,
+            hash_collisions: EntriesCache::default(),
+            all_keywords: EntriesCache::default(),
+        }
+    }
+
+    /// Undo and redo apply at the start of the next frame.
+    fn show_undo_buttons(ui: &mut egui::Ui, history: &mut History, history_locked: bool) {
+        let locked_text = "Save or discard your edits first";
+        let undo_button = ui.add_enabled(!history_locked && history.journal.next_undo().is_some(), egui::Button::new("Undo"));
+        let undo_button = match history.journal.next_undo() {
+            Some(entry) => undo_button.on_hover_text(format!("Undo \"{}\" (Ctrl+Z)", entry.description)).on_disabled_hover_text(locked_text),
+            None => undo_button.on_disabled_hover_text("Nothing to undo"),
+        };
+        if undo_button.clicked() {
+            history.target = Some(history.journal.position() - 1);
+            ui.ctx().request_repaint();
+        }
+
+        let redo_button = ui.add_enabled(!history_locked && history.journal.next_redo().is_some(), egui::Button::new("Redo"));
+        let redo_button = match history.journal.next_redo() {
+            Some(entry) => redo_button.on_hover_text(format!("Redo \"{}\" (Ctrl+Shift+Z)", entry.description)).on_disabled_hover_text(locked_text),
+            None => redo_button.on_disabled_hover_text("Nothing to redo"),
+        };
+        if redo_button.clicked() {
+            history.target = Some(history.journal.position() + 1);
+            ui.ctx().request_repaint();
+        }
+    }
+
+    /// Get the keys of every costume entry that passes the current filters.
+    fn filter_saves(
+        keyword_filter: Option<&str>,
+        search_query: &costume::search::SearchQuery,
+        tag_filter: Option<&TagFilter>,
+        tag_database: &tags::TagDatabase,
+        timestamp_display: &costume::TimestampDisplay,
+        locked_costume_entries: &std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>,
+    ) -> Vec<PathBuf> {
+        locked_costume_entries.iter()
+            .filter(|(_, entry)| keyword_filter.is_none_or(|keyword| entry.save.get_metadata().unwrap().has_keyword(keyword)))
+            .filter(|(_, entry)| tag_filter.is_none_or(|filter| tag_database.has(&entry.tag_key(), filter.kind, &filter.name)))
+            .filter(|(_, entry)| entry.matches_search(search_query, timestamp_display))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// See CostumeEntry::load_full_save. Stops at the first failure.
+    fn load_full_saves(paths: &[&PathBuf], costume_entries: &mut HashMap<PathBuf, CostumeEntry>) -> Result<(), AppError> {
+        paths.iter().try_for_each(|path| costume_entries.get_mut(*path).unwrap().load_full_save(path))
+    }
+
+    /// The embedded spec catalog plus every part referenced by the loaded saves (other than
+    /// `exclude`), since those presumably load in-game.
+    fn learn_spec_catalog(exclude: Option<&Path>, locked_costume_entries: &std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>) -> costume::SpecCatalog {
+        let mut spec_catalog = costume::SpecCatalog::embedded().clone();
+        for (path, entry) in locked_costume_entries.iter() {
+            if exclude != Some(path.as_path()) {
+                spec_catalog.learn_parts_from_spec(entry.save.get_metadata().unwrap().spec());
+            }
+        }
+
+        spec_catalog
+    }
+
+    fn save_app_config(costume_dir: &RwLock<Option<PathBuf>>, timestamp_display: &RwLock<costume::TimestampDisplay>, trash_retention_days: u32, logger: &LoggerHandle) {
+        let app_config = AppConfig {
+            costume_dir: costume_dir.read().unwrap().clone(),
+            timestamp_display: timestamp_display.read().unwrap().clone(),
+            trash_retention_days,
+        };
+        if let Err(e) = fs::write(APP_CONFIG_FILE_NAME, app_config.serialize()) {
+            // TODO should we display this error to the user?
+            logger.log(LogLevel::Error, &e.to_string());
+        }
+    }
+
+    /// The batch rename dialog, if it's open.
+    fn show_batch_rename(&mut self, ctx: &egui::Context, window_rect: egui::Rect, costume_entries: &mut std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>, timestamp_display: &costume::TimestampDisplay) {
+        let Some(mut batch_rename) = self.batch_rename.take() else { return };
+        let mut close_modal = false;
+        egui::Modal::new(egui::Id::new("Batch Rename")).show(ctx, |ui| {
+            ui.set_max_height(window_rect.height() * 0.9);
+            ui.set_max_width(window_rect.width() * 0.8);
+            ui.set_min_size([0.0, 0.0].into());
+
+            ui.label(format!("Batch Rename {} Saves", batch_rename.file_paths.len()));
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Template:");
+                let template_edit = ui.add(egui::TextEdit::singleline(&mut batch_rename.template).desired_width(400.0))
+                    .on_hover_text(
+                        "Placeholders: {account} {character} {keyword} {name} {date:%Y%m%d} {created:%Y%m%d} {hash:8} {n:03}\n\
+                        Use {{ and }} for literal braces. The timestamp suffix is kept."
+                    );
+                if template_edit.changed() {
+                    batch_rename.replan(costume_entries, timestamp_display);
+                }
+            });
+            if let Some(err) = &batch_rename.template_error {
+                ui.colored_label(ui.visuals().error_fg_color, err.to_string());
+            }
+
+            ui.separator();
+            egui::ScrollArea::vertical().max_height(window_rect.height() * 0.6).show(ui, |ui| {
+                egui::Grid::new("batch rename preview").striped(true).show(ui, |ui| {
+                    ui.strong("Current File Name");
+                    ui.strong("New File Name");
+                    ui.end_row();
+                    for planned in batch_rename.plan.iter() {
+                        ui.label(planned.old_path.file_name().unwrap().to_string_lossy());
+                        let new_file_name = planned.new_file_name.to_string();
+                        match &planned.problem {
+                            Some(problem) => { ui.colored_label(ui.visuals().error_fg_color, new_file_name).on_hover_text(problem.to_string()); },
+                            None if planned.is_unchanged() => { ui.weak(new_file_name); },
+                            None => { ui.label(new_file_name); },
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let num_problems = batch_rename.plan.iter().filter(|planned| planned.problem.is_some()).count();
+                let has_changes = batch_rename.plan.iter().any(|planned| !planned.is_unchanged());
+                let can_apply = batch_rename.template_error.is_none() && num_problems == 0 && has_changes;
+                let apply_button = ui.add_enabled(can_apply, egui::Button::new("Apply"))
+                    .on_disabled_hover_text(if batch_rename.template_error.is_some() {
+                        "The template is invalid".to_owned()
+                    } else if num_problems > 0 {
+                        format!("{num_problems} of the new names have problems, hover over them for details")
+                    } else {
+                        "Nothing to change".to_owned()
+                    });
+                if apply_button.clicked() {
+                    let renamed = {
+                        let _operation = self.own_operations.begin(batch_rename.plan.iter()
+                            .filter(|planned| !planned.is_unchanged())
+                            .flat_map(|planned| [planned.old_path.clone(), planned.new_path.clone()]));
+                        rename::apply_renames(&batch_rename.plan)
+                    };
+                    match renamed {
+                        Ok(()) => {
+                            let mut renamed_paths = HashSet::new();
+                            let mut tag_relinks = Vec::new();
+                            for planned in batch_rename.plan.iter().filter(|planned| !planned.is_unchanged()) {
+                                let mut entry = costume_entries.remove(&planned.old_path).unwrap();
+                                let old_tag_key = entry.tag_key();
+                                entry.file_name = planned.new_file_name.clone();
+                                entry.regenerate_in_game_display_name(timestamp_display);
+                                tag_relinks.push((old_tag_key, entry.tag_key()));
+                                costume_entries.insert(planned.new_path.clone(), entry);
+                                renamed_paths.insert(planned.new_path.clone());
+                            }
+                            self.tags.relink(tag_relinks, &self.logger);
+                            self.logger.log(LogLevel::Info, format!("batch renamed {} saves", renamed_paths.len()).as_str());
+                            let mut recording = journal::Recording::default();
+                            for (from, to) in rename::rename_steps(&batch_rename.plan) {
+                                recording.renamed(from, to);
+                            }
+                            self.history.record(format!("Batch rename {} saves", renamed_paths.len()), recording, &self.logger);
+
+                            let selected_paths: HashSet<&PathBuf> = batch_rename.plan.iter().map(|planned| &planned.new_path).collect();
+                            self.refresh_sorted_saves(timestamp_display, costume_entries);
+                            self.selected_costumes = self.sorted_saves.iter().enumerate()
+                                .filter(|(_, path)| selected_paths.contains(path))
+                                .map(|(idx, _)| idx)
+                                .collect();
+                        },
+                        Err(err) => self.logger.log_err_ack_required(AppError::BatchRenameFailed(err)),
+                    }
+                    close_modal = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    close_modal = true;
+                }
+            });
+        });
+
+        if !close_modal {
+            self.batch_rename = Some(batch_rename);
+        }
+    }
+
+    /// The pack import dialog, if it's open.
+    fn show_pack_import(&mut self, ctx: &egui::Context, window_rect: egui::Rect, costume_entries: &mut std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>, timestamp_display: &costume::TimestampDisplay) {
+        let Some(mut pack_import) = self.pack_import.take() else { return };
+        let mut close_modal = false;
+        egui::Modal::new(egui::Id::new("Import Pack")).show(ctx, |ui| {
+            ui.set_max_height(window_rect.height() * 0.9);
+            ui.set_max_width(window_rect.width() * 0.8);
+            ui.set_min_size([0.0, 0.0].into());
+
+            let pack = &pack_import.pack;
+            ui.label(format!("Import {} Saves from {:?}", pack.entries.len(), pack_import.pack_path.file_name().unwrap_or_default()));
+            ui.weak(format!("Created by {} at {}", pack.created_by, pack.created));
+            if !pack.note.is_empty() {
+                ui.label(&pack.note);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("When a save with the same name already exists:");
+                let mut policy_changed = false;
+                for (policy, label, hover_text) in [
+                    (pack::ConflictPolicy::Skip, "Skip", "Keep the existing save. Also skips costumes identical to an existing save."),
+                    (pack::ConflictPolicy::Rename, "Rename", "Import under a numbered name, e.g. \"Name (2)\"."),
+                    (pack::ConflictPolicy::Overwrite, "Overwrite", "Replace the existing save."),
+                ] {
+                    policy_changed |= ui.radio_value(&mut pack_import.policy, policy, label).on_hover_text(hover_text).changed();
+                }
+                if policy_changed {
+                    if let Some(costume_dir) = self.costume_dir.read().unwrap().as_ref() {
+                        pack_import.replan(costume_dir, costume_entries);
+                    }
+                }
+            });
+
+            ui.separator();
+            egui::ScrollArea::vertical().max_height(window_rect.height() * 0.6).show(ui, |ui| {
+                egui::Grid::new("pack import preview").striped(true).show(ui, |ui| {
+                    ui.strong("File Name");
+                    ui.strong("Account / Character");
+                    ui.strong("Tags");
+                    ui.strong("Action");
+                    ui.end_row();
+                    for planned in pack_import.planned.iter() {
+                        let entry = &pack_import.pack.entries[planned.entry_index];
+                        ui.label(entry.file_name.to_string());
+                        ui.label(format!("{}{}", entry.account_name, entry.character_name));
+                        ui.label(entry.tags.join(", "));
+                        let action = match &planned.action {
+                            pack::ImportAction::Create(path) => ui.label(format!("Import as {:?}", path.file_name().unwrap())),
+                            pack::ImportAction::Overwrite(path) => ui.colored_label(ui.visuals().warn_fg_color, format!("Overwrite {:?}", path.file_name().unwrap())),
+                            pack::ImportAction::Skip(reason) => ui.weak(format!("Skip: {reason}")),
+                            pack::ImportAction::Invalid(err) => ui.colored_label(ui.visuals().error_fg_color, format!("Invalid: {err}")),
+                        };
+                        if let Some(identical_to) = &planned.identical_to {
+                            action.on_hover_text(format!("Same costume as {:?}", identical_to.file_name().unwrap()));
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let num_writes = pack_import.planned.iter()
+                    .filter(|planned| matches!(planned.action, pack::ImportAction::Create(_) | pack::ImportAction::Overwrite(_)))
+                    .count();
+                let import_button = ui.add_enabled(num_writes > 0, egui::Button::new(format!("Import {num_writes} Saves")))
+                    .on_disabled_hover_text("Nothing to import");
+                if import_button.clicked() {
+                    // For undoing overwrites.
+                    let overwritten_bytes: HashMap<PathBuf, Vec<u8>> = pack_import.planned.iter()
+                        .filter_map(|planned| match &planned.action {
+                            pack::ImportAction::Overwrite(path) => Some((path.clone(), fs::read(path).ok()?)),
+                            _ => None,
+                        })
+                        .collect();
+                    let results = {
+                        let _operation = self.own_operations.begin(pack_import.planned.iter().filter_map(|planned| match &planned.action {
+                            pack::ImportAction::Create(path) | pack::ImportAction::Overwrite(path) => Some(path.clone()),
+                            _ => None,
+                        }));
+                        pack::apply_import(&pack_import.pack, &pack_import.planned, &self.logger)
+                    };
+                    let mut imported_paths = HashSet::new();
+                    let mut failures = Vec::new();
+                    let mut recording = journal::Recording::default();
+                    for (planned, result) in pack_import.planned.iter().zip(results) {
+                        match result {
+                            Ok(Some(path)) => {
+                                let Ok(save) = &pack_import.pack.entries[planned.entry_index].save else { unreachable!() };
+                                let save_bytes = save.0.serialize().into_vec();
+                                match overwritten_bytes.get(&path) {
+                                    Some(old_bytes) => recording.written(path.clone(), old_bytes.clone(), path.clone(), save_bytes.clone()),
+                                    None if matches!(planned.action, pack::ImportAction::Create(_)) => recording.created(path.clone(), save_bytes.clone()),
+                                    None => self.logger.log(LogLevel::Warn, format!("can't undo overwriting {path:?}, its old contents couldn't be read").as_str()),
+                                }
+                                // NOTE Round trip through bytes since CostumeSave isn't Clone.
+                                let save = costume::CostumeSave::parse(&save_bytes).unwrap();
+                                let mut entry = CostumeEntry::new(&path, save, timestamp_display);
+                                entry.mark_written(&path);
+                                costume_entries.insert(path.clone(), entry);
+                                imported_paths.insert(path);
+                            },
+                            Ok(None) => {},
+                            Err(err) => failures.push(err),
+                        }
+                    }
+                    self.logger.log(LogLevel::Info, format!("imported {} saves from {:?}", imported_paths.len(), pack_import.pack_path).as_str());
+                    self.history.record(format!("Import {} saves from a pack", imported_paths.len()), recording, &self.logger);
+
+                    self.refresh_sorted_saves(timestamp_display, costume_entries);
+                    self.selected_costumes = self.sorted_saves.iter().enumerate()
+                        .filter(|(_, path)| imported_paths.contains(*path))
+                        .map(|(idx, _)| idx)
+                        .collect();
+                    self.costume_edit = None;
+
+                    if !failures.is_empty() {
+                        self.logger.log_err_ack_required(AppError::PackImportIncomplete { failures, attempted: num_writes });
+                    }
+                    close_modal = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    close_modal = true;
+                }
+            });
+        });
+
+        if !close_modal {
+            self.pack_import = Some(pack_import);
+        }
+    }
+
+    /// The duplicate review dialog, if it's open.
+    fn show_duplicate_review(&mut self, ctx: &egui::Context, window_rect: egui::Rect, costume_entries: &mut std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>, timestamp_display: &costume::TimestampDisplay) {
+        let Some(mut duplicate_review) = self.duplicate_review.take() else { return };
+        const THUMBNAIL_SIZE: f32 = 150.0;
+        let mut close_modal = false;
+        egui::Modal::new(egui::Id::new("Duplicate Review")).show(ctx, |ui| {
+            ui.set_max_height(window_rect.height() * 0.9);
+            ui.set_max_width(window_rect.width() * 0.9);
+            ui.set_min_size([0.0, 0.0].into());
+
+            ui.label("Find Duplicate Costumes");
+            ui.separator();
+            ui.horizontal(|ui| {
+                let mut regroup = false;
+                regroup |= ui.radio_value(&mut duplicate_review.kind, DuplicateKind::SameSpec, "Same costume spec")
+                    .on_hover_text("Saves whose costumes are exactly the same, whatever their names or preview images.")
+                    .changed();
+                let similar_image = ui.radio_value(&mut duplicate_review.kind, DuplicateKind::SimilarImage, "Similar preview image")
+                    .on_hover_text("Saves whose preview images look alike. Also catches costumes with small differences, e.g. stance.");
+                if similar_image.changed() {
+                    regroup = true;
+                    // Hash every preview that isn't already on its way from the decode threads.
+                    for (path, entry) in costume_entries.iter() {
+                        if entry.image_hash.is_none() && !matches!(entry.image_texture, CostumeImage::Loading) {
+                            _ = self.decode_job_tx.send(DecodeJob { file_path: path.clone(), content_hash: entry.content_hash, hash_only: true, make_thumbnail: entry.thumbnail.is_none() });
+                        }
+                    }
+                }
+                if duplicate_review.kind == DuplicateKind::SimilarImage {
+                    ui.separator();
+                    regroup |= ui.add(egui::Slider::new(&mut duplicate_review.max_image_distance, 0..=16).text("max difference"))
+                        .on_hover_text("How many of the 64 bits of the image hashes may differ")
+                        .changed();
+                }
+                if regroup {
+                    duplicate_review.regroup(costume_entries);
+                }
+            });
+
+            if duplicate_review.kind == DuplicateKind::SimilarImage {
+                let num_hashed = costume_entries.values().filter(|entry| entry.image_hash.is_some()).count();
+                if num_hashed != duplicate_review.num_hashed {
+                    duplicate_review.regroup(costume_entries);
+                }
+                if num_hashed < costume_entries.len() {
+                    ui.weak(format!("Hashing preview images... {num_hashed} of {}", costume_entries.len()));
+                }
+            }
+
+            ui.separator();
+            if duplicate_review.groups.is_empty() {
+                ui.label("No duplicates found.");
+            }
+            let row_height = THUMBNAIL_SIZE + 4.0 * ui.text_style_height(&egui::TextStyle::Body);
+            egui::ScrollArea::vertical().max_height(window_rect.height() * 0.6).show_rows(ui, row_height, duplicate_review.groups.len(), |ui, group_range| {
+                for group_idx in group_range {
+                    let group = &duplicate_review.groups[group_idx];
+                    egui::ScrollArea::horizontal().id_salt(group_idx).show(ui, |ui| {
+                        ui.set_height(row_height);
+                        ui.horizontal(|ui| {
+                            for (idx, path) in group.iter().enumerate() {
+                                // NOTE Saves removed externally regroup but that's only noticed next frame.
+                                let Some(entry) = costume_entries.get_mut(path) else { continue };
+                                ui.vertical(|ui| {
+                                    ui.set_width(THUMBNAIL_SIZE);
+                                    if let CostumeImage::Loaded(texture) = &entry.image_texture {
+                                        ui.add(egui::Image::new(texture).maintain_aspect_ratio(true).max_size([THUMBNAIL_SIZE, THUMBNAIL_SIZE].into()));
+                                    } else {
+                                        if matches!(entry.image_texture, CostumeImage::NotLoaded) {
+                                            _ = self.decode_job_tx.send(DecodeJob { file_path: path.clone(), content_hash: entry.content_hash, hash_only: false, make_thumbnail: entry.thumbnail.is_none() });
+                                            entry.image_texture = CostumeImage::Loading;
+                                        }
+                                        ui.add_sized([THUMBNAIL_SIZE, THUMBNAIL_SIZE], egui::Label::new("loading image..."));
+                                    }
+                                    ui.radio_value(&mut duplicate_review.keepers[group_idx], idx, "Keep");
+                                    ui.add(egui::Label::new(entry.file_name.to_string()).truncate())
+                                        .on_hover_text(path.to_string_lossy());
+                                    ui.add(egui::Label::new(egui::RichText::new(&entry.in_game_display_name).weak()).truncate());
+                                });
+                            }
+                        });
+                    });
+                    ui.separator();
+                }
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Keep Newest").on_hover_text("Keep the newest save of every group").clicked() {
+                    duplicate_review.keep(dedupe::Keep::Newest, costume_entries);
+                }
+                if ui.button("Keep Oldest").on_hover_text("Keep the oldest save of every group").clicked() {
+                    duplicate_review.keep(dedupe::Keep::Oldest, costume_entries);
+                }
+                ui.separator();
+                ui.radio_value(&mut duplicate_review.removal, dedupe::Removal::Archive, "Archive the rest")
+                    .on_hover_text(format!("Move the other saves into a {:?} folder in the costume directory", dedupe::ARCHIVE_DIR_NAME));
+                ui.radio_value(&mut duplicate_review.removal, dedupe::Removal::Trash, "Move the rest to the trash")
+                    .on_hover_text("Move the other saves to the trash, after confirming, like deleting them");
+            });
+            ui.horizontal(|ui| {
+                let num_to_remove = duplicate_review.num_to_remove();
+                let label = match duplicate_review.removal {
+                    dedupe::Removal::Archive => format!("Archive {num_to_remove} Duplicates"),
+                    dedupe::Removal::Trash => format!("Move {num_to_remove} Duplicates to the Trash..."),
+                };
+                if ui.add_enabled(num_to_remove > 0, egui::Button::new(label)).clicked() {
+                    let to_remove: Vec<PathBuf> = duplicate_review.groups.iter().zip(duplicate_review.keepers.iter())
+                        .flat_map(|(group, &keeper)| group.iter().enumerate().filter(move |(idx, _)| *idx != keeper).map(|(_, path)| path.clone()))
+                        .collect();
+                    match duplicate_review.removal {
+                        // The delete confirmation regroups once they're gone.
+                        dedupe::Removal::Trash => self.delete_confirmation = Some(to_remove),
+                        dedupe::Removal::Archive => {
+                            let mut failures = Vec::new();
+                            let mut recording = journal::Recording::default();
+                            for path in to_remove.iter() {
+                                let archived = {
+                                    let _operation = self.own_operations.begin([path.clone()]);
+                                    dedupe::archive_duplicate(path)
+                                };
+                                match archived {
+                                    Ok(archive_path) => {
+                                        costume_entries.remove(path);
+                                        self.logger.log(LogLevel::Info, format!("archived {path:?} to {archive_path:?}").as_str());
+                                        recording.renamed(path.clone(), archive_path);
+                                    },
+                                    Err(err) => failures.push((path.clone(), err)),
+                                }
+                            }
+                            self.history.record(format!("Archive {num_to_remove} duplicates"), recording, &self.logger);
+
+                            self.refresh_sorted_saves(timestamp_display, costume_entries);
+                            self.selected_costumes.clear();
+                            self.costume_edit = None;
+                            duplicate_review.regroup(costume_entries);
+
+                            if !failures.is_empty() {
+                                self.logger.log_err_ack_required(AppError::DuplicateRemovalIncomplete { failures, attempted: num_to_remove });
+                            }
+                        },
+                    }
+                }
+
+                if ui.button("Close").clicked() {
+                    close_modal = true;
+                }
+            });
+        });
+
+        if !close_modal {
+            self.duplicate_review = Some(duplicate_review);
+        }
+    }
+
+    /// The trash browser, if it's open.
+    fn show_trash_browser(&mut self, ctx: &egui::Context, window_rect: egui::Rect, costume_entries: &mut std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>, timestamp_display: &costume::TimestampDisplay) {
+        let Some(mut trash_browser) = self.trash_browser.take() else { return };
+        let mut close_modal = false;
+        egui::Modal::new(egui::Id::new("Trash")).show(ctx, |ui| {
+            ui.set_max_height(window_rect.height() * 0.9);
+            ui.set_max_width(window_rect.width() * 0.5);
+            ui.set_min_size([0.0, 0.0].into());
+
+            ui.label("Trash");
+            match trash_browser.trash.kind() {
+                trash::TrashKind::Home => ui.weak("Saves deleted from the costume directory, kept in your desktop's trash"),
+                trash::TrashKind::App => ui.weak(format!("Saves deleted from the costume directory, kept in {:?}", trash_browser.trash.dir())),
+            };
+            ui.separator();
+            if trash_browser.trashed_saves.is_empty() {
+                ui.label("The trash is empty");
+            } else {
+                egui::ScrollArea::vertical().max_height(window_rect.height() * 0.5).show(ui, |ui| {
+                    egui::Grid::new("trash_grid").striped(true).show(ui, |ui| {
+                        // Newest first.
+                        for (idx, trashed) in trash_browser.trashed_saves.iter().enumerate().rev() {
+                            let mut selected = trash_browser.selected.contains(&idx);
+                            if ui.checkbox(&mut selected, trashed.original_path.file_name().unwrap().to_string_lossy()).changed() {
+                                if selected {
+                                    trash_browser.selected.insert(idx);
+                                } else {
+                                    trash_browser.selected.remove(&idx);
+                                }
+                            }
+                            ui.label(format!("deleted {}", trashed.deletion_date.format("%Y-%m-%d %H:%M")));
+                            ui.end_row();
+                        }
+                    });
+                });
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Delete saves trashed more than");
+                let retention = ui.add(egui::DragValue::new(&mut self.trash_retention_days).range(0..=3650).suffix(" days"))
+                    .on_hover_text("Checked on startup and whenever the trash is opened. 0 keeps saves until the trash is emptied.");
+                ui.label("ago");
+                if retention.drag_stopped() || (retention.changed() && !retention.dragged()) {
+                    Self::save_app_config(&self.costume_dir, &self.timestamp_display, self.trash_retention_days, &self.logger);
+                }
+            });
+            ui.horizontal(|ui| {
+                let num_selected = trash_browser.selected.len();
+                let restore_button = ui.add_enabled(num_selected > 0, egui::Button::new(format!("Restore {num_selected} Saves")))
+                    .on_disabled_hover_text("Select saves to restore first");
+                if restore_button.clicked() {
+                    let mut selected_indices: Vec<usize> = trash_browser.selected.iter().copied().collect();
+                    selected_indices.sort();
+                    let mut recording = journal::Recording::default();
+                    let mut failures = Vec::new();
+                    let mut restored_paths = HashSet::new();
+                    for trashed in selected_indices.into_iter().map(|idx| &trash_browser.trashed_saves[idx]) {
+                        // NOTE Whatever put the save in the trash wrote the info file, which isn't
+                        // necessarily us.
+                        let info = fs::read(&trashed.info_path).unwrap_or_else(|_| trashed.info_contents().into_bytes());
+                        let restored = {
+                            let _operation = self.own_operations.begin([trashed.original_path.clone()]);
+                            trash_browser.trash.restore(trashed)
+                        };
+                        if let Err(err) = restored {
+                            failures.push(err);
+                            continue;
+                        }
+                        self.logger.log(LogLevel::Info, format!("restored {:?} from the trash", trashed.original_path).as_str());
+                        recording.renamed(trashed.trashed_path.clone(), trashed.original_path.clone());
+                        recording.deleted(trashed.info_path.clone(), info);
+
+                        if costume::is_valid_costume_file_name(&trashed.original_path) {
+                            match CostumeEntry::load(&trashed.original_path, timestamp_display) {
+                                Ok(entry) => {
+                                    costume_entries.insert(trashed.original_path.clone(), entry);
+                                    restored_paths.insert(trashed.original_path.clone());
+                                },
+                                Err(err) => self.logger.log(LogLevel::Warn, err.to_string().as_str()),
+                            }
+                        }
+                    }
+                    self.history.record(format!("Restore {} saves from the trash", num_selected - failures.len()), recording, &self.logger);
+                    trash_browser.refresh(&self.logger);
+
+                    self.tags.counts = None;
+                    self.refresh_sorted_saves(timestamp_display, costume_entries);
+                    self.selected_costumes = self.sorted_saves.iter().enumerate()
+                        .filter(|(_, path)| restored_paths.contains(*path))
+                        .map(|(idx, _)| idx)
+                        .collect();
+                    self.selection_range_pivot = self.selected_costumes.iter().min().copied().unwrap_or(0);
+                    self.costume_edit = None;
+
+                    if !failures.is_empty() {
+                        self.logger.log_err_ack_required(AppError::TrashIncomplete { action: "restore", failures, attempted: num_selected });
+                    }
+                }
+
+                ui.separator();
+                if trash_browser.confirm_empty {
+                    ui.label(format!("Permanently delete all {} saves?", trash_browser.trashed_saves.len()));
+                    if ui.button("Empty Trash").clicked() {
+                        let failures = delete_from_trash(&trash_browser.trash, trash_browser.trashed_saves.iter(), &mut self.tags, &self.logger);
+                        let attempted = trash_browser.trashed_saves.len();
+                        trash_browser.refresh(&self.logger);
+                        if !failures.is_empty() {
+                            self.logger.log_err_ack_required(AppError::TrashIncomplete { action: "delete", failures, attempted });
+                        }
+                    }
+                    if ui.button("Keep Them").clicked() {
+                        trash_browser.confirm_empty = false;
+                    }
+                } else if ui.add_enabled(!trash_browser.trashed_saves.is_empty(), egui::Button::new("Empty Trash...")).clicked() {
+                    trash_browser.confirm_empty = true;
+                }
+
+                ui.separator();
+                if ui.button("Close").clicked() {
+                    close_modal = true;
+                }
+            });
+        });
+
+        if !close_modal {
+            self.trash_browser = Some(trash_browser);
+        }
+    }
+
+    /// The undo history side panel.
+    fn show_history_panel(&mut self, ctx: &egui::Context, history_locked: bool, timestamp_display: &costume::TimestampDisplay) {
+        egui::SidePanel::right("history_panel").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.strong("History");
+                Self::show_undo_buttons(ui, &mut self.history, history_locked);
+            });
+            ui.separator();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                let position = self.history.journal.position();
+                let mut target = None;
+                if ui.selectable_label(position == 0, "Before the oldest change").clicked() {
+                    target = Some(0);
+                }
+                // Newest first. Entries past the position were undone and can be redone.
+                for (index, entry) in self.history.journal.entries().iter().enumerate().rev() {
+                    let time = chrono::DateTime::from_timestamp(entry.time, 0)
+                        .and_then(|time| costume::datetime_to_j2000(&time))
+                        .and_then(|j2000_timestamp| timestamp_display.format_j2000(j2000_timestamp))
+                        .unwrap_or_default();
+                    let text = egui::RichText::new(format!("{}\n{time}", entry.description));
+                    let text = if index < position { text } else { text.weak() };
+                    let response = ui.selectable_label(index + 1 == position, text)
+                        .on_hover_text(format!("{} file changes. Click to undo or redo up to here.", entry.changes.len()));
+                    if response.clicked() {
+                        target = Some(index + 1);
+                    }
+                }
+                if let Some(target) = target.filter(|target| *target != position) {
+                    if history_locked {
+                        self.logger.log(LogLevel::Warn, "can't undo or redo while there are unsaved edits or a dialog is open");
+                    } else {
+                        self.history.target = Some(target);
+                        ctx.request_repaint();
+                    }
+                }
+            });
+        });
+    }
+
+    /// Editing every selected save at once.
+    fn show_bulk_edit(&mut self, ui: &mut egui::Ui, selected_paths: &[PathBuf], costume_entries: &mut std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>, timestamp_display: &costume::TimestampDisplay) {
+        ui.label("Bulk Edit:");
+        let mut bulk_edit = std::mem::take(&mut self.bulk_edit);
+        egui::Grid::new("bulk edit").num_columns(2).show(ui, |ui| {
+            ui.checkbox(&mut bulk_edit.set_account_name, "Account Name:");
+            ui.add_enabled(bulk_edit.set_account_name, egui::TextEdit::singleline(&mut bulk_edit.account_name));
+            ui.end_row();
+
+            ui.checkbox(&mut bulk_edit.set_character_name, "Character Name:");
+            ui.add_enabled(bulk_edit.set_character_name, egui::TextEdit::singleline(&mut bulk_edit.character_name));
+            ui.end_row();
+
+            ui.checkbox(&mut bulk_edit.set_keywords, "Keywords:");
+            ui.add_enabled(bulk_edit.set_keywords, egui::TextEdit::singleline(&mut bulk_edit.keywords))
+                .on_hover_text("Comma-separated, replaces every keyword. Leave empty to remove them all.");
+            ui.end_row();
+
+            ui.label("Timestamps:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut bulk_edit.timestamp_edit, BulkTimestampEdit::Keep, "Keep");
+                ui.radio_value(&mut bulk_edit.timestamp_edit, BulkTimestampEdit::Strip, "Strip");
+                if ui.radio_value(&mut bulk_edit.timestamp_edit, BulkTimestampEdit::Set, "Set").clicked() && bulk_edit.j2000_timestamp == 0 {
+                    bulk_edit.j2000_timestamp = costume::datetime_to_j2000(&chrono::Utc::now()).unwrap_or(0);
+                }
+            });
+            ui.end_row();
+
+            if bulk_edit.timestamp_edit == BulkTimestampEdit::Set {
+                ui.label("");
+                ui.horizontal(|ui| {
+                    // Same as the single save editor except the timestamp is always set.
+                    let Some(datetime) = timestamp_display.j2000_to_naive(bulk_edit.j2000_timestamp) else {
+                        ui.colored_label(ui.visuals().warn_fg_color, "out of range");
+                        return;
+                    };
+                    let mut date = datetime.date();
+                    let (mut hour, mut minute, mut second) = (datetime.hour(), datetime.minute(), datetime.second());
+                    let mut changed = ui.add(egui_extras::DatePickerButton::new(&mut date).id_salt("bulk timestamp date")).changed();
+                    changed |= ui.add(egui::DragValue::new(&mut hour).range(0..=23)).changed();
+                    ui.label(":");
+                    changed |= ui.add(egui::DragValue::new(&mut minute).range(0..=59)).changed();
+                    ui.label(":");
+                    changed |= ui.add(egui::DragValue::new(&mut second).range(0..=59)).changed();
+                    ui.label(timestamp_display.time_zone.label());
+
+                    let new_j2000_timestamp = timestamp_display.naive_to_j2000(date.and_hms_opt(hour, minute, second).unwrap());
+                    if let (true, Some(new_j2000_timestamp)) = (changed, new_j2000_timestamp) {
+                        bulk_edit.j2000_timestamp = new_j2000_timestamp;
+                    }
+                });
+                ui.end_row();
+            }
+
+            ui.label("Find in Save Names:");
+            ui.text_edit_singleline(&mut bulk_edit.find);
+            ui.end_row();
+
+            ui.label("Replace With:");
+            ui.add_enabled(!bulk_edit.find.is_empty(), egui::TextEdit::singleline(&mut bulk_edit.replace));
+            ui.end_row();
+        });
+
+        let previews = bulk_edit.preview(selected_paths, costume_entries, timestamp_display);
+        let num_changed = previews.iter().filter(|preview| preview.has_changes).count();
+        let num_problems = previews.iter().filter(|preview| preview.has_changes && preview.problem.is_some()).count();
+        egui::CollapsingHeader::new(format!("Preview ({num_changed} changed)")).show(ui, |ui| {
+            egui::ScrollArea::both().id_salt("bulk edit preview").max_height(300.0).show(ui, |ui| {
+                egui::Grid::new("bulk edit preview grid").striped(true).show(ui, |ui| {
+                    ui.strong("File Name");
+                    ui.strong("In-Game Display");
+                    ui.end_row();
+                    for preview in previews.iter() {
+                        let file_name = preview.new_file_name.to_string();
+                        match &preview.problem {
+                            Some(problem) if preview.has_changes => {
+                                ui.colored_label(ui.visuals().error_fg_color, file_name).on_hover_text(problem);
+                            },
+                            _ if !preview.has_changes => { ui.weak(file_name); },
+                            _ => { ui.label(file_name).on_hover_text(format!("was {:?}", preview.file_path.file_name().unwrap())); },
+                        }
+                        ui.label(&preview.new_in_game_display_name);
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+
+        let apply_button = ui.add_enabled(num_changed > 0 && num_problems == 0, egui::Button::new(format!("Save {num_changed} Costumes")))
+            .on_disabled_hover_text(if num_changed == 0 {
+                "Nothing would change".to_owned()
+            } else {
+                format!("{num_problems} of the saves can't be written, see the preview for details")
+            });
+        if apply_button.clicked() {
+            let mut failures = Vec::new();
+            let mut new_selected_paths = HashSet::new();
+            let mut recording = journal::Recording::default();
+            for preview in previews.into_iter() {
+                let old_file_path = &preview.file_path;
+                if !preview.has_changes {
+                    new_selected_paths.insert(old_file_path.clone());
+                    continue;
+                }
+
+                let new_file_path = old_file_path.with_file_name(preview.new_file_name.to_string());
+                let costume = costume_entries.get_mut(old_file_path).unwrap();
+                if new_file_path != *old_file_path && new_file_path.exists() {
+                    failures.push(AppError::CostumeSaveFailed { source: None, which: old_file_path.clone(), message: format!("{new_file_path:?} already exists") });
+                    new_selected_paths.insert(old_file_path.clone());
+                    continue;
+                }
+                if costume.changed_on_disk(old_file_path) {
+                    failures.push(AppError::CostumeSaveFailed { source: None, which: old_file_path.clone(), message: "changed outside of the app since it was loaded".to_owned() });
+                    new_selected_paths.insert(old_file_path.clone());
+                    continue;
+                }
+
+                let old_tag_key = costume.tag_key();
+                if let Err(costume_load_error) = costume.load_full_save(old_file_path) {
+                    failures.push(costume_load_error);
+                    new_selected_paths.insert(old_file_path.clone());
+                    continue;
+                }
+                // For undoing the edit.
+                let old_bytes = match fs::read(old_file_path) {
+                    Ok(old_bytes) => old_bytes,
+                    Err(err) => {
+                        failures.push(AppError::CostumeSaveFailed { source: Some(err), which: old_file_path.clone(), message: "failed to read file".to_owned() });
+                        new_selected_paths.insert(old_file_path.clone());
+                        continue;
+                    },
+                };
+                let original_metadata = costume.save.get_metadata().unwrap();
+                let metadata = bulk_edit.edit_metadata(&original_metadata).into_owned();
+                let original_metadata = original_metadata.revert_snapshot(&metadata);
+                costume.update_metadata(&metadata);
+                let written = {
+                    let _operation = self.own_operations.begin([old_file_path.clone(), new_file_path.clone()]);
+                    write_costume_save(&costume.save, old_file_path, &new_file_path, &self.logger)
+                };
+                if let Err(costume_save_error) = written {
+                    failures.push(costume_save_error);
+                    // REVERT COSTUME CHANGES
+                    costume.update_metadata(&original_metadata);
+                    new_selected_paths.insert(old_file_path.clone());
+                    continue;
+                }
+                recording.written(old_file_path.clone(), old_bytes, new_file_path.clone(), costume.save.0.serialize().into());
+
+                let mut entry = costume_entries.remove(old_file_path).unwrap();
+                entry.mark_written(&new_file_path);
+                entry.file_name = preview.new_file_name;
+                entry.in_game_display_name = preview.new_in_game_display_name;
+                self.tags.relink([(old_tag_key, entry.tag_key())], &self.logger);
+                costume_entries.insert(new_file_path.clone(), entry);
+                new_selected_paths.insert(new_file_path);
+            }
+            self.logger.log(LogLevel::Info, format!("bulk edited {} of {num_changed} saves", num_changed - failures.len()).as_str());
+            self.history.record(format!("Bulk edit {} saves", num_changed - failures.len()), recording, &self.logger);
+            if failures.is_empty() {
+                bulk_edit = BulkEdit::default();
+            } else {
+                self.logger.log_err_ack_required(AppError::BulkEditIncomplete { failures, attempted: num_changed });
+            }
+
+            self.refresh_sorted_saves(timestamp_display, costume_entries);
+            self.selected_costumes = self.sorted_saves.iter().enumerate()
+                .filter(|(_, path)| new_selected_paths.contains(*path))
+                .map(|(idx, _)| idx)
+                .collect();
+            // New keywords can filter saves out, possibly leaving a single selection
+            // that needs an editor.
+            self.selection_range_pivot = self.selected_costumes.iter().min().copied().unwrap_or(0);
+            self.costume_edit = (self.selected_costumes.len() == 1)
+                .then(|| CostumeEdit::new_from_entry(&costume_entries[&self.sorted_saves[self.selection_range_pivot]]));
+        }
+        self.bulk_edit = bulk_edit;
+    }
+
+    /// Refilter and resort the list after the entries or the filters changed.
+    fn refresh_sorted_saves(&mut self, timestamp_display: &costume::TimestampDisplay, locked_costume_entries: &std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>) {
+        self.entries_generation += 1;
+        self.sorted_saves = Self::filter_saves(self.keyword_filter.as_deref(), &self.search_query, self.tag_filter.as_ref(), &self.tags.database, timestamp_display, locked_costume_entries);
+        Self::sort_saves(self.sort_type, self.display_type, &mut self.sorted_saves, locked_costume_entries);
+    }
+
+    // TODO Should we just clear our selected costumes in here? I think basically every time we
+    // sort we do that.
+    // FIXME We might need to support case-insensitive sorting on non-ascii characters, in which
+    // case we'll need to use to_lowercase(). Might require more cloning than is necessary, so
+    // maybe find a way to do that efficiently.
+    fn sort_saves(sort_type: SortType, display_type: DisplayType, keys_to_sort: &mut [PathBuf], locked_costume_entries: &std::sync::MutexGuard<HashMap<PathBuf, CostumeEntry>>) {
+        match sort_type {
+            SortType::Name => {
+                match display_type {
+                    DisplayType::DisplayName => {
+                        keys_to_sort.sort_by_key(|k| {
+                            let entry = &locked_costume_entries[k];
+                            let metadata = entry.save.get_metadata().unwrap();
+                            costume::in_game_display_sort_key(metadata.account_name(), metadata.character_name(), entry.file_name.j2000_timestamp)
+                        });
+                    },
+
+                    DisplayType::FileName => {
+                        keys_to_sort.sort_by_key(|k| k.file_stem().unwrap().to_ascii_lowercase());
+                    },
+                }
+            },
+
+            SortType::CreationTime => {
+                keys_to_sort.sort_by_key(|k| {
+                    let metadata = fs::metadata(k).unwrap();
+                    std::cmp::Reverse(metadata.created().unwrap())
+                });
+            },
+
+            SortType::ModifiedTime => {
+                keys_to_sort.sort_by_key(|k| {
+                    let metadata = fs::metadata(k).unwrap();
+                    std::cmp::Reverse(metadata.modified().unwrap())
+                });
+            }
+        };
+    }
+}
+
+impl eframe::App for App {
+    // Gracefully shut down all our supporting threads.
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.logger.log(LogLevel::Info, "signalling shutdown");
+        self.shutdown_flag.store(true, atomic::Ordering::Release);
+        // NOTE(RA): I think draining the vector like this is okay for now since we shouldn't
+        // update again after handling this exit event.
+        self.support_thread_handles.drain(..).for_each(|thread_handle| {
+            thread_handle.join().unwrap();
+        });
+
+        let mut metadata_index = index::MetadataIndex::default();
+        for (path, entry) in self.costume_entries.lock().unwrap().iter() {
+            if let Some(indexed) = entry.to_index() {
+                metadata_index.insert(path.clone(), indexed);
+            }
+        }
+        match metadata_index.save(&index::MetadataIndex::default_path()) {
+            Ok(()) => self.logger.log(LogLevel::Info, format!("wrote {} saves to the metadata index", metadata_index.len()).as_str()),
+            Err(err) => self.logger.log(LogLevel::Warn, format!("failed to write the metadata index: {err}").as_str()),
+        }
+        self.logger.log(LogLevel::Info, "shutdown complete");
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let window_rect = ctx.available_rect();
+
+        if self.logger.ui_ack_required() {
+            egui::Modal::new(egui::Id::new("error modal")).show(ctx, |ui| {
+                ui.set_max_size(window_rect.size() * 0.9);
+                ui.set_min_width(0.0);
+
+                {
+                    let logger = self.logger.shared_logger.read().unwrap();
+                    let error = logger.last_error.as_ref().unwrap();
+                    let header = match error {
+                        AppError::CostumeSaveFailed { .. } => "Costume Save Failed",
+                        AppError::CostumeLoadFailed { .. } => "Costume Load Failed",
+                        AppError::BatchRenameFailed(_) => "Batch Rename Failed",
+                        AppError::BulkEditIncomplete { .. } => "Bulk Edit Failed",
+                        AppError::ExportFailed(_) => "Export Failed",
+                        AppError::PackFailed(_) => "Costume Pack Failed",
+                        AppError::PackImportIncomplete { .. } => "Pack Import Failed",
+                        AppError::DuplicateRemovalIncomplete { .. } => "Duplicate Removal Failed",
+                        AppError::TagDatabaseFailed(_) => "Tag Database Error",
+                        AppError::HistoryFailed(_) => "Undo History Error",
+                        AppError::TrashFailed(_) | AppError::TrashIncomplete { .. } => "Trash Failed",
+                        AppError::RecoveryFailed { .. } => "Save Recovery Failed",
+                        AppError::UndoFailed { redo: false, .. } => "Undo Failed",
+                        AppError::UndoFailed { redo: true, .. } => "Redo Failed",
+                    };
+                    let description = error.to_string();
+                    ui.label(header);
+                    ui.label(description);
+
+                    ui.separator();
+                    egui::ScrollArea::both().show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        let locked_logger = self.logger.shared_logger.read().unwrap();
+                        for log in locked_logger.logs.iter() {
+                            ui.label(&log.message);
+                        }
+                    });
+                    ui.separator();
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Okay").clicked() {
+                        self.logger.ack_errors();
+                    }
+
+                    if ui.button("Copy Logs to Clipboard").clicked() {
+                        let logs = &self.logger.shared_logger.read().unwrap().logs;
+                        let num_logs = logs.len();
+                        // 100 bytes per log + bytes for newlines be a good starting assumption
+                        let mut serialized = String::with_capacity(num_logs * 100 + num_logs - 1);
+                        for Log { message, .. } in logs.iter() {
+                            serialized.push_str(message);
+                            serialized.push('\n');
+                        }
+
+                        ui.output_mut(|platform| platform.copied_text = serialized);
+                    }
+                });
+            });
+
+            return;
+        }
+
+        // NOTE Locked through a clone of the Arc so that the guard doesn't borrow self, which the
+        // panels below need mutably.
+        let costume_entries_mutex = Arc::clone(&self.costume_entries);
+        let mut costume_entries = costume_entries_mutex.lock().unwrap();
+        let current_modifiers = ctx.input(|input| input.modifiers);
+        let timestamp_display = self.timestamp_display.read().unwrap().clone();
+        // Whether the tag filter or which saves it matches changed, so the list needs refiltering.
+        let mut tags_changed = false;
+
+        // NOTE Undo and redo reload the saves they touch, which would throw away unsaved edits.
+        let history_locked = ctx.memory(|memory| memory.top_modal_layer()).is_some()
+            || self.costume_edit.as_ref().is_some_and(|costume_edit| {
+                self.selected_costumes.iter().next()
+                    .and_then(|idx| costume_entries.get(&self.sorted_saves[*idx]))
+                    .is_some_and(|entry| costume_edit.has_changes(entry))
+            });
+        if !history_locked && !ctx.wants_keyboard_input() {
+            // NOTE Check redo first since the undo shortcut also matches with shift held.
+            let redo_shortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z);
+            let undo_shortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+            let position = self.history.journal.position();
+            if ctx.input_mut(|input| input.consume_shortcut(&redo_shortcut)) {
+                self.history.target = Some((position + 1).min(self.history.journal.entries().len()));
+            } else if ctx.input_mut(|input| input.consume_shortcut(&undo_shortcut)) {
+                self.history.target = Some(position.saturating_sub(1));
+            }
+        }
+
+        let mut history_message = None;
+        if let Some(target) = self.history.target.take().filter(|_| !history_locked) {
+            let costume_dir = self.costume_dir.read().unwrap().clone();
+            let mut tag_relinks = Vec::new();
+            let mut modified = Vec::new();
+            while self.history.journal.position() != target {
+                let redo = self.history.journal.position() < target;
+                let entry = match redo {
+                    true => self.history.journal.next_redo(),
+                    false => self.history.journal.next_undo(),
+                }.unwrap().clone();
+                let paths: HashSet<PathBuf> = entry.paths().into_iter().collect();
+                let result = {
+                    let _operation = self.own_operations.begin(paths.iter().cloned());
+                    match redo {
+                        true => self.history.journal.redo(),
+                        false => self.history.journal.undo(),
+                    }
+                };
+
+                // Reload whatever is there now even if the step failed, in case it couldn't be
+                // rolled back completely.
+                let mut old_tag_keys = HashMap::new();
+                for file_path in paths.iter() {
+                    ctx.forget_image(&thumbnail_uri(file_path));
+                    let old_entry = costume_entries.remove(file_path);
+                    let in_costume_dir = file_path.parent() == costume_dir.as_deref() && costume::is_valid_costume_file_name(file_path);
+                    if in_costume_dir && file_path.exists() {
+                        match CostumeEntry::load(file_path, &timestamp_display) {
+                            Ok(costume_entry) => {
+                                if let Some(old_entry) = old_entry.as_ref() {
+                                    tag_relinks.push((old_entry.tag_key(), costume_entry.tag_key()));
+                                    modified.push(file_path.clone());
+                                }
+                                costume_entries.insert(file_path.clone(), costume_entry);
+                            },
+                            Err(err) => self.logger.log(LogLevel::Warn, err.to_string().as_str()),
+                        }
+                    }
+                    if let Some(old_entry) = old_entry {
+                        old_tag_keys.insert(file_path.clone(), old_entry.tag_key());
+                    }
+                }
 
-        while let Ok(priority_message) = self.ui_priority_message_rx.try_recv() {
+                let action = if redo { "redo" } else { "undo" };
+                match result {
+                    Ok(()) => {
+                        for (from, to) in entry.net_moves(redo) {
+                            if let (Some(old_tag_key), Some(costume_entry)) = (old_tag_keys.remove(&from), costume_entries.get(&to)) {
+                                tag_relinks.push((old_tag_key, costume_entry.tag_key()));
+                            }
+                        }
+                        self.logger.log(LogLevel::Info, format!("{action}: {}", entry.description).as_str());
+                    },
+                    Err(source) => {
+                        self.logger.log_err_ack_required(AppError::UndoFailed { redo, description: entry.description, source });
+                        break;
+                    },
+                }
+            }
+            tag_relinks.retain(|(old_tag_key, new_tag_key)| old_tag_key != new_tag_key);
+            history_message = Some(UiPriorityMessage::FileListChangedExternally { tag_relinks, modified });
+        }
+
+        for priority_message in history_message.into_iter().chain(self.ui_priority_message_rx.try_iter().collect::<Vec<_>>()) {
             match priority_message {
-                UiPriorityMessage::FileListChangedExternally => {
-                    // NOTE(RA): For now we're just going to reset selections whenever the file
-                    // list changes. Might change this later. We used to only do this whenever the
-                    // file(s) the user was viewing were removed from the file system.
-                    self.selected_costumes.clear();
-                    self.costume_edit = None;
-                    self.sorted_saves = costume_entries.keys().cloned().collect();
-                    Self::sort_saves(self.sort_type, self.display_type, &mut self.sorted_saves, &costume_entries);
+                UiPriorityMessage::FileListChangedExternally { tag_relinks, modified } => {
+                    self.tags.relink(tag_relinks, &self.logger);
+                    for file_path in modified.iter() {
+                        // The old texture went away with the old entry but egui keeps the thumbnail.
+                        ctx.forget_image(&thumbnail_uri(file_path));
+                    }
+
+                    // Keep whatever is still there selected, and the editor open if its save is.
+                    let selected_paths: HashSet<PathBuf> = self.selected_costumes.iter().map(|idx| self.sorted_saves[*idx].clone()).collect();
+                    let edited_path = self.costume_edit.as_ref().and_then(|_| selected_paths.iter().next()).cloned();
+                    self.batch_rename = None;
+                    self.pack_import = None;
+                    self.tags.counts = None;
+                    if let Some(duplicate_review) = self.duplicate_review.as_mut() {
+                        duplicate_review.regroup(&costume_entries);
+                    }
+                    self.refresh_sorted_saves(&timestamp_display, &costume_entries);
+                    self.selected_costumes = self.sorted_saves.iter().enumerate()
+                        .filter(|(_, path)| selected_paths.contains(*path))
+                        .map(|(idx, _)| idx)
+                        .collect();
+                    self.selection_range_pivot = self.selected_costumes.iter().min().copied().unwrap_or(0);
+
+                    let single_selection = (self.selected_costumes.len() == 1).then(|| self.sorted_saves[self.selection_range_pivot].clone());
+                    match single_selection {
+                        Some(file_path) if edited_path.as_ref() == Some(&file_path) => {
+                            let entry = &costume_entries[&file_path];
+                            if modified.contains(&file_path) {
+                                if self.costume_edit.as_ref().unwrap().has_changes(entry) {
+                                    self.edit_conflict = Some(EditConflict { file_path, show_differences: false });
+                                } else {
+                                    self.costume_edit = Some(CostumeEdit::new_from_entry(entry));
+                                }
+                            }
+                        },
+                        Some(file_path) => {
+                            self.costume_edit = Some(CostumeEdit::new_from_entry(&costume_entries[&file_path]));
+                            self.edit_conflict = None;
+                        },
+                        None => {
+                            self.costume_edit = None;
+                            self.edit_conflict = None;
+                        },
+                    }
                 },
             }
         }
@@ -576,14 +2737,428 @@ impl eframe::App for App {
             let message = self.ui_message_rx.try_recv();
             if message.is_err() { break; }
             match message.unwrap() {
-                UiMessage::JpegDecoded { file_path, texture_handle } => {
-                    if costume_entries.contains_key(&file_path) {
-                        costume_entries.get_mut(&file_path).unwrap().image_texture = CostumeImage::Loaded(texture_handle);
+                UiMessage::JpegDecoded { file_path, content_hash, texture_handle, image_hash, thumbnail } => {
+                    let Some(entry) = costume_entries.get_mut(&file_path) else { continue };
+                    if entry.content_hash != content_hash {
+                        // The save was reloaded or written while this was decoding. Ask again for
+                        // the current version.
+                        if matches!(entry.image_texture, CostumeImage::Loading) {
+                            entry.image_texture = CostumeImage::NotLoaded;
+                        }
+                        continue;
+                    }
+                    entry.image_texture = CostumeImage::Loaded(texture_handle);
+                    entry.image_hash = Some(image_hash);
+                    entry.thumbnail = entry.thumbnail.take().or(thumbnail);
+                },
+                UiMessage::ImageHashed { file_path, content_hash, image_hash, thumbnail } => {
+                    let Some(entry) = costume_entries.get_mut(&file_path) else { continue };
+                    if entry.content_hash == content_hash {
+                        entry.image_hash = Some(image_hash);
+                        entry.thumbnail = entry.thumbnail.take().or(thumbnail);
+                    }
+                },
+                UiMessage::DecodeStale { file_path, content_hash } => {
+                    // NOTE If the entry doesn't match the file yet it stays loading until the
+                    // scanner reloads it, which replaces its image anyway.
+                    let Some(entry) = costume_entries.get_mut(&file_path) else { continue };
+                    if entry.content_hash == Some(content_hash) && matches!(entry.image_texture, CostumeImage::Loading) {
+                        entry.image_texture = CostumeImage::NotLoaded;
                     }
                 },
+                UiMessage::ScanProgress { num_loaded, num_found } => {
+                    self.scan_progress = (num_loaded < num_found).then_some((num_loaded, num_found));
+                },
+            }
+        }
+
+        if let Some(mut new_costume) = self.new_costume.take() {
+            let mut close_modal = false;
+            egui::Modal::new(egui::Id::new("New Costume")).show(ctx, |ui| {
+                ui.set_max_height(window_rect.height() * 0.9);
+                ui.set_max_width(window_rect.width() * 0.5);
+                ui.set_min_size([0.0, 0.0].into());
+
+                ui.label("New Costume");
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Save Name:");
+                    ui.text_edit_singleline(&mut new_costume.save_name);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Account Name:");
+                    ui.text_edit_singleline(&mut new_costume.account_name);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Character Name:");
+                    ui.text_edit_singleline(&mut new_costume.character_name);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Image:");
+                    ui.label(if new_costume.image_jpeg.is_some() { new_costume.image_source.as_str() } else { "none" });
+                    if ui.button("Choose Image...").clicked() {
+                        let image_path = rfd::FileDialog::new()
+                            .set_title("Select an image for the costume save")
+                            .add_filter("Images", &["jpg", "jpeg", "png", "bmp", "gif", "webp"])
+                            .pick_file();
+                        if let Some(image_path) = image_path {
+                            match image::open(&image_path).and_then(|image| encode_costume_image(&image)) {
+                                Ok(image_jpeg) => {
+                                    new_costume.image_jpeg = Some(image_jpeg);
+                                    new_costume.image_source = image_path.to_string_lossy().into_owned();
+                                },
+                                Err(err) => self.logger.log(LogLevel::Error, format!("failed to load image {image_path:?}: {err}").as_str()),
+                            }
+                        }
+                    }
+                });
+
+                ui.label("Spec:");
+                let scroll_area = egui::ScrollArea::vertical().id_salt("new costume spec").max_height(window_rect.height() * 0.5);
+                scroll_area.show(ui, |ui| {
+                    let diagnostics = &new_costume.spec_diagnostics;
+                    let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                        let job = layout_costume_spec(ui, text, diagnostics, wrap_width);
+                        ui.fonts(|fonts| fonts.layout_job(job))
+                    };
+                    let spec_editor = ui.add(
+                        egui::TextEdit::multiline(&mut new_costume.costume_spec)
+                            .code_editor()
+                            .desired_rows(12)
+                            .desired_width(f32::INFINITY)
+                            .layouter(&mut layouter)
+                    );
+                    if spec_editor.changed() {
+                        new_costume.validate_spec(&self.spec_catalog);
+                    }
+                });
+                show_spec_diagnostics(ui, &new_costume.spec_diagnostics);
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let costume_dir = self.costume_dir.read().unwrap().clone();
+                    let spec_is_empty = costume::is_empty_spec(&new_costume.costume_spec);
+                    let spec_has_errors = costume::has_errors(&new_costume.spec_diagnostics);
+                    let can_create = costume_dir.is_some() && new_costume.image_jpeg.is_some() && !spec_is_empty && !spec_has_errors;
+                    let create_button = ui.add_enabled(can_create, egui::Button::new("Create"))
+                        .on_disabled_hover_text(if costume_dir.is_none() {
+                            "No costume directory selected"
+                        } else if new_costume.image_jpeg.is_none() {
+                            "Choose an image first"
+                        } else if spec_is_empty {
+                            "Enter a costume spec first"
+                        } else {
+                            "The costume spec has errors"
+                        });
+                    if create_button.clicked() {
+                        let costume_dir = costume_dir.as_ref().unwrap();
+                        let file_name = costume::CostumeFileName {
+                            save_name: new_costume.save_name.clone(),
+                            j2000_timestamp: costume::datetime_to_j2000(&chrono::Utc::now()),
+                            extension: "jpg".to_owned(),
+                        };
+                        let new_file_path = costume_dir.join(file_name.to_string());
+
+                        if costume_entries.contains_key(&new_file_path) || new_file_path.exists() {
+                            self.file_exists_warning_modal_open = true;
+                        } else {
+                            let image_jpeg = new_costume.image_jpeg.as_ref().unwrap();
+                            let created = {
+                                let _operation = self.own_operations.begin([new_file_path.clone()]);
+                                costume::CostumeSave::new(image_jpeg, &new_costume.account_name, &new_costume.character_name, &new_costume.costume_spec)
+                                    .map_err(|err| AppError::CostumeSaveFailed {
+                                        source: None,
+                                        which: new_file_path.clone(),
+                                        message: format!("failed to build costume save: {err}"),
+                                    })
+                                    .and_then(|save| create_costume_save(&save, &new_file_path).map(|_| save))
+                            };
+
+                            match created {
+                                Ok(save) => {
+                                    self.logger.log(LogLevel::Info, format!("created {new_file_path:?}").as_str());
+                                    let mut recording = journal::Recording::default();
+                                    recording.created(new_file_path.clone(), save.0.serialize().into());
+                                    self.history.record(format!("Create {}", file_name), recording, &self.logger);
+                                    let mut entry = CostumeEntry::new(&new_file_path, save, &timestamp_display);
+                                    entry.mark_written(&new_file_path);
+                                    self.costume_edit = Some(CostumeEdit::new_from_entry(&entry));
+                                    costume_entries.insert(new_file_path.clone(), entry);
+                                    self.refresh_sorted_saves(&timestamp_display, &costume_entries);
+                                    self.selected_costumes.clear();
+                                    match self.sorted_saves.iter().position(|save| *save == new_file_path) {
+                                        Some(new_index) => {
+                                            self.selected_costumes.insert(new_index);
+                                            self.selection_range_pivot = new_index;
+                                        },
+                                        // Filtered out by the keyword filter.
+                                        None => self.costume_edit = None,
+                                    }
+                                    close_modal = true;
+                                },
+                                Err(costume_save_error) => self.logger.log_err_ack_required(costume_save_error),
+                            }
+                        }
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        close_modal = true;
+                    }
+                });
+            });
+
+            if !close_modal {
+                self.new_costume = Some(new_costume);
+            }
+        }
+
+        self.show_batch_rename(ctx, window_rect, &mut costume_entries, &timestamp_display);
+
+        if let Some(note) = self.pack_export_note.as_mut() {
+            let mut close_modal = false;
+            egui::Modal::new(egui::Id::new("Export Pack")).show(ctx, |ui| {
+                ui.label(format!("Export {} Saves as a Costume Pack", self.selected_costumes.len()));
+                ui.separator();
+                ui.label("Note for whoever imports the pack:");
+                ui.add(egui::TextEdit::multiline(note).desired_width(400.0).desired_rows(4));
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Choose File...").clicked() {
+                        let pack_path = rfd::FileDialog::new()
+                            .set_title("Export the selected saves to a costume pack")
+                            .add_filter("Costume pack", &[pack::EXTENSION])
+                            .set_file_name(format!("costumes.{}", pack::EXTENSION))
+                            .save_file();
+                        if let Some(pack_path) = pack_path {
+                            let mut selected_indices: Vec<usize> = self.selected_costumes.iter().copied().collect();
+                            selected_indices.sort();
+                            let selected_paths: Vec<&PathBuf> = selected_indices.into_iter().map(|idx| &self.sorted_saves[idx]).collect();
+                            if let Err(costume_load_error) = Self::load_full_saves(&selected_paths, &mut costume_entries) {
+                                self.logger.log_err_ack_required(costume_load_error);
+                            } else {
+                                let saves = selected_paths.iter().map(|path| {
+                                    let entry = &costume_entries[*path];
+                                    (&entry.file_name, &entry.save)
+                                });
+                                match pack::write_pack(&pack_path, saves, note) {
+                                    Ok(num_packed) => self.logger.log(LogLevel::Info, format!("packed {num_packed} saves into {pack_path:?}").as_str()),
+                                    Err(err) => self.logger.log_err_ack_required(AppError::PackFailed(err)),
+                                }
+                            }
+                            close_modal = true;
+                        }
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        close_modal = true;
+                    }
+                });
+            });
+
+            if close_modal {
+                self.pack_export_note = None;
+            }
+        }
+
+        if let Some(tag_rename) = self.tag_rename.as_mut() {
+            let mut close_modal = false;
+            egui::Modal::new(egui::Id::new("Rename Tag")).show(ctx, |ui| {
+                let what = match tag_rename.kind {
+                    tags::TagKind::Tag => "Tag",
+                    tags::TagKind::Collection => "Collection",
+                };
+                ui.label(format!("Rename {what} \"{}\"", tag_rename.old_name));
+                ui.separator();
+                let name_edit = ui.text_edit_singleline(&mut tag_rename.new_name);
+                let new_name = tag_rename.new_name.trim();
+                let is_valid = !new_name.is_empty() && !self.tags.database.names(tag_rename.kind).any(|name| name == new_name);
+                if !is_valid && new_name != tag_rename.old_name {
+                    ui.colored_label(ui.visuals().warn_fg_color, "Name is empty or already in use");
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let submitted = name_edit.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+                    if ui.add_enabled(is_valid, egui::Button::new("Rename")).clicked() || (is_valid && submitted) {
+                        self.tags.database.rename(tag_rename.kind, &tag_rename.old_name, new_name);
+                        self.tags.changed(&self.logger);
+                        let old_filter = TagFilter { kind: tag_rename.kind, name: tag_rename.old_name.clone() };
+                        if self.tag_filter.as_ref() == Some(&old_filter) {
+                            self.tag_filter = Some(TagFilter { kind: tag_rename.kind, name: new_name.to_owned() });
+                        }
+                        close_modal = true;
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        close_modal = true;
+                    }
+                });
+            });
+
+            if close_modal {
+                self.tag_rename = None;
+            }
+        }
+
+        self.show_pack_import(ctx, window_rect, &mut costume_entries, &timestamp_display);
+
+        self.show_duplicate_review(ctx, window_rect, &mut costume_entries, &timestamp_display);
+
+        if !self.interrupted_saves.is_empty() {
+            let mut recoveries = Vec::new();
+            let mut close_modal = false;
+            egui::Modal::new(egui::Id::new("Interrupted Saves")).show(ctx, |ui| {
+                ui.set_max_height(window_rect.height() * 0.9);
+                ui.set_max_width(window_rect.width() * 0.7);
+                ui.set_min_size([0.0, 0.0].into());
+
+                ui.label("Interrupted Saves");
+                ui.label("These saves were still being written when the app last closed. Choose what to keep for each, the recommended choice is highlighted.");
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(window_rect.height() * 0.6).show(ui, |ui| {
+                    egui::Grid::new("interrupted_saves_grid").striped(true).show(ui, |ui| {
+                        for (idx, interrupted) in self.interrupted_saves.iter().enumerate() {
+                            let old_file_name = interrupted.old_path.file_name().unwrap().to_string_lossy();
+                            if interrupted.old_path == interrupted.new_path {
+                                ui.label(old_file_name);
+                            } else {
+                                ui.label(format!("{old_file_name} → {}", interrupted.new_path.file_name().unwrap().to_string_lossy()));
+                            }
+                            match (interrupted.is_created(), interrupted.is_committed()) {
+                                (false, true) => ui.weak("saved, but the backup of the original was left behind"),
+                                (true, true) => ui.weak("created, but the temp file was left behind"),
+                                (false, false) => ui.weak("not saved"),
+                                (true, false) => ui.weak("not created"),
+                            };
+
+                            let recommended = interrupted.recommended();
+                            let choices = [
+                                match interrupted.is_created() {
+                                    true => (transaction::Recovery::RollBack, "Discard", "Don't create the save"),
+                                    false => (transaction::Recovery::RollBack, "Restore Original", "Keep the save as it was before"),
+                                },
+                                match interrupted.is_committed() {
+                                    true => (transaction::Recovery::RollForward, "Clean Up", "Keep the save as it is and delete the backup"),
+                                    false => (transaction::Recovery::RollForward, "Keep New Version", "Finish the save"),
+                                },
+                            ];
+                            for (recovery, text, hover_text) in choices {
+                                let button = ui.add_enabled(interrupted.can_recover(recovery), egui::Button::new(text).selected(recovery == recommended))
+                                    .on_hover_text(hover_text)
+                                    .on_disabled_hover_text("The files needed for this are gone, another file is in the way, or it would leave the save behind twice");
+                                if button.clicked() {
+                                    recoveries.push((idx, recovery));
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Use Recommended for All").clicked() {
+                        recoveries = self.interrupted_saves.iter().enumerate().map(|(idx, interrupted)| (idx, interrupted.recommended())).collect();
+                    }
+                    if ui.button("Decide Later").on_hover_text("Leave the files alone until the next start").clicked() {
+                        close_modal = true;
+                    }
+                });
+            });
+
+            // NOTE The scanner picks up the recovered saves like any other outside change.
+            for (idx, recovery) in recoveries.into_iter().rev() {
+                let interrupted = self.interrupted_saves.remove(idx);
+                match interrupted.recover(recovery) {
+                    Ok(()) => self.logger.log(LogLevel::Info, format!("recovered the interrupted save of {:?} ({recovery:?})", interrupted.old_path).as_str()),
+                    Err(source) => self.logger.log_err_ack_required(AppError::RecoveryFailed { which: interrupted.old_path, source }),
+                }
+            }
+            if close_modal {
+                self.interrupted_saves.clear();
+            }
+        }
+
+        if let Some(paths_to_delete) = self.delete_confirmation.take() {
+            let mut close_modal = false;
+            let trash = self.costume_dir.read().unwrap().as_deref().map(trash::Trash::for_costume_dir);
+            egui::Modal::new(egui::Id::new("Delete Confirmation")).show(ctx, |ui| {
+                ui.set_max_height(window_rect.height() * 0.9);
+                ui.set_max_width(window_rect.width() * 0.5);
+                ui.set_min_size([0.0, 0.0].into());
+
+                ui.label(format!("Move {} saves to the trash?", paths_to_delete.len()));
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(window_rect.height() * 0.5).show(ui, |ui| {
+                    for path in paths_to_delete.iter() {
+                        ui.label(path.file_name().unwrap().to_string_lossy());
+                    }
+                });
+                ui.separator();
+                match trash.as_ref() {
+                    Some(trash) if trash.kind() == trash::TrashKind::Home => {
+                        ui.label("They can be restored from the trash browser or your file manager's trash.");
+                    },
+                    Some(trash) => {
+                        ui.label(format!("They can be restored from the trash browser. The trash is kept in {:?}.", trash.dir()));
+                    },
+                    None => {},
+                }
+
+                ui.horizontal(|ui| {
+                    let trash_button = ui.add_enabled(trash.is_some(), egui::Button::new("Move to Trash"))
+                        .on_disabled_hover_text("No costume directory selected");
+                    if trash_button.clicked() {
+                        let trash = trash.as_ref().unwrap();
+                        let deletion_date = chrono::Local::now().naive_local();
+                        let mut recording = journal::Recording::default();
+                        let mut failures = Vec::new();
+                        for path in paths_to_delete.iter() {
+                            let trashed = {
+                                let _operation = self.own_operations.begin([path.clone()]);
+                                trash.trash(path, deletion_date)
+                            };
+                            match trashed {
+                                Ok(trashed) => {
+                                    // NOTE Tags are kept so that restoring the save brings them back.
+                                    costume_entries.remove(path);
+                                    self.logger.log(LogLevel::Info, format!("moved {path:?} to the trash at {:?}", trashed.trashed_path).as_str());
+                                    recording.created(trashed.info_path.clone(), trashed.info_contents().into_bytes());
+                                    recording.renamed(path.clone(), trashed.trashed_path);
+                                },
+                                Err(err) => failures.push(err),
+                            }
+                        }
+                        self.history.record(format!("Delete {} saves", paths_to_delete.len() - failures.len()), recording, &self.logger);
+
+                        self.tags.counts = None;
+                        self.refresh_sorted_saves(&timestamp_display, &costume_entries);
+                        self.selected_costumes.clear();
+                        self.costume_edit = None;
+                        if let Some(duplicate_review) = self.duplicate_review.as_mut() {
+                            duplicate_review.regroup(&costume_entries);
+                        }
+
+                        if !failures.is_empty() {
+                            self.logger.log_err_ack_required(AppError::TrashIncomplete { action: "move to the trash", failures, attempted: paths_to_delete.len() });
+                        }
+                        close_modal = true;
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        close_modal = true;
+                    }
+                });
+            });
+
+            if !close_modal {
+                self.delete_confirmation = Some(paths_to_delete);
             }
         }
 
+        self.show_trash_browser(ctx, window_rect, &mut costume_entries, &timestamp_display);
+
         if self.file_exists_warning_modal_open {
             egui::Modal::new(egui::Id::new("File Exists Warning")).show(ctx, |ui| {
                 ui.label("A file with the same name already exists!");
@@ -593,6 +3168,79 @@ impl eframe::App for App {
             });
         }
 
+        if let Some(edit_conflict) = self.edit_conflict.as_mut() {
+            let mut close_modal = false;
+            egui::Modal::new(egui::Id::new("Edit Conflict")).show(ctx, |ui| {
+                ui.set_max_height(window_rect.height() * 0.9);
+                ui.set_max_width(window_rect.width() * 0.6);
+                ui.set_min_size([0.0, 0.0].into());
+
+                let (Some(entry), Some(costume_edit)) = (costume_entries.get(&edit_conflict.file_path), self.costume_edit.as_mut()) else {
+                    close_modal = true;
+                    return;
+                };
+                let theirs = entry.save.get_metadata().unwrap();
+
+                ui.heading("Save Changed on Disk");
+                ui.label(format!(
+                    "{:?} was changed outside of the app while you were editing it.",
+                    edit_conflict.file_path.file_name().unwrap(),
+                ));
+
+                if edit_conflict.show_differences {
+                    ui.separator();
+                    let their_keywords = theirs.keywords().collect::<Vec<_>>().join(", ");
+                    let fields = [
+                        ("Account Name", costume_edit.account_name.as_str(), theirs.account_name()),
+                        ("Character Name", costume_edit.character_name.as_str(), theirs.character_name()),
+                        ("Keywords", costume_edit.keywords.as_str(), their_keywords.as_str()),
+                    ];
+                    egui::Grid::new("edit conflict fields").striped(true).show(ui, |ui| {
+                        ui.strong("");
+                        ui.strong("Yours");
+                        ui.strong("On Disk");
+                        ui.end_row();
+                        for (label, mine, theirs) in fields.into_iter().filter(|(_, mine, theirs)| mine != theirs) {
+                            ui.label(label);
+                            ui.label(mine);
+                            ui.label(theirs);
+                            ui.end_row();
+                        }
+                    });
+
+                    ui.label("Costume spec (- on disk, + yours):");
+                    egui::ScrollArea::vertical().max_height(window_rect.height() * 0.5).show(ui, |ui| {
+                        for line in costume::diff::diff_lines(theirs.spec(), &costume_edit.costume_spec) {
+                            match line {
+                                costume::diff::DiffLine::Same(line) => { ui.monospace(format!("  {line}")); },
+                                costume::diff::DiffLine::Removed(line) => { ui.colored_label(ui.visuals().error_fg_color, egui::RichText::new(format!("- {line}")).monospace()); },
+                                costume::diff::DiffLine::Added(line) => { ui.colored_label(ui.visuals().warn_fg_color, egui::RichText::new(format!("+ {line}")).monospace()); },
+                            }
+                        }
+                    });
+                    ui.separator();
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Keep Mine").on_hover_text("Keep editing your version. Saving it overwrites the changes on disk.").clicked() {
+                        // Everything that differs from the save on disk is now an edit.
+                        costume_edit.apply_text_edits(&theirs);
+                        close_modal = true;
+                    }
+                    if ui.button("Take Theirs").on_hover_text("Discard your edits and show the save as it is on disk").clicked() {
+                        drop(theirs);
+                        *costume_edit = CostumeEdit::new_from_entry(entry);
+                        close_modal = true;
+                    }
+                    ui.toggle_value(&mut edit_conflict.show_differences, "Show Differences");
+                });
+            });
+
+            if close_modal {
+                self.edit_conflict = None;
+            }
+        }
+
         if self.costume_spec_edit_open {
             assert_eq!(self.selected_costumes.len(), 1);
             assert!(self.costume_edit.is_some());
@@ -612,31 +3260,45 @@ impl eframe::App for App {
                     // toggling the checkbox on/off for some reason.
                     let save_idx = self.selected_costumes.iter().last().unwrap();
                     let entry = &costume_entries[&self.sorted_saves[*save_idx]];
-                    let costume::CostumeMetadata { spec, hash, .. } = entry.save.get_metadata();
-                    self.costume_edit.as_mut().unwrap().costume_spec = spec.to_owned();
-                    self.costume_edit.as_mut().unwrap().costume_hash = hash.to_owned();
+                    let original_metadata = entry.save.get_metadata().unwrap();
+                    let costume_edit = self.costume_edit.as_mut().unwrap();
+                    costume_edit.costume_spec = original_metadata.spec().to_owned();
+                    costume_edit.apply_text_edits(&original_metadata);
+                    costume_edit.spec_diagnostics.clear();
                 }
                 ui.separator();
                 ui.horizontal(|ui| {
                     ui.label("Hash:");
-                    ui.label(&self.costume_edit.as_ref().unwrap().costume_hash);
+                    ui.label(self.costume_edit.as_ref().unwrap().metadata.hash());
                 });
 
-                let scroll_area = egui::ScrollArea::vertical();
+                let costume_edit = self.costume_edit.as_mut().unwrap();
+                let scroll_area = egui::ScrollArea::vertical().id_salt("spec editor").max_height(window_rect.height() * 0.6);
                 scroll_area.show(ui, |ui| {
+                    let diagnostics = &costume_edit.spec_diagnostics;
+                    let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                        let job = layout_costume_spec(ui, text, diagnostics, wrap_width);
+                        ui.fonts(|fonts| fonts.layout_job(job))
+                    };
                     let spec_editor = ui.add_enabled(
                         self.confirm_edit_spec,
-                        egui::TextEdit::multiline(&mut self.costume_edit.as_mut().unwrap().costume_spec)
+                        egui::TextEdit::multiline(&mut costume_edit.costume_spec)
                             .code_editor()
                             .desired_rows(12)
                             .desired_width(f32::INFINITY)
+                            .layouter(&mut layouter)
                     );
 
                     if spec_editor.changed() {
-                        self.costume_edit.as_mut().unwrap().costume_hash = costume::generate_costume_hash(&self.costume_edit.as_ref().unwrap().costume_spec);
+                        let save_idx = self.selected_costumes.iter().last().unwrap();
+                        let entry = &costume_entries[&self.sorted_saves[*save_idx]];
+                        costume_edit.apply_text_edits(&entry.save.get_metadata().unwrap());
+                        costume_edit.validate_spec(&self.spec_catalog);
                     }
                 });
 
+                show_spec_diagnostics(ui, &costume_edit.spec_diagnostics);
+
                 ui.centered_and_justified(|ui| {
                     let close_text = if self.confirm_edit_spec { "Save and Close" } else { "Cancel and Close" };
                     if ui.button(close_text).clicked() {
@@ -646,6 +3308,10 @@ impl eframe::App for App {
             });
         }
 
+        if self.history.panel_open {
+            self.show_history_panel(ctx, history_locked, &timestamp_display);
+        }
+
         egui::SidePanel::right("details_display").show(ctx, |ui| {
             // NOTE: For now we're just assuming that the selected costume and the costume edit
             // data are properly tied together. Maybe we should tie these together better so that
@@ -657,6 +3323,101 @@ impl eframe::App for App {
             } else {
                 if self.selected_costumes.len() > 1 {
                     ui.label(format!("{} selected items", self.selected_costumes.len()));
+
+                    ui.separator();
+
+                    let mut selected_indices: Vec<usize> = self.selected_costumes.iter().copied().collect();
+                    selected_indices.sort();
+                    let selected_paths: Vec<PathBuf> = selected_indices.into_iter().map(|idx| self.sorted_saves[idx].clone()).collect();
+
+                    self.show_bulk_edit(ui, &selected_paths, &mut costume_entries, &timestamp_display);
+
+                    ui.separator();
+
+                    ui.label("Shift Timestamps:");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.timestamp_shift.days).suffix(" days"));
+                        ui.add(egui::DragValue::new(&mut self.timestamp_shift.hours).suffix(" hours"));
+                        ui.add(egui::DragValue::new(&mut self.timestamp_shift.minutes).suffix(" minutes"));
+                    });
+                    let shift_seconds = self.timestamp_shift.as_seconds();
+                    let shifts_before_2000 = self.selected_costumes.iter()
+                        .filter_map(|idx| costume_entries[&self.sorted_saves[*idx]].file_name.j2000_timestamp)
+                        .any(|j2000_timestamp| j2000_timestamp.checked_add(shift_seconds).is_none_or(|shifted| shifted < 0));
+                    let shift_button = ui.add_enabled(shift_seconds != 0 && !shifts_before_2000, egui::Button::new("Apply Shift"))
+                        .on_hover_text("Saves without a timestamp are left alone")
+                        .on_disabled_hover_text(if shifts_before_2000 {
+                            "A timestamp would end up before 2000, which the game can't show"
+                        } else {
+                            "Saves without a timestamp are left alone"
+                        });
+                    if shift_button.clicked() {
+                        let costume_dir = self.costume_dir.read().unwrap().clone();
+                        debug_assert!(costume_dir.is_some());
+                        let costume_dir = costume_dir.as_ref().unwrap();
+
+                        let mut saves_to_shift: Vec<(PathBuf, i64)> = self.selected_costumes.iter()
+                            .map(|idx| &self.sorted_saves[*idx])
+                            .filter_map(|path| Some((path.clone(), costume_entries[path].file_name.j2000_timestamp?)))
+                            .collect();
+                        // NOTE Shift the saves furthest along in the shift direction first so that
+                        // a save never gets renamed onto a selected save that hasn't moved yet.
+                        saves_to_shift.sort_by_key(|(_, j2000_timestamp)| *j2000_timestamp);
+                        if shift_seconds > 0 {
+                            saves_to_shift.reverse();
+                        }
+
+                        let mut shifted_paths = Vec::new();
+                        let mut recording = journal::Recording::default();
+                        for (old_file_path, j2000_timestamp) in saves_to_shift {
+                            let Some(new_j2000_timestamp) = j2000_timestamp.checked_add(shift_seconds).filter(|shifted| *shifted >= 0) else { continue };
+                            let mut new_file_name = costume_entries[&old_file_path].file_name.clone();
+                            new_file_name.j2000_timestamp = Some(new_j2000_timestamp);
+                            let new_file_path = costume_dir.join(new_file_name.to_string());
+
+                            // FIXME Same Windows case insensitivity issue as when saving.
+                            if costume_entries.contains_key(&new_file_path) {
+                                self.logger.log(LogLevel::Warn, format!("not shifting {old_file_path:?}: {new_file_path:?} already exists").as_str());
+                                continue;
+                            }
+                            let renamed = {
+                                let _operation = self.own_operations.begin([old_file_path.clone(), new_file_path.clone()]);
+                                fs::rename(&old_file_path, &new_file_path)
+                            };
+                            if let Err(err) = renamed {
+                                self.logger.log(LogLevel::Error, format!("failed to rename {old_file_path:?} to {new_file_path:?}: {err}").as_str());
+                                continue;
+                            }
+                            recording.renamed(old_file_path.clone(), new_file_path.clone());
+
+                            let mut entry = costume_entries.remove(&old_file_path).unwrap();
+                            let old_tag_key = entry.tag_key();
+                            entry.file_name = new_file_name;
+                            entry.regenerate_in_game_display_name(&timestamp_display);
+                            self.tags.relink([(old_tag_key, entry.tag_key())], &self.logger);
+                            costume_entries.insert(new_file_path.clone(), entry);
+                            shifted_paths.push(new_file_path);
+                        }
+                        self.logger.log(LogLevel::Info, format!("shifted the timestamps of {} saves by {shift_seconds} seconds", shifted_paths.len()).as_str());
+                        self.history.record(format!("Shift the timestamps of {} saves", shifted_paths.len()), recording, &self.logger);
+
+                        let selected_paths: HashSet<PathBuf> = self.selected_costumes.iter()
+                            .map(|idx| self.sorted_saves[*idx].clone())
+                            .filter(|path| costume_entries.contains_key(path))
+                            .chain(shifted_paths)
+                            .collect();
+                        self.refresh_sorted_saves(&timestamp_display, &costume_entries);
+                        self.selected_costumes = self.sorted_saves.iter().enumerate()
+                            .filter(|(_, path)| selected_paths.contains(*path))
+                            .map(|(idx, _)| idx)
+                            .collect();
+                    }
+
+                    ui.separator();
+
+                    if ui.button("Batch Rename...").on_hover_text("Rename every selected save from a template").clicked() {
+                        self.batch_rename = Some(BatchRename::new(selected_paths, &costume_entries, &timestamp_display));
+                    }
                 } else if self.selected_costumes.len() == 1 {
                     // FIXME probably ultimately unnecessary clone
                     let costume_path = &self.sorted_saves[*self.selected_costumes.iter().last().unwrap()].clone();
@@ -672,7 +3433,7 @@ impl eframe::App for App {
                         ui.add(image);
                     } else {
                         if matches!(costume.image_texture, CostumeImage::NotLoaded) {
-                            _ = self.decode_job_tx.send(costume_path.clone());
+                            _ = self.decode_job_tx.send(DecodeJob { file_path: costume_path.clone(), content_hash: costume.content_hash, hash_only: false, make_thumbnail: costume.thumbnail.is_none() });
                             costume.image_texture = CostumeImage::Loading;
                         }
                         ui.label("loading image...");
@@ -680,57 +3441,137 @@ impl eframe::App for App {
 
                     ui.horizontal(|ui| {
                         ui.label("File Name:");
-                        ui.label(&costume_edit.file_name);
+                        ui.label(costume_edit.file_name.to_string());
                     });
                     ui.horizontal(|ui| {
                         ui.label("In-Game Display:");
                         ui.label(&costume_edit.in_game_display_name);
                     });
+                    ui.horizontal(|ui| {
+                        ui.label("Record Version:");
+                        match costume.save.get_metadata().unwrap().record_version() {
+                            Some(version) if version == costume::EXPECTED_RECORD_VERSION => ui.label(version.to_string()),
+                            Some(version) => ui.colored_label(ui.visuals().warn_fg_color, format!("{version} (expected {})", costume::EXPECTED_RECORD_VERSION)),
+                            None => ui.colored_label(ui.visuals().warn_fg_color, "missing or invalid"),
+                        };
+                    });
+                    let tag_key = costume.tag_key();
+                    for (kind, label) in [(tags::TagKind::Tag, "Tags:"), (tags::TagKind::Collection, "Collections:")] {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label(label);
+                            let current: Vec<String> = self.tags.database.of(&tag_key, kind).map(str::to_owned).collect();
+                            for name in current.iter() {
+                                if ui.small_button(format!("{name} 🗙")).on_hover_text("Remove").clicked() {
+                                    self.tags.database.remove(&tag_key, kind, name);
+                                    self.tags.changed(&self.logger);
+                                    tags_changed = true;
+                                }
+                            }
+                            let addable: Vec<String> = self.tags.database.names(kind)
+                                .filter(|name| !current.iter().any(|current| current == name))
+                                .map(str::to_owned)
+                                .collect();
+                            if !addable.is_empty() {
+                                egui::ComboBox::from_id_salt(("add to save", kind)).selected_text("Add...").show_ui(ui, |ui| {
+                                    for name in addable {
+                                        if ui.selectable_label(false, &name).clicked() {
+                                            self.tags.database.add(tag_key.clone(), kind, &name);
+                                            self.tags.changed(&self.logger);
+                                            tags_changed = true;
+                                        }
+                                    }
+                                });
+                            }
+                        });
+                    }
 
                     ui.separator();
 
                     ui.horizontal(|ui| {
                         ui.label("Save Name:");
-                        if ui.text_edit_singleline(&mut costume_edit.save_name).changed() {
-                            costume_edit.regenerate_indirect_fields();
-                        }
+                        ui.text_edit_singleline(&mut costume_edit.file_name.save_name);
                     });
-                    if costume.j2000_timestamp.is_some() && ui.checkbox(&mut costume_edit.strip_timestamp, "Strip Timestamp").changed() {
-                        if costume_edit.strip_timestamp {
-                            costume_edit.timestamp = None;
-                        } else {
-                            costume_edit.timestamp = costume.j2000_timestamp;
+                    ui.horizontal(|ui| {
+                        ui.label("Timestamp:");
+                        let j2000_timestamp = costume_edit.file_name.j2000_timestamp;
+                        match (j2000_timestamp, j2000_timestamp.and_then(|j2000_timestamp| timestamp_display.j2000_to_naive(j2000_timestamp))) {
+                            (Some(_), Some(datetime)) => {
+                                let mut date = datetime.date();
+                                let (mut hour, mut minute, mut second) = (datetime.hour(), datetime.minute(), datetime.second());
+                                let mut changed = ui.add(egui_extras::DatePickerButton::new(&mut date).id_salt("timestamp date")).changed();
+                                changed |= ui.add(egui::DragValue::new(&mut hour).range(0..=23)).changed();
+                                ui.label(":");
+                                changed |= ui.add(egui::DragValue::new(&mut minute).range(0..=59)).changed();
+                                ui.label(":");
+                                changed |= ui.add(egui::DragValue::new(&mut second).range(0..=59)).changed();
+                                ui.label(timestamp_display.time_zone.label());
+
+                                // The ranges above guarantee a valid time. Times before 2000, which
+                                // can't be written as a timestamp, and times skipped by daylight
+                                // saving time are ignored.
+                                let new_j2000_timestamp = timestamp_display.naive_to_j2000(date.and_hms_opt(hour, minute, second).unwrap());
+                                if let (true, Some(new_j2000_timestamp)) = (changed, new_j2000_timestamp) {
+                                    costume_edit.file_name.j2000_timestamp = Some(new_j2000_timestamp);
+                                    costume_edit.regenerate_indirect_fields(&timestamp_display);
+                                }
+                            },
+                            (Some(_), None) => {
+                                ui.colored_label(ui.visuals().warn_fg_color, "out of range");
+                            },
+                            (None, _) => {
+                                if ui.button("Add Timestamp").on_hover_text("Defaults to the file's creation time").clicked() {
+                                    costume_edit.file_name.j2000_timestamp = Some(get_default_j2000_timestamp(costume_path));
+                                    costume_edit.regenerate_indirect_fields(&timestamp_display);
+                                }
+                            },
                         }
 
-                        costume_edit.regenerate_indirect_fields();
-                    }
+                        if costume_edit.file_name.j2000_timestamp.is_some() && ui.button("Strip").clicked() {
+                            costume_edit.file_name.j2000_timestamp = None;
+                            costume_edit.regenerate_indirect_fields(&timestamp_display);
+                        }
+                    });
 
                     ui.horizontal(|ui| {
                         ui.label("Account Name:");
                         if ui.text_edit_singleline(&mut costume_edit.account_name).changed() {
-                            costume_edit.regenerate_indirect_fields();
+                            costume_edit.apply_text_edits(&costume.save.get_metadata().unwrap());
+                            costume_edit.regenerate_indirect_fields(&timestamp_display);
                         };
                     });
                     ui.horizontal(|ui| {
                         ui.label("Character Name:");
                         if ui.text_edit_singleline(&mut costume_edit.character_name).changed() {
-                            costume_edit.regenerate_indirect_fields();
+                            costume_edit.apply_text_edits(&costume.save.get_metadata().unwrap());
+                            costume_edit.regenerate_indirect_fields(&timestamp_display);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Keywords:");
+                        let keywords_edit = ui.text_edit_singleline(&mut costume_edit.keywords)
+                            .on_hover_text("Comma-separated, e.g. \"FightClub, FC, Female\"");
+                        if keywords_edit.changed() {
+                            costume_edit.apply_text_edits(&costume.save.get_metadata().unwrap());
                         }
                     });
+                    let has_changes = costume_edit.has_changes(costume);
                     if ui.button("Edit Spec").clicked() {
                         self.costume_spec_edit_open = true;
+                        self.spec_catalog = Self::learn_spec_catalog(Some(costume_path), &costume_entries);
                     }
 
-                    // TODO disable button if nothing was changed?
+                    let spec_has_errors = costume::has_errors(&costume_edit.spec_diagnostics);
                     // TODO if only file name was changed maybe only rename the file via OS?
-                    if ui.button("Save").clicked() {
-                        let costume_dir = self.costume_dir.read().unwrap();
+                    let save_button = ui.add_enabled(has_changes && !spec_has_errors, egui::Button::new("Save"))
+                        .on_disabled_hover_text(if spec_has_errors { "The costume spec has errors" } else { "Nothing has changed" });
+                    if save_button.clicked() {
+                        let costume_dir = self.costume_dir.read().unwrap().clone();
                         debug_assert!(costume_dir.is_some());
                         let costume_dir = costume_dir.as_ref().unwrap();
                         debug_assert!(costume_path.as_path().parent() == Some(costume_dir));
 
                         let old_file_path = costume_path;
-                        let new_file_path = costume_dir.join(&costume_edit.file_name);
+                        let new_file_path = costume_dir.join(costume_edit.file_name.to_string());
                         let file_name_changed = new_file_path.file_name().unwrap() != old_file_path.file_name().unwrap();
 
                         // FIXME There is potentially a massive, terrible bug here on Windows where
@@ -739,246 +3580,74 @@ impl eframe::App for App {
                         // casing, we WON'T catch it!
                         if file_name_changed && costume_entries.contains_key(&new_file_path) {
                             self.file_exists_warning_modal_open = true;
+                        } else if costume_entries[costume_path].changed_on_disk(old_file_path) {
+                            // Changed since the scanner last saw it, so reload it and let the user
+                            // decide instead of overwriting someone else's changes.
+                            match CostumeEntry::load(old_file_path, &timestamp_display) {
+                                Ok(reloaded) => {
+                                    self.logger.log(LogLevel::Warn, format!("{old_file_path:?} was changed outside of the app, not saving over it").as_str());
+                                    let costume = costume_entries.get_mut(costume_path).unwrap();
+                                    self.tags.relink([(costume.tag_key(), reloaded.tag_key())], &self.logger);
+                                    ctx.forget_image(&thumbnail_uri(old_file_path));
+                                    *costume = reloaded;
+                                    self.edit_conflict = Some(EditConflict { file_path: old_file_path.clone(), show_differences: false });
+                                },
+                                Err(costume_load_error) => self.logger.log_err_ack_required(costume_load_error),
+                            }
                         } else {
                             self.logger.log(LogLevel::Info, format!("attempting to save {old_file_path:?} as {new_file_path:?}").as_str());
-                            // TODO Create a SaveError type, map all errors in this block to that
-                            // and include additional information about the failed operation, and
-                            // just use the `?` operator. Return a result and log if there's an
-                            // error afterward.
-                            // TODO find a way to ergonomically (and efficiently) compress all the
-                            // error cleanup code.
                             let successfully_saved = (|| {
                                 let costume = costume_entries.get_mut(costume_path).unwrap();
-                                // NOTE we use a temp file so that we're not immediately
-                                // overwriting the existing file in the case the file name hasn't
-                                // changed. If the save operation fails we don't want to lose or
-                                // corrupt the original file.
-                                let mut temp_file_path = new_file_path.clone();
-                                // FIXME should probably grab the current extension of new_file_path and
-                                // use that to create the temp extension
-                                temp_file_path.set_extension("jpg.CCM_TEMP");
-                                let mut temp_file = match fs::File::create(&temp_file_path) {
-                                    Ok(file) => file,
+                                let old_tag_key = costume.tag_key();
+                                if let Err(costume_load_error) = costume.load_full_save(old_file_path) {
+                                    self.logger.log_err_ack_required(costume_load_error);
+                                    return false;
+                                }
+                                // For undoing the save.
+                                let old_bytes = match fs::read(old_file_path) {
+                                    Ok(old_bytes) => old_bytes,
                                     Err(err) => {
-                                        let costume_save_error = AppError::CostumeSaveFailed {
-                                            which: costume_path.clone(),
-                                            source: Some(err),
-                                            message: format!("failed to open temp file {temp_file_path:?}"),
-                                        };
-                                        self.logger.log_err_ack_required(costume_save_error);
+                                        self.logger.log_err_ack_required(AppError::CostumeSaveFailed { source: Some(err), which: old_file_path.clone(), message: "failed to read file".to_owned() });
                                         return false;
-                                    }
+                                    },
                                 };
-
-                                let original_metadata = costume.save.get_metadata().create_update();
+                                // Only the fields we're about to overwrite need to be kept around.
+                                let original_metadata = costume.save.get_metadata().unwrap().revert_snapshot(&costume_edit.metadata);
                                 let original_file_name = costume.file_name.clone();
                                 let original_in_game_display_name = costume.in_game_display_name.clone();
-                                let original_timestamp = costume.j2000_timestamp;
 
-                                costume.save.update_metadata(costume_edit.create_update());
+                                costume.update_metadata(&costume_edit.metadata);
                                 costume.in_game_display_name = costume_edit.in_game_display_name.clone();
                                 if file_name_changed {
                                     costume.file_name = costume_edit.file_name.clone();
-                                    costume.j2000_timestamp = costume_edit.timestamp;
-                                }
-
-                                let serialized = costume.save.0.serialize();
-                                if let Err(err) = temp_file.write_all(&serialized) {
-                                    let costume_save_error = AppError::CostumeSaveFailed {
-                                        which: costume_path.clone(),
-                                        source: Some(err),
-                                        message: format!("failed to write temp file {temp_file_path:?}"),
-                                    };
-                                    self.logger.log_err_ack_required(costume_save_error);
-
-                                    //
-                                    // BEGIN CLEANUP
-                                    //
-
-                                    // DELETE TEMP FILE
-                                    if let Err(err) = fs::remove_file(&temp_file_path) {
-                                        self.logger.log(
-                                            LogLevel::Warn,
-                                            format!("failed to remove temp file {temp_file_path:?} after costume save failure: {err}").as_str()
-                                        );
-                                    }
-
-                                    // REVERT COSTUME CHANGES
-                                    costume.save.update_metadata(original_metadata);
-                                    costume.file_name = original_file_name;
-                                    costume.in_game_display_name = original_in_game_display_name;
-                                    costume.j2000_timestamp = original_timestamp;
-
-                                    //
-                                    // END CLEANUP
-                                    //
-
-                                    return false;
-                                }
-
-                                // Need to copy old creation time to new file
-                                #[cfg(windows)]
-                                {
-                                    (|| {
-                                        // NOTE Failure to update file times does NOT abort the
-                                        // save process. It's not ideal but we can still continue.
-                                        use std::os::windows::fs::FileTimesExt;
-                                        let old_file = match fs::File::open(old_file_path) {
-                                            Ok(file) => file,
-                                            Err(err) => {
-                                                self.logger.log(
-                                                    LogLevel::Warn,
-                                                    format!("failed to update file times: failed to open original file {old_file_path:?}: {err}").as_str(),
-                                                );
-                                                return;
-                                            }
-                                        };
-                                        let old_metadata = match old_file.metadata() {
-                                            Ok(metadata) => metadata,
-                                            Err(err) => {
-                                                self.logger.log(
-                                                    LogLevel::Warn,
-                                                    format!("failed to update file times: failed to get metadata for original file {old_file_path:?}: {err}").as_str(),
-                                                );
-                                                return;
-                                            },
-                                        };
-                                        let new_metadata = match temp_file.metadata() {
-                                            Ok(metadata) => metadata,
-                                            Err(err) => {
-                                                self.logger.log(
-                                                    LogLevel::Warn,
-                                                    format!("failed to update file times: failed to get metadata for temp file {temp_file_path:?}: {err}").as_str(),
-                                                );
-                                                return;
-                                            },
-                                        };
-                                        // SAFETY: This section is conditionally compiled for windows so
-                                        // setting/getting the file creation time should not error.
-                                        let times = fs::FileTimes::new()
-                                            .set_created(old_metadata.created().unwrap())
-                                            .set_accessed(new_metadata.accessed().unwrap())
-                                            .set_modified(new_metadata.modified().unwrap());
-                                        if let Err(err) = temp_file.set_times(times) {
-                                            self.logger.log(
-                                                LogLevel::Warn,
-                                                format!("failed to update file times: failed to set times for {temp_file_path:?}: {err}").as_str(),
-                                            );
-                                        }
-                                    })();
-                                }
-
-                                // Rename the old file so that we have something to revert to if
-                                // the temp file rename fails.
-                                let mut old_file_backup_path = old_file_path.clone();
-                                old_file_backup_path.set_extension("jpg.CCM_BAK");
-                                if let Err(err) = fs::rename(old_file_path, &old_file_backup_path) {
-                                    let costume_save_error = AppError::CostumeSaveFailed {
-                                        which: costume_path.clone(),
-                                        source: Some(err),
-                                        message: "failed to rename original file".to_string(),
-                                    };
-                                    self.logger.log_err_ack_required(costume_save_error);
-
-                                    //
-                                    // BEGIN CLEANUP
-                                    //
-
-                                    // DELETE TEMP FILE
-                                    if let Err(err) = fs::remove_file(&temp_file_path) {
-                                        self.logger.log(
-                                            LogLevel::Warn,
-                                            format!("failed to remove temp file {temp_file_path:?} after costume save failure: {err}").as_str()
-                                        );
-                                    }
-
-                                    // REVERT COSTUME CHANGES
-                                    costume.save.update_metadata(original_metadata);
-                                    costume.file_name = original_file_name;
-                                    costume.in_game_display_name = original_in_game_display_name;
-                                    costume.j2000_timestamp = original_timestamp;
-
-                                    //
-                                    // END CLEANUP
-                                    //
-
-                                    return false;
                                 }
 
-                                if let Err(err) = fs::rename(&temp_file_path, &new_file_path) {
-                                    let costume_save_error = AppError::CostumeSaveFailed {
-                                        which: costume_path.clone(),
-                                        source: Some(err),
-                                        message: "failed to rename temp file".to_string(),
-                                    };
+                                let written = {
+                                    let _operation = self.own_operations.begin([old_file_path.clone(), new_file_path.clone()]);
+                                    write_costume_save(&costume.save, old_file_path, &new_file_path, &self.logger)
+                                };
+                                if let Err(costume_save_error) = written {
                                     self.logger.log_err_ack_required(costume_save_error);
 
-                                    //
-                                    // BEGIN CLEANUP
-                                    //
-
-                                    // DELETE TEMP FILE
-                                    if let Err(err) = fs::remove_file(&temp_file_path) {
-                                        self.logger.log(
-                                            LogLevel::Warn,
-                                            format!("failed to remove temp file {temp_file_path:?} after costume save failure: {err}").as_str()
-                                        );
-                                    }
-
                                     // REVERT COSTUME CHANGES
-                                    costume.save.update_metadata(original_metadata);
+                                    costume.update_metadata(&original_metadata);
                                     costume.file_name = original_file_name;
                                     costume.in_game_display_name = original_in_game_display_name;
-                                    costume.j2000_timestamp = original_timestamp;
-
-                                    // REVERT NAME OF BACKUP OF ORIGINAL FILE
-                                    if let Err(err) = fs::rename(&old_file_backup_path, old_file_path) {
-                                        // TODO should this be a separate app error?
-                                        let costume_save_error = AppError::CostumeSaveFailed {
-                                            which: costume_path.clone(),
-                                            source: Some(err),
-                                            message: format!(
-                                                "failed to revert old file backup rename ({:?} --> {:?})",
-                                                old_file_backup_path,
-                                                old_file_path
-                                            )
-                                        };
-                                        self.logger.log_err_ack_required(costume_save_error);
-                                    }
-
-                                    //
-                                    // END CLEANUP
-                                    //
 
                                     return false;
                                 }
 
-                                if let Err(err) = fs::remove_file(&old_file_backup_path) {
-                                    self.logger.log(LogLevel::Warn, format!("failed to remove renamed old file path: {err}").as_str());
-                                }
-
+                                costume.mark_written(&new_file_path);
                                 self.logger.log(LogLevel::Info, format!("successfully saved {new_file_path:?}").as_str());
+                                let mut recording = journal::Recording::default();
+                                recording.written(old_file_path.clone(), old_bytes, new_file_path.clone(), costume.save.0.serialize().into());
+                                self.history.record(format!("Save {}", costume.file_name), recording, &self.logger);
+                                costume_edit.metadata.mark_clean();
+                                self.tags.relink([(old_tag_key, costume.tag_key())], &self.logger);
 
                                 true
                             })();
 
-                            // NOTE we should always inform the scanning thread that we potentially
-                            // updated the costume directory, even if the save was a failure (and
-                            // even if it was such a failure that we didn't actually make any
-                            // changes to the directory).
-
-                            // TODO find a way to compress this code since we do the exact same thing when
-                            // deleting files.
-
-                            // Signal to the scanning thread that we initiated the file system change.
-                            // This avoids cases where we update the file system, react to the update,
-                            // then the scanner sees that something was changed and gives us ANOTHER
-                            // notification that the file system was changed.
-                            let costume_dir = self.costume_dir.read().unwrap();
-                            debug_assert!(costume_dir.is_some());
-                            let last_modified_time = costume_dir.as_ref().unwrap().metadata().unwrap().modified().unwrap();
-                            let _ = self.scanner_tx.send(last_modified_time);
-
                             if successfully_saved {
                                 // FIXME really lazy and inefficient. I don't think we can know where the new
                                 // save name will be after sorting (maybe we actually can) but we can probably
@@ -989,47 +3658,249 @@ impl eframe::App for App {
 
                                 let entry = costume_entries.remove(old_file_path).unwrap();
                                 costume_entries.insert(new_file_path.clone(), entry);
-                                self.sorted_saves = costume_entries.keys().cloned().collect();
-                                Self::sort_saves(self.sort_type, self.display_type, &mut self.sorted_saves, &costume_entries);
+                                self.refresh_sorted_saves(&timestamp_display, &costume_entries);
 
-                                let (new_index, _) = self.sorted_saves.iter().enumerate().find(|(_, save)| **save == new_file_path).unwrap();
-                                self.selected_costumes.insert(new_index);
+                                // The save may no longer pass the filters if its keywords were changed.
+                                if let Some((new_index, _)) = self.sorted_saves.iter().enumerate().find(|(_, save)| **save == new_file_path) {
+                                    self.selected_costumes.insert(new_index);
+                                }
                             }
                         }
                     }
                 }
 
                 if ui.button("Delete").clicked() {
-                    // TODO show delete confirmation popup
-                    for selected_idx in self.selected_costumes.iter() {
-                        let costume_path = &self.sorted_saves[*selected_idx];
-                        if let Err(err) = fs::remove_file(costume_path) {
-                            self.logger.log(LogLevel::Error, format!("failed to delete {costume_path:?}: {err}").as_str());
-                            continue;
-                        }
-                        costume_entries.remove(costume_path);
-                        self.logger.log(LogLevel::Info, format!("deleted {costume_path:?}").as_str());
-                    }
-                    self.sorted_saves = costume_entries.keys().cloned().collect();
-                    Self::sort_saves(self.sort_type, self.display_type, &mut self.sorted_saves, &costume_entries);
-                    self.selected_costumes.clear();
-                    // TODO find a way to compress this code since we do the exact same thing when
-                    // saving files.
-
-                    // Signal to the scanning thread that we initiated the file system change.
-                    // This avoids cases where we update the file system, react to the update,
-                    // then the scanner sees that something was changed and gives us ANOTHER
-                    // notification that the file system was changed.
-                    let costume_dir = self.costume_dir.read().unwrap();
-                    debug_assert!(costume_dir.is_some());
-                    let last_modified_time = costume_dir.as_ref().unwrap().metadata().unwrap().modified().unwrap();
-                    let _ = self.scanner_tx.send(last_modified_time);
+                    let mut selected_indices: Vec<usize> = self.selected_costumes.iter().copied().collect();
+                    selected_indices.sort();
+                    self.delete_confirmation = Some(selected_indices.into_iter().map(|idx| self.sorted_saves[idx].clone()).collect());
                 }
             }
 
         });
 
+        egui::SidePanel::left("tags_panel").show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                if ui.selectable_label(self.tag_filter.is_none(), "All Saves").clicked() && self.tag_filter.is_some() {
+                    self.tag_filter = None;
+                    tags_changed = true;
+                }
+
+                let selected_paths: Vec<PathBuf> = self.selected_costumes.iter().map(|idx| self.sorted_saves[*idx].clone()).collect();
+                let counts = self.tags.counts(&costume_entries).clone();
+                for (kind, heading, new_name) in [
+                    (tags::TagKind::Tag, "Tags", &mut self.new_tag_name),
+                    (tags::TagKind::Collection, "Collections", &mut self.new_collection_name),
+                ] {
+                    ui.separator();
+                    ui.strong(heading);
+                    let names: Vec<String> = self.tags.database.names(kind).map(str::to_owned).collect();
+                    for name in names {
+                        let filter = TagFilter { kind, name: name.clone() };
+                        let is_active = self.tag_filter.as_ref() == Some(&filter);
+                        let count = counts.get(&(kind, name.clone())).copied().unwrap_or(0);
+                        let response = ui.selectable_label(is_active, format!("{name} ({count})"))
+                            .on_hover_text("Drag saves here to add them. Right-click for more.");
+
+                        if response.dnd_hover_payload::<DraggedSaves>().is_some() {
+                            ui.painter().rect_stroke(response.rect, 2.0, ui.visuals().selection.stroke);
+                        }
+                        if let Some(dragged) = response.dnd_release_payload::<DraggedSaves>() {
+                            for path in dragged.0.iter() {
+                                if let Some(entry) = costume_entries.get(path) {
+                                    self.tags.database.add(entry.tag_key(), kind, &name);
+                                }
+                            }
+                            self.tags.changed(&self.logger);
+                            tags_changed = true;
+                        }
+                        if response.clicked() {
+                            self.tag_filter = (!is_active).then_some(filter);
+                            tags_changed = true;
+                        }
+
+                        response.context_menu(|ui| {
+                            if ui.add_enabled(!selected_paths.is_empty(), egui::Button::new("Add selected saves")).clicked() {
+                                for path in selected_paths.iter() {
+                                    self.tags.database.add(costume_entries[path].tag_key(), kind, &name);
+                                }
+                                self.tags.changed(&self.logger);
+                                tags_changed = true;
+                                ui.close_menu();
+                            }
+                            if ui.add_enabled(!selected_paths.is_empty(), egui::Button::new("Remove selected saves")).clicked() {
+                                for path in selected_paths.iter() {
+                                    self.tags.database.remove(&costume_entries[path].tag_key(), kind, &name);
+                                }
+                                self.tags.changed(&self.logger);
+                                tags_changed = true;
+                                ui.close_menu();
+                            }
+                            if ui.button("Rename...").clicked() {
+                                self.tag_rename = Some(TagRename { kind, old_name: name.clone(), new_name: name.clone() });
+                                ui.close_menu();
+                            }
+                            if ui.button("Delete").on_hover_text("Saves are kept, they just lose this").clicked() {
+                                self.tags.database.delete(kind, &name);
+                                self.tags.changed(&self.logger);
+                                if is_active {
+                                    self.tag_filter = None;
+                                }
+                                tags_changed = true;
+                                ui.close_menu();
+                            }
+                        });
+                    }
+
+                    ui.horizontal(|ui| {
+                        let name_edit = ui.add(egui::TextEdit::singleline(new_name).desired_width(100.0).hint_text("New name"));
+                        let submitted = name_edit.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+                        if (ui.button("Add").clicked() || submitted) && self.tags.database.create(kind, new_name.trim()) {
+                            self.tags.changed(&self.logger);
+                            new_name.clear();
+                        }
+                    });
+                }
+            });
+        });
+
+        if tags_changed {
+            // Keep whatever is still visible selected.
+            let selected_paths: HashSet<PathBuf> = self.selected_costumes.iter().map(|idx| self.sorted_saves[*idx].clone()).collect();
+            self.refresh_sorted_saves(&timestamp_display, &costume_entries);
+            self.selected_costumes = self.sorted_saves.iter().enumerate()
+                .filter(|(_, path)| selected_paths.contains(*path))
+                .map(|(idx, _)| idx)
+                .collect();
+            if self.selected_costumes.len() != selected_paths.len() {
+                self.selected_costumes.clear();
+                self.costume_edit = None;
+            }
+            self.selection_range_pivot = self.selected_costumes.iter().min().copied().unwrap_or(0);
+        }
+
+        if let Some(dragged) = egui::DragAndDrop::payload::<DraggedSaves>(ctx) {
+            if let Some(pointer) = ctx.pointer_interact_pos() {
+                egui::Area::new(egui::Id::new("dragged saves"))
+                    .order(egui::Order::Tooltip)
+                    .fixed_pos(pointer + egui::vec2(12.0, 12.0))
+                    .interactable(false)
+                    .show(ctx, |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.label(format!("{} saves, drop on a tag or collection", dragged.0.len()));
+                        });
+                    });
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("New costume from spec text...").clicked() {
+                    self.spec_catalog = Self::learn_spec_catalog(None, &costume_entries);
+                    let mut new_costume = NewCostume::default();
+                    new_costume.validate_spec(&self.spec_catalog);
+                    self.new_costume = Some(new_costume);
+                }
+
+                if ui.button("New costume from clipboard").on_hover_text("Uses the clipboard text as the spec and the clipboard image, if any, as the image").clicked() {
+                    match arboard::Clipboard::new() {
+                        Ok(mut clipboard) => {
+                            let mut new_costume = NewCostume::default();
+                            match clipboard.get_text() {
+                                Ok(text) => new_costume.costume_spec = text,
+                                Err(err) => self.logger.log(LogLevel::Warn, format!("no costume spec text in the clipboard: {err}").as_str()),
+                            }
+                            if let Ok(image_data) = clipboard.get_image() {
+                                let image = image::RgbaImage::from_raw(image_data.width as u32, image_data.height as u32, image_data.bytes.into_owned())
+                                    .map(image::DynamicImage::ImageRgba8);
+                                match image.map(|image| encode_costume_image(&image)) {
+                                    Some(Ok(image_jpeg)) => {
+                                        new_costume.image_jpeg = Some(image_jpeg);
+                                        new_costume.image_source = "clipboard".to_owned();
+                                    },
+                                    Some(Err(err)) => self.logger.log(LogLevel::Error, format!("failed to encode clipboard image: {err}").as_str()),
+                                    None => self.logger.log(LogLevel::Error, "clipboard image has an unexpected size"),
+                                }
+                            }
+
+                            self.spec_catalog = Self::learn_spec_catalog(None, &costume_entries);
+                            new_costume.validate_spec(&self.spec_catalog);
+                            self.new_costume = Some(new_costume);
+                        },
+                        Err(err) => self.logger.log(LogLevel::Error, format!("failed to open clipboard: {err}").as_str()),
+                    }
+                }
+
+                let export_button = ui.add_enabled(!self.selected_costumes.is_empty(), egui::Button::new("Export selected..."))
+                    .on_hover_text("Write the selected saves to a JSON costume library with the images embedded. Use `ccm import` to turn it back into saves.");
+                if export_button.clicked() {
+                    let library_path = rfd::FileDialog::new()
+                        .set_title("Export the selected saves to a costume library")
+                        .add_filter("Costume library", &["json"])
+                        .set_file_name("costumes.json")
+                        .save_file();
+                    if let Some(library_path) = library_path {
+                        let mut selected_indices: Vec<usize> = self.selected_costumes.iter().copied().collect();
+                        selected_indices.sort();
+                        let selected_paths: Vec<&PathBuf> = selected_indices.into_iter().map(|idx| &self.sorted_saves[idx]).collect();
+                        if let Err(costume_load_error) = Self::load_full_saves(&selected_paths, &mut costume_entries) {
+                            self.logger.log_err_ack_required(costume_load_error);
+                        } else {
+                            let saves = selected_paths.iter().map(|path| {
+                                let entry = &costume_entries[*path];
+                                (&entry.file_name, &entry.save)
+                            });
+                            match library::export_library(&library_path, saves, library::ImageStorage::Embedded) {
+                                Ok(num_exported) => self.logger.log(LogLevel::Info, format!("exported {num_exported} saves to {library_path:?}").as_str()),
+                                Err(err) => self.logger.log_err_ack_required(AppError::ExportFailed(err)),
+                            }
+                        }
+                    }
+                }
+
+                let export_pack_button = ui.add_enabled(!self.selected_costumes.is_empty(), egui::Button::new("Export selected as pack..."))
+                    .on_hover_text("Bundle the selected saves, untouched, into a .ccmpack archive for sharing.");
+                if export_pack_button.clicked() {
+                    self.pack_export_note = Some(String::new());
+                }
+
+                let import_pack_button = ui.add_enabled(self.costume_dir.read().unwrap().is_some(), egui::Button::new("Import pack..."))
+                    .on_disabled_hover_text("Choose a costume directory first");
+                if import_pack_button.clicked() {
+                    let pack_path = rfd::FileDialog::new()
+                        .set_title("Import a costume pack")
+                        .add_filter("Costume pack", &[pack::EXTENSION])
+                        .pick_file();
+                    if let Some(pack_path) = pack_path {
+                        match pack::read_pack(&pack_path) {
+                            Ok(pack) => {
+                                let costume_dir = self.costume_dir.read().unwrap();
+                                self.pack_import = Some(PackImport::new(pack_path, pack, costume_dir.as_ref().unwrap(), &costume_entries));
+                            },
+                            Err(err) => self.logger.log_err_ack_required(AppError::PackFailed(err)),
+                        }
+                    }
+                }
+
+                let find_duplicates_button = ui.add_enabled(!costume_entries.is_empty(), egui::Button::new("Find duplicates..."))
+                    .on_hover_text("Group saves of the same costume and archive or delete the extra copies.");
+                if find_duplicates_button.clicked() {
+                    self.duplicate_review = Some(DuplicateReview::new(&costume_entries));
+                }
+
+                let costume_dir = self.costume_dir.read().unwrap().clone();
+                let trash_button = ui.add_enabled(costume_dir.is_some(), egui::Button::new("Trash..."))
+                    .on_hover_text("Restore deleted saves or empty the trash")
+                    .on_disabled_hover_text("Choose a costume directory first");
+                if trash_button.clicked() {
+                    let costume_dir = costume_dir.unwrap();
+                    purge_trash(&costume_dir, self.trash_retention_days, &mut self.tags, &self.logger);
+                    self.trash_browser = Some(TrashBrowser::new(&costume_dir, &self.logger));
+                }
+
+                ui.separator();
+                Self::show_undo_buttons(ui, &mut self.history, history_locked);
+                ui.toggle_value(&mut self.history.panel_open, "History");
+            });
             ui.horizontal(|ui| {
                 if ui.button("Change").clicked() {
                     // TODO I feel like this could be a bit cleaner
@@ -1044,11 +3915,10 @@ impl eframe::App for App {
                     };
                     if let Some(dir) = new_costume_dir {
                         self.logger.log(LogLevel::Info, format!("changing costume directory to {dir:?}").as_str());
-                        if let Err(e) = fs::write(APP_CONFIG_FILE_NAME, dir.to_str().unwrap()) {
-                            // TODO should we display this error to the user?
-                            self.logger.log(LogLevel::Error, &e.to_string());
-                        }
+                        purge_trash(&dir, self.trash_retention_days, &mut self.tags, &self.logger);
+                        self.interrupted_saves = find_interrupted_saves(&dir, &self.logger);
                         self.costume_dir.write().unwrap().replace(dir);
+                        Self::save_app_config(&self.costume_dir, &self.timestamp_display, self.trash_retention_days, &self.logger);
                     }
                 }
 
@@ -1060,6 +3930,89 @@ impl eframe::App for App {
                 }
             });
 
+            let saves_with_warnings: Vec<PathBuf> = costume_entries.iter()
+                .filter(|(_, entry)| !entry.warnings.is_empty())
+                .map(|(path, _)| path.clone())
+                .collect();
+            if !saves_with_warnings.is_empty() {
+                ui.horizontal(|ui| {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!("{} saves have a missing or unexpected record version (see logs)", saves_with_warnings.len()),
+                    );
+                    if ui.button("Fix All").clicked() {
+                        let mut num_fixed = 0;
+                        let mut recording = journal::Recording::default();
+                        for costume_path in saves_with_warnings.iter() {
+                            let entry = costume_entries.get_mut(costume_path).unwrap();
+                            if entry.changed_on_disk(costume_path) {
+                                self.logger.log(LogLevel::Warn, format!("not fixing {costume_path:?}: it was changed outside of the app since it was loaded").as_str());
+                                continue;
+                            }
+                            if let Err(costume_load_error) = entry.load_full_save(costume_path) {
+                                self.logger.log_err_ack_required(costume_load_error);
+                                break;
+                            }
+                            // For undoing the fix.
+                            let old_bytes = match fs::read(costume_path) {
+                                Ok(old_bytes) => old_bytes,
+                                Err(err) => {
+                                    self.logger.log_err_ack_required(AppError::CostumeSaveFailed { source: Some(err), which: costume_path.clone(), message: "failed to read file".to_owned() });
+                                    break;
+                                },
+                            };
+                            // Fix a copy so that there's nothing to revert if writing fails.
+                            let Ok(mut fixed_save) = costume::CostumeSave::parse(&entry.save.0.serialize()) else { continue };
+                            let mut record_version_fix = costume::CostumeMetadata::default();
+                            record_version_fix.set_record_version(Some(costume::EXPECTED_RECORD_VERSION));
+                            fixed_save.update_metadata(&record_version_fix);
+
+                            let written = {
+                                let _operation = self.own_operations.begin([costume_path.clone()]);
+                                write_costume_save(&fixed_save, costume_path, costume_path, &self.logger)
+                            };
+                            if let Err(costume_save_error) = written {
+                                self.logger.log_err_ack_required(costume_save_error);
+                                break;
+                            }
+                            recording.written(costume_path.clone(), old_bytes, costume_path.clone(), fixed_save.0.serialize().into());
+                            entry.save = fixed_save;
+                            entry.mark_written(costume_path);
+                            num_fixed += 1;
+                        }
+                        self.entries_generation += 1;
+                        self.logger.log(LogLevel::Info, format!("fixed the record version of {num_fixed} saves").as_str());
+                        self.history.record(format!("Fix the record version of {num_fixed} saves"), recording, &self.logger);
+                    }
+                });
+            }
+
+            let hash_collisions = self.hash_collisions.get(self.entries_generation, || {
+                let entry_metadata: Vec<(&PathBuf, costume::CostumeMetadata)> = costume_entries.iter()
+                    .map(|(path, entry)| (path, entry.save.get_metadata().unwrap()))
+                    .collect();
+                costume::hash::find_collisions(entry_metadata.iter().map(|(path, metadata)| {
+                    // Go by the stored hash since that's what the game sees, even if it's stale.
+                    let hash = costume::hash::CostumeHash::parse(metadata.hash()).unwrap_or_else(|_| costume::hash::CostumeHash::of(metadata.spec()));
+                    ((*path).clone(), hash, metadata.spec())
+                }))
+            });
+            if !hash_collisions.is_empty() {
+                let mut details = String::new();
+                for (hash, groups) in hash_collisions.iter() {
+                    details.push_str(&format!("{}:\n", hash.to_string().trim_end_matches('\0')));
+                    for (spec_index, group) in groups.iter().enumerate() {
+                        for path in group {
+                            details.push_str(&format!("  spec {}: {}\n", spec_index + 1, path.file_name().unwrap().to_string_lossy()));
+                        }
+                    }
+                }
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!("{} costume hashes are shared by saves with different specs", hash_collisions.len()),
+                ).on_hover_text(details.trim_end());
+            }
+
             let prev_display_type = self.display_type;
             let prev_sort_type = self.sort_type;
             ui.horizontal(|ui| {
@@ -1074,13 +4027,136 @@ impl eframe::App for App {
                 ui.selectable_value(&mut self.sort_type, SortType::CreationTime, "Creation Time");
                 ui.selectable_value(&mut self.sort_type, SortType::ModifiedTime, "Modified Time");
             });
-            let sort_needed = self.sort_type != prev_sort_type || self.sort_type == SortType::Name && self.display_type != prev_display_type;
-            if sort_needed {
+            ui.horizontal(|ui| {
+                ui.label("Timestamps:");
+                let mut new_timestamp_display = timestamp_display.clone();
+                ui.selectable_value(&mut new_timestamp_display.time_zone, costume::DisplayTimeZone::Utc, "UTC");
+                ui.selectable_value(&mut new_timestamp_display.time_zone, costume::DisplayTimeZone::Local, "Local");
+                let is_fixed = matches!(new_timestamp_display.time_zone, costume::DisplayTimeZone::Fixed(_));
+                if ui.selectable_label(is_fixed, "Fixed Offset").clicked() && !is_fixed {
+                    new_timestamp_display.time_zone = costume::DisplayTimeZone::Fixed(chrono::FixedOffset::east_opt(0).unwrap());
+                }
+                if let costume::DisplayTimeZone::Fixed(offset) = &mut new_timestamp_display.time_zone {
+                    // Edited in minutes, shown as e.g. "+05:30".
+                    let mut offset_minutes = offset.local_minus_utc() / 60;
+                    let max_minutes = 24 * 60 - 1;
+                    ui.add(
+                        egui::DragValue::new(&mut offset_minutes)
+                            .range(-max_minutes..=max_minutes)
+                            .speed(15)
+                            .custom_formatter(|minutes, _| {
+                                let minutes = minutes as i32;
+                                format!("{}{:02}:{:02}", if minutes < 0 { '-' } else { '+' }, minutes.abs() / 60, minutes.abs() % 60)
+                            })
+                            .custom_parser(|text| {
+                                text.parse::<chrono::FixedOffset>().ok().map(|offset| f64::from(offset.local_minus_utc() / 60))
+                            }),
+                    );
+                    *offset = chrono::FixedOffset::east_opt(offset_minutes * 60).unwrap();
+                }
+                ui.text_edit_singleline(&mut self.timestamp_format_edit)
+                    .on_hover_text(format!("strftime-style format, e.g. \"{}\"", costume::DEFAULT_TIMESTAMP_FORMAT));
+                if costume::TimestampDisplay::is_valid_format(&self.timestamp_format_edit) {
+                    new_timestamp_display.format = self.timestamp_format_edit.clone();
+                } else {
+                    ui.colored_label(ui.visuals().error_fg_color, "invalid format");
+                }
+
+                if new_timestamp_display != timestamp_display {
+                    for entry in costume_entries.values_mut() {
+                        entry.regenerate_in_game_display_name(&new_timestamp_display);
+                    }
+                    if let Some(costume_edit) = self.costume_edit.as_mut() {
+                        costume_edit.regenerate_indirect_fields(&new_timestamp_display);
+                    }
+                    // NOTE No need to re-sort since sorting by display name uses the raw timestamps. The
+                    // search does depend on the display though, through dates and display names.
+                    if !self.search_query.is_empty() {
+                        self.refresh_sorted_saves(&new_timestamp_display, &costume_entries);
+                        self.costume_edit = None;
+                        self.selected_costumes.clear();
+                        self.selection_range_pivot = 0;
+                    }
+                    *self.timestamp_display.write().unwrap() = new_timestamp_display;
+                    Self::save_app_config(&self.costume_dir, &self.timestamp_display, self.trash_retention_days, &self.logger);
+                }
+            });
+            let prev_keyword_filter = self.keyword_filter.clone();
+            ui.horizontal(|ui| {
+                ui.label("Keyword:");
+                let all_keywords = self.all_keywords.get(self.entries_generation, || {
+                    let mut all_keywords: Vec<String> = costume_entries.values()
+                        .flat_map(|entry| entry.save.get_metadata().unwrap().keywords().map(str::to_owned).collect::<Vec<_>>())
+                        .collect();
+                    all_keywords.sort_by_key(|keyword| keyword.to_ascii_lowercase());
+                    all_keywords.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+                    all_keywords
+                });
+
+                egui::ComboBox::from_id_salt("keyword filter")
+                    .selected_text(self.keyword_filter.as_deref().unwrap_or("Any"))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.keyword_filter, None, "Any");
+                        for keyword in all_keywords.iter() {
+                            ui.selectable_value(&mut self.keyword_filter, Some(keyword.clone()), keyword);
+                        }
+                    });
+            });
+            // Some(true) if the new query only narrows the old one so the current list can just be
+            // filtered further, Some(false) if everything has to be searched again.
+            let mut search_narrowed = None;
+            ui.horizontal(|ui| {
+                ui.label("Search:");
+                let search_edit = ui.add(egui::TextEdit::singleline(&mut self.search_text).hint_text("text, account:, character:, keyword:, part:, has:, before:, after:"))
+                    .on_hover_text(
+                        "Free text matches the display name, file name and costume spec.\n\
+                        account:TEXT  character:TEXT  keyword:KEYWORD  part:TEXT\n\
+                        has:timestamp  has:keyword  before:YYYY-MM-DD  after:YYYY-MM-DD\n\
+                        Quote values with spaces, e.g. character:\"Dr X\". Prefix a term with - to exclude it."
+                    );
+                if search_edit.changed() {
+                    match costume::search::SearchQuery::parse(&self.search_text) {
+                        Ok(search_query) => {
+                            self.search_error = None;
+                            if search_query != self.search_query {
+                                search_narrowed = Some(search_query.narrows(&self.search_query));
+                                self.search_query = search_query;
+                            }
+                        },
+                        // Keep showing the results of the last valid query while typing.
+                        Err(err) => self.search_error = Some(err),
+                    }
+                }
+                if let Some(err) = &self.search_error {
+                    ui.colored_label(ui.visuals().error_fg_color, err.to_string());
+                }
+            });
+
+            if self.keyword_filter != prev_keyword_filter || search_narrowed == Some(false) {
+                self.entries_generation += 1;
+                self.sorted_saves = Self::filter_saves(self.keyword_filter.as_deref(), &self.search_query, self.tag_filter.as_ref(), &self.tags.database, &timestamp_display, &costume_entries);
+            } else if search_narrowed == Some(true) {
+                // Filtering in place keeps the sort order.
+                self.sorted_saves.retain(|path| costume_entries[path].matches_search(&self.search_query, &timestamp_display));
+            }
+
+            let sort_needed = self.sort_type != prev_sort_type
+                || self.sort_type == SortType::Name && self.display_type != prev_display_type
+                || self.keyword_filter != prev_keyword_filter
+                || search_narrowed == Some(false);
+            if sort_needed || search_narrowed.is_some() {
+                self.costume_edit = None;
                 self.selected_costumes.clear();
                 self.selection_range_pivot = 0;
+            }
+            if sort_needed {
                 Self::sort_saves(self.sort_type, self.display_type, &mut self.sorted_saves, &costume_entries);
             }
 
+            if let Some((num_loaded, num_found)) = self.scan_progress {
+                ui.add(egui::ProgressBar::new(num_loaded as f32 / num_found as f32).text(format!("Loading saves: {num_loaded} of {num_found}")));
+            }
+
             ui.separator();
             egui::ScrollArea::vertical().show(ui, |ui| {
                 let available_width = ui.available_width();
@@ -1111,8 +4187,8 @@ impl eframe::App for App {
                         let entry = costume_entries.get_mut(save_file_name).unwrap();
                         let is_selected = self.selected_costumes.contains(&idx);
                         let display_name = match self.display_type {
-                            DisplayType::DisplayName => entry.in_game_display_name.as_str(),
-                            DisplayType::FileName => entry.file_name.as_str(),
+                            DisplayType::DisplayName => entry.in_game_display_name.clone(),
+                            DisplayType::FileName => entry.file_name.to_string(),
                         };
 
                         let selectable_costume_item = if self.show_images_in_selection_list {
@@ -1143,6 +4219,9 @@ impl eframe::App for App {
                                         // similar to this. Maybe find a way to pull this logic out into a function?
                                         if let CostumeImage::Loaded(texture) = &entry.image_texture {
                                             ui.add(egui::Image::new(texture).fit_to_exact_size(IMAGE_SIZE.into()));
+                                        } else if let Some(thumbnail) = entry.thumbnail.as_ref() {
+                                            // NOTE egui keeps the decoded thumbnail around under this URI.
+                                            ui.add(egui::Image::from_bytes(thumbnail_uri(save_file_name), Arc::clone(thumbnail)).fit_to_exact_size(IMAGE_SIZE.into()));
                                         } else {
                                             ui.label("loading image...");
                                         }
@@ -1169,7 +4248,7 @@ impl eframe::App for App {
 
                             entry.image_visible_in_grid = scroll_area_clip_rect.intersects(custom_button.rect);
                             if entry.image_visible_in_grid && matches!(entry.image_texture, CostumeImage::NotLoaded) {
-                                _ = self.decode_job_tx.send(save_file_name.clone());
+                                _ = self.decode_job_tx.send(DecodeJob { file_path: save_file_name.clone(), content_hash: entry.content_hash, hash_only: false, make_thumbnail: entry.thumbnail.is_none() });
                                 entry.image_texture = CostumeImage::Loading;
                             }
 
@@ -1181,6 +4260,16 @@ impl eframe::App for App {
                             selectable_label
                         };
 
+                        let selectable_costume_item = selectable_costume_item.interact(egui::Sense::drag());
+                        if selectable_costume_item.drag_started() {
+                            let dragged = if is_selected {
+                                self.selected_costumes.iter().map(|idx| self.sorted_saves[*idx].clone()).collect()
+                            } else {
+                                vec![save_file_name.clone()]
+                            };
+                            selectable_costume_item.dnd_set_drag_payload(DraggedSaves(dragged));
+                        }
+
                         if selectable_costume_item.clicked() {
                             if self.selected_costumes.is_empty() {
                                 self.selected_costumes.insert(idx);
@@ -1244,18 +4333,110 @@ impl eframe::App for App {
 const DEFAULT_COSTUME_DIR: &str = "C:\\Program Files (x86)\\Steam\\steamapps\\common\\Champions Online\\Champions Online\\Live\\screenshots";
 const APP_CONFIG_FILE_NAME: &str = "ccm_config.cfg";
 
+/// Persisted app settings, stored as `key=value` lines. Older versions stored nothing but the
+/// costume directory path, which is still accepted.
+struct AppConfig {
+    costume_dir: Option<PathBuf>,
+    timestamp_display: costume::TimestampDisplay,
+    /// Saves in the trash longer than this many days are deleted for good. Zero keeps them forever.
+    trash_retention_days: u32,
+}
+
+impl AppConfig {
+    const COSTUME_DIR_KEY: &str = "costume_dir";
+    const DISPLAY_TIME_ZONE_KEY: &str = "display_time_zone";
+    const DISPLAY_TIME_FORMAT_KEY: &str = "display_time_format";
+    const TRASH_RETENTION_DAYS_KEY: &str = "trash_retention_days";
+    const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
+
+    fn new(costume_dir: Option<PathBuf>) -> Self {
+        Self { costume_dir, timestamp_display: costume::TimestampDisplay::default(), trash_retention_days: Self::DEFAULT_TRASH_RETENTION_DAYS }
+    }
+
+    fn parse(config: &str) -> Self {
+        let mut app_config = Self::new(None);
+        let is_legacy = !config.lines().any(|line| {
+            line.split_once('=').is_some_and(|(key, _)| {
+                [Self::COSTUME_DIR_KEY, Self::DISPLAY_TIME_ZONE_KEY, Self::DISPLAY_TIME_FORMAT_KEY, Self::TRASH_RETENTION_DAYS_KEY].contains(&key)
+            })
+        });
+        if is_legacy {
+            app_config.costume_dir = (!config.is_empty()).then(|| config.into());
+            return app_config;
+        }
+
+        for (key, value) in config.lines().filter_map(|line| line.split_once('=')) {
+            match key {
+                Self::COSTUME_DIR_KEY => app_config.costume_dir = Some(value.into()),
+                Self::DISPLAY_TIME_ZONE_KEY => match value {
+                    "utc" => app_config.timestamp_display.time_zone = costume::DisplayTimeZone::Utc,
+                    "local" => app_config.timestamp_display.time_zone = costume::DisplayTimeZone::Local,
+                    _ => if let Ok(offset) = value.parse() {
+                        app_config.timestamp_display.time_zone = costume::DisplayTimeZone::Fixed(offset);
+                    },
+                },
+                Self::DISPLAY_TIME_FORMAT_KEY if costume::TimestampDisplay::is_valid_format(value) => {
+                    app_config.timestamp_display.format = value.to_owned();
+                },
+                Self::TRASH_RETENTION_DAYS_KEY => if let Ok(days) = value.parse() {
+                    app_config.trash_retention_days = days;
+                },
+                _ => {},
+            }
+        }
+
+        app_config
+    }
+
+    /// Load the config that lives next to the executable, falling back to the defaults if there
+    /// isn't one.
+    fn load() -> Self {
+        let config_path = env::current_exe()
+            .expect("failed to get dir of executable")
+            .with_file_name(APP_CONFIG_FILE_NAME);
+        match fs::read(config_path) {
+            Ok(app_config_bytes) => Self::parse(&String::from_utf8(app_config_bytes).unwrap()),
+            Err(_) => Self::new(None),
+        }
+    }
+
+    fn serialize(&self) -> String {
+        let mut serialized = String::new();
+        if let Some(costume_dir) = self.costume_dir.as_ref() {
+            serialized.push_str(&format!("{}={}\n", Self::COSTUME_DIR_KEY, costume_dir.to_str().unwrap()));
+        }
+        // NOTE Fixed offsets are written as e.g. "+05:30", which FixedOffset parses back.
+        let time_zone = match self.timestamp_display.time_zone {
+            costume::DisplayTimeZone::Utc => "utc".to_owned(),
+            costume::DisplayTimeZone::Local => "local".to_owned(),
+            costume::DisplayTimeZone::Fixed(offset) => offset.to_string(),
+        };
+        serialized.push_str(&format!("{}={time_zone}\n", Self::DISPLAY_TIME_ZONE_KEY));
+        serialized.push_str(&format!("{}={}\n", Self::DISPLAY_TIME_FORMAT_KEY, self.timestamp_display.format));
+        serialized.push_str(&format!("{}={}\n", Self::TRASH_RETENTION_DAYS_KEY, self.trash_retention_days));
+
+        serialized
+    }
+}
+
 fn main() {
+    // NOTE The CLI has to run before we change the cwd since it takes paths relative to wherever
+    // it was invoked from.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !matches!(args.first().map(String::as_str), None | Some("gui")) {
+        std::process::exit(cli::run(&args));
+    }
+
     let exe_path = env::current_exe().expect("failed to get dir of executable");
     env::set_current_dir(exe_path.parent().unwrap()).expect("failed to set cwd");
 
-    let mut costume_dir: Option<PathBuf> = None;
-    if fs::exists(APP_CONFIG_FILE_NAME).expect("failed to check if app config file exists") {
-        let app_config_bytes = fs::read(APP_CONFIG_FILE_NAME).unwrap();
-        costume_dir.replace(String::from_utf8(app_config_bytes).unwrap().into());
-    } else if fs::exists(DEFAULT_COSTUME_DIR).expect("failed to check if default costume dir exists") {
-        costume_dir.replace(DEFAULT_COSTUME_DIR.into());
+    let mut app_config = AppConfig::load();
+    if app_config.costume_dir.is_none() && fs::exists(DEFAULT_COSTUME_DIR).expect("failed to check if default costume dir exists") {
+        app_config.costume_dir.replace(DEFAULT_COSTUME_DIR.into());
     }
-    let costume_dir = Arc::new(RwLock::new(costume_dir));
+    let costume_dir = Arc::new(RwLock::new(app_config.costume_dir));
+    let trash_retention_days = app_config.trash_retention_days;
+    let timestamp_display = Arc::new(RwLock::new(app_config.timestamp_display));
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([1280.0, 720.0]),
@@ -1268,9 +4449,8 @@ fn main() {
         Box::new(|cc| {
             let (ui_message_tx, ui_message_rx) = mpsc::channel::<UiMessage>();
             let (ui_priority_message_tx, ui_priority_message_rx) = mpsc::channel::<UiPriorityMessage>();
-            // For the UI thread to communicate that it updated the filesystem so the scanner
-            // thread doesn't run unnecessarily.
-            let (scanner_tx, scanner_rx) = mpsc::channel::<SystemTime>();
+            // For the UI thread to tell the scanner which file system changes are its own.
+            let own_operations = watcher::OwnOperations::default();
             // For the main app to signal graceful thread shutdown on exit
             let shutdown_flag = Arc::new(atomic::AtomicBool::new(false));
 
@@ -1284,7 +4464,7 @@ fn main() {
 
             let available_cores = thread::available_parallelism().map(NonZero::get).unwrap_or(MAX_DECODE_THREADS);
             let num_workers = MAX_DECODE_THREADS.min(available_cores);
-            let (decode_job_tx, decode_job_rx) = mpsc::channel::<PathBuf>();
+            let (decode_job_tx, decode_job_rx) = mpsc::channel::<DecodeJob>();
             let decode_job_rx = Arc::new(Mutex::new(decode_job_rx));
 
             // workers for decoding
@@ -1305,7 +4485,7 @@ fn main() {
                         }
                         let decode_job = decode_job_rx.lock().unwrap().recv_timeout(Duration::from_millis(32));
 
-                        if let Ok(file_path) = decode_job {
+                        if let Ok(DecodeJob { file_path, content_hash, hash_only, make_thumbnail }) = decode_job {
                             // TODO Instead of reading the file again, maybe we should just
                             // serialize the costume and use _those_ bytes? The costume data is
                             // owned by a hashmap behind a mutex though... Or maybe we need to
@@ -1319,16 +4499,30 @@ fn main() {
                                     continue;
                                 }
                             };
+                            let file_content_hash = index::ContentHash::of(&jpeg_bytes);
+                            if content_hash.is_some_and(|content_hash| content_hash != file_content_hash) {
+                                logger.log(LogLevel::Info, format!("not decoding {:?}, it changed since it was loaded", file_path).as_str());
+                                _ = ui_message_tx.send(UiMessage::DecodeStale { file_path, content_hash: file_content_hash });
+                                ctx.request_repaint();
+                                continue;
+                            }
 
                             let mut decoder = zune_jpeg::JpegDecoder::new(jpeg_bytes);
                             // TODO when we implement logging, if this fails send to the UI as an error to display.
                             if let Ok(pixels) = decoder.decode() {
                                 // TODO default if doesn't exist
                                 let info = decoder.info().expect("no jpeg info");
+                                let image_hash = dedupe::ImageHash::of_rgb(&pixels, info.width as usize, info.height as usize);
+                                let thumbnail = make_thumbnail.then(|| index::thumbnail_jpeg(&pixels, info.width as usize, info.height as usize)).flatten();
+                                if hash_only {
+                                    _ = ui_message_tx.send(UiMessage::ImageHashed { file_path, content_hash, image_hash, thumbnail });
+                                    ctx.request_repaint();
+                                    continue;
+                                }
                                 let image = egui::ColorImage::from_rgb([info.width as usize, info.height as usize], &pixels);
                                 let texture_handle = ctx.load_texture(file_path.to_str().unwrap(), image, egui::TextureOptions::default());
                                 logger.log(LogLevel::Info, format!("decoded {:?}", file_path).as_str());
-                                _ = ui_message_tx.send(UiMessage::JpegDecoded { file_path, texture_handle });
+                                _ = ui_message_tx.send(UiMessage::JpegDecoded { file_path, content_hash, texture_handle, image_hash, thumbnail });
                                 ctx.request_repaint();
                             }
                         }
@@ -1352,95 +4546,32 @@ fn main() {
             // SCANNING THREAD
             {
                 let costume_dir = Arc::clone(&costume_dir);
+                let timestamp_display = Arc::clone(&timestamp_display);
                 let costume_entries = Arc::clone(&costume_entries);
                 let shutdown_flag = Arc::clone(&shutdown_flag);
                 let frame = cc.egui_ctx.clone();
-                let logger = LOGGER.new_handle("SCANNER");
+                let ui_message_tx = ui_message_tx.clone();
+                let own_operations = own_operations.clone();
                 let scanner_handle = thread::spawn(move || {
-                    let mut last_modified_time: Option<SystemTime> = None;
-                    loop {
-                        if shutdown_flag.load(atomic::Ordering::Acquire) {
-                            break;
-                        }
-                        if logger.ui_ack_required() {
-                            thread::sleep(Duration::from_millis(100));
-                            continue;
-                        }
-
-                        // If the UI initiated file system changes we need to know so that we don't
-                        // misidentify an external file system change.
-                        //
-                        // FIXME we might miss some messages, resulting in erroneous file system
-                        // detections. I think that because we haven't blocked trying to lock the
-                        // saves hashmap yet, the UI can still technically send messages through
-                        // this pipe AFTER the scanner thread has passed it this loop iteration...
-                        while let Ok(ui_last_modified_time) = scanner_rx.try_recv() {
-                            last_modified_time = Some(ui_last_modified_time);
-                        }
-
-                        let directory_entries_to_check = (|| {
-                            // TODO cleanup
-                            // TODO use match statements to log on fs failures
-                            if let Ok(costume_dir_guard) = costume_dir.try_read() {
-                                if let Some(dir_to_read) = costume_dir_guard.as_ref() {
-                                    if let Ok(modified_time) = fs::metadata(dir_to_read).and_then(|m| m.modified()) {
-                                        if last_modified_time.is_none_or(|lmt| modified_time != lmt) {
-                                            logger.log(LogLevel::Info, "detected file system change");
-                                            last_modified_time = Some(modified_time);
-                                            return fs::read_dir(dir_to_read).ok();
-                                        }
-                                    }
-                                }
-                            }
-
-                            None
-                        })();
-
-                        if let Some(directory_entries_to_check) = directory_entries_to_check {
-                            let mut costume_entries = costume_entries.lock().unwrap();
-                            let mut missing_files: HashSet<PathBuf> = HashSet::from_iter(costume_entries.keys().cloned());
-                            let mut num_new_files = 0;
-                            for directory_entry in directory_entries_to_check.flatten() {
-                                let file_path = directory_entry.path();
-                                #[allow(clippy::map_entry)]
-                                if costume_entries.contains_key(&file_path) {
-                                    missing_files.remove(file_path.as_path());
-                                } else if costume::is_valid_costume_file_name(&file_path) {
-                                    let jpeg_raw = match fs::read(&file_path) {
-                                        Ok(contents) => contents,
-                                        Err(err) => {
-                                            logger.log(LogLevel::Warn, format!("error reading {file_path:?}: {}", err).as_str());
-                                            continue;
-                                        }
-                                    };
-
-                                    let save = match costume::CostumeSave::parse(&jpeg_raw) {
-                                        Ok(parsed) => parsed,
-                                        Err(err) => {
-                                            logger.log(LogLevel::Warn, format!("failed to parse save file {file_path:?}: {}", err).as_str());
-                                            continue;
-                                        }
-                                    };
-
-                                    let costume_entry = CostumeEntry::new(&file_path, save);
-                                    costume_entries.insert(file_path, costume_entry);
-                                    num_new_files += 1;
-                                }
-                            }
-                            let num_missing_files = missing_files.len();
-                            for missing_file in missing_files {
-                                // TODO figure out if we need to explicitly forget image textures here.
-                                costume_entries.remove(&missing_file);
-                            }
-                            logger.log(LogLevel::Info, format!("added {num_new_files} new costumes, removed {num_missing_files} missing costumes").as_str());
-                            _ = ui_priority_message_tx.send(UiPriorityMessage::FileListChangedExternally);
-                            frame.request_repaint();
-                        }
-
-                        thread::sleep(Duration::from_millis(100));
-                    }
-
-                    logger.log(LogLevel::Info, "shutting down");
+                    let logger = LOGGER.new_handle("SCANNER");
+                    let metadata_index = index::MetadataIndex::load(&index::MetadataIndex::default_path()).unwrap_or_else(|err| {
+                        logger.log(LogLevel::Warn, format!("ignoring the metadata index, every save will be parsed again: {err}").as_str());
+                        index::MetadataIndex::default()
+                    });
+                    logger.log(LogLevel::Info, format!("{} saves in the metadata index", metadata_index.len()).as_str());
+                    let scanner = Scanner {
+                        costume_dir,
+                        timestamp_display,
+                        costume_entries,
+                        own_operations,
+                        ui_message_tx,
+                        ui_priority_message_tx,
+                        frame,
+                        logger,
+                        metadata_index,
+                        watched: None,
+                    };
+                    scanner.run(&shutdown_flag);
                 });
 
                 support_thread_handles.push(scanner_handle);
@@ -1448,13 +4579,15 @@ fn main() {
 
             let args = AppArgs {
                 costume_dir,
+                timestamp_display,
                 costume_entries,
                 shutdown_flag,
                 support_thread_handles,
                 ui_priority_message_rx,
                 ui_message_rx,
-                scanner_tx,
+                own_operations,
                 decode_job_tx,
+                trash_retention_days,
                 logger: LOGGER.new_handle("UI"),
             };
 
@@ -1462,3 +4595,194 @@ fn main() {
         })
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util::TestDir;
+
+    fn write_save(file_path: &Path, spec: &str) {
+        fs::write(file_path, test_util::save("@Account", "Character", spec).0.serialize()).unwrap();
+    }
+
+    fn bulk_edit_entries(file_names: &[&str]) -> (Vec<PathBuf>, HashMap<PathBuf, CostumeEntry>) {
+        let save = test_util::save("@Account", "Character", "CostumeV2\n{\n}\n");
+        let file_paths: Vec<PathBuf> = file_names.iter().map(|file_name| Path::new("saves").join(file_name)).collect();
+        let costume_entries = file_paths.iter()
+            .map(|file_path| {
+                let save = costume::CostumeSave::parse(&save.0.serialize()).unwrap();
+                (file_path.clone(), CostumeEntry::new(file_path, save, &costume::TimestampDisplay::default()))
+            })
+            .collect();
+        (file_paths, costume_entries)
+    }
+
+    fn preview_names(bulk_edit: &BulkEdit, file_paths: &[PathBuf], costume_entries: &HashMap<PathBuf, CostumeEntry>) -> Vec<(String, Option<String>)> {
+        bulk_edit.preview(file_paths, costume_entries, &costume::TimestampDisplay::default()).into_iter()
+            .map(|preview| (preview.new_file_name.to_string(), preview.problem))
+            .collect()
+    }
+
+    #[test]
+    fn bulk_edit_previews_problems() {
+        let (file_paths, costume_entries) = bulk_edit_entries(&[
+            "Costume_v-2.jpg",
+            "Costume_v-3_100.jpg",
+            "Costume_a-1_5.jpg",
+            "Costume_a_1_5.jpg",
+            "Costume_x_7.jpg",
+            "Costume_x_8.jpg",
+            "Costume_foo_9.jpg",
+            "Costume_bar_9.jpg",
+            "Costume_BAZ_9.jpg",
+        ]);
+        const TRAILING_DIGITS: &str = "save name ends in \"_<digits>\" and would be read as a timestamp";
+        let problem = |problem: &str| Some(problem.to_owned());
+
+        // Trailing digits are only a problem when there's no timestamp after them.
+        let mut bulk_edit = BulkEdit { find: "-".to_owned(), replace: "_".to_owned(), ..BulkEdit::default() };
+        assert_eq!(preview_names(&bulk_edit, &file_paths[0..2], &costume_entries), vec![
+            ("Costume_v_2.jpg".to_owned(), problem(TRAILING_DIGITS)),
+            ("Costume_v_3_100.jpg".to_owned(), None),
+        ]);
+        bulk_edit.timestamp_edit = BulkTimestampEdit::Strip;
+        assert_eq!(preview_names(&bulk_edit, &file_paths[1..2], &costume_entries), vec![("Costume_v_3.jpg".to_owned(), problem(TRAILING_DIGITS))]);
+
+        // The new name belongs to a save that isn't being edited.
+        bulk_edit.timestamp_edit = BulkTimestampEdit::Keep;
+        assert_eq!(preview_names(&bulk_edit, &file_paths[2..3], &costume_entries), vec![("Costume_a_1_5.jpg".to_owned(), problem("a save with the new name already exists"))]);
+
+        // Setting the timestamp along with the name maps both saves to the same name.
+        let bulk_edit = BulkEdit { find: "x".to_owned(), replace: "z".to_owned(), timestamp_edit: BulkTimestampEdit::Set, j2000_timestamp: 1, ..BulkEdit::default() };
+        assert_eq!(preview_names(&bulk_edit, &file_paths[4..6], &costume_entries), vec![
+            ("Costume_z_1.jpg".to_owned(), None),
+            ("Costume_z_1.jpg".to_owned(), problem("another selected save would get the same name")),
+        ]);
+
+        // Renaming a save to a different case of its own name is fine, but to a different case of
+        // another save's name collides on Windows.
+        let bulk_edit = BulkEdit { find: "foo".to_owned(), replace: "Foo".to_owned(), ..BulkEdit::default() };
+        let previews = bulk_edit.preview(&file_paths[6..7], &costume_entries, &costume::TimestampDisplay::default());
+        assert_eq!(previews[0].new_file_name.to_string(), "Costume_Foo_9.jpg");
+        assert!(previews[0].has_changes);
+        assert_eq!(previews[0].problem, None);
+        let bulk_edit = BulkEdit { find: "bar".to_owned(), replace: "baz".to_owned(), ..BulkEdit::default() };
+        assert_eq!(preview_names(&bulk_edit, &file_paths[7..8], &costume_entries), vec![("Costume_baz_9.jpg".to_owned(), problem("a save with the new name already exists"))]);
+    }
+
+    #[test]
+    fn bulk_edit_changes_only_what_differs() {
+        let (file_paths, costume_entries) = bulk_edit_entries(&["Costume_old_11.jpg"]);
+        let entry = &costume_entries[&file_paths[0]];
+        let metadata = entry.save.get_metadata().unwrap();
+
+        let bulk_edit = BulkEdit { set_account_name: true, account_name: "@Account".to_owned(), find: "missing".to_owned(), replace: "x".to_owned(), ..BulkEdit::default() };
+        assert!(!bulk_edit.edit_metadata(&metadata).is_dirty());
+        assert_eq!(bulk_edit.edit_file_name(&entry.file_name), entry.file_name);
+        assert!(!bulk_edit.preview(&file_paths, &costume_entries, &costume::TimestampDisplay::default())[0].has_changes);
+
+        let bulk_edit = BulkEdit { set_keywords: true, keywords: "FC, Hero".to_owned(), find: "old".to_owned(), replace: "new".to_owned(), timestamp_edit: BulkTimestampEdit::Strip, ..BulkEdit::default() };
+        let edited = bulk_edit.edit_metadata(&metadata);
+        assert!(edited.is_dirty());
+        assert_eq!(edited.keywords().collect::<Vec<_>>(), ["FC", "Hero"]);
+        assert_eq!(edited.account_name(), "@Account");
+        assert_eq!(bulk_edit.edit_file_name(&entry.file_name).to_string(), "Costume_new.jpg");
+
+        let bulk_edit = BulkEdit { timestamp_edit: BulkTimestampEdit::Set, j2000_timestamp: 42, ..bulk_edit };
+        let preview = &bulk_edit.preview(&file_paths, &costume_entries, &costume::TimestampDisplay::default())[0];
+        assert_eq!(preview.new_file_name.to_string(), "Costume_new_42.jpg");
+        assert_eq!(preview.new_in_game_display_name, "@AccountCharacter 2000-01-01 00:00:42");
+        assert!(preview.has_changes);
+        assert_eq!(preview.problem, None);
+    }
+
+    #[test]
+    fn scanner_reloads_only_changed_saves() {
+        use watcher::WatchEvent;
+
+        let dir = TestDir::new("scanner");
+        let modified_path = dir.join("Costume_Modified.jpg");
+        let touched_path = dir.join("Costume_Touched.jpg");
+        let renamed_path = dir.join("Costume_Renamed.jpg");
+        let broken_path = dir.join("Costume_Broken.jpg");
+        let removed_path = dir.join("Costume_Removed.jpg");
+        for file_path in [&modified_path, &touched_path, &renamed_path, &broken_path, &removed_path] {
+            write_save(file_path, "CostumeV2\n{\n\tSkeleton Female\n}\n");
+        }
+
+        let (ui_message_tx, _ui_message_rx) = mpsc::channel();
+        let (ui_priority_message_tx, ui_priority_message_rx) = mpsc::channel();
+        let mut scanner = Scanner {
+            costume_dir: Arc::new(RwLock::new(Some(dir.to_path_buf()))),
+            timestamp_display: Arc::new(RwLock::new(costume::TimestampDisplay::default())),
+            costume_entries: Arc::new(Mutex::new(HashMap::new())),
+            own_operations: watcher::OwnOperations::default(),
+            ui_message_tx,
+            ui_priority_message_tx,
+            frame: egui::Context::default(),
+            logger: LOGGER.new_handle("test"),
+            metadata_index: index::MetadataIndex::default(),
+            watched: None,
+        };
+        scanner.full_scan(&dir);
+        assert_eq!(scanner.costume_entries.lock().unwrap().len(), 5);
+        _ = ui_priority_message_rx.try_recv().unwrap();
+        // Stands in for a decoded image, which must survive everything but a reload.
+        for costume_entry in scanner.costume_entries.lock().unwrap().values_mut() {
+            costume_entry.image_hash = Some(dedupe::ImageHash(1));
+        }
+        let old_modified_key = scanner.costume_entries.lock().unwrap()[&modified_path].tag_key();
+
+        write_save(&modified_path, "CostumeV2\n{\n\tSkeleton Male\n}\n");
+        let touched_bytes = fs::read(&touched_path).unwrap();
+        fs::write(&touched_path, touched_bytes).unwrap();
+        let moved_path = dir.join("Costume_Moved.jpg");
+        fs::rename(&renamed_path, &moved_path).unwrap();
+        fs::write(&broken_path, b"not a jpeg").unwrap();
+        fs::remove_file(&removed_path).unwrap();
+        // How a transaction writes a new save.
+        let created_path = dir.join("Costume_Created.jpg");
+        let temp_path = dir.join("ccm_save_0.CCM_TEMP");
+        write_save(&temp_path, "CostumeV2\n{\n}\n");
+        fs::rename(&temp_path, &created_path).unwrap();
+
+        scanner.apply_events(vec![
+            WatchEvent::Modified(modified_path.clone()),
+            WatchEvent::Modified(touched_path.clone()),
+            WatchEvent::Renamed { from: renamed_path.clone(), to: moved_path.clone() },
+            WatchEvent::Modified(broken_path.clone()),
+            WatchEvent::Removed(removed_path.clone()),
+            WatchEvent::Renamed { from: temp_path, to: created_path.clone() },
+        ]);
+
+        let UiPriorityMessage::FileListChangedExternally { tag_relinks, modified } = ui_priority_message_rx.try_recv().unwrap();
+        // Only a reloaded save can conflict with an open edit.
+        assert_eq!(modified, vec![modified_path.clone()]);
+        let costume_entries = scanner.costume_entries.lock().unwrap();
+        let mut file_paths: Vec<&PathBuf> = costume_entries.keys().collect();
+        file_paths.sort();
+        assert_eq!(file_paths, vec![&created_path, &modified_path, &moved_path, &touched_path]);
+
+        let modified_entry = &costume_entries[&modified_path];
+        assert!(modified_entry.save.get_metadata().unwrap().spec().contains("Skeleton Male"));
+        assert_eq!(modified_entry.image_hash, None);
+        assert_eq!(costume_entries[&touched_path].image_hash, Some(dedupe::ImageHash(1)));
+        assert_eq!(costume_entries[&moved_path].image_hash, Some(dedupe::ImageHash(1)));
+        assert_eq!(costume_entries[&moved_path].file_name.save_name, "Moved");
+        assert_eq!(costume_entries[&created_path].image_hash, None);
+
+        let moved_key = costume_entries[&moved_path].tag_key();
+        assert_eq!(tag_relinks.len(), 2);
+        assert!(tag_relinks.contains(&(old_modified_key, modified_entry.tag_key())));
+        assert!(tag_relinks.iter().any(|(old_tag_key, new_tag_key)| old_tag_key != new_tag_key && *new_tag_key == moved_key));
+        drop(costume_entries);
+    }
+    #[test]
+    fn app_config_round_trips_fixed_offset() {
+        let mut app_config = AppConfig::new(Some(PathBuf::from("costumes")));
+        app_config.timestamp_display.time_zone = costume::DisplayTimeZone::Fixed(chrono::FixedOffset::east_opt(5 * 3600 + 30 * 60).unwrap());
+        let serialized = app_config.serialize();
+        assert!(serialized.contains("display_time_zone=+05:30\n"));
+        assert_eq!(AppConfig::parse(&serialized).timestamp_display, app_config.timestamp_display);
+    }
+}
@@ -0,0 +1,366 @@
+// Costume packs (.ccmpack): zip archives bundling whole saves with a manifest, for sharing many
+// costumes at once. Shared by `ccm pack`/`ccm unpack` and the GUI.
+//
+// Unlike costume libraries (see library.rs), packs carry the saves byte-for-byte under "saves/" so
+// that importing is just verifying and copying. The manifest, "manifest.json", looks like:
+//
+// {
+//   "format": "ccmpack",
+//   "version": 1,
+//   "created_by": "ccm 0.1.0",
+//   "created": "2024-03-10T07:00:00Z",
+//   "note": "Free text from whoever made the pack",
+//   "saves": [
+//     {
+//       "file": "saves/Costume_Example_700000000.jpg",
+//       "account_name": "@Account",
+//       "character_name": "Character",
+//       "hash": "7799123456",
+//       "tags": ["FightClub"]
+//     }
+//   ]
+// }
+//
+// Entries are stored uncompressed since JPEGs don't compress.
+
+use crate::{costume, create_costume_save, json::{self, JsonValue}, rename, write_costume_save, AppError, LoggerHandle};
+
+use std::{
+    collections::HashSet,
+    fmt,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+pub const EXTENSION: &str = "ccmpack";
+const FORMAT_NAME: &str = "ccmpack";
+const FORMAT_VERSION: i64 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const SAVES_DIR: &str = "saves/";
+/// Far bigger than any real save. Guards against decompression bombs in hand-made packs.
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum PackError {
+    Io { which: PathBuf, source: io::Error },
+    Zip(zip::result::ZipError),
+    Json(json::JsonParseError),
+    /// The archive is readable but not a valid pack.
+    Invalid(String),
+}
+
+impl std::error::Error for PackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Zip(err) => Some(err),
+            Self::Json(err) => Some(err),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { which, source } => write!(f, "{which:?}: {source}"),
+            Self::Zip(err) => write!(f, "invalid archive: {err}"),
+            Self::Json(err) => write!(f, "invalid manifest JSON at {err}"),
+            Self::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for PackError {
+    fn from(err: zip::result::ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
+pub struct PackEntry {
+    pub file_name: costume::CostumeFileName,
+    pub account_name: String,
+    pub character_name: String,
+    pub tags: Vec<String>,
+    /// Error if the save doesn't parse or its hash doesn't match its spec or the manifest.
+    pub save: Result<costume::CostumeSave, String>,
+}
+
+pub struct Pack {
+    pub created_by: String,
+    pub created: String,
+    pub note: String,
+    pub entries: Vec<PackEntry>,
+}
+
+/// Write the given saves to a new pack at `pack_path`.
+pub fn write_pack<'a>(
+    pack_path: &Path,
+    saves: impl IntoIterator<Item = (&'a costume::CostumeFileName, &'a costume::CostumeSave)>,
+    note: &str,
+) -> Result<usize, PackError> {
+    let io_error = |source| PackError::Io { which: pack_path.to_path_buf(), source };
+    let file = fs::File::create(pack_path).map_err(io_error)?;
+    let mut zip = zip::ZipWriter::new(io::BufWriter::new(file));
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    let mut manifest_saves = Vec::new();
+    let mut written = HashSet::new();
+    for (file_name, save) in saves {
+        // Zip entry names are case-sensitive but the costume directory might not be.
+        if !written.insert(file_name.to_string().to_lowercase()) {
+            return Err(PackError::Invalid(format!("more than one save is named {file_name}")));
+        }
+        let metadata = save.get_metadata().unwrap();
+        let entry_name = format!("{SAVES_DIR}{file_name}");
+        zip.start_file(entry_name.as_str(), options)?;
+        zip.write_all(&save.0.serialize()).map_err(io_error)?;
+
+        let tags: Vec<String> = metadata.keywords().map(json::string).collect();
+        manifest_saves.push(format!(
+            "    {{\n      \"file\": {},\n      \"account_name\": {},\n      \"character_name\": {},\n      \"hash\": {},\n      \"tags\": [{}]\n    }}",
+            json::string(&entry_name),
+            json::string(metadata.account_name()),
+            json::string(metadata.character_name()),
+            json::string(metadata.hash().trim_end_matches('\0')),
+            tags.join(", "),
+        ));
+    }
+
+    let manifest = format!(
+        "{{\n  \"format\": {},\n  \"version\": {FORMAT_VERSION},\n  \"created_by\": {},\n  \"created\": {},\n  \"note\": {},\n  \"saves\": [\n{}\n  ]\n}}\n",
+        json::string(FORMAT_NAME),
+        json::string(&format!("ccm {}", env!("CARGO_PKG_VERSION"))),
+        json::string(&chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+        json::string(note),
+        manifest_saves.join(",\n"),
+    );
+    zip.start_file(MANIFEST_FILE, options)?;
+    zip.write_all(manifest.as_bytes()).map_err(io_error)?;
+    zip.finish()?.flush().map_err(io_error)?;
+
+    Ok(manifest_saves.len())
+}
+
+/// Read a pack and verify every save in it. Problems with individual saves are reported per entry
+/// rather than failing the whole pack.
+pub fn read_pack(pack_path: &Path) -> Result<Pack, PackError> {
+    let file = fs::File::open(pack_path).map_err(|source| PackError::Io { which: pack_path.to_path_buf(), source })?;
+    let mut zip = zip::ZipArchive::new(io::BufReader::new(file))?;
+
+    let read_entry = |zip: &mut zip::ZipArchive<_>, name: &str| -> Result<Vec<u8>, String> {
+        let entry = zip.by_name(name).map_err(|err| format!("{name:?}: {err}"))?;
+        let mut bytes = Vec::new();
+        entry.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut bytes).map_err(|err| format!("{name:?}: {err}"))?;
+        if bytes.len() as u64 > MAX_ENTRY_SIZE {
+            return Err(format!("{name:?} is too big"));
+        }
+        Ok(bytes)
+    };
+
+    let manifest = read_entry(&mut zip, MANIFEST_FILE).map_err(PackError::Invalid)?;
+    let manifest = String::from_utf8(manifest).map_err(|_| PackError::Invalid("manifest isn't valid UTF-8".to_owned()))?;
+    let manifest = JsonValue::parse(&manifest).map_err(PackError::Json)?;
+    if manifest.get("format").and_then(JsonValue::as_str) != Some(FORMAT_NAME) {
+        return Err(PackError::Invalid(format!("not a costume pack (expected \"format\": {FORMAT_NAME:?})")));
+    }
+    match manifest.get("version").and_then(JsonValue::as_i64) {
+        Some(FORMAT_VERSION) => {},
+        Some(version) => return Err(PackError::Invalid(format!("unsupported pack version {version}"))),
+        None => return Err(PackError::Invalid("missing \"version\"".to_owned())),
+    }
+    let string = |value: &JsonValue, key| value.get(key).and_then(JsonValue::as_str).unwrap_or_default().to_owned();
+
+    let manifest_saves = manifest.get("saves").and_then(JsonValue::as_array).ok_or_else(|| PackError::Invalid("missing \"saves\" list".to_owned()))?;
+    let mut entries = Vec::new();
+    for (index, manifest_save) in manifest_saves.iter().enumerate() {
+        let invalid = |message: &str| PackError::Invalid(format!("save {} in the manifest: {message}", index + 1));
+        let entry_name = manifest_save.get("file").and_then(JsonValue::as_str).ok_or_else(|| invalid("missing \"file\""))?;
+        // NOTE Only the file name is ever used for the imported save so odd entry names can't escape
+        // the costume directory.
+        let file_name = entry_name.strip_prefix(SAVES_DIR)
+            .filter(|file_name| !file_name.contains(['/', '\\']) && costume::is_valid_costume_file_name(Path::new(file_name)))
+            .and_then(|file_name| costume::CostumeFileName::parse(file_name).ok())
+            .ok_or_else(|| invalid("\"file\" isn't a costume save in \"saves/\""))?;
+        let manifest_hash = string(manifest_save, "hash");
+
+        let save = read_entry(&mut zip, entry_name).and_then(|bytes| {
+            let save = costume::CostumeSave::parse(&bytes).map_err(|err| format!("failed to parse: {err}"))?;
+            let metadata = save.get_metadata().unwrap();
+            if metadata.hash() != costume::generate_costume_hash(metadata.spec()) {
+                return Err("the save's hash doesn't match its spec".to_owned());
+            }
+            if metadata.hash().trim_end_matches('\0') != manifest_hash {
+                return Err("the save's hash doesn't match the manifest".to_owned());
+            }
+            drop(metadata);
+            Ok(save)
+        });
+
+        entries.push(PackEntry {
+            file_name,
+            account_name: string(manifest_save, "account_name"),
+            character_name: string(manifest_save, "character_name"),
+            tags: manifest_save.get("tags")
+                .and_then(JsonValue::as_array)
+                .map(|tags| tags.iter().filter_map(JsonValue::as_str).map(str::to_owned).collect())
+                .unwrap_or_default(),
+            save,
+        });
+    }
+
+    Ok(Pack {
+        created_by: string(&manifest, "created_by"),
+        created: string(&manifest, "created"),
+        note: string(&manifest, "note"),
+        entries,
+    })
+}
+
+/// What to do with a pack save whose name is already taken in the costume directory.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum ConflictPolicy {
+    /// Import under "<save name> (2)", "<save name> (3)" etc.
+    Rename,
+    /// Leave the existing save alone. Also skips saves identical to an existing save.
+    Skip,
+    /// Replace the existing save (keeping a backup until the write succeeds).
+    Overwrite,
+}
+
+pub enum ImportAction {
+    Create(PathBuf),
+    Overwrite(PathBuf),
+    Skip(String),
+    /// The pack entry itself is broken.
+    Invalid(String),
+}
+
+pub struct PlannedImport {
+    /// Index into Pack::entries.
+    pub entry_index: usize,
+    pub action: ImportAction,
+    /// An existing save with exactly the same costume spec, if any.
+    pub identical_to: Option<PathBuf>,
+}
+
+/// Decide what to do with every entry in the pack. `existing_saves` is every save currently in
+/// `costume_dir`; other files in the directory are also avoided.
+pub fn plan_import<'a>(
+    pack: &Pack,
+    costume_dir: &Path,
+    existing_saves: impl IntoIterator<Item = (&'a Path, &'a costume::CostumeSave)>,
+    policy: ConflictPolicy,
+) -> Vec<PlannedImport> {
+    let existing_saves: Vec<(&Path, &costume::CostumeSave)> = existing_saves.into_iter().collect();
+    let mut taken: HashSet<PathBuf> = fs::read_dir(costume_dir)
+        .map(|dir_entries| dir_entries.flatten().map(|dir_entry| rename::collision_key(&dir_entry.path())).collect())
+        .unwrap_or_default();
+    taken.extend(existing_saves.iter().map(|(path, _)| rename::collision_key(path)));
+
+    let mut planned = Vec::new();
+    // Names this import will create, so that two entries never land on the same file.
+    let mut claimed = HashSet::new();
+    for (entry_index, entry) in pack.entries.iter().enumerate() {
+        let save = match &entry.save {
+            Ok(save) => save,
+            Err(err) => {
+                planned.push(PlannedImport { entry_index, action: ImportAction::Invalid(err.clone()), identical_to: None });
+                continue;
+            },
+        };
+        let file_path = costume_dir.join(entry.file_name.to_string());
+        let key = rename::collision_key(&file_path);
+        let spec = save.get_metadata().unwrap().spec().to_owned();
+        // Prefer reporting the save this one would collide with.
+        let identical_to = existing_saves.iter()
+            .filter(|(_, existing)| existing.get_metadata().unwrap().spec() == spec)
+            .min_by_key(|(path, _)| rename::collision_key(path) != key)
+            .map(|(path, _)| path.to_path_buf());
+
+        let action = if policy == ConflictPolicy::Skip && identical_to.is_some() {
+            ImportAction::Skip("same costume as an existing save".to_owned())
+        } else if claimed.contains(&key) {
+            ImportAction::Skip("another save in the pack has the same name".to_owned())
+        } else if !taken.contains(&key) {
+            ImportAction::Create(file_path)
+        } else {
+            match policy {
+                ConflictPolicy::Skip => ImportAction::Skip("a save with the same name already exists".to_owned()),
+                ConflictPolicy::Overwrite => ImportAction::Overwrite(file_path),
+                ConflictPolicy::Rename => {
+                    let free_path = (2..).map(|n| {
+                        let file_name = costume::CostumeFileName { save_name: format!("{} ({n})", entry.file_name.save_name), ..entry.file_name.clone() };
+                        costume_dir.join(file_name.to_string())
+                    }).find(|path| {
+                        let key = rename::collision_key(path);
+                        !taken.contains(&key) && !claimed.contains(&key)
+                    }).unwrap();
+                    ImportAction::Create(free_path)
+                },
+            }
+        };
+
+        if let ImportAction::Create(path) | ImportAction::Overwrite(path) = &action {
+            claimed.insert(rename::collision_key(path));
+        }
+        planned.push(PlannedImport { entry_index, action, identical_to });
+    }
+
+    planned
+}
+
+/// Carry out the planned creates and overwrites. Returns the result of each planned import in order,
+/// with the path written to, or None if nothing needed writing.
+pub fn apply_import(pack: &Pack, planned: &[PlannedImport], logger: &LoggerHandle) -> Vec<Result<Option<PathBuf>, AppError>> {
+    planned.iter().map(|planned| {
+        let Ok(save) = &pack.entries[planned.entry_index].save else { return Ok(None) };
+        match &planned.action {
+            // NOTE A file that appeared since planning is never clobbered.
+            ImportAction::Create(path) => create_costume_save(save, path).map(|_| Some(path.clone())),
+            ImportAction::Overwrite(path) => write_costume_save(save, path, path, logger).map(|_| Some(path.clone())),
+            ImportAction::Skip(_) | ImportAction::Invalid(_) => Ok(None),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, TestDir};
+
+    #[test]
+    fn pack_round_trips_and_plans_conflicts() {
+        let save_a = test_util::save("@Account", "A", "CostumeV2\n{\n}\n");
+        let save_b = test_util::save("@Account", "B", "CostumeV2\n{\n\tSkeleton Female\n}\n");
+        let name_a = costume::CostumeFileName::parse("Costume_A_700000000.jpg").unwrap();
+        let name_b = costume::CostumeFileName::parse("Costume_B_700000000.jpg").unwrap();
+        let dir = TestDir::new("pack");
+
+        let pack_path = dir.join("test.ccmpack");
+        assert!(write_pack(&pack_path, [(&name_a, &save_a), (&name_a, &save_b)], "").is_err());
+        assert_eq!(write_pack(&pack_path, [(&name_a, &save_a), (&name_b, &save_b)], "a \"note\"").unwrap(), 2);
+        let pack = read_pack(&pack_path).unwrap();
+        assert_eq!(pack.note, "a \"note\"");
+        assert_eq!(pack.entries.len(), 2);
+        assert_eq!(pack.entries[1].save.as_ref().unwrap().0.serialize(), save_b.0.serialize());
+
+        // A is already there, identical. B's name is taken by a different costume.
+        let costume_dir = dir.join("saves");
+        fs::create_dir(&costume_dir).unwrap();
+        let existing = [(costume_dir.join("Costume_A_700000000.jpg"), &save_a), (costume_dir.join("Costume_B_700000000.jpg"), &save_a)];
+        let existing_saves = || existing.iter().map(|(path, save)| (path.as_path(), *save));
+
+        let planned = plan_import(&pack, &costume_dir, existing_saves(), ConflictPolicy::Skip);
+        assert!(planned.iter().all(|planned| matches!(planned.action, ImportAction::Skip(_))));
+        assert_eq!(planned[0].identical_to.as_deref(), Some(existing[0].0.as_path()));
+
+        let planned = plan_import(&pack, &costume_dir, existing_saves(), ConflictPolicy::Rename);
+        let ImportAction::Create(path) = &planned[1].action else { panic!() };
+        assert_eq!(path.file_name().unwrap(), "Costume_B (2)_700000000.jpg");
+
+        let planned = plan_import(&pack, &costume_dir, existing_saves(), ConflictPolicy::Overwrite);
+        assert!(matches!(&planned[1].action, ImportAction::Overwrite(path) if *path == existing[1].0));
+    }
+}
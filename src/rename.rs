@@ -0,0 +1,224 @@
+// Batch renaming of costume saves from a save name template (see costume::template). Shared by the
+// `rename` command and the GUI's batch rename dialog.
+//
+// Planning never touches the saves themselves, only the directory listings, so it's cheap enough to
+// redo on every template edit for a live preview. Applying is all or nothing: every save is first
+// moved to a temporary name then to its new name, and any failure moves everything back. The
+// temporary names mean saves in the batch can trade names with each other.
+
+use crate::costume::{self, template::{RenameTemplate, TemplateInput}};
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+const TEMP_EXTENSION: &str = "jpg.CCM_RENAME";
+
+#[derive(Debug)]
+pub enum RenameProblem {
+    /// Another save in the batch would get the same name.
+    Duplicate(PathBuf),
+    /// A file outside the batch already has the new name.
+    AlreadyExists,
+    /// The game would read the new file name back with a different save name or timestamp.
+    Ambiguous,
+}
+
+impl fmt::Display for RenameProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Duplicate(other) => write!(f, "same new name as {other:?}"),
+            Self::AlreadyExists => write!(f, "a file with the new name already exists"),
+            Self::Ambiguous => write!(f, "save name ends in \"_<digits>\" and would be read as a timestamp"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PlannedRename {
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub new_file_name: costume::CostumeFileName,
+    pub problem: Option<RenameProblem>,
+}
+
+impl PlannedRename {
+    pub fn is_unchanged(&self) -> bool {
+        self.old_path == self.new_path
+    }
+}
+
+#[derive(Debug)]
+pub struct RenameError {
+    pub which: PathBuf,
+    pub source: io::Error,
+    /// Paths that couldn't be moved back to their original names after the failure.
+    pub unrestored: Vec<PathBuf>,
+}
+
+impl std::error::Error for RenameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to rename {:?}: {}", self.which, self.source)?;
+        if self.unrestored.is_empty() {
+            write!(f, " (every save was restored to its original name)")
+        } else {
+            write!(f, " (failed to restore {:?})", self.unrestored)
+        }
+    }
+}
+
+/// Lowercased so that names differing only by case count as the same, since they are on Windows.
+pub fn collision_key(path: &Path) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().to_lowercase())
+}
+
+/// Render the template for each save, in order (the counter starts at 1), and check the results for
+/// problems. Keeps the j2000 timestamp suffix and extension of every save.
+pub fn plan_renames<'a>(
+    template: &RenameTemplate,
+    sources: impl IntoIterator<Item = (&'a Path, &'a costume::CostumeFileName, &'a costume::CostumeSave)>,
+    timestamp_display: &costume::TimestampDisplay,
+) -> Vec<PlannedRename> {
+    let mut plan: Vec<PlannedRename> = sources.into_iter().enumerate().map(|(index, (old_path, file_name, save))| {
+        // NOTE Parsed saves always have valid metadata.
+        let metadata = save.get_metadata().unwrap();
+        let created_j2000_timestamp = fs::metadata(old_path)
+            .and_then(|metadata| metadata.created())
+            .ok()
+            .and_then(|created| costume::datetime_to_j2000(&chrono::DateTime::<chrono::Utc>::from(created)));
+        let input = TemplateInput {
+            account_name: metadata.account_name(),
+            character_name: metadata.character_name(),
+            first_keyword: metadata.keywords().next(),
+            save_name: &file_name.save_name,
+            j2000_timestamp: file_name.j2000_timestamp,
+            created_j2000_timestamp,
+            hash: costume::hash::CostumeHash::parse(metadata.hash())
+                .unwrap_or_else(|_| costume::hash::CostumeHash::of(metadata.spec())),
+            counter: index + 1,
+        };
+
+        let new_file_name = costume::CostumeFileName {
+            save_name: template.render(&input, timestamp_display),
+            ..file_name.clone()
+        };
+        let new_path = old_path.with_file_name(new_file_name.to_string());
+        let problem = (costume::CostumeFileName::parse(&new_file_name.to_string()).ok().as_ref() != Some(&new_file_name))
+            .then_some(RenameProblem::Ambiguous);
+
+        PlannedRename { old_path: old_path.to_path_buf(), new_path, new_file_name, problem }
+    }).collect();
+
+    // Everything currently in the affected directories, minus the saves being renamed since they'll
+    // have moved out of the way.
+    let batch: HashSet<PathBuf> = plan.iter().map(|rename| collision_key(&rename.old_path)).collect();
+    let mut existing = HashSet::new();
+    for dir in plan.iter().filter_map(|rename| rename.old_path.parent()).collect::<HashSet<_>>() {
+        let dir_to_read = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let Ok(dir_entries) = fs::read_dir(dir_to_read) else { continue };
+        existing.extend(
+            dir_entries.flatten()
+                .map(|dir_entry| collision_key(&dir.join(dir_entry.file_name())))
+                .filter(|key| !batch.contains(key))
+        );
+    }
+
+    let mut claimed: HashMap<PathBuf, usize> = HashMap::new();
+    for index in 0..plan.len() {
+        let key = collision_key(&plan[index].new_path);
+        let problem = if existing.contains(&key) {
+            Some(RenameProblem::AlreadyExists)
+        } else if let Some(&other) = claimed.get(&key) {
+            let (other_path, path) = (plan[other].old_path.clone(), plan[index].old_path.clone());
+            plan[other].problem.get_or_insert(RenameProblem::Duplicate(path));
+            Some(RenameProblem::Duplicate(other_path))
+        } else {
+            claimed.insert(key, index);
+            None
+        };
+        if plan[index].problem.is_none() {
+            plan[index].problem = problem;
+        }
+    }
+
+    plan
+}
+
+/// The individual renames `apply_renames` does, in order. Every save moves out of the way to a temp
+/// name before any save takes its new name, so that saves can swap names.
+pub fn rename_steps(plan: &[PlannedRename]) -> Vec<(PathBuf, PathBuf)> {
+    let renames: Vec<&PlannedRename> = plan.iter().filter(|rename| !rename.is_unchanged()).collect();
+    let temp_path = |rename: &PlannedRename| rename.old_path.with_extension(TEMP_EXTENSION);
+    renames.iter().map(|rename| (rename.old_path.clone(), temp_path(rename)))
+        .chain(renames.iter().map(|rename| (temp_path(rename), rename.new_path.clone())))
+        .collect()
+}
+
+/// Apply a plan that has no problems. Either every save is renamed or, as far as possible, none are.
+pub fn apply_renames(plan: &[PlannedRename]) -> Result<(), RenameError> {
+    debug_assert!(plan.iter().all(|rename| rename.problem.is_none()));
+
+    let steps = rename_steps(plan);
+    let steps = steps.iter().map(|(from, to)| (from.as_path(), to.as_path()));
+    let mut completed: Vec<(&Path, &Path)> = Vec::new();
+    for (from, to) in steps {
+        // NOTE fs::rename silently replaces the destination on some platforms and the plan may be
+        // stale by now.
+        let result = if to.exists() {
+            Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{to:?} already exists")))
+        } else {
+            fs::rename(from, to)
+        };
+
+        if let Err(source) = result {
+            let unrestored = completed.into_iter().rev()
+                .filter(|(from, to)| fs::rename(to, from).is_err())
+                .map(|(_, to)| to.to_path_buf())
+                .collect();
+            return Err(RenameError { which: from.to_path_buf(), source, unrestored });
+        }
+        completed.push((from, to));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planned(old_path: &Path, new_path: &Path) -> PlannedRename {
+        let new_file_name = costume::CostumeFileName::parse(new_path.file_name().unwrap().to_str().unwrap()).unwrap();
+        PlannedRename { old_path: old_path.to_path_buf(), new_path: new_path.to_path_buf(), new_file_name, problem: None }
+    }
+
+    #[test]
+    fn apply_swaps_and_rolls_back() {
+        let dir = crate::test_util::TestDir::new("rename");
+        let (a, b, c) = (dir.join("Costume_A.jpg"), dir.join("Costume_B.jpg"), dir.join("Costume_C.jpg"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        apply_renames(&[planned(&a, &b), planned(&b, &a)]).unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "a");
+
+        // C appearing after planning makes the second rename fail, which has to undo the first.
+        fs::write(&c, "c").unwrap();
+        let err = apply_renames(&[planned(&a, &dir.join("Costume_D.jpg")), planned(&b, &c)]).unwrap_err();
+        assert!(err.unrestored.is_empty());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "a");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+    }
+}
@@ -0,0 +1,345 @@
+// User-defined tags and collections. They live in a JSON file next to the executable instead of in
+// the saves, so the game never sees them and they're free-form unlike keywords.
+//
+// A save is identified by its file name plus the hash of its spec (SaveKey). ccm relinks the key
+// whenever it renames or edits a save, so tags survive anything done through ccm but not renames
+// done elsewhere. The file looks like:
+//
+// {
+//   "format": "ccm-tags",
+//   "version": 1,
+//   "tags": ["villains", "WIP"],
+//   "collections": ["Team Theme"],
+//   "saves": [
+//     {
+//       "file": "Costume_Example_700000000.jpg",
+//       "hash": "77991234567",
+//       "tags": ["WIP"],
+//       "collections": ["Team Theme"]
+//     }
+//   ]
+// }
+//
+// Tags and collections behave the same. Collections are a separate namespace for named groupings
+// (a team, an event) as opposed to descriptive labels. Names are listed at the top level too so
+// that ones no save has yet are kept.
+
+use crate::{costume, json::{self, JsonValue}};
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+pub const FILE_NAME: &str = "ccm_tags.json";
+const FORMAT_NAME: &str = "ccm-tags";
+const FORMAT_VERSION: i64 = 1;
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct SaveKey {
+    pub file_name: String,
+    pub hash: String,
+}
+
+impl SaveKey {
+    pub fn of(file_name: &costume::CostumeFileName, save: &costume::CostumeSave) -> Self {
+        // NOTE Parsed saves always have valid metadata. The stored hash isn't trusted.
+        Self::new(file_name, costume::hash::CostumeHash::of(save.get_metadata().unwrap().spec()))
+    }
+
+    /// `spec_hash` must be the hash of the save's spec, not the hash stored in the save.
+    pub fn new(file_name: &costume::CostumeFileName, spec_hash: costume::hash::CostumeHash) -> Self {
+        Self { file_name: file_name.to_string(), hash: spec_hash.to_string() }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum TagKind {
+    Tag,
+    Collection,
+}
+
+impl TagKind {
+    fn json_key(self) -> &'static str {
+        match self {
+            Self::Tag => "tags",
+            Self::Collection => "collections",
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+struct SaveTags {
+    tags: BTreeSet<String>,
+    collections: BTreeSet<String>,
+}
+
+impl SaveTags {
+    fn names(&self, kind: TagKind) -> &BTreeSet<String> {
+        match kind {
+            TagKind::Tag => &self.tags,
+            TagKind::Collection => &self.collections,
+        }
+    }
+
+    fn names_mut(&mut self, kind: TagKind) -> &mut BTreeSet<String> {
+        match kind {
+            TagKind::Tag => &mut self.tags,
+            TagKind::Collection => &mut self.collections,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.collections.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub enum TagDatabaseError {
+    Io { which: PathBuf, source: io::Error },
+    Json(json::JsonParseError),
+    /// The file is valid JSON but not a tag database.
+    Invalid(String),
+}
+
+impl std::error::Error for TagDatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Json(err) => Some(err),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for TagDatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { which, source } => write!(f, "{which:?}: {source}"),
+            Self::Json(err) => write!(f, "invalid JSON at {err}"),
+            Self::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct TagDatabase {
+    tags: BTreeSet<String>,
+    collections: BTreeSet<String>,
+    saves: HashMap<SaveKey, SaveTags>,
+}
+
+impl TagDatabase {
+    /// The database next to the executable.
+    pub fn default_path() -> PathBuf {
+        std::env::current_exe()
+            .expect("failed to get dir of executable")
+            .with_file_name(FILE_NAME)
+    }
+
+    /// A missing file is an empty database.
+    pub fn load(path: &Path) -> Result<Self, TagDatabaseError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(TagDatabaseError::Io { which: path.to_path_buf(), source }),
+        }
+    }
+
+    /// Written to a temporary file first so that a failed write never loses the old database.
+    pub fn save(&self, path: &Path) -> Result<(), TagDatabaseError> {
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, self.serialize())
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|source| TagDatabaseError::Io { which: path.to_path_buf(), source })
+    }
+
+    fn parse(text: &str) -> Result<Self, TagDatabaseError> {
+        let document = JsonValue::parse(text).map_err(TagDatabaseError::Json)?;
+        if document.get("format").and_then(JsonValue::as_str) != Some(FORMAT_NAME) {
+            return Err(TagDatabaseError::Invalid(format!("not a tag database (expected \"format\": {FORMAT_NAME:?})")));
+        }
+        match document.get("version").and_then(JsonValue::as_i64) {
+            Some(FORMAT_VERSION) => {},
+            Some(version) => return Err(TagDatabaseError::Invalid(format!("unsupported tag database version {version}"))),
+            None => return Err(TagDatabaseError::Invalid("missing \"version\"".to_owned())),
+        }
+        let names = |value: &JsonValue, key| -> BTreeSet<String> {
+            value.get(key)
+                .and_then(JsonValue::as_array)
+                .map(|names| names.iter().filter_map(JsonValue::as_str).filter(|name| !name.is_empty()).map(str::to_owned).collect())
+                .unwrap_or_default()
+        };
+
+        let mut database = Self {
+            tags: names(&document, TagKind::Tag.json_key()),
+            collections: names(&document, TagKind::Collection.json_key()),
+            saves: HashMap::new(),
+        };
+        let saves = document.get("saves").and_then(JsonValue::as_array).unwrap_or_default();
+        for (index, save) in saves.iter().enumerate() {
+            let string = |key| save.get(key).and_then(JsonValue::as_str).map(str::to_owned)
+                .ok_or_else(|| TagDatabaseError::Invalid(format!("save {}: missing {key:?}", index + 1)));
+            let key = SaveKey { file_name: string("file")?, hash: string("hash")? };
+            let save_tags = SaveTags { tags: names(save, TagKind::Tag.json_key()), collections: names(save, TagKind::Collection.json_key()) };
+            // Names only used by saves still count as existing.
+            database.tags.extend(save_tags.tags.iter().cloned());
+            database.collections.extend(save_tags.collections.iter().cloned());
+            database.saves.insert(key, save_tags);
+        }
+
+        Ok(database)
+    }
+
+    fn serialize(&self) -> String {
+        let names = |names: &BTreeSet<String>| names.iter().map(|name| json::string(name)).collect::<Vec<_>>().join(", ");
+
+        // Sorted so that the file diffs nicely.
+        let mut saves: Vec<(&SaveKey, &SaveTags)> = self.saves.iter().filter(|(_, save_tags)| !save_tags.is_empty()).collect();
+        saves.sort_by(|(a, _), (b, _)| (&a.file_name, &a.hash).cmp(&(&b.file_name, &b.hash)));
+        let saves: Vec<String> = saves.into_iter().map(|(key, save_tags)| format!(
+            "    {{\n      \"file\": {},\n      \"hash\": {},\n      \"tags\": [{}],\n      \"collections\": [{}]\n    }}",
+            json::string(&key.file_name),
+            json::string(&key.hash),
+            names(&save_tags.tags),
+            names(&save_tags.collections),
+        )).collect();
+
+        format!(
+            "{{\n  \"format\": {},\n  \"version\": {FORMAT_VERSION},\n  \"tags\": [{}],\n  \"collections\": [{}],\n  \"saves\": [\n{}\n  ]\n}}\n",
+            json::string(FORMAT_NAME),
+            names(&self.tags),
+            names(&self.collections),
+            saves.join(",\n"),
+        )
+    }
+
+    fn all_names_mut(&mut self, kind: TagKind) -> &mut BTreeSet<String> {
+        match kind {
+            TagKind::Tag => &mut self.tags,
+            TagKind::Collection => &mut self.collections,
+        }
+    }
+
+    /// Every tag or collection name, sorted.
+    pub fn names(&self, kind: TagKind) -> impl Iterator<Item = &str> {
+        match kind {
+            TagKind::Tag => self.tags.iter(),
+            TagKind::Collection => self.collections.iter(),
+        }.map(String::as_str)
+    }
+
+    /// Returns false if the name is empty or already exists.
+    pub fn create(&mut self, kind: TagKind, name: &str) -> bool {
+        !name.is_empty() && self.all_names_mut(kind).insert(name.to_owned())
+    }
+
+    /// Also removes it from every save.
+    pub fn delete(&mut self, kind: TagKind, name: &str) {
+        self.all_names_mut(kind).remove(name);
+        for save_tags in self.saves.values_mut() {
+            save_tags.names_mut(kind).remove(name);
+        }
+        self.saves.retain(|_, save_tags| !save_tags.is_empty());
+    }
+
+    /// Returns false if the new name is empty or already exists.
+    pub fn rename(&mut self, kind: TagKind, old_name: &str, new_name: &str) -> bool {
+        if !self.create(kind, new_name) {
+            return false;
+        }
+        self.all_names_mut(kind).remove(old_name);
+        for save_tags in self.saves.values_mut() {
+            if save_tags.names_mut(kind).remove(old_name) {
+                save_tags.names_mut(kind).insert(new_name.to_owned());
+            }
+        }
+        true
+    }
+
+    pub fn has(&self, key: &SaveKey, kind: TagKind, name: &str) -> bool {
+        self.saves.get(key).is_some_and(|save_tags| save_tags.names(kind).contains(name))
+    }
+
+    /// Tags or collections of a save, sorted.
+    pub fn of(&self, key: &SaveKey, kind: TagKind) -> impl Iterator<Item = &str> {
+        self.saves.get(key).into_iter().flat_map(move |save_tags| save_tags.names(kind).iter().map(String::as_str))
+    }
+
+    /// Creates the name if needed. Returns false if the save already had it.
+    pub fn add(&mut self, key: SaveKey, kind: TagKind, name: &str) -> bool {
+        self.create(kind, name);
+        self.saves.entry(key).or_default().names_mut(kind).insert(name.to_owned())
+    }
+
+    pub fn remove(&mut self, key: &SaveKey, kind: TagKind, name: &str) {
+        if let Some(save_tags) = self.saves.get_mut(key) {
+            save_tags.names_mut(kind).remove(name);
+            if save_tags.is_empty() {
+                self.saves.remove(key);
+            }
+        }
+    }
+
+    /// Move everything from each save's old key to its new one after renames or edits. Saves renamed
+    /// together may have swapped names. Returns whether anything changed.
+    pub fn relink(&mut self, relinks: impl IntoIterator<Item = (SaveKey, SaveKey)>) -> bool {
+        // Take everything out before putting anything back so one save's tags never land on another
+        // that's also moving.
+        let moved: Vec<(SaveTags, SaveKey)> = relinks.into_iter()
+            .filter(|(old_key, new_key)| old_key != new_key)
+            .filter_map(|(old_key, new_key)| Some((self.saves.remove(&old_key)?, new_key)))
+            .collect();
+        let changed = !moved.is_empty();
+        for (save_tags, new_key) in moved {
+            let merged = self.saves.entry(new_key).or_default();
+            merged.tags.extend(save_tags.tags);
+            merged.collections.extend(save_tags.collections);
+        }
+        changed
+    }
+
+    /// Drop a deleted save. Returns whether it had anything.
+    pub fn forget(&mut self, key: &SaveKey) -> bool {
+        self.saves.remove(key).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_round_trips_and_relinks() {
+        let key = |file_name: &str| SaveKey { file_name: file_name.to_owned(), hash: "77991".to_owned() };
+        let mut database = TagDatabase::default();
+        database.create(TagKind::Tag, "empty");
+        database.add(key("Costume_A.jpg"), TagKind::Tag, "WIP \"quoted\"");
+        database.add(key("Costume_A.jpg"), TagKind::Collection, "Team");
+        database.add(key("Costume_B.jpg"), TagKind::Tag, "villains");
+
+        let mut database = TagDatabase::parse(&database.serialize()).unwrap();
+        assert_eq!(database.names(TagKind::Tag).collect::<Vec<_>>(), ["WIP \"quoted\"", "empty", "villains"]);
+        assert!(database.has(&key("Costume_A.jpg"), TagKind::Collection, "Team"));
+
+        assert!(database.relink([(key("Costume_A.jpg"), key("Costume_C.jpg"))]));
+        assert!(!database.has(&key("Costume_A.jpg"), TagKind::Tag, "WIP \"quoted\""));
+        assert!(database.has(&key("Costume_C.jpg"), TagKind::Tag, "WIP \"quoted\""));
+        assert!(database.relink([(key("Costume_B.jpg"), key("Costume_C.jpg")), (key("Costume_C.jpg"), key("Costume_B.jpg"))]));
+        assert!(database.has(&key("Costume_C.jpg"), TagKind::Tag, "villains"));
+        assert!(!database.has(&key("Costume_C.jpg"), TagKind::Collection, "Team"));
+        assert!(database.has(&key("Costume_B.jpg"), TagKind::Collection, "Team"));
+        assert!(database.relink([(key("Costume_C.jpg"), key("Costume_B.jpg")), (key("Costume_B.jpg"), key("Costume_C.jpg"))]));
+
+        assert!(database.rename(TagKind::Tag, "villains", "heroes"));
+        assert!(!database.rename(TagKind::Tag, "heroes", "empty"));
+        assert_eq!(database.of(&key("Costume_B.jpg"), TagKind::Tag).collect::<Vec<_>>(), ["heroes"]);
+        database.delete(TagKind::Tag, "heroes");
+        assert!(database.of(&key("Costume_B.jpg"), TagKind::Tag).next().is_none());
+
+        assert!(TagDatabase::parse("{\"format\": \"ccm-library\", \"version\": 1}").is_err());
+    }
+}
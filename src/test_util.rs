@@ -0,0 +1,55 @@
+// Fixtures shared by the tests of several modules.
+
+use crate::costume;
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A fresh directory under the system temp dir, removed again when dropped so that a failing test
+/// doesn't leave it behind.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// `name` must be unique among the tests since they run in parallel.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ccm_{name}_test_{}", std::process::id()));
+        _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A blank 16x16 JPEG.
+pub fn image_jpeg() -> Vec<u8> {
+    let mut image_jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut image_jpeg).encode_image(&image::RgbImage::new(16, 16)).unwrap();
+    image_jpeg
+}
+
+/// A new save with a blank image.
+pub fn save(account_name: &str, character_name: &str, spec: &str) -> costume::CostumeSave {
+    costume::CostumeSave::new(&image_jpeg(), account_name, character_name, spec).unwrap()
+}
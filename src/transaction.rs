@@ -0,0 +1,594 @@
+// Crash-safe saving, and recovery from saves that were interrupted anyway.
+//
+// A save is staged to a temp file next to the original, then committed by moving the original to a
+// backup and the temp file into place. Every step is synced to disk before the next one so that
+// whatever survives a crash tells how far the save got: the rename of the temp file is the commit
+// point, so a leftover temp file means the save never happened and a leftover backup on its own
+// means it did.
+//
+// Leftovers are named after a number rather than the save, e.g. `ccm_save_3.CCM_BAK`, since names
+// built from the save's could get too long for the file system or for MAX_PATH on Windows. The
+// `ccm_save_3.CCM_SAVE` record written before anything else says which save they belong to.
+//
+// A new save has no original, so it's committed by moving the temp file into place without ever
+// replacing whatever is there. The record leaves the original's name empty.
+//
+// Older versions named the temp file after the new file and the backup after the original, without
+// a record, so leftovers from a save that renamed can only be matched up by elimination.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const LEFTOVER_PREFIX: &str = "ccm_save_";
+const RECORD_SUFFIX: &str = ".CCM_SAVE";
+const TEMP_SUFFIX: &str = ".CCM_TEMP";
+const BACKUP_SUFFIX: &str = ".CCM_BAK";
+/// Give up looking for a free leftover number after this many tries.
+const MAX_LEFTOVER_ATTEMPTS: u32 = 10_000;
+
+#[derive(Debug)]
+pub struct TransactionError {
+    pub message: String,
+    pub source: io::Error,
+    /// What went wrong before the rollback failed. The original is stuck at its backup path, where
+    /// recovery will find it.
+    pub cause: Option<Box<TransactionError>>,
+}
+
+impl TransactionError {
+    fn new(message: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        move |source| Self { message: message.into(), source, cause: None }
+    }
+}
+
+impl std::error::Error for TransactionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.message, self.source)
+    }
+}
+
+/// Make renames and new files in `dir` survive a power cut.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    fs::File::open(dir)?.sync_all()
+}
+
+// NOTE Directories can't be opened as files on Windows without going through the Win32 API, and
+// NTFS journals renames anyway.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Move `from` to `to` unless something is already there. A hard link fails rather than replace
+/// the destination, but not every file system has them, e.g. FAT.
+fn move_no_clobber(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        // NOTE `to` is in place once it's linked. If `from` is left behind, recovery cleans it up.
+        Ok(()) => {
+            _ = fs::remove_file(from);
+            Ok(())
+        },
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(err),
+        Err(_) if to.exists() => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{to:?} already exists"))),
+        Err(_) => fs::rename(from, to),
+    }
+}
+
+/// Replaces `old_path` with new contents at `new_path`, which may be the same path. Either the
+/// whole save happens or, as far as anything reading the directory can tell, none of it does.
+pub struct SaveTransaction {
+    old_path: PathBuf,
+    new_path: PathBuf,
+    /// There's no original, and `new_path` must not exist yet.
+    created: bool,
+}
+
+impl SaveTransaction {
+    /// `old_path` and `new_path` must be in the same directory.
+    pub fn new(old_path: &Path, new_path: &Path) -> Self {
+        debug_assert_eq!(old_path.parent(), new_path.parent());
+        Self { old_path: old_path.to_path_buf(), new_path: new_path.to_path_buf(), created: false }
+    }
+
+    /// Create a new save at `path`. Committing fails rather than replace a file that's there.
+    pub fn create(path: &Path) -> Self {
+        Self { old_path: path.to_path_buf(), new_path: path.to_path_buf(), created: true }
+    }
+
+    /// Write the new contents to a temp file. `prepare` gets to touch up the file, e.g. its times,
+    /// before it's synced, and gets its path. Nothing visible changes until `StagedSave::commit`.
+    pub fn stage(self, bytes: &[u8], prepare: impl FnOnce(&fs::File, &Path)) -> Result<StagedSave, TransactionError> {
+        let dir = self.old_path.parent().unwrap_or(Path::new(""));
+        let leftover_path = |number: u32, suffix: &str| dir.join(format!("{LEFTOVER_PREFIX}{number}{suffix}"));
+
+        // NOTE Creating the record is what claims a number, so that the leftovers of a save that's
+        // still waiting to be recovered are never overwritten.
+        let mut claimed = None;
+        for number in 1..=MAX_LEFTOVER_ATTEMPTS {
+            let (record_path, temp_path, backup_path) = (leftover_path(number, RECORD_SUFFIX), leftover_path(number, TEMP_SUFFIX), leftover_path(number, BACKUP_SUFFIX));
+            if temp_path.exists() || backup_path.exists() {
+                continue;
+            }
+            match fs::OpenOptions::new().write(true).create_new(true).open(&record_path) {
+                Ok(record_file) => {
+                    claimed = Some((record_file, StagedSave { old_path: self.old_path.clone(), new_path: self.new_path.clone(), created: self.created, record_path, temp_path, backup_path }));
+                    break;
+                },
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(TransactionError::new(format!("failed to create save record {record_path:?}"))(err)),
+            }
+        }
+        let Some((mut record_file, staged)) = claimed else {
+            let source = io::Error::new(io::ErrorKind::AlreadyExists, "too many leftovers of interrupted saves");
+            return Err(TransactionError::new("failed to find a free name for the save record")(source));
+        };
+
+        let old_file_name = if self.created { Default::default() } else { file_name_of(&self.old_path) };
+        let record = format!("{old_file_name}\n{}\n", file_name_of(&self.new_path));
+        let result = record_file.write_all(record.as_bytes())
+            .and_then(|_| record_file.sync_all())
+            .map_err(TransactionError::new(format!("failed to write save record {:?}", staged.record_path)));
+        drop(record_file);
+        let result = result
+            .and_then(|_| fs::File::create(&staged.temp_path).map_err(TransactionError::new(format!("failed to open temp file {:?}", staged.temp_path))))
+            .and_then(|mut temp_file| {
+                temp_file.write_all(bytes).map_err(TransactionError::new(format!("failed to write temp file {:?}", staged.temp_path)))?;
+                prepare(&temp_file, &staged.temp_path);
+                temp_file.sync_all().map_err(TransactionError::new(format!("failed to sync temp file {:?}", staged.temp_path)))
+            })
+            // NOTE The record and temp file have to be on disk before the backup is, or a crash
+            // could leave a backup that looks like the save went through.
+            .and_then(|_| sync_dir(dir).map_err(TransactionError::new("failed to sync the costume directory")));
+        match result {
+            Ok(()) => Ok(staged),
+            Err(err) => {
+                _ = fs::remove_file(&staged.temp_path);
+                _ = fs::remove_file(&staged.record_path);
+                Err(err)
+            },
+        }
+    }
+}
+
+fn file_name_of(path: &Path) -> std::borrow::Cow<'_, str> {
+    path.file_name().unwrap_or_default().to_string_lossy()
+}
+
+/// A save whose new contents are on disk, ready to be moved into place.
+pub struct StagedSave {
+    old_path: PathBuf,
+    new_path: PathBuf,
+    created: bool,
+    record_path: PathBuf,
+    temp_path: PathBuf,
+    backup_path: PathBuf,
+}
+
+impl StagedSave {
+    fn dir(&self) -> &Path {
+        self.old_path.parent().unwrap_or(Path::new(""))
+    }
+
+    /// Move the staged file into place.
+    pub fn commit(self) -> Result<(), TransactionError> {
+        let dir = self.dir().to_path_buf();
+        if self.created {
+            if let Err(err) = move_no_clobber(&self.temp_path, &self.new_path) {
+                return Err(self.roll_back(TransactionError::new("failed to move temp file into place")(err), false));
+            }
+            _ = sync_dir(&dir);
+            if fs::remove_file(&self.record_path).is_ok() {
+                _ = sync_dir(&dir);
+            }
+            return Ok(());
+        }
+
+        // Keep the original around to roll back to until the new file is in place.
+        if let Err(err) = fs::rename(&self.old_path, &self.backup_path) {
+            return Err(self.roll_back(TransactionError::new("failed to rename original file")(err), false));
+        }
+        if let Err(err) = sync_dir(&dir) {
+            return Err(self.roll_back(TransactionError::new("failed to sync the costume directory")(err), true));
+        }
+
+        // NOTE fs::rename silently replaces the destination on some platforms, and saving under a
+        // new name must never clobber another save.
+        let committed = match self.new_path != self.old_path && self.new_path.exists() {
+            true => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", self.new_path))),
+            false => fs::rename(&self.temp_path, &self.new_path),
+        };
+        if let Err(err) = committed {
+            return Err(self.roll_back(TransactionError::new("failed to rename temp file")(err), true));
+        }
+
+        // NOTE The save went through whatever happens from here. If the backup can't be removed or
+        // the removal doesn't make it to disk, recovery will clean it up.
+        _ = sync_dir(&dir);
+        if fs::remove_file(&self.backup_path).is_ok() && fs::remove_file(&self.record_path).is_ok() {
+            _ = sync_dir(&dir);
+        }
+        Ok(())
+    }
+
+    /// `backed_up` is whether the original was moved to the backup path.
+    fn roll_back(&self, err: TransactionError, backed_up: bool) -> TransactionError {
+        _ = fs::remove_file(&self.temp_path);
+        if backed_up {
+            if let Err(source) = fs::rename(&self.backup_path, &self.old_path) {
+                // NOTE The record stays so that recovery can tell what the backup is.
+                return TransactionError {
+                    message: format!("failed to revert old file backup rename ({:?} --> {:?})", self.backup_path, self.old_path),
+                    source,
+                    cause: Some(Box::new(err)),
+                };
+            }
+        }
+        _ = fs::remove_file(&self.record_path);
+        _ = sync_dir(self.dir());
+        err
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Recovery {
+    /// Put the original back as if the save never happened.
+    RollBack,
+    /// Finish the save, or just clean up after it if it already went through.
+    RollForward,
+}
+
+/// What's left of a save that was interrupted.
+#[derive(Clone, Debug)]
+pub struct InterruptedSave {
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub temp_path: Option<PathBuf>,
+    pub backup_path: Option<PathBuf>,
+    /// None for leftovers from older versions, which didn't write one.
+    record_path: Option<PathBuf>,
+    committed: bool,
+    /// A new save, so there's no original to go back to.
+    created: bool,
+    /// Leftovers from an older version that couldn't be matched up with the other half of a save
+    /// that renamed. Whatever happened to the other half, recovering this one must not leave the
+    /// save behind twice.
+    unpaired: bool,
+}
+
+impl InterruptedSave {
+    /// Whether the save got past its commit point, so only cleaning up was left.
+    pub fn is_committed(&self) -> bool {
+        self.committed
+    }
+
+    /// Whether it was a new save rather than a change to an existing one.
+    pub fn is_created(&self) -> bool {
+        self.created
+    }
+
+    /// Whether `path` can be recovered to. Another file there is only replaced if it's the save
+    /// itself, i.e. the save didn't rename.
+    fn can_replace(&self, path: &Path) -> bool {
+        (!self.created && self.old_path == self.new_path) || !path.exists()
+    }
+
+    fn check(&self, recovery: Recovery) -> io::Result<()> {
+        if self.created {
+            // NOTE Once a new save is in place, only the temp file is left to clean up. Taking the
+            // save back out is the same as deleting it, which isn't recovery's call.
+            return match (recovery, self.committed) {
+                (Recovery::RollBack, true) => Err(io::Error::new(io::ErrorKind::InvalidInput, "the new save is already in place")),
+                (Recovery::RollForward, false) if !self.can_replace(&self.new_path) => {
+                    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", self.new_path)))
+                },
+                _ => Ok(()),
+            };
+        }
+        let (keep, keep_at) = match recovery {
+            Recovery::RollBack => (&self.backup_path, &self.old_path),
+            Recovery::RollForward => (&self.temp_path, &self.new_path),
+        };
+        let (is_possible, err) = match (keep, recovery) {
+            // Which file the save was renamed to isn't known.
+            (Some(_), _) if self.unpaired && (recovery == Recovery::RollForward || self.committed) => {
+                (false, io::Error::new(io::ErrorKind::InvalidInput, "can't tell which save this belongs to"))
+            },
+            (Some(_), _) => (self.can_replace(keep_at), io::Error::new(io::ErrorKind::AlreadyExists, format!("{keep_at:?} already exists"))),
+            // Nothing to move, so it's just a matter of discarding the other leftover.
+            (None, Recovery::RollBack) => (keep_at.exists() || self.unpaired, io::Error::new(io::ErrorKind::NotFound, "nothing left to recover the save from")),
+            (None, Recovery::RollForward) => (keep_at.exists() || (self.unpaired && self.committed), io::Error::new(io::ErrorKind::NotFound, "nothing left to recover the save from")),
+        };
+        if is_possible { Ok(()) } else { Err(err) }
+    }
+
+    /// False if it would leave neither the original nor the new contents behind, e.g. because the
+    /// save was deleted since, or if it would replace another save.
+    pub fn can_recover(&self, recovery: Recovery) -> bool {
+        self.check(recovery).is_ok()
+    }
+
+    /// Whichever leaves the save as if it either fully happened or didn't happen at all.
+    pub fn recommended(&self) -> Recovery {
+        let recovery = if self.is_committed() { Recovery::RollForward } else { Recovery::RollBack };
+        match self.can_recover(recovery) {
+            true => recovery,
+            false if recovery == Recovery::RollBack => Recovery::RollForward,
+            false => Recovery::RollBack,
+        }
+    }
+
+    pub fn recover(&self, recovery: Recovery) -> io::Result<()> {
+        self.check(recovery)?;
+        if self.created {
+            if let Some(temp_path) = self.temp_path.as_ref() {
+                match recovery == Recovery::RollForward && !self.committed {
+                    true => move_no_clobber(temp_path, &self.new_path)?,
+                    false => fs::remove_file(temp_path)?,
+                }
+            }
+            if let Some(record_path) = self.record_path.as_ref() {
+                fs::remove_file(record_path)?;
+            }
+            return sync_dir(self.new_path.parent().unwrap_or(Path::new("")));
+        }
+        let (keep, keep_at, discard) = match recovery {
+            Recovery::RollBack => (&self.backup_path, &self.old_path, &self.temp_path),
+            Recovery::RollForward => (&self.temp_path, &self.new_path, &self.backup_path),
+        };
+        if let Some(keep) = keep {
+            fs::rename(keep, keep_at)?;
+        }
+        if let Some(discard) = discard {
+            fs::remove_file(discard)?;
+        }
+        if let Some(record_path) = self.record_path.as_ref() {
+            fs::remove_file(record_path)?;
+        }
+        sync_dir(self.old_path.parent().unwrap_or(Path::new("")))
+    }
+}
+
+#[derive(Default)]
+struct Leftovers {
+    record_path: Option<PathBuf>,
+    temp_path: Option<PathBuf>,
+    backup_path: Option<PathBuf>,
+}
+
+/// Every interrupted save in `dir`, sorted so that the list doesn't jump around.
+pub fn find_interrupted_saves(dir: &Path) -> io::Result<Vec<InterruptedSave>> {
+    // Keyed by leftover number.
+    let mut recorded: BTreeMap<u32, Leftovers> = BTreeMap::new();
+    // Keyed by the file name of the save they're named after.
+    let mut legacy: BTreeMap<String, Leftovers> = BTreeMap::new();
+    for dir_entry in fs::read_dir(dir)?.flatten() {
+        let leftover_path = dir_entry.path();
+        let Some(file_name) = leftover_path.file_name().and_then(|file_name| file_name.to_str()) else { continue };
+        let (stem, suffix) = match [RECORD_SUFFIX, TEMP_SUFFIX, BACKUP_SUFFIX].into_iter().find_map(|suffix| Some((file_name.strip_suffix(suffix)?, suffix))) {
+            Some((stem, suffix)) if !stem.is_empty() => (stem, suffix),
+            _ => continue,
+        };
+        let leftovers = match stem.strip_prefix(LEFTOVER_PREFIX).and_then(|number| number.parse().ok()) {
+            Some(number) => recorded.entry(number).or_default(),
+            None if suffix == RECORD_SUFFIX => continue,
+            None => legacy.entry(stem.to_owned()).or_default(),
+        };
+        let slot = match suffix {
+            RECORD_SUFFIX => &mut leftovers.record_path,
+            TEMP_SUFFIX => &mut leftovers.temp_path,
+            _ => &mut leftovers.backup_path,
+        };
+        *slot = Some(leftover_path);
+    }
+
+    let mut interrupted = Vec::new();
+    for leftovers in recorded.into_values() {
+        let Some(record_path) = leftovers.record_path else { continue };
+        if leftovers.temp_path.is_none() && leftovers.backup_path.is_none() {
+            // NOTE Either the save went through and only the record wasn't cleaned up, or it never
+            // got as far as the temp file. There's nothing to decide either way.
+            _ = fs::remove_file(&record_path);
+            continue;
+        }
+        let Ok(record) = fs::read_to_string(&record_path) else { continue };
+        let Some((old_file_name, new_file_name)) = record.lines().next().zip(record.lines().nth(1)) else { continue };
+        let created = old_file_name.is_empty();
+        let new_path = dir.join(new_file_name);
+        // NOTE A new save is moved into place by linking it, so a crash before the temp file was
+        // removed leaves both behind.
+        let committed = match (&leftovers.temp_path, created) {
+            (None, _) => true,
+            (Some(temp_path), true) => fs::read(&new_path).ok().is_some_and(|bytes| fs::read(temp_path).ok() == Some(bytes)),
+            (Some(_), false) => false,
+        };
+        interrupted.push(InterruptedSave {
+            old_path: if created { new_path.clone() } else { dir.join(old_file_name) },
+            new_path,
+            committed,
+            created,
+            temp_path: leftovers.temp_path,
+            backup_path: leftovers.backup_path,
+            record_path: Some(record_path),
+            unpaired: false,
+        });
+    }
+
+    // NOTE Older versions made the temp file before the backup and only removed it once it was moved
+    // into place or the backup was restored. So a backup whose save is gone is from a save that
+    // renamed, and it was committed unless a temp file whose save is gone is left too. Those two
+    // can only be paired up if there's one of each.
+    let (mut lone_backups, mut lone_temps) = (Vec::new(), Vec::new());
+    for (file_name, leftovers) in legacy {
+        let path = dir.join(&file_name);
+        let legacy_save = |temp_path: Option<PathBuf>, backup_path: Option<PathBuf>| InterruptedSave {
+            old_path: path.clone(),
+            new_path: path.clone(),
+            committed: temp_path.is_none(),
+            temp_path,
+            backup_path,
+            record_path: None,
+            created: false,
+            unpaired: false,
+        };
+        match (leftovers.temp_path, leftovers.backup_path) {
+            (Some(temp_path), None) if !path.exists() => lone_temps.push(legacy_save(Some(temp_path), None)),
+            (None, Some(backup_path)) if !path.exists() => lone_backups.push(legacy_save(None, Some(backup_path))),
+            (temp_path, backup_path) => interrupted.push(legacy_save(temp_path, backup_path)),
+        }
+    }
+    match (lone_backups.len(), lone_temps.len()) {
+        (1, 1) => {
+            let (backup, temp) = (lone_backups.pop().unwrap(), lone_temps.pop().unwrap());
+            interrupted.push(InterruptedSave { new_path: temp.new_path, temp_path: temp.temp_path, committed: false, ..backup });
+        },
+        (_, num_lone_temps) => {
+            // NOTE If there are lone temp files, any of the backups could belong to one, so they're
+            // all restored rather than risk throwing away the only copy of a save.
+            let committed = num_lone_temps == 0;
+            interrupted.extend(lone_backups.into_iter().map(|backup| InterruptedSave { committed, unpaired: true, ..backup }));
+            interrupted.extend(lone_temps.into_iter().map(|temp| InterruptedSave { unpaired: true, ..temp }));
+        },
+    }
+
+    interrupted.sort_by(|a, b| (&a.old_path, &a.new_path).cmp(&(&b.old_path, &b.new_path)));
+    Ok(interrupted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn commits_and_recovers() {
+        let dir = TestDir::new("transaction_recovers");
+        let save = |name: &str| dir.join(format!("Costume_{name}.jpg"));
+
+        fs::write(save("A"), "old A").unwrap();
+        let transaction = SaveTransaction::new(&save("A"), &save("A"));
+        transaction.stage(b"new A", |_, _| {}).unwrap().commit().unwrap();
+        assert_eq!(fs::read_to_string(save("A")).unwrap(), "new A");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Renaming onto another save is refused and rolled back.
+        fs::write(save("B"), "B").unwrap();
+        let staged = SaveTransaction::new(&save("A"), &save("B")).stage(b"newer A", |_, _| {}).unwrap();
+        assert!(staged.commit().is_err());
+        assert_eq!(fs::read_to_string(save("A")).unwrap(), "new A");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // Died after backing up A before moving the temp file into place as C, so it never happened.
+        let staged = SaveTransaction::new(&save("A"), &save("C")).stage(b"C", |_, _| {}).unwrap();
+        fs::rename(save("A"), &staged.backup_path).unwrap();
+        // Leftovers are named after a number, not the save.
+        assert_eq!(file_name_of(&staged.backup_path), "ccm_save_1.CCM_BAK");
+        // Died after saving B in place but before removing the backup, so it did.
+        let staged = SaveTransaction::new(&save("B"), &save("B")).stage(b"new B", |_, _| {}).unwrap();
+        fs::rename(save("B"), &staged.backup_path).unwrap();
+        fs::rename(&staged.temp_path, save("B")).unwrap();
+
+        let interrupted = find_interrupted_saves(&dir).unwrap();
+        assert_eq!(interrupted.len(), 2);
+        assert_eq!((interrupted[0].old_path.as_path(), interrupted[0].new_path.as_path()), (save("A").as_path(), save("C").as_path()));
+        assert_eq!(interrupted[0].recommended(), Recovery::RollBack);
+        assert_eq!(interrupted[1].recommended(), Recovery::RollForward);
+
+        // Finishing the save is refused once something else has taken the new name.
+        fs::write(save("C"), "other C").unwrap();
+        assert!(!interrupted[0].can_recover(Recovery::RollForward));
+        assert_eq!(interrupted[0].recover(Recovery::RollForward).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(save("C")).unwrap(), "other C");
+        fs::remove_file(save("C")).unwrap();
+
+        for interrupted_save in interrupted.iter() {
+            interrupted_save.recover(interrupted_save.recommended()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(save("A")).unwrap(), "new A");
+        assert_eq!(fs::read_to_string(save("B")).unwrap(), "new B");
+        assert!(!save("C").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert!(find_interrupted_saves(&dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn creates_without_clobbering() {
+        let dir = TestDir::new("transaction_creates");
+        let save = |name: &str| dir.join(format!("Costume_{name}.jpg"));
+
+        SaveTransaction::create(&save("A")).stage(b"A", |_, _| {}).unwrap().commit().unwrap();
+        assert_eq!(fs::read_to_string(save("A")).unwrap(), "A");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Creating over another save is refused and leaves nothing behind.
+        let staged = SaveTransaction::create(&save("A")).stage(b"other A", |_, _| {}).unwrap();
+        assert_eq!(staged.commit().unwrap_err().source.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(save("A")).unwrap(), "A");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Died before moving the temp file into place, so B never happened.
+        let staged = SaveTransaction::create(&save("B")).stage(b"B", |_, _| {}).unwrap();
+        // Died after linking C into place but before removing the temp file, so it did.
+        let staged_c = SaveTransaction::create(&save("C")).stage(b"C", |_, _| {}).unwrap();
+        fs::hard_link(&staged_c.temp_path, save("C")).unwrap();
+
+        let interrupted = find_interrupted_saves(&dir).unwrap();
+        assert_eq!(interrupted.len(), 2);
+        assert!(interrupted.iter().all(InterruptedSave::is_created));
+        assert_eq!((interrupted[0].new_path.as_path(), interrupted[0].recommended()), (save("B").as_path(), Recovery::RollBack));
+        assert_eq!((interrupted[1].new_path.as_path(), interrupted[1].recommended()), (save("C").as_path(), Recovery::RollForward));
+        assert!(!interrupted[1].can_recover(Recovery::RollBack));
+
+        // Finishing B is refused once something else has taken its name.
+        fs::write(save("B"), "other B").unwrap();
+        assert!(!interrupted[0].can_recover(Recovery::RollForward));
+        fs::remove_file(save("B")).unwrap();
+
+        interrupted[0].recover(Recovery::RollForward).unwrap();
+        interrupted[1].recover(Recovery::RollForward).unwrap();
+        assert_eq!(fs::read_to_string(save("B")).unwrap(), "B");
+        assert_eq!(fs::read_to_string(save("C")).unwrap(), "C");
+        assert!(!staged.temp_path.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn recovers_legacy_leftovers_to_one_save() {
+        let dir = TestDir::new("transaction_legacy");
+        let save = |name: &str| dir.join(format!("Costume_{name}.jpg"));
+        let leftover = |name: &str, suffix: &str| dir.join(format!("Costume_{name}.jpg{suffix}"));
+
+        // Died renaming A to B after backing up A.
+        fs::write(leftover("A", BACKUP_SUFFIX), "old A").unwrap();
+        fs::write(leftover("B", TEMP_SUFFIX), "new A").unwrap();
+        let interrupted = find_interrupted_saves(&dir).unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!((interrupted[0].old_path.as_path(), interrupted[0].new_path.as_path()), (save("A").as_path(), save("B").as_path()));
+        interrupted[0].recover(interrupted[0].recommended()).unwrap();
+        assert_eq!(fs::read_to_string(save("A")).unwrap(), "old A");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Died renaming A to C after moving the temp file into place, so C is the save now.
+        fs::rename(save("A"), save("C")).unwrap();
+        fs::write(leftover("A", BACKUP_SUFFIX), "old A").unwrap();
+        let interrupted = find_interrupted_saves(&dir).unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert!(interrupted[0].is_committed());
+        assert!(!interrupted[0].can_recover(Recovery::RollBack));
+        interrupted[0].recover(interrupted[0].recommended()).unwrap();
+        assert!(!save("A").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,357 @@
+// Deleting saves into a trash they can be restored from. Used by the GUI's Delete button and trash
+// browser.
+//
+// Both trashes use the freedesktop.org trash layout: the file moves into `files/` and a
+// `info/<name>.trashinfo` next to it records where it came from and when. On Linux that's the
+// user's home trash, so saves show up in the file manager's trash too, as long as the costume
+// directory is on the same file system (moving into the trash has to be a rename). Otherwise it's
+// our own TRASH_DIR_NAME next to the costume directory.
+//
+// The home trash holds everything else the user deleted, so listing, emptying and retention only
+// ever touch saves that came from the costume directory.
+
+use std::{
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+pub const TRASH_DIR_NAME: &str = ".ccm_trash";
+const FILES_DIR_NAME: &str = "files";
+const INFO_DIR_NAME: &str = "info";
+const INFO_EXTENSION: &str = "trashinfo";
+const INFO_HEADER: &str = "[Trash Info]";
+const DELETION_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+/// Give up looking for a free name in the trash after this many tries.
+const MAX_NAME_ATTEMPTS: u32 = 10_000;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum TrashKind {
+    /// TRASH_DIR_NAME next to the costume directory.
+    App,
+    /// The freedesktop.org home trash.
+    Home,
+}
+
+#[derive(Debug)]
+pub enum TrashError {
+    Io { which: PathBuf, source: io::Error },
+    /// Restoring would replace a file that's there now.
+    AlreadyExists(PathBuf),
+}
+
+impl std::error::Error for TrashError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::AlreadyExists(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for TrashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { which, source } => write!(f, "{which:?}: {source}"),
+            Self::AlreadyExists(which) => write!(f, "{which:?} already exists"),
+        }
+    }
+}
+
+fn io_error(which: &Path) -> impl FnOnce(io::Error) -> TrashError + '_ {
+    move |source| TrashError::Io { which: which.to_path_buf(), source }
+}
+
+/// A save in the trash.
+#[derive(Clone, Debug)]
+pub struct TrashedSave {
+    /// Where the save is now.
+    pub trashed_path: PathBuf,
+    pub info_path: PathBuf,
+    pub original_path: PathBuf,
+    /// Local time, as the spec has it.
+    pub deletion_date: chrono::NaiveDateTime,
+}
+
+impl TrashedSave {
+    /// What `info_path` holds, for recording the trashing in the undo history.
+    pub fn info_contents(&self) -> String {
+        format!(
+            "{INFO_HEADER}\nPath={}\nDeletionDate={}\n",
+            encode_path(&self.original_path),
+            self.deletion_date.format(DELETION_DATE_FORMAT),
+        )
+    }
+
+    /// Whether it's been in the trash longer than `retention_days`. Zero keeps saves forever.
+    pub fn is_expired(&self, retention_days: u32, now: chrono::NaiveDateTime) -> bool {
+        retention_days > 0 && now - self.deletion_date > chrono::TimeDelta::days(retention_days.into())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Trash {
+    kind: TrashKind,
+    dir: PathBuf,
+}
+
+impl Trash {
+    /// The trash to delete saves in `costume_dir` into.
+    pub fn for_costume_dir(costume_dir: &Path) -> Self {
+        if let Some(home_trash_dir) = Self::home_trash_dir().filter(|dir| same_file_system(dir, costume_dir)) {
+            return Self { kind: TrashKind::Home, dir: home_trash_dir };
+        }
+        let dir = match costume_dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.join(TRASH_DIR_NAME),
+            _ => costume_dir.join(TRASH_DIR_NAME),
+        };
+        Self { kind: TrashKind::App, dir }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn home_trash_dir() -> Option<PathBuf> {
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".local/share")))?;
+        Some(data_home.join("Trash"))
+    }
+
+    // TODO The Windows recycle bin and the macOS trash.
+    #[cfg(not(target_os = "linux"))]
+    fn home_trash_dir() -> Option<PathBuf> {
+        None
+    }
+
+    pub fn kind(&self) -> TrashKind {
+        self.kind
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Move a save into the trash.
+    pub fn trash(&self, path: &Path, deletion_date: chrono::NaiveDateTime) -> Result<TrashedSave, TrashError> {
+        let files_dir = self.dir.join(FILES_DIR_NAME);
+        let info_dir = self.dir.join(INFO_DIR_NAME);
+        fs::create_dir_all(&files_dir).map_err(io_error(&files_dir))?;
+        fs::create_dir_all(&info_dir).map_err(io_error(&info_dir))?;
+        let original_path = std::path::absolute(path).map_err(io_error(path))?;
+
+        // NOTE Creating the info file is what claims a name, as the spec requires, so that two
+        // programs trashing at once can't pick the same one.
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        let (stem, extension) = file_name.rsplit_once('.').unwrap_or((&file_name, ""));
+        for attempt in 1..=MAX_NAME_ATTEMPTS {
+            let trash_name = match (attempt, extension) {
+                (1, _) => file_name.to_string(),
+                (_, "") => format!("{stem}.{attempt}"),
+                _ => format!("{stem}.{attempt}.{extension}"),
+            };
+            let trashed = TrashedSave {
+                trashed_path: files_dir.join(&trash_name),
+                info_path: info_dir.join(format!("{trash_name}.{INFO_EXTENSION}")),
+                original_path: original_path.clone(),
+                deletion_date,
+            };
+            if trashed.trashed_path.exists() {
+                continue;
+            }
+            match fs::OpenOptions::new().write(true).create_new(true).open(&trashed.info_path) {
+                Ok(mut info_file) => {
+                    use io::Write;
+                    let written = info_file.write_all(trashed.info_contents().as_bytes()).map_err(io_error(&trashed.info_path));
+                    drop(info_file);
+                    let moved = written.and_then(|_| fs::rename(path, &trashed.trashed_path).map_err(io_error(path)));
+                    if let Err(err) = moved {
+                        _ = fs::remove_file(&trashed.info_path);
+                        return Err(err);
+                    }
+                    return Ok(trashed);
+                },
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(TrashError::Io { which: trashed.info_path, source: err }),
+            }
+        }
+
+        Err(TrashError::AlreadyExists(files_dir.join(&*file_name)))
+    }
+
+    /// Every save in the trash that was deleted from `costume_dir`, oldest first.
+    pub fn list(&self, costume_dir: &Path) -> Result<Vec<TrashedSave>, TrashError> {
+        let costume_dir = std::path::absolute(costume_dir).map_err(io_error(costume_dir))?;
+        let info_dir = self.dir.join(INFO_DIR_NAME);
+        let dir_entries = match fs::read_dir(&info_dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(TrashError::Io { which: info_dir, source: err }),
+        };
+
+        let mut trashed_saves = Vec::new();
+        for dir_entry in dir_entries.flatten() {
+            let info_path = dir_entry.path();
+            if info_path.extension().is_none_or(|extension| extension != INFO_EXTENSION) {
+                continue;
+            }
+            // Not ours to judge if it doesn't parse, whoever wrote it.
+            let Some((original_path, deletion_date)) = fs::read_to_string(&info_path).ok().and_then(|info| parse_info(&info)) else { continue };
+            if original_path.parent() != Some(costume_dir.as_path()) {
+                continue;
+            }
+            let trashed_path = self.dir.join(FILES_DIR_NAME).join(info_path.file_stem().unwrap());
+            if trashed_path.symlink_metadata().is_err() {
+                continue;
+            }
+            trashed_saves.push(TrashedSave { trashed_path, info_path, original_path, deletion_date });
+        }
+        trashed_saves.sort_by(|a, b| a.deletion_date.cmp(&b.deletion_date).then_with(|| a.trashed_path.cmp(&b.trashed_path)));
+
+        Ok(trashed_saves)
+    }
+
+    /// Move a save back to where it was deleted from.
+    pub fn restore(&self, trashed: &TrashedSave) -> Result<(), TrashError> {
+        // NOTE fs::rename silently replaces the destination on some platforms.
+        if trashed.original_path.exists() {
+            return Err(TrashError::AlreadyExists(trashed.original_path.clone()));
+        }
+        fs::rename(&trashed.trashed_path, &trashed.original_path).map_err(io_error(&trashed.trashed_path))?;
+        fs::remove_file(&trashed.info_path).map_err(io_error(&trashed.info_path))
+    }
+
+    /// Remove a save from the trash for good.
+    pub fn delete(&self, trashed: &TrashedSave) -> Result<(), TrashError> {
+        match fs::remove_file(&trashed.trashed_path) {
+            Ok(()) => {},
+            // Already gone, e.g. emptied from the file manager, which is what we wanted anyway.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(TrashError::Io { which: trashed.trashed_path.clone(), source: err }),
+        }
+        fs::remove_file(&trashed.info_path).map_err(io_error(&trashed.info_path))
+    }
+}
+
+#[cfg(unix)]
+fn same_file_system(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    // The trash may not exist yet, in which case it'll be created on its closest existing parent.
+    let device = |path: &Path| path.ancestors().find_map(|ancestor| fs::metadata(ancestor).ok()).map(|metadata| metadata.dev());
+    device(a).is_some() && device(a) == device(b)
+}
+
+#[cfg(not(unix))]
+fn same_file_system(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+fn parse_info(info: &str) -> Option<(PathBuf, chrono::NaiveDateTime)> {
+    let mut lines = info.lines();
+    if lines.next()?.trim() != INFO_HEADER {
+        return None;
+    }
+    let (mut path, mut deletion_date) = (None, None);
+    for (key, value) in lines.filter_map(|line| line.split_once('=')) {
+        match key {
+            "Path" => path = Some(decode_path(value)?),
+            "DeletionDate" => deletion_date = chrono::NaiveDateTime::parse_from_str(value, DELETION_DATE_FORMAT).ok(),
+            _ => {},
+        }
+    }
+    // Relative paths are relative to the trash's top directory, which is never the costume directory.
+    Some((path.filter(|path| path.is_absolute())?, deletion_date?))
+}
+
+/// Percent-encode everything but unreserved characters and separators, as the spec asks.
+fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for byte in path_bytes(path).iter() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(*byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn decode_path(encoded: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    path_from_bytes(bytes)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        std::borrow::Cow::Borrowed(path) => path.as_bytes().into(),
+        std::borrow::Cow::Owned(path) => path.into_bytes().into(),
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(std::ffi::OsString::from_vec(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn trashes_and_restores() {
+        let test_dir = TestDir::new("trash");
+        let (costume_dir, other_dir) = (test_dir.join("My Saves"), test_dir.join("other"));
+        fs::create_dir_all(&costume_dir).unwrap();
+        fs::create_dir_all(&other_dir).unwrap();
+        let save = costume_dir.join("Costume_A 100%.jpg");
+        let trash = Trash { kind: TrashKind::App, dir: test_dir.join(TRASH_DIR_NAME) };
+        let date = |day: u32| chrono::NaiveDate::from_ymd_opt(2024, 5, day).unwrap().and_hms_opt(12, 0, 0).unwrap();
+
+        // The same name twice, plus something from elsewhere that isn't ours to list.
+        fs::write(&save, "first").unwrap();
+        let first = trash.trash(&save, date(1)).unwrap();
+        fs::write(&save, "second").unwrap();
+        let second = trash.trash(&save, date(2)).unwrap();
+        fs::write(other_dir.join("Costume_B.jpg"), "other").unwrap();
+        trash.trash(&other_dir.join("Costume_B.jpg"), date(3)).unwrap();
+        assert!(!save.exists());
+        assert_eq!(second.trashed_path.file_name().unwrap(), "Costume_A 100%.2.jpg");
+        assert!(fs::read_to_string(&first.info_path).unwrap().contains("/My%20Saves/Costume_A%20100%25.jpg\n"));
+
+        let listed = trash.list(&costume_dir).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!((listed[0].original_path.as_path(), listed[0].deletion_date), (save.as_path(), date(1)));
+        assert_eq!(fs::read_to_string(&listed[1].trashed_path).unwrap(), "second");
+
+        trash.restore(&listed[1]).unwrap();
+        assert_eq!(fs::read_to_string(&save).unwrap(), "second");
+        assert!(matches!(trash.restore(&listed[0]), Err(TrashError::AlreadyExists(_))));
+        trash.delete(&listed[0]).unwrap();
+        assert!(trash.list(&costume_dir).unwrap().is_empty());
+        assert_eq!(trash.list(&other_dir).unwrap().len(), 1);
+    }
+}
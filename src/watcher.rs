@@ -0,0 +1,630 @@
+// Watching the costume directory for changes to individual saves. Used by the GUI's scanner thread.
+//
+// Events come from the OS through the notify crate (inotify on Linux, ReadDirectoryChangesW on
+// Windows and FSEvents on macOS) and, if that fails, from polling the
+// directory listing. Either way they're filtered down to costume save file names and coalesced:
+// the raw events of e.g. a save being written through a temp file and a rename settle into a single
+// Modified once nothing has happened for COALESCE_DELAY.
+//
+// The app's own writes are registered with OwnOperations before they happen so that they can be
+// told apart from external changes. Once an operation is done, what it left each save as is
+// recorded, and an event is only taken to be the app's own if the save still looks like that.
+
+use crate::{costume, index::{ContentHash, FileStamp}};
+
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How long the directory has to be quiet before pending events are emitted.
+const COALESCE_DELAY: Duration = Duration::from_millis(150);
+/// Pending events are emitted after this long even if the directory never goes quiet.
+const MAX_COALESCE_DELAY: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the first half of a rename waits for the second half before it's taken to be a file
+/// moved out of the directory. The halves can be read separately.
+const MOVE_PAIR_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum WatchEvent {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf),
+    Renamed { from: PathBuf, to: PathBuf },
+    /// Events were lost or the directory itself went away, so everything has to be scanned again.
+    Rescan,
+}
+
+impl WatchEvent {
+    fn paths(&self) -> impl Iterator<Item = &Path> {
+        let (first, second) = match self {
+            Self::Created(path) | Self::Modified(path) | Self::Removed(path) => (Some(path.as_path()), None),
+            Self::Renamed { from, to } => (Some(from.as_path()), Some(to.as_path())),
+            Self::Rescan => (None, None),
+        };
+        first.into_iter().chain(second)
+    }
+
+    /// Reduce an event to what it means for costume saves, e.g. a temp file renamed to a save's name
+    /// is that save being created.
+    fn for_costume_saves(self) -> Option<Self> {
+        let is_save = |path: &Path| costume::is_valid_costume_file_name(path);
+        match self {
+            Self::Created(ref path) | Self::Modified(ref path) | Self::Removed(ref path) if !is_save(path) => None,
+            Self::Renamed { from, to } => match (is_save(&from), is_save(&to)) {
+                (true, true) => Some(Self::Renamed { from, to }),
+                (false, true) => Some(Self::Created(to)),
+                (true, false) => Some(Self::Removed(from)),
+                (false, false) => None,
+            },
+            event => Some(event),
+        }
+    }
+}
+
+/// One of the app's own file system operations, which is finished when this is dropped, whether
+/// or not it succeeded.
+#[must_use]
+pub struct OwnOperation {
+    operations: OwnOperations,
+    id: u64,
+}
+
+impl Drop for OwnOperation {
+    fn drop(&mut self) {
+        self.operations.finish(self.id);
+    }
+}
+
+/// What a save was left as, or None if it doesn't exist.
+type FileState = Option<(Option<FileStamp>, ContentHash)>;
+
+fn file_state(path: &Path) -> io::Result<FileState> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some((fs::metadata(path).ok().as_ref().and_then(FileStamp::of), ContentHash::of(&bytes)))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+struct Operation {
+    /// Each path with what the operation left it as, or None while it's still in progress.
+    paths: Vec<(PathBuf, Option<FileState>)>,
+}
+
+#[derive(Default)]
+struct Operations {
+    next_id: u64,
+    /// Kept until an event shows that every path was changed since, which may be never.
+    operations: HashMap<u64, Operation>,
+}
+
+/// The app's own changes to the costume directory, whose events are suppressed. Cloning shares the
+/// same set of operations.
+#[derive(Clone, Default)]
+pub struct OwnOperations(Arc<Mutex<Operations>>);
+
+impl OwnOperations {
+    /// Call before touching `paths` and keep the result around until done. Temp and backup files
+    /// don't need to be included since they aren't saves.
+    pub fn begin(&self, paths: impl IntoIterator<Item = PathBuf>) -> OwnOperation {
+        let mut operations = self.0.lock().unwrap();
+        let id = operations.next_id;
+        operations.next_id += 1;
+        operations.operations.insert(id, Operation { paths: paths.into_iter().map(|path| (path, None)).collect() });
+        OwnOperation { operations: self.clone(), id }
+    }
+
+    fn finish(&self, id: u64) {
+        // NOTE Read outside the lock since it means reading whole saves.
+        let Some(paths) = self.0.lock().unwrap().operations.get(&id).map(|operation| operation.paths.iter().map(|(path, _)| path.clone()).collect::<Vec<_>>()) else { return };
+        let states: Vec<_> = paths.into_iter().map(|path| {
+            // A save that can't be read can't be matched, so its events aren't suppressed.
+            let state = file_state(&path).ok();
+            (path, state)
+        }).collect();
+        let mut operations = self.0.lock().unwrap();
+        match states.iter().all(|(_, state)| state.is_none()) {
+            true => _ = operations.operations.remove(&id),
+            false => if let Some(operation) = operations.operations.get_mut(&id) {
+                operation.paths = states.into_iter().filter(|(_, state)| state.is_some()).collect();
+            },
+        }
+    }
+
+    /// Whether the event is from one of the app's operations, i.e. every save it's about is either
+    /// still being changed by one or is how the most recent one left it.
+    fn is_own(&self, event: &WatchEvent) -> bool {
+        if *event == WatchEvent::Rescan {
+            return false;
+        }
+        let mut operations = self.0.lock().unwrap();
+        let is_own = event.paths().all(|path| {
+            let latest = operations.operations.iter_mut()
+                .filter_map(|(id, operation)| Some((*id, operation.paths.iter_mut().position(|(own_path, _)| own_path == path)?, operation)))
+                .max_by_key(|(id, ..)| *id);
+            let Some((_, index, operation)) = latest else { return false };
+            let Some(expected) = operation.paths[index].1 else { return true };
+            match file_state(path).is_ok_and(|state| state == expected) {
+                true => true,
+                // Changed since, so the operation has nothing more to say about this save.
+                false => {
+                    operation.paths.remove(index);
+                    false
+                },
+            }
+        });
+        operations.operations.retain(|_, operation| !operation.paths.is_empty());
+        is_own
+    }
+}
+
+/// Merges raw events into the net change to each save.
+#[derive(Default)]
+struct Coalescer {
+    /// Roughly in the order the changes happened.
+    pending: Vec<(PathBuf, PendingChange)>,
+    rescan: bool,
+    first_pending_at: Option<Instant>,
+    last_pending_at: Option<Instant>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+enum PendingChange {
+    Created,
+    Modified,
+    Removed,
+    Renamed { from: PathBuf, modified: bool },
+}
+
+impl Coalescer {
+    fn take(&mut self, path: &Path) -> Option<PendingChange> {
+        let index = self.pending.iter().position(|(pending_path, _)| pending_path == path)?;
+        Some(self.pending.remove(index).1)
+    }
+
+    fn set(&mut self, path: PathBuf, change: PendingChange) {
+        match self.pending.iter_mut().find(|(pending_path, _)| *pending_path == path) {
+            Some((_, pending_change)) => *pending_change = change,
+            None => self.pending.push((path, change)),
+        }
+    }
+
+    fn push(&mut self, event: WatchEvent, now: Instant) {
+        self.first_pending_at.get_or_insert(now);
+        self.last_pending_at = Some(now);
+
+        match event {
+            WatchEvent::Created(path) => {
+                let change = match self.take(&path) {
+                    // Replaced, e.g. by an editor that writes a new file and deletes the old one.
+                    Some(PendingChange::Removed) => PendingChange::Modified,
+                    _ => PendingChange::Created,
+                };
+                self.set(path, change);
+            },
+            WatchEvent::Modified(path) => {
+                let change = match self.take(&path) {
+                    Some(PendingChange::Created) => PendingChange::Created,
+                    Some(PendingChange::Renamed { from, .. }) => PendingChange::Renamed { from, modified: true },
+                    _ => PendingChange::Modified,
+                };
+                self.set(path, change);
+            },
+            WatchEvent::Removed(path) => match self.take(&path) {
+                // Never existed as far as anyone outside this burst is concerned.
+                Some(PendingChange::Created) => {},
+                Some(PendingChange::Renamed { from, .. }) => self.set(from, PendingChange::Removed),
+                _ => self.set(path, PendingChange::Removed),
+            },
+            WatchEvent::Renamed { from, to } => {
+                let change = match self.take(&from) {
+                    Some(PendingChange::Created) => PendingChange::Created,
+                    Some(PendingChange::Renamed { from: original, .. }) if original == to => PendingChange::Modified,
+                    Some(PendingChange::Renamed { from: original, modified }) => PendingChange::Renamed { from: original, modified },
+                    Some(PendingChange::Modified) => PendingChange::Renamed { from, modified: true },
+                    _ => PendingChange::Renamed { from, modified: false },
+                };
+                self.set(to, change);
+            },
+            WatchEvent::Rescan => {
+                self.pending.clear();
+                self.rescan = true;
+            },
+        }
+    }
+
+    /// The net events, if the burst they're part of is over.
+    fn drain_settled(&mut self, now: Instant) -> Vec<WatchEvent> {
+        let (Some(first_pending_at), Some(last_pending_at)) = (self.first_pending_at, self.last_pending_at) else { return Vec::new() };
+        if now - last_pending_at < COALESCE_DELAY && now - first_pending_at < MAX_COALESCE_DELAY {
+            return Vec::new();
+        }
+        self.first_pending_at = None;
+        self.last_pending_at = None;
+
+        if std::mem::take(&mut self.rescan) {
+            self.pending.clear();
+            return vec![WatchEvent::Rescan];
+        }
+        let mut events = Vec::new();
+        for (path, change) in self.pending.drain(..) {
+            match change {
+                PendingChange::Created => events.push(WatchEvent::Created(path)),
+                PendingChange::Modified => events.push(WatchEvent::Modified(path)),
+                PendingChange::Removed => events.push(WatchEvent::Removed(path)),
+                PendingChange::Renamed { from, modified } => {
+                    events.push(WatchEvent::Renamed { from, to: path.clone() });
+                    if modified {
+                        events.push(WatchEvent::Modified(path));
+                    }
+                },
+            }
+        }
+        events
+    }
+}
+
+/// Pairs up the halves of renames that the OS reports as separate events, which may come in
+/// separate reads.
+struct MovePairs<K> {
+    /// First halves waiting for their second half, oldest first.
+    pending: Vec<(K, PathBuf, Instant)>,
+}
+
+impl<K> Default for MovePairs<K> {
+    fn default() -> Self {
+        Self { pending: Vec::new() }
+    }
+}
+
+impl<K: PartialEq> MovePairs<K> {
+    fn take(&mut self, key: &K) -> Option<PathBuf> {
+        let index = self.pending.iter().position(|(pending_key, ..)| pending_key == key)?;
+        Some(self.pending.remove(index).1)
+    }
+
+    /// The first half of a rename. An earlier first half under the same key can't be paired any
+    /// more, so it comes back as a removal.
+    fn moved_from(&mut self, key: K, path: PathBuf, now: Instant) -> Option<WatchEvent> {
+        let replaced = self.take(&key).map(WatchEvent::Removed);
+        self.pending.push((key, path, now));
+        replaced
+    }
+
+    /// The second half of a rename, or a file moved in from elsewhere if there's no first half.
+    fn moved_to(&mut self, key: K, path: PathBuf) -> WatchEvent {
+        match self.take(&key) {
+            Some(from) => WatchEvent::Renamed { from, to: path },
+            None => WatchEvent::Created(path),
+        }
+    }
+
+    /// First halves that waited MOVE_PAIR_DELAY for nothing, i.e. files moved out to somewhere else.
+    fn expire(&mut self, now: Instant) -> Vec<WatchEvent> {
+        let (expired, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(_, _, moved_at)| now.saturating_duration_since(*moved_at) >= MOVE_PAIR_DELAY);
+        self.pending = pending;
+        expired.into_iter().map(|(_, path, _)| WatchEvent::Removed(path)).collect()
+    }
+
+    /// `timeout`, cut short to when the oldest first half expires.
+    fn timeout(&self, timeout: Duration, now: Instant) -> Duration {
+        self.pending.iter()
+            .map(|(_, _, moved_at)| (*moved_at + MOVE_PAIR_DELAY).saturating_duration_since(now))
+            .fold(timeout, Duration::min)
+    }
+}
+
+enum Backend {
+    Notify(notify_backend::Notify),
+    Polling(Polling),
+}
+
+pub struct Watcher {
+    backend: Backend,
+    coalescer: Coalescer,
+    own_operations: OwnOperations,
+}
+
+impl Watcher {
+    /// Watch using the OS's change notifications. Fails if the OS refuses, e.g. because it's out of
+    /// watches or the directory is on a network share, in which case use `polling`.
+    pub fn native(dir: &Path, own_operations: OwnOperations) -> io::Result<Self> {
+        let backend = Backend::Notify(notify_backend::Notify::new(dir)?);
+
+        Ok(Self { backend, coalescer: Coalescer::default(), own_operations })
+    }
+
+    pub fn polling(dir: &Path, own_operations: OwnOperations) -> Self {
+        Self { backend: Backend::Polling(Polling::new(dir)), coalescer: Coalescer::default(), own_operations }
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        match self.backend {
+            Backend::Notify(_) => notify_backend::NAME,
+            Backend::Polling(_) => "polling",
+        }
+    }
+
+    /// Wait up to `timeout` for changes. Returns nothing until a burst of changes has settled.
+    pub fn wait(&mut self, timeout: Duration) -> Vec<WatchEvent> {
+        let raw_events = match &mut self.backend {
+            Backend::Notify(notify) => notify.wait(timeout),
+            Backend::Polling(polling) => polling.wait(timeout),
+        };
+
+        let now = Instant::now();
+        for event in raw_events.into_iter().filter_map(WatchEvent::for_costume_saves) {
+            if !self.own_operations.is_own(&event) {
+                self.coalescer.push(event, now);
+            }
+        }
+        self.coalescer.drain_settled(now)
+    }
+}
+
+/// Diffs the directory listing every POLL_INTERVAL. Renames are recognized by a save disappearing
+/// while another with the same size and modified time appears.
+struct Polling {
+    dir: PathBuf,
+    /// Size and modified time of every file, or None before the first poll.
+    snapshot: Option<HashMap<PathBuf, (u64, Option<SystemTime>)>>,
+    next_poll_at: Instant,
+}
+
+impl Polling {
+    fn new(dir: &Path) -> Self {
+        let mut polling = Self { dir: dir.to_path_buf(), snapshot: None, next_poll_at: Instant::now() };
+        // The caller scans the directory itself to begin with, so only report changes after that.
+        polling.poll();
+        polling
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Vec<WatchEvent> {
+        let until_poll = self.next_poll_at.saturating_duration_since(Instant::now());
+        if until_poll > timeout {
+            thread::sleep(timeout);
+            return Vec::new();
+        }
+        thread::sleep(until_poll);
+        self.poll()
+    }
+
+    fn poll(&mut self) -> Vec<WatchEvent> {
+        self.next_poll_at = Instant::now() + POLL_INTERVAL;
+        let Ok(dir_entries) = fs::read_dir(&self.dir) else {
+            let had_snapshot = self.snapshot.take().is_some();
+            return if had_snapshot { vec![WatchEvent::Rescan] } else { Vec::new() };
+        };
+        let snapshot: HashMap<PathBuf, (u64, Option<SystemTime>)> = dir_entries.flatten()
+            .filter_map(|dir_entry| {
+                let metadata = dir_entry.metadata().ok().filter(fs::Metadata::is_file)?;
+                Some((dir_entry.path(), (metadata.len(), metadata.modified().ok())))
+            })
+            .collect();
+        let Some(previous) = self.snapshot.replace(snapshot) else { return vec![WatchEvent::Rescan] };
+        let snapshot = self.snapshot.as_ref().unwrap();
+
+        let mut removed: Vec<(&PathBuf, &(u64, Option<SystemTime>))> = previous.iter().filter(|(path, _)| !snapshot.contains_key(*path)).collect();
+        let mut events = Vec::new();
+        for (path, stamp) in snapshot.iter() {
+            match previous.get(path) {
+                Some(previous_stamp) if previous_stamp == stamp => {},
+                Some(_) => events.push(WatchEvent::Modified(path.clone())),
+                None => match removed.iter().position(|(_, removed_stamp)| *removed_stamp == stamp && stamp.1.is_some()) {
+                    Some(index) => events.push(WatchEvent::Renamed { from: removed.swap_remove(index).0.clone(), to: path.clone() }),
+                    None => events.push(WatchEvent::Created(path.clone())),
+                },
+            }
+        }
+        events.extend(removed.into_iter().map(|(path, _)| WatchEvent::Removed(path.clone())));
+        events
+    }
+}
+
+mod notify_backend {
+    use super::{MovePairs, WatchEvent};
+
+    use notify::{
+        event::{ModifyKind, RenameMode},
+        EventKind,
+        Watcher as _,
+    };
+    use std::{
+        io,
+        path::{Path, PathBuf},
+        sync::mpsc,
+        time::{Duration, Instant},
+    };
+
+    #[cfg(windows)]
+    pub const NAME: &str = "ReadDirectoryChangesW";
+    #[cfg(target_os = "macos")]
+    pub const NAME: &str = "FSEvents";
+    #[cfg(target_os = "linux")]
+    pub const NAME: &str = "inotify";
+    #[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+    pub const NAME: &str = "notify";
+
+    pub struct Notify {
+        // NOTE Watching stops when this is dropped.
+        _watcher: notify::RecommendedWatcher,
+        receiver: mpsc::Receiver<notify::Result<notify::Event>>,
+        dir: PathBuf,
+        /// Keyed by the events' tracker, i.e. inotify's cookie. ReadDirectoryChangesW doesn't set
+        /// one, but its halves of a rename always come one right after the other.
+        move_pairs: MovePairs<Option<usize>>,
+    }
+
+    impl Notify {
+        pub fn new(dir: &Path) -> io::Result<Self> {
+            let into_io_error = |err: notify::Error| match err.kind {
+                notify::ErrorKind::Io(err) => err,
+                kind => io::Error::other(format!("{kind:?}")),
+            };
+            let (sender, receiver) = mpsc::channel();
+            let mut watcher = notify::recommended_watcher(sender).map_err(into_io_error)?;
+            watcher.watch(dir, notify::RecursiveMode::NonRecursive).map_err(into_io_error)?;
+            Ok(Self { _watcher: watcher, receiver, dir: dir.to_path_buf(), move_pairs: MovePairs::default() })
+        }
+
+        pub fn wait(&mut self, timeout: Duration) -> Vec<WatchEvent> {
+            let first = self.receiver.recv_timeout(self.move_pairs.timeout(timeout, Instant::now())).ok();
+            let now = Instant::now();
+            let mut events = Vec::new();
+            for result in first.into_iter().chain(self.receiver.try_iter().collect::<Vec<_>>()) {
+                self.convert(result, now, &mut events);
+            }
+            events.extend(self.move_pairs.expire(now));
+            events
+        }
+
+        fn convert(&mut self, result: notify::Result<notify::Event>, now: Instant, events: &mut Vec<WatchEvent>) {
+            let event = match result {
+                Ok(event) if !event.need_rescan() => event,
+                _ => {
+                    events.push(WatchEvent::Rescan);
+                    return;
+                },
+            };
+            // NOTE inotify reports the directory itself being moved or deleted as an event about its
+            // own path.
+            if event.paths.contains(&self.dir) && matches!(event.kind, EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))) {
+                events.push(WatchEvent::Rescan);
+                return;
+            }
+            let tracker = event.tracker();
+            // NOTE FSEvents reports canonical paths, e.g. through /private on macOS, so put them
+            // back in the directory as the scanner knows it.
+            let paths = event.paths.iter().filter_map(|path| Some(self.dir.join(path.file_name()?)));
+
+            match event.kind {
+                // NOTE inotify follows up the two halves of a rename with one for the pair, which
+                // MovePairs has already matched up from the halves.
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if tracker.is_some() => {},
+                EventKind::Create(_) => events.extend(paths.map(WatchEvent::Created)),
+                EventKind::Remove(_) => events.extend(paths.map(WatchEvent::Removed)),
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                    let mut paths = paths;
+                    if let (Some(from), Some(to)) = (paths.next(), paths.next()) {
+                        events.push(WatchEvent::Renamed { from, to });
+                    }
+                },
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                    for path in paths.collect::<Vec<_>>() {
+                        events.extend(self.move_pairs.moved_from(tracker, path, now));
+                    }
+                },
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                    for path in paths.collect::<Vec<_>>() {
+                        events.push(self.move_pairs.moved_to(tracker, path));
+                    }
+                },
+                // NOTE FSEvents doesn't tell which side of a rename a path was on, so go by whether
+                // it's there now.
+                EventKind::Modify(ModifyKind::Name(_)) => {
+                    events.extend(paths.map(|path| if path.exists() { WatchEvent::Created(path) } else { WatchEvent::Removed(path) }));
+                },
+                EventKind::Modify(_) => events.extend(paths.map(WatchEvent::Modified)),
+                EventKind::Access(_) | EventKind::Any | EventKind::Other => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesces_bursts() {
+        let path = |name: &str| PathBuf::from(format!("saves/Costume_{name}.jpg"));
+        let start = Instant::now();
+        let settled = start + COALESCE_DELAY;
+        let mut coalescer = Coalescer::default();
+
+        // Written through a backup and a temp file, as write_costume_save does.
+        coalescer.push(WatchEvent::Removed(path("A")), start);
+        coalescer.push(WatchEvent::Created(path("A")), start);
+        // Created, then renamed twice and edited.
+        coalescer.push(WatchEvent::Created(path("B")), start);
+        coalescer.push(WatchEvent::Renamed { from: path("B"), to: path("C") }, start);
+        coalescer.push(WatchEvent::Renamed { from: path("D"), to: path("E") }, start);
+        coalescer.push(WatchEvent::Modified(path("E")), start);
+        coalescer.push(WatchEvent::Created(path("F")), start);
+        coalescer.push(WatchEvent::Removed(path("F")), start);
+        coalescer.push(WatchEvent::Renamed { from: path("G"), to: path("H") }, start);
+        coalescer.push(WatchEvent::Removed(path("H")), start);
+
+        assert!(coalescer.drain_settled(start + COALESCE_DELAY / 2).is_empty());
+        assert_eq!(coalescer.drain_settled(settled), [
+            WatchEvent::Modified(path("A")),
+            WatchEvent::Created(path("C")),
+            WatchEvent::Renamed { from: path("D"), to: path("E") },
+            WatchEvent::Modified(path("E")),
+            WatchEvent::Removed(path("G")),
+        ]);
+        assert!(coalescer.drain_settled(settled).is_empty());
+
+        coalescer.push(WatchEvent::Modified(path("A")), settled);
+        coalescer.push(WatchEvent::Rescan, settled);
+        assert_eq!(coalescer.drain_settled(settled + MAX_COALESCE_DELAY), [WatchEvent::Rescan]);
+
+        assert_eq!(WatchEvent::Renamed { from: PathBuf::from("Costume_A.jpg.CCM_TEMP"), to: path("A") }.for_costume_saves(), Some(WatchEvent::Created(path("A"))));
+        assert_eq!(WatchEvent::Modified(PathBuf::from("notes.txt")).for_costume_saves(), None);
+    }
+
+    #[test]
+    fn pairs_renames_across_reads() {
+        let path = |name: &str| PathBuf::from(format!("saves/Costume_{name}.jpg"));
+        let start = Instant::now();
+        let mut move_pairs = MovePairs::default();
+
+        // The first half at the end of one read, the second at the start of the next.
+        assert_eq!(move_pairs.moved_from(1, path("A"), start), None);
+        assert!(move_pairs.expire(start).is_empty());
+        assert_eq!(move_pairs.timeout(Duration::from_secs(1), start), MOVE_PAIR_DELAY);
+        assert_eq!(move_pairs.moved_to(1, path("B")), WatchEvent::Renamed { from: path("A"), to: path("B") });
+
+        assert_eq!(move_pairs.moved_to(2, path("C")), WatchEvent::Created(path("C")));
+        assert_eq!(move_pairs.moved_from(3, path("D"), start), None);
+        assert_eq!(move_pairs.moved_from(3, path("E"), start), Some(WatchEvent::Removed(path("D"))));
+        assert!(move_pairs.expire(start + MOVE_PAIR_DELAY / 2).is_empty());
+        assert_eq!(move_pairs.expire(start + MOVE_PAIR_DELAY), [WatchEvent::Removed(path("E"))]);
+        assert_eq!(move_pairs.timeout(Duration::from_secs(1), start), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn own_operations_are_suppressed() {
+        let dir = crate::test_util::TestDir::new("watcher");
+        let path = |name: &str| dir.join(format!("Costume_{name}.jpg"));
+        let own_operations = OwnOperations::default();
+
+        let event = WatchEvent::Renamed { from: path("A"), to: path("B") };
+        fs::write(path("A"), "A").unwrap();
+        let operation = own_operations.begin([path("A"), path("B")]);
+        assert!(own_operations.is_own(&event));
+        fs::rename(path("A"), path("B")).unwrap();
+        drop(operation);
+        // However late the events come, as long as the saves are how the operation left them.
+        assert!(own_operations.is_own(&event));
+        assert!(own_operations.is_own(&WatchEvent::Modified(path("B"))));
+        assert!(!own_operations.is_own(&WatchEvent::Created(path("C"))));
+        assert!(!own_operations.is_own(&WatchEvent::Rescan));
+
+        // Changed by something else since.
+        fs::write(path("B"), "external").unwrap();
+        assert!(!own_operations.is_own(&WatchEvent::Modified(path("B"))));
+        fs::write(path("A"), "external").unwrap();
+        assert!(!own_operations.is_own(&WatchEvent::Created(path("A"))));
+        assert!(own_operations.0.lock().unwrap().operations.is_empty());
+    }
+}